@@ -0,0 +1,49 @@
+//! Benchmarks for the ThermoBeacon advertisement parser.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use packed_struct::prelude::*;
+use thermobeacon_server::{
+    parse_advertisement,
+    thermobeacon_protocol::{ThermoBeaconMinMaxRawData, ThermoBeaconRawData},
+    Reading, ThermoBeaconFrame,
+};
+
+/// Captured 18 byte frame (temperature and humidity) of a ThermoBeacon
+const DATA_FRAME: [u8; 18] = [
+    0x00, 0x00, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x48, 0x0d, 0x58, 0x01, 0xd0, 0x02, 0x80, 0x51,
+    0x01, 0x00,
+];
+
+/// Captured 20 byte frame (min and max temperature) of a ThermoBeacon
+const MIN_MAX_FRAME: [u8; 20] = [
+    0x00, 0x00, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x90, 0x01, 0x10, 0x0e, 0x00, 0x00, 0xf0, 0xff,
+    0x20, 0x1c, 0x00, 0x00,
+];
+
+fn unpack_frames(c: &mut Criterion) {
+    c.bench_function("unpack data frame", |b| {
+        b.iter(|| ThermoBeaconRawData::unpack(black_box(&DATA_FRAME)).unwrap())
+    });
+    c.bench_function("unpack min/max frame", |b| {
+        b.iter(|| ThermoBeaconMinMaxRawData::unpack(black_box(&MIN_MAX_FRAME)).unwrap())
+    });
+}
+
+fn parse_pipeline(c: &mut Criterion) {
+    c.bench_function("parse advertisements to full read result", |b| {
+        b.iter(|| {
+            let data = match parse_advertisement(black_box(&DATA_FRAME)).unwrap() {
+                ThermoBeaconFrame::Data(data) => data,
+                _ => unreachable!(),
+            };
+            let min_max_data = match parse_advertisement(black_box(&MIN_MAX_FRAME)).unwrap() {
+                ThermoBeaconFrame::MinMax(min_max_data) => min_max_data,
+                _ => unreachable!(),
+            };
+            let result: Reading = (data, min_max_data).into();
+            serde_json::to_string(&result).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, unpack_frames, parse_pipeline);
+criterion_main!(benches);
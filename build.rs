@@ -0,0 +1,8 @@
+//! Generates the Rust types of the protobuf payload from the shipped schema, with the pure Rust compiler protox instead of protoc
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=thermobeacon.proto");
+    let file_descriptors = protox::compile(["thermobeacon.proto"], ["."])?;
+    prost_build::compile_fds(file_descriptors)?;
+    Ok(())
+}
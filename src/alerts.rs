@@ -0,0 +1,395 @@
+//! Threshold alerts: rules comparing a metric of the readings of a device to a value. An alert is raised once the condition
+//! held for the minimum duration of the rule and cleared once the metric is back beyond the hysteresis. The state of the rules
+//! is kept across runs (and optionally across restarts), the changes are published to the alert topics of the devices and
+//! sent to the notifiers of the rule within its time windows.
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::Path,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    configuration::{AlertRule, AlertsConfig, AppConfig, AppDevice, Metric, Operator},
+    notifications,
+    sink::{Message, Sink},
+    time_window,
+};
+
+impl Operator {
+    fn compare(self, value: f32, threshold: f32) -> bool {
+        match self {
+            Operator::Greater => value > threshold,
+            Operator::GreaterOrEqual => value >= threshold,
+            Operator::Less => value < threshold,
+            Operator::LessOrEqual => value <= threshold,
+        }
+    }
+
+    /// Threshold keeping a raised alert: the value moved away from the alert condition by the hysteresis
+    fn keep_threshold(self, threshold: f32, hysteresis: f32) -> f32 {
+        match self {
+            Operator::Greater | Operator::GreaterOrEqual => threshold - hysteresis,
+            Operator::Less | Operator::LessOrEqual => threshold + hysteresis,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+        }
+    }
+}
+
+/// State of a rule for a device
+#[derive(Debug, Clone, Default, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+struct RuleState {
+    /// Start of the current breach of the condition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    since: Option<DateTime<Utc>>,
+    /// The breach lasted long enough to raise the alert
+    #[serde(default)]
+    raised: bool,
+}
+
+/// State of all rules, by rule name and MAC address of the device. Loaded from the state file on first use
+static RULE_STATES: Mutex<Option<HashMap<String, RuleState>>> = Mutex::new(None);
+
+/// Change of an alert, the payload of the alert topic
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub device: String,
+    /// True while the alert is raised, false once it is cleared
+    pub raised: bool,
+    pub metric: Metric,
+    pub value: f32,
+    pub unit: &'static str,
+    pub operator: &'static str,
+    pub threshold: f32,
+    /// Start of the breach (RFC 3339 in the configured timezone)
+    pub since: String,
+    /// Time of the change (RFC 3339 in the configured timezone)
+    pub changed_at: String,
+}
+
+impl Alert {
+    /// Text of the notifications
+    fn text(&self) -> String {
+        if self.raised {
+            format!(
+                "{}: {} {:.1}{} {} {:.1}{} since {}",
+                self.device,
+                notifications::metric_label(self.metric),
+                self.value,
+                self.unit,
+                self.operator,
+                self.threshold,
+                self.unit,
+                self.since
+            )
+        } else {
+            format!(
+                "{}: {} back at {:.1}{}, alert cleared",
+                self.device,
+                notifications::metric_label(self.metric),
+                self.value,
+                self.unit
+            )
+        }
+    }
+}
+
+/// Checks that the rule names are unique and usable in topics, the hysteresis is not negative and the notifiers exist
+pub fn validate(config: &AppConfig) -> Result<(), String> {
+    let Some(alerts) = &config.alerts else {
+        return Ok(());
+    };
+    let mut names = HashSet::new();
+    for rule in alerts.rules.iter() {
+        if rule.name.is_empty() || rule.name.contains(['/', '+', '#']) {
+            return Err(format!(
+                "Invalid rule name '{}': must not be empty or contain '/', '+' or '#'",
+                rule.name
+            ));
+        }
+        if !names.insert(rule.name.as_str()) {
+            return Err(format!("Duplicate rule name '{}'", rule.name));
+        }
+        if rule.hysteresis < 0.0 {
+            return Err(format!("Rule '{}' has a negative hysteresis", rule.name));
+        }
+        let notifiers = config.notifications.as_ref().map(|n| &n.notifiers);
+        if let Some(name) = rule
+            .notify
+            .iter()
+            .find(|name| !notifiers.is_some_and(|n| n.contains_key(*name)))
+        {
+            return Err(format!(
+                "Rule '{}' notifies the unknown notifier '{}'",
+                rule.name, name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Next state of a rule for a device, given the value of its metric at the given time.
+/// Returns the new state and if the alert was raised (true) or cleared (false).
+fn step(
+    rule: &AlertRule,
+    state: &RuleState,
+    value: f32,
+    now: DateTime<Utc>,
+) -> (RuleState, Option<bool>) {
+    if state.raised {
+        let keep = rule.operator.keep_threshold(rule.value, rule.hysteresis);
+        if rule.operator.compare(value, keep) {
+            return (state.clone(), None);
+        }
+        return (RuleState::default(), Some(false));
+    }
+    if !rule.operator.compare(value, rule.value) {
+        return (RuleState::default(), None);
+    }
+    let since = state.since.unwrap_or(now);
+    let held = (now - since).to_std().unwrap_or_default();
+    if held >= rule.duration.unwrap_or_default() {
+        (
+            RuleState {
+                since: Some(since),
+                raised: true,
+            },
+            Some(true),
+        )
+    } else {
+        (
+            RuleState {
+                since: Some(since),
+                raised: false,
+            },
+            None,
+        )
+    }
+}
+
+/// Reads the state file, if configured and present
+fn load(alerts: &AlertsConfig) -> Result<HashMap<String, RuleState>, Box<dyn Error + Send + Sync>> {
+    let Some(file) = &alerts.state_file else {
+        return Ok(HashMap::new());
+    };
+    match std::fs::read(file) {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the state file, if configured
+fn save(
+    alerts: &AlertsConfig,
+    states: &HashMap<String, RuleState>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(file) = &alerts.state_file else {
+        return Ok(());
+    };
+    if let Some(dir) = Path::new(file).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(file, serde_json::to_vec(states)?)?;
+    Ok(())
+}
+
+/// Evaluates the rules on the messages of the given devices. Returns the changed alerts with their devices and rules
+fn evaluate<'a>(
+    config: &'a AppConfig,
+    devices: &'a [AppDevice],
+    messages: &[Message],
+    now: DateTime<Utc>,
+) -> Vec<(&'a AppDevice, &'a AlertRule, Alert)> {
+    let Some(alerts) = &config.alerts else {
+        return vec![];
+    };
+    let mut guard = RULE_STATES.lock().unwrap();
+    let states = guard.get_or_insert_with(|| {
+        load(alerts).unwrap_or_else(|e| {
+            warn!("Failed to read the state of the alerts: {}", e);
+            HashMap::new()
+        })
+    });
+
+    let tz = config.tz();
+    let mut changed = false;
+    let mut changes = vec![];
+    for msg in messages {
+        let Some(device) = devices.iter().find(|d| d.name == msg.name) else {
+            continue;
+        };
+        for rule in alerts
+            .rules
+            .iter()
+            .filter(|r| r.devices.is_empty() || r.devices.contains(&device.name))
+        {
+            let (value, unit) =
+                notifications::metric_value(config.units(device), rule.metric, &msg.data);
+            let key = format!("{}/{}", rule.name, msg.data.mac);
+            let state = states.get(&key).cloned().unwrap_or_default();
+            let (next, raised) = step(rule, &state, value, now);
+            if next == state {
+                continue;
+            }
+            changed = true;
+            if let Some(raised) = raised {
+                let since = next.since.or(state.since).unwrap_or(now);
+                changes.push((
+                    device,
+                    rule,
+                    Alert {
+                        rule: rule.name.clone(),
+                        device: device.name.clone(),
+                        raised,
+                        metric: rule.metric,
+                        value,
+                        unit,
+                        operator: rule.operator.symbol(),
+                        threshold: rule.value,
+                        since: since.with_timezone(&tz).to_rfc3339(),
+                        changed_at: now.with_timezone(&tz).to_rfc3339(),
+                    },
+                ));
+            }
+            states.insert(key, next);
+        }
+    }
+    if changed {
+        if let Err(e) = save(alerts, states) {
+            warn!("Failed to write the state of the alerts: {}", e);
+        }
+    }
+    changes
+}
+
+/// Evaluates the rules on the messages of the given devices and publishes the raised and cleared alerts to all sinks and, within
+/// the time windows of the rules, to their notifiers. Failures are only logged.
+pub async fn publish(
+    config: &AppConfig,
+    devices: &[AppDevice],
+    messages: &[Message],
+    sinks: &[Box<dyn Sink>],
+) {
+    let now = Utc::now();
+    for (device, rule, alert) in evaluate(config, devices, messages, now) {
+        if alert.raised {
+            warn!("Alert {} raised: {}", rule.name, alert.text());
+        } else {
+            info!("Alert {} cleared: {}", rule.name, alert.text());
+        }
+        for sink in sinks {
+            if let Err(e) = sink.publish_alert(device, &alert).await {
+                error!(
+                    "Failed to deliver alert {} of {} to {}: {}",
+                    rule.name,
+                    device.name,
+                    sink.name(),
+                    e
+                );
+            }
+        }
+        if time_window::any_active(&rule.windows, &now.with_timezone(&config.tz())) {
+            notifications::send_all(config, &rule.notify, &rule.name, &alert.text()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn freezer_rule() -> AlertRule {
+        AlertRule {
+            name: "freezer_warm".to_string(),
+            devices: vec![],
+            metric: Metric::Temperature,
+            operator: Operator::Greater,
+            value: -10.0,
+            hysteresis: 1.0,
+            duration: Some(Duration::from_secs(600)),
+            notify: vec![],
+            windows: vec![],
+        }
+    }
+
+    #[test]
+    fn raises_after_minimum_duration() {
+        let rule = freezer_rule();
+        let start = Utc::now();
+        let (pending, raised) = step(&rule, &RuleState::default(), -8.0, start);
+        assert_eq!(raised, None);
+        assert_eq!(pending.since, Some(start));
+
+        let later = start + chrono::Duration::minutes(5);
+        let (pending, raised) = step(&rule, &pending, -8.0, later);
+        assert_eq!((pending.raised, raised), (false, None));
+
+        // A reading back below the value resets the breach
+        let (reset, _) = step(&rule, &pending, -12.0, later);
+        assert_eq!(reset, RuleState::default());
+
+        let (state, raised) = step(&rule, &pending, -8.0, start + chrono::Duration::minutes(10));
+        assert_eq!((state.raised, raised), (true, Some(true)));
+    }
+
+    #[test]
+    fn clears_beyond_hysteresis() {
+        let rule = freezer_rule();
+        let raised = RuleState {
+            since: Some(Utc::now()),
+            raised: true,
+        };
+        let now = Utc::now();
+        assert_eq!(step(&rule, &raised, -10.5, now), (raised.clone(), None));
+        assert_eq!(
+            step(&rule, &raised, -11.0, now),
+            (RuleState::default(), Some(false))
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_rule_names() {
+        let config = AppConfig {
+            alerts: Some(AlertsConfig {
+                state_file: None,
+                rules: vec![freezer_rule(), freezer_rule()],
+            }),
+            ..Default::default()
+        };
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn rounds_the_threshold_of_notifications() {
+        let alert = Alert {
+            rule: "greenhouse_hot".to_string(),
+            device: "Greenhouse".to_string(),
+            raised: true,
+            metric: Metric::Temperature,
+            value: 25.46,
+            unit: "°C",
+            operator: ">",
+            threshold: 25.3,
+            since: "2024-05-01T12:00:00+02:00".to_string(),
+            changed_at: "2024-05-01T12:10:00+02:00".to_string(),
+        };
+        assert_eq!(
+            alert.text(),
+            "Greenhouse: temperature 25.5°C > 25.3°C since 2024-05-01T12:00:00+02:00"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! AWS IoT Core profile of the MQTT connection: mutual TLS with the device certificate on port 443 (ALPN 'x-amzn-mqtt-ca'),
+//! the thing name as client id, the keep-alive limits of AWS IoT and optionally a named shadow per device.
+
+use std::{error::Error, ops::RangeInclusive};
+
+use crate::configuration::{AppConfig, AppDevice, MqttConfig};
+
+/// ALPN protocol of MQTT with X.509 client certificates on port 443
+const ALPN_PROTOCOL: &str = "x-amzn-mqtt-ca";
+
+/// Keep alive times accepted by AWS IoT Core (seconds)
+const KEEP_ALIVE: RangeInclusive<u64> = 30..=1200;
+
+/// Fills in the settings of the AWS IoT Core profile not configured explicitly
+pub fn apply_profile(mqtt: &mut MqttConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(aws_iot) = mqtt.aws_iot.clone() else {
+        return Ok(());
+    };
+    if mqtt.client_cert.is_none() {
+        return Err("AWS IoT Core requires the device certificate in 'client_cert'".into());
+    }
+    let url = mqtt
+        .url
+        .get_or_insert_with(|| format!("ssl://{}:443", aws_iot.endpoint));
+    if url.ends_with(":443") && mqtt.alpn.is_empty() {
+        mqtt.alpn = vec![ALPN_PROTOCOL.to_string()];
+    }
+    // The IoT policies usually only allow the thing name as client id
+    if mqtt.client_id.is_none() {
+        mqtt.client_id = Some(aws_iot.thing_name.clone());
+    }
+    if !KEEP_ALIVE.contains(&mqtt.keep_alive) {
+        let keep_alive = mqtt
+            .keep_alive
+            .clamp(*KEEP_ALIVE.start(), *KEEP_ALIVE.end());
+        warn!(
+            "Keep alive of {}s not supported by AWS IoT Core, using {}s",
+            mqtt.keep_alive, keep_alive
+        );
+        mqtt.keep_alive = keep_alive;
+    }
+    // QoS 2 is not supported
+    if mqtt.default_qos.is_some_and(|qos| qos > 1) {
+        warn!("QoS 2 not supported by AWS IoT Core, using QoS 1");
+        mqtt.default_qos = Some(1);
+    }
+    Ok(())
+}
+
+/// Name of the shadow of the given device: only letters, digits, ':', '_' and '-' are allowed
+fn shadow_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect()
+}
+
+/// Update topic of the named shadow of the given device, if the readings are published to shadows
+pub fn shadow_topic(config: &AppConfig, device: &AppDevice) -> Option<String> {
+    let aws_iot = config.mqtt.as_ref()?.aws_iot.as_ref()?;
+    // An explicit topic of the device takes precedence
+    if !aws_iot.shadow || device.topic.is_some() {
+        return None;
+    }
+    Some(format!(
+        "$aws/things/{}/shadow/name/{}/update",
+        aws_iot.thing_name,
+        shadow_name(&device.name)
+    ))
+}
+
+/// Wraps the (JSON) payload into the reported state of a shadow update
+pub fn reported_state(payload: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let reported: serde_json::Value = serde_json::from_slice(payload)
+        .map_err(|e| format!("Shadow updates require JSON payloads: {}", e))?;
+    Ok(serde_json::to_vec(&serde_json::json!({
+        "state": { "reported": reported }
+    }))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::AwsIotConfig;
+
+    fn aws_iot_config() -> MqttConfig {
+        MqttConfig {
+            client_cert: Some("/etc/thermobeacon/device.pem.crt".to_string()),
+            keep_alive: 10,
+            aws_iot: Some(AwsIotConfig {
+                endpoint: "abc123-ats.iot.eu-central-1.amazonaws.com".to_string(),
+                thing_name: "gateway-1".to_string(),
+                shadow: true,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn applies_profile() {
+        let mut mqtt = aws_iot_config();
+        apply_profile(&mut mqtt).unwrap();
+        assert_eq!(
+            mqtt.url.as_deref(),
+            Some("ssl://abc123-ats.iot.eu-central-1.amazonaws.com:443")
+        );
+        assert_eq!(mqtt.alpn, vec![ALPN_PROTOCOL.to_string()]);
+        assert_eq!(mqtt.client_id.as_deref(), Some("gateway-1"));
+        assert_eq!(mqtt.keep_alive, 30);
+
+        let mut without_cert = MqttConfig {
+            client_cert: None,
+            ..aws_iot_config()
+        };
+        assert!(apply_profile(&mut without_cert).is_err());
+    }
+
+    #[test]
+    fn publishes_to_named_shadows() {
+        let config = AppConfig {
+            mqtt: Some(aws_iot_config()),
+            ..Default::default()
+        };
+        let device = AppDevice {
+            name: "Living room".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            shadow_topic(&config, &device).as_deref(),
+            Some("$aws/things/gateway-1/shadow/name/Living_room/update")
+        );
+        assert_eq!(
+            reported_state(br#"{"temperature":21.5}"#).unwrap(),
+            br#"{"state":{"reported":{"temperature":21.5}}}"#
+        );
+        assert!(reported_state(&[0xa1, 0x01]).is_err());
+    }
+}
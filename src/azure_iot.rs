@@ -0,0 +1,265 @@
+//! Azure IoT Hub dialect of the MQTT connection: the device id as client id, the username of IoT Hub, SAS tokens signed with the
+//! key of the device (renewed in the background) and the device-to-cloud topic. IoT Hub closes the connection on publishes to
+//! any other topic, so the readings, button presses, recovered readings and availability of the devices are all sent as
+//! device-to-cloud messages, told apart by their properties.
+
+use std::error::Error;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    configuration::{AppConfig, AppDevice, MqttConfig, MqttProtocol, PayloadFormat, PublishMode},
+    mqtt::{AsyncClient, MqttClient},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// API version announced in the username
+const API_VERSION: &str = "2021-04-12";
+
+/// Percent-encodes all but the unreserved characters (RFC 3986)
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// SAS token of the device, signed with its symmetric key (base64) and valid until the given time
+fn sas_token(
+    host_name: &str,
+    device_id: &str,
+    key: &str,
+    expiry: DateTime<Utc>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let resource_uri = url_encode(&format!("{}/devices/{}", host_name, device_id));
+    let expiry = expiry.timestamp();
+    let mut mac = HmacSha256::new_from_slice(&STANDARD.decode(key.trim())?)?;
+    mac.update(format!("{}\n{}", resource_uri, expiry).as_bytes());
+    let signature = STANDARD.encode(mac.finalize().into_bytes());
+    Ok(format!(
+        "SharedAccessSignature sr={}&sig={}&se={}",
+        resource_uri,
+        url_encode(&signature),
+        expiry
+    ))
+}
+
+/// Fills in the settings of the Azure IoT Hub dialect and rejects the features publishing to other topics
+pub fn apply_profile(mqtt: &mut MqttConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(hub) = mqtt.azure_iot_hub.clone() else {
+        return Ok(());
+    };
+    let unsupported = [
+        ("homeassistant", mqtt.homeassistant),
+        ("publish_mode", mqtt.publish_mode != PublishMode::Json),
+        ("commands", mqtt.commands),
+        ("leader_election", mqtt.leader_election.is_some()),
+        ("devices_topic", mqtt.devices_topic.is_some()),
+        ("bridge_info_interval", mqtt.bridge_info_interval.is_some()),
+        ("permission_check", mqtt.permission_check),
+        ("latency_check", mqtt.latency_check),
+    ];
+    if let Some((setting, _)) = unsupported.iter().find(|(_, enabled)| *enabled) {
+        return Err(format!(
+            "'{}' is not supported by Azure IoT Hub, which only accepts device-to-cloud messages",
+            setting
+        )
+        .into());
+    }
+    if hub.shared_access_key.is_none() && mqtt.client_cert.is_none() {
+        return Err(
+            "Azure IoT Hub requires the 'shared_access_key' or the device certificate in 'client_cert'"
+                .into(),
+        );
+    }
+
+    mqtt.url
+        .get_or_insert_with(|| format!("ssl://{}:8883", hub.host_name));
+    if mqtt
+        .client_id
+        .as_ref()
+        .is_some_and(|client_id| *client_id != hub.device_id)
+    {
+        warn!("Azure IoT Hub requires the device id as client id, ignoring the 'client_id'");
+    }
+    mqtt.client_id = Some(hub.device_id.clone());
+    mqtt.username = Some(format!(
+        "{}/{}/?api-version={}",
+        hub.host_name, hub.device_id, API_VERSION
+    ));
+    // MQTT 5 is not generally available for IoT Hub
+    mqtt.protocol = MqttProtocol::V311;
+    if mqtt.default_qos.is_some_and(|qos| qos > 1) {
+        warn!("QoS 2 not supported by Azure IoT Hub, using QoS 1");
+        mqtt.default_qos = Some(1);
+    }
+    if let Some(key) = &hub.shared_access_key {
+        mqtt.password = Some(sas_token(
+            &hub.host_name,
+            &hub.device_id,
+            key,
+            Utc::now() + hub.token_validity,
+        )?);
+    }
+    Ok(())
+}
+
+/// Renews the SAS token of the connection in the background before it expires, as IoT Hub closes connections with an expired token
+pub fn spawn_token_renewal(cli: AsyncClient, mqtt_config: &MqttConfig) {
+    let Some(hub) = mqtt_config.azure_iot_hub.clone() else {
+        return;
+    };
+    let Some(key) = hub.shared_access_key.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        // Well before the expiry, so a failed reconnect is retried with the same token
+        let interval = hub.token_validity.mul_f64(0.8);
+        loop {
+            tokio::time::sleep(interval).await;
+            let token = match sas_token(
+                &hub.host_name,
+                &hub.device_id,
+                &key,
+                Utc::now() + hub.token_validity,
+            ) {
+                Ok(token) => token,
+                Err(e) => {
+                    error!("Failed to renew the SAS token of Azure IoT Hub: {}", e);
+                    continue;
+                }
+            };
+            match cli.set_password(token).await {
+                Ok(()) => debug!("Renewed the SAS token of Azure IoT Hub"),
+                Err(e) => warn!(
+                    "Failed to reconnect to Azure IoT Hub with the renewed SAS token: {}",
+                    e
+                ),
+            }
+        }
+    });
+}
+
+/// Device-to-cloud topic of the messages of the given device, with its name and the given properties in the property bag.
+/// `reading` marks messages carrying the message document of a reading. None if the device is not published to IoT Hub.
+pub fn event_topic(
+    config: &AppConfig,
+    device: &AppDevice,
+    properties: &[(&str, &str)],
+    reading: bool,
+) -> Option<String> {
+    let hub = config.mqtt.as_ref()?.azure_iot_hub.as_ref()?;
+    // Devices of other brokers
+    if device.broker.is_some() {
+        return None;
+    }
+    let mut bag = vec![("device", device.name.as_str())];
+    bag.extend_from_slice(properties);
+    // Content type and encoding of JSON documents, so the message routing can query the body
+    if reading
+        && config.payload_format(device) == PayloadFormat::Json
+        && config.payload_template(device).is_none()
+    {
+        bag.extend([("$.ct", "application/json"), ("$.ce", "utf-8")]);
+    }
+    let bag: Vec<String> = bag
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, url_encode(value)))
+        .collect();
+    Some(format!(
+        "devices/{}/messages/events/{}",
+        hub.device_id,
+        bag.join("&")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::AzureIotHubConfig;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    fn hub_config() -> MqttConfig {
+        MqttConfig {
+            azure_iot_hub: Some(AzureIotHubConfig {
+                host_name: "my-hub.azure-devices.net".to_string(),
+                device_id: "gateway-1".to_string(),
+                shared_access_key: Some("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string()),
+                shared_access_key_file: None,
+                token_validity: Duration::from_secs(3600),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signs_sas_tokens() {
+        let expiry = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert_eq!(
+            sas_token(
+                "my-hub.azure-devices.net",
+                "gateway-1",
+                "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=",
+                expiry
+            )
+            .unwrap(),
+            "SharedAccessSignature sr=my-hub.azure-devices.net%2Fdevices%2Fgateway-1&sig=7nrepz2GWd%2FXUC2aJaQ6q4Ip3Npd%2FmrxcD767uR9Bxo%3D&se=1700000000"
+        );
+    }
+
+    #[test]
+    fn applies_profile() {
+        let mut mqtt = hub_config();
+        apply_profile(&mut mqtt).unwrap();
+        assert_eq!(
+            mqtt.url.as_deref(),
+            Some("ssl://my-hub.azure-devices.net:8883")
+        );
+        assert_eq!(mqtt.client_id.as_deref(), Some("gateway-1"));
+        assert_eq!(
+            mqtt.username.as_deref(),
+            Some("my-hub.azure-devices.net/gateway-1/?api-version=2021-04-12")
+        );
+        assert_eq!(mqtt.protocol, MqttProtocol::V311);
+        assert!(mqtt
+            .password
+            .unwrap()
+            .starts_with("SharedAccessSignature sr="));
+
+        let mut homeassistant = MqttConfig {
+            homeassistant: true,
+            ..hub_config()
+        };
+        assert!(apply_profile(&mut homeassistant).is_err());
+    }
+
+    #[test]
+    fn builds_event_topics() {
+        let config = AppConfig {
+            mqtt: Some(hub_config()),
+            ..Default::default()
+        };
+        let device = AppDevice {
+            name: "Living room".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            event_topic(&config, &device, &[], true).as_deref(),
+            Some("devices/gateway-1/messages/events/device=Living%20room&$.ct=application%2Fjson&$.ce=utf-8")
+        );
+        assert_eq!(
+            event_topic(&config, &device, &[("event", "button")], false).as_deref(),
+            Some("devices/gateway-1/messages/events/device=Living%20room&event=button")
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use btleplug::api::BDAddr;
+
+use crate::configuration::AppDevice;
+
+/// Maximum number of runs a missing device is skipped
+const MAX_SKIPPED_RUNS: u32 = 32;
+
+/// State of a device missing in the last runs
+#[derive(Debug, Default)]
+struct MissingState {
+    /// Number of consecutive runs the device was searched but not found
+    misses: u32,
+    /// Number of upcoming runs the device is not searched for
+    skip: u32,
+}
+
+/// Reduces the effort spent on devices missing for many runs: after the configured number of misses, the device is only searched for in every 2nd, 4th, 8th ... run.
+/// Once the device is found again, it is searched for in every run.
+pub struct DeviceBackoff {
+    /// Number of consecutive misses before backing off, 0 disables the backoff
+    threshold: u32,
+    states: Mutex<HashMap<BDAddr, MissingState>>,
+}
+
+impl DeviceBackoff {
+    pub fn new(threshold: u32) -> Self {
+        DeviceBackoff {
+            threshold,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the devices to search for in the current run
+    pub fn devices_to_read(&self, devices: &[AppDevice]) -> Vec<AppDevice> {
+        let mut states = self.states.lock().unwrap();
+        devices
+            .iter()
+            .filter(|device| {
+                let mac = device.mac.parse::<BDAddr>().unwrap();
+                match states.get_mut(&mac) {
+                    Some(state) if state.skip > 0 => {
+                        state.skip -= 1;
+                        debug!(
+                            "Skipping missing device {} ({} more runs)",
+                            device.name, state.skip
+                        );
+                        false
+                    }
+                    _ => true,
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Number of consecutive runs the given device was searched but not found
+    pub fn misses(&self, mac: &BDAddr) -> u32 {
+        self.states
+            .lock()
+            .unwrap()
+            .get(mac)
+            .map(|state| state.misses)
+            .unwrap_or(0)
+    }
+
+    /// Updates the state of all searched devices with the devices found in the current run
+    pub fn update(&self, searched: &[AppDevice], found: &[BDAddr]) {
+        let mut states = self.states.lock().unwrap();
+        for device in searched {
+            let mac = device.mac.parse::<BDAddr>().unwrap();
+            if found.contains(&mac) {
+                if let Some(state) = states.remove(&mac) {
+                    info!(
+                        "Device {} is back after {} missed runs",
+                        device.name, state.misses
+                    );
+                }
+                continue;
+            }
+
+            let state = states.entry(mac).or_default();
+            state.misses += 1;
+            if state.misses == 1 {
+                info!("Device {} not found", device.name);
+            } else if self.threshold > 0 && state.misses >= self.threshold {
+                let exponent = (state.misses - self.threshold).min(5);
+                state.skip = (1 << exponent).min(MAX_SKIPPED_RUNS);
+                if state.misses == self.threshold {
+                    warn!(
+                        "Device {} missing for {} runs, searching it less often",
+                        device.name, state.misses
+                    );
+                } else {
+                    debug!(
+                        "Device {} missing for {} runs, skipping the next {} runs",
+                        device.name, state.misses, state.skip
+                    );
+                }
+            } else {
+                debug!("Device {} missing for {} runs", device.name, state.misses);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulates a run: returns if the device was searched and updates the backoff with the search result
+    fn run(backoff: &DeviceBackoff, device: &AppDevice, in_range: bool) -> bool {
+        let searched = backoff.devices_to_read(std::slice::from_ref(device));
+        let mac = device.mac.parse::<BDAddr>().unwrap();
+        let found = if in_range && !searched.is_empty() {
+            vec![mac]
+        } else {
+            vec![]
+        };
+        backoff.update(&searched, &found);
+        !searched.is_empty()
+    }
+
+    #[test]
+    fn searches_missing_devices_less_often_until_found() {
+        let backoff = DeviceBackoff::new(2);
+        let device = AppDevice {
+            mac: "11:22:33:44:55:66".to_string(),
+            name: "Attic".to_string(),
+            ..Default::default()
+        };
+        let mac = device.mac.parse::<BDAddr>().unwrap();
+
+        let searched: Vec<bool> = (0..7).map(|_| run(&backoff, &device, false)).collect();
+        // Skips 1 run after the 2nd miss, 2 runs after the 3rd miss
+        assert_eq!(searched, vec![true, true, false, true, false, false, true]);
+        assert_eq!(backoff.misses(&mac), 4);
+
+        // Skips 4 runs after the 4th miss, found in the next searched run
+        let searched: Vec<bool> = (0..6).map(|_| run(&backoff, &device, true)).collect();
+        assert_eq!(searched, vec![false, false, false, false, true, true]);
+        assert_eq!(backoff.misses(&mac), 0);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    error::Error,
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::configuration::AppConfig;
+
+/// Name of the manifest within the archive
+static MANIFEST: &str = "manifest.json";
+
+/// Describes the content of a backup archive
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+struct Manifest {
+    /// Version of the server that created the backup
+    version: String,
+    /// Time the backup was created (RFC 3339, UTC)
+    created: String,
+    /// Name of the gateway instance, if configured
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    instance: Option<String>,
+    /// Names of the included state directories
+    contents: Vec<String>,
+}
+
+/// Local state directories of the given configuration: name within the archive and local path
+fn state_dirs(config: &AppConfig) -> Vec<(&'static str, PathBuf)> {
+    let mut dirs = vec![];
+    if let Some(record_log) = &config.record_log {
+        dirs.push(("record_log", PathBuf::from(&record_log.path)));
+    }
+    if let Some(spool) = &config.spool {
+        dirs.push(("spool", PathBuf::from(&spool.path)));
+    }
+    if let Some(store) = &config.store {
+        dirs.push(("store", PathBuf::from(&store.path)));
+    }
+    if let Some(state) = config
+        .mqtt
+        .as_ref()
+        .and_then(|m| m.homeassistant_state.as_ref())
+    {
+        dirs.push(("homeassistant", PathBuf::from(state)));
+    }
+    dirs
+}
+
+/// Bundles all local state (record logs, spooled messages, history store and announced Home Assistant entities) into a gzip compressed tar archive. Returns the names of the included state directories.
+pub fn backup(
+    config: &AppConfig,
+    file: &Path,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let dirs: Vec<_> = state_dirs(config)
+        .into_iter()
+        .filter(|(_, path)| path.is_dir())
+        .collect();
+
+    let manifest = Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created: Utc::now().to_rfc3339(),
+        instance: config.instance_name.clone(),
+        contents: dirs.iter().map(|(name, _)| name.to_string()).collect(),
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+
+    let mut archive =
+        tar::Builder::new(GzEncoder::new(File::create(file)?, Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    archive.append_data(&mut header, MANIFEST, manifest_json.as_slice())?;
+
+    for (name, path) in dirs.iter() {
+        debug!("Adding {} to backup", path.display());
+        archive.append_dir_all(name, path)?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(manifest.contents)
+}
+
+/// Checks that the path within the archive does not escape the target directory
+fn is_safe(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Restores the local state from a backup archive into the directories of the given configuration. Existing files are only replaced if `force` is set.
+/// Returns the number of restored files.
+pub fn restore(
+    config: &AppConfig,
+    file: &Path,
+    force: bool,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let dirs = state_dirs(config);
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(file)?));
+    let mut restored = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !is_safe(&path) {
+            return Err(format!("Unsafe path {} in backup", path.display()).into());
+        }
+        if path == Path::new(MANIFEST) {
+            let manifest: Manifest = serde_json::from_reader(&mut entry)?;
+            info!(
+                "Restoring backup of version {} created {}",
+                manifest.version, manifest.created
+            );
+            continue;
+        }
+
+        let mut components = path.components();
+        let name = components.next().and_then(|c| c.as_os_str().to_str());
+        let target = match dirs.iter().find(|(dir, _)| Some(*dir) == name) {
+            Some((_, dir)) => dir.join(components.as_path()),
+            None => {
+                warn!(
+                    "Skipping {}, the corresponding state is not configured",
+                    path.display()
+                );
+                continue;
+            }
+        };
+
+        if entry.header().entry_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        if target.exists() && !force {
+            return Err(format!(
+                "{} already exists, use --force to replace existing files",
+                target.display()
+            )
+            .into());
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&target)?;
+        trace!("Restored {}", target.display());
+        restored += 1;
+    }
+    Ok(restored)
+}
@@ -0,0 +1,209 @@
+//! Scanner using BlueZ directly through [bluer](https://docs.rs/bluer) instead of btleplug (Cargo feature `bluer`, Linux only).
+//! The discovery is restricted to LE devices and, unless disabled, reports each advertisement (duplicate data), even if its content did not change.
+//! Passive scans register an advertisement monitor matching the company ids and service data UUIDs of the filter.
+
+use async_trait::async_trait;
+use bluer::monitor::{Monitor, MonitorEvent, Pattern, RssiSamplingPeriod, Type};
+use bluer::{AdapterEvent, DiscoveryFilter, DiscoveryTransport, Session};
+use btleplug::api::{bleuuid::BleUuid, BDAddr, PeripheralProperties};
+use futures::StreamExt;
+use std::error::Error;
+use std::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::scanner::{
+    AdvertisementId, BleScanner, ScanParameters, ScannerAdapter, ScannerPeripheral,
+};
+
+/// AD type of the manufacturer specific data
+const MANUFACTURER_DATA: u8 = 0xFF;
+/// AD type of the service data with a 16 bit UUID
+const SERVICE_DATA_16: u8 = 0x16;
+
+/// Scanner of all adapters of the local BlueZ daemon
+#[derive(Clone)]
+pub struct BluerScanner {
+    session: Session,
+}
+
+impl BluerScanner {
+    /// Connects to the BlueZ daemon
+    pub async fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(BluerScanner {
+            session: Session::new().await?,
+        })
+    }
+}
+
+#[async_trait]
+impl BleScanner for BluerScanner {
+    type Adapter = BluerAdapter;
+
+    async fn adapters(&self) -> Result<Vec<BluerAdapter>, Box<dyn Error + Send + Sync>> {
+        let mut adapters = vec![];
+        for name in self.session.adapter_names().await? {
+            adapters.push(BluerAdapter {
+                adapter: self.session.adapter(&name)?,
+                discovery: Mutex::new(None),
+            });
+        }
+        Ok(adapters)
+    }
+}
+
+/// A single BlueZ adapter
+pub struct BluerAdapter {
+    adapter: bluer::Adapter,
+    /// Task consuming the discovery events, the discovery stops when it is aborted
+    discovery: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[async_trait]
+impl ScannerAdapter for BluerAdapter {
+    type Peripheral = BluerPeripheral;
+
+    async fn info(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(format!(
+            "{} ({})",
+            self.adapter.name(),
+            self.adapter.address().await?
+        ))
+    }
+
+    async fn start_scan(
+        &self,
+        parameters: &ScanParameters,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.adapter.set_powered(true).await?;
+        let task = match (&parameters.filter, parameters.passive) {
+            (Some(filter), true) => self.monitor(filter).await?,
+            (None, true) => {
+                warn!("Passive scans require a filter, scanning actively");
+                self.discover(parameters).await?
+            }
+            (_, false) => self.discover(parameters).await?,
+        };
+        if let Some(previous) = self.discovery.lock().unwrap().replace(task) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(task) = self.discovery.lock().unwrap().take() {
+            task.abort();
+        }
+        Ok(())
+    }
+
+    async fn peripherals(&self) -> Result<Vec<BluerPeripheral>, Box<dyn Error + Send + Sync>> {
+        let mut peripherals = vec![];
+        for address in self.adapter.device_addresses().await? {
+            peripherals.push(BluerPeripheral {
+                device: self.adapter.device(address)?,
+            });
+        }
+        Ok(peripherals)
+    }
+
+    async fn power_on(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.adapter.set_powered(true).await?)
+    }
+
+    async fn powered(&self) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        Ok(Some(self.adapter.is_powered().await?))
+    }
+}
+
+impl BluerAdapter {
+    /// Starts an active discovery
+    async fn discover(
+        &self,
+        parameters: &ScanParameters,
+    ) -> Result<JoinHandle<()>, Box<dyn Error + Send + Sync>> {
+        self.adapter
+            .set_discovery_filter(DiscoveryFilter {
+                transport: DiscoveryTransport::Le,
+                duplicate_data: parameters.duplicate_data,
+                uuids: parameters.services().into_iter().collect(),
+                ..Default::default()
+            })
+            .await?;
+        let mut events = self.adapter.discover_devices_with_changes().await?;
+        // The properties are read from the devices, the events only keep the discovery running
+        Ok(tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let AdapterEvent::DeviceAdded(address) = event {
+                    trace!("Discovered {}", address);
+                }
+            }
+        }))
+    }
+
+    /// Starts a passive scan with an advertisement monitor matching the given advertisements
+    async fn monitor(
+        &self,
+        filter: &[AdvertisementId],
+    ) -> Result<JoinHandle<()>, Box<dyn Error + Send + Sync>> {
+        let patterns = filter
+            .iter()
+            .filter_map(|id| match id {
+                AdvertisementId::CompanyId(key) => Some(Pattern {
+                    data_type: MANUFACTURER_DATA,
+                    start_position: 0,
+                    content: key.to_le_bytes().to_vec(),
+                }),
+                AdvertisementId::Service(uuid) => Some(Pattern {
+                    data_type: SERVICE_DATA_16,
+                    start_position: 0,
+                    content: uuid.to_ble_u16()?.to_le_bytes().to_vec(),
+                }),
+            })
+            .collect();
+        let monitors = self.adapter.monitor().await?;
+        let mut monitor = monitors
+            .register(Monitor {
+                monitor_type: Type::OrPatterns,
+                rssi_sampling_period: Some(RssiSamplingPeriod::All),
+                patterns: Some(patterns),
+                ..Default::default()
+            })
+            .await?;
+        // The monitor is unregistered once the task is aborted
+        Ok(tokio::spawn(async move {
+            let _monitors = monitors;
+            while let Some(event) = monitor.next().await {
+                if let MonitorEvent::DeviceFound(device) = event {
+                    trace!("Found {}", device.device);
+                }
+            }
+        }))
+    }
+}
+
+/// A device known to a BlueZ adapter
+#[derive(Clone)]
+pub struct BluerPeripheral {
+    device: bluer::Device,
+}
+
+#[async_trait]
+impl ScannerPeripheral for BluerPeripheral {
+    fn address(&self) -> BDAddr {
+        BDAddr::from(self.device.address().0)
+    }
+
+    async fn properties(
+        &self,
+    ) -> Result<Option<PeripheralProperties>, Box<dyn Error + Send + Sync>> {
+        Ok(Some(PeripheralProperties {
+            address: self.address(),
+            local_name: self.device.name().await?,
+            rssi: self.device.rssi().await?,
+            tx_power_level: self.device.tx_power().await?,
+            manufacturer_data: self.device.manufacturer_data().await?.unwrap_or_default(),
+            service_data: self.device.service_data().await?.unwrap_or_default(),
+            ..Default::default()
+        }))
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::mqtt::{AsyncClient, MqttClient};
+use btleplug::{
+    api::{Central, Manager as _},
+    platform::Manager,
+};
+use chrono::{DateTime, Utc};
+use serde_derive::Serialize;
+use tokio::sync::Notify;
+
+use crate::{
+    configuration::AppConfig, device_info, publish_policy::PublishPolicy, remote_devices,
+    thermobeacon_gatt::DeviceInfo,
+};
+
+/// Statistics of the last finished run
+#[derive(Debug, Clone, Serialize)]
+pub struct RunStats {
+    /// End of the run (RFC 3339)
+    pub finished_at: String,
+    /// Duration of the scan and the delivery in ms
+    pub duration_ms: u128,
+    /// Names of the devices found
+    pub devices_found: Vec<String>,
+    /// Error of a failed run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// State of the gateway, published (retained) to '{bridge topic}/state' after each run
+#[derive(Debug, Serialize)]
+struct BridgeState {
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_run: Option<RunStats>,
+}
+
+/// Configured device, as reported in the bridge info
+#[derive(Debug, Serialize)]
+struct BridgeDevice {
+    mac: String,
+    name: String,
+    /// Revisions read from the device, if any
+    #[serde(flatten)]
+    info: Option<DeviceInfo>,
+}
+
+/// Information about the gateway, published periodically to '{bridge topic}/info'
+#[derive(Debug, Serialize)]
+struct BridgeInfo {
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    started_at: String,
+    uptime: i64,
+    devices: Vec<BridgeDevice>,
+    adapters: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_run: Option<RunStats>,
+}
+
+/// Statistics of the last finished run, if any
+static LAST_RUN: Mutex<Option<RunStats>> = Mutex::new(None);
+
+/// Signals a finished run to the publishing task
+static RUN_FINISHED: OnceLock<Notify> = OnceLock::new();
+
+fn run_finished() -> &'static Notify {
+    RUN_FINISHED.get_or_init(Notify::new)
+}
+
+/// Records the statistics of a finished run and triggers the publication of the bridge state
+pub fn completed(stats: RunStats) {
+    *LAST_RUN.lock().unwrap() = Some(stats);
+    run_finished().notify_one();
+}
+
+/// Names of the available Bluetooth adapters
+async fn adapters(manager: &Manager) -> Vec<String> {
+    let mut names = vec![];
+    for adapter in manager.adapters().await.unwrap_or_default() {
+        names.push(adapter.adapter_info().await.unwrap_or_default());
+    }
+    names
+}
+
+async fn publish(cli: &AsyncClient, topic: String, payload: String, policy: PublishPolicy) {
+    if let Err(e) = cli.publish(policy.message(&topic, payload)).await {
+        warn!("Failed to publish bridge message to {}: {}", topic, e);
+    }
+}
+
+/// Publishes the bridge state after each run and the bridge info in the given interval
+pub async fn start(config: &AppConfig, cli: &AsyncClient, manager: &Manager, interval: Duration) {
+    let topic = config.bridge_topic();
+    let started_at: DateTime<Utc> = Utc::now();
+    let adapters = adapters(manager).await;
+    let config = config.clone();
+    let cli = cli.clone();
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let now = Utc::now();
+                    let info = BridgeInfo {
+                        version: env!("CARGO_PKG_VERSION"),
+                        instance: config.instance_name.clone(),
+                        started_at: started_at.to_rfc3339(),
+                        uptime: (now - started_at).num_seconds(),
+                        devices: remote_devices::apply(&config)
+                            .devices
+                            .into_iter()
+                            .map(|d| BridgeDevice {
+                                info: d.mac.parse().ok().and_then(device_info::get),
+                                mac: d.mac,
+                                name: d.name,
+                            })
+                            .collect(),
+                        adapters: adapters.clone(),
+                        last_run: LAST_RUN.lock().unwrap().clone(),
+                    };
+                    let payload = serde_json::to_string(&info).unwrap();
+                    publish(&cli, format!("{}/info", topic), payload, PublishPolicy::BRIDGE_INFO).await;
+                }
+                _ = run_finished().notified() => {
+                    let last_run = LAST_RUN.lock().unwrap().clone();
+                    let state = BridgeState {
+                        state: match &last_run {
+                            Some(RunStats { error: Some(_), .. }) => "error",
+                            _ => "ok",
+                        },
+                        last_run,
+                    };
+                    let payload = serde_json::to_string(&state).unwrap();
+                    publish(&cli, format!("{}/state", topic), payload, PublishPolicy::BRIDGE_STATE).await;
+                }
+            }
+        }
+    });
+}
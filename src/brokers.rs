@@ -0,0 +1,83 @@
+use std::{collections::HashMap, error::Error, sync::Mutex};
+
+use crate::mqtt::AsyncClient;
+
+use crate::{
+    configuration::{AppConfig, MqttConfig},
+    homeassistant,
+};
+
+/// Configurations of the named brokers
+static BROKER_CONFIGS: Mutex<Option<HashMap<String, MqttConfig>>> = Mutex::new(None);
+
+/// Availability topic of this gateway, announced on each named broker as well
+static AVAILABILITY_TOPIC: Mutex<Option<String>> = Mutex::new(None);
+
+/// Connected clients of the named brokers
+static CLIENTS: Mutex<Option<HashMap<String, AsyncClient>>> = Mutex::new(None);
+
+/// Registers the named brokers devices can publish to instead of the default broker and connects to them.
+/// Brokers that are not reachable now are connected on first use.
+pub async fn init(config: &AppConfig) {
+    *BROKER_CONFIGS.lock().unwrap() = Some(config.brokers.clone());
+    *AVAILABILITY_TOPIC.lock().unwrap() = Some(config.availability_topic());
+    for name in config.brokers.keys() {
+        if let Err(e) = client(name).await {
+            error!("Failed to connect to MQTT broker {}: {}", name, e);
+        }
+    }
+}
+
+/// Returns the client of the named broker, connecting to it if necessary
+pub async fn client(name: &str) -> Result<AsyncClient, Box<dyn Error + Send + Sync>> {
+    if let Some(cli) = CLIENTS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|clients| clients.get(name))
+    {
+        return Ok(cli.clone());
+    }
+
+    let broker_config = BROKER_CONFIGS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|configs| configs.get(name).cloned())
+        .ok_or_else(|| format!("MQTT broker {} is not configured", name))?;
+    debug!("Connecting to MQTT broker {}", name);
+    let availability_topic = AVAILABILITY_TOPIC.lock().unwrap().clone();
+    let cli = crate::connect_to_mqtt(&broker_config, availability_topic.as_deref()).await?;
+    CLIENTS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(name.to_string(), cli.clone());
+    Ok(cli)
+}
+
+/// Publishes the Home Assistant discovery messages of the devices of each named broker with auto-discovery enabled
+pub async fn publish_homeassistant_discovery(config: &AppConfig) {
+    for (name, broker_config) in config.brokers.iter() {
+        if !broker_config.homeassistant {
+            continue;
+        }
+        let result = match client(name).await {
+            Ok(cli) => {
+                homeassistant::publish_homeassistant_device_discovery_messages(
+                    config,
+                    &cli,
+                    Some(name),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to publish Home Assistant discovery messages to broker {}: {}",
+                name, e
+            );
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Decoder for BTHome v2 advertisements (service data of the UUID 0xFCD2), see https://bthome.io/format/
+//!
+//! bytes | content
+//! ========================================================
+//! 00-00 | device information (bit 0: encrypted, bit 2: trigger based, bits 5-7: version)
+//! 01-   | measurements, each an object id followed by its value (little endian)
+//!
+//! The fields of a reading are determined by the objects the device advertises, so any BTHome sensor measuring the temperature can be bridged.
+
+use btleplug::api::{bleuuid::uuid_from_u16, BDAddr};
+use std::error::Error;
+use uuid::Uuid;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// UUID of the service data of BTHome advertisements
+pub const SERVICE_UUID: Uuid = uuid_from_u16(0xFCD2);
+
+const ENCRYPTED: u8 = 0x01;
+const VERSION: u8 = 2;
+
+/// Length of the value of an object id, None for unknown object ids. The length of text and raw objects (0x53, 0x54) is given by their first byte.
+fn object_length(id: u8) -> Option<usize> {
+    match id {
+        0x00 | 0x01 | 0x09 | 0x0F..=0x11 | 0x15..=0x2F | 0x3A | 0x46 | 0x57..=0x59 | 0x60 => {
+            Some(1)
+        }
+        0x02
+        | 0x03
+        | 0x06..=0x08
+        | 0x0C..=0x0E
+        | 0x12..=0x14
+        | 0x3C
+        | 0x3D
+        | 0x3F
+        | 0x40
+        | 0x41
+        | 0x43..=0x45
+        | 0x47..=0x4A
+        | 0x51
+        | 0x52
+        | 0x56
+        | 0x5A
+        | 0x5D..=0x5F
+        | 0xF0 => Some(2),
+        0x04 | 0x05 | 0x0A | 0x0B | 0x42 | 0x4B | 0xF2 => Some(3),
+        0x3E | 0x4C..=0x50 | 0x55 | 0x5B | 0x5C | 0xF1 => Some(4),
+        _ => None,
+    }
+}
+
+/// Unsigned little endian value of up to 4 bytes
+fn unsigned(value: &[u8]) -> u32 {
+    value
+        .iter()
+        .rev()
+        .fold(0, |result, byte| (result << 8) | u32::from(*byte))
+}
+
+/// Signed little endian value of 1 or 2 bytes
+fn signed(value: &[u8]) -> i32 {
+    match value {
+        [v] => i32::from(*v as i8),
+        [l, h] => i32::from(i16::from_le_bytes([*l, *h])),
+        _ => 0,
+    }
+}
+
+/// Decodes the service data of a BTHome v2 advertisement. Objects not covered by the reading are skipped, decoding stops at the first unknown object id.
+/// Advertisements without temperature are rejected, missing humidity and battery level are reported as 0.
+pub fn parse(
+    mac: BDAddr,
+    data: &[u8],
+) -> Result<ThermoBeaconFullReadResult, Box<dyn Error + Send + Sync>> {
+    let (info, mut objects) = data.split_first().ok_or("Empty BTHome advertisement")?;
+    if info & ENCRYPTED != 0 {
+        return Err("Encrypted BTHome advertisements are not supported".into());
+    }
+    if info >> 5 != VERSION {
+        return Err(format!("Unsupported BTHome version {}", info >> 5).into());
+    }
+
+    let mut result = ThermoBeaconFullReadResult {
+        mac,
+        ..Default::default()
+    };
+    let mut temperature = None;
+    while let Some((id, rest)) = objects.split_first() {
+        let length = match *id {
+            0x53 | 0x54 => 1 + usize::from(*rest.first().ok_or("Truncated BTHome object")?),
+            id => match object_length(id) {
+                Some(length) => length,
+                None => {
+                    trace!("Unknown BTHome object id {:#04x} of {}", id, mac);
+                    break;
+                }
+            },
+        };
+        if rest.len() < length {
+            return Err(format!("Truncated BTHome object {:#04x}", id).into());
+        }
+        let (value, remaining) = rest.split_at(length);
+        objects = remaining;
+        match id {
+            0x01 => result.battery_level = unsigned(value) as f32,
+            0x02 => temperature = Some(signed(value) as f32 / 100.0),
+            0x45 => temperature = Some(signed(value) as f32 / 10.0),
+            0x57 => temperature = Some(signed(value) as f32),
+            0x03 => result.humidity = unsigned(value) as f32 / 100.0,
+            0x2E => result.humidity = unsigned(value) as f32,
+            0x04 => result.pressure = Some(unsigned(value) as f32 / 100.0),
+            0x0C => result.battery_voltage = Some(unsigned(value) as f32 / 1000.0),
+            0x4A => result.battery_voltage = Some(unsigned(value) as f32 / 10.0),
+            // Button event, 0 is 'none'
+            0x3A => result.button_pressed = value[0] != 0,
+            _ => {}
+        }
+    }
+    result.temperature = temperature.ok_or("BTHome advertisement without temperature")?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_objects_of_the_advertisement() {
+        let mac: BDAddr = "3c:2e:f5:00:00:04".parse().unwrap();
+        // Shelly BLU H&T: packet id, battery 93 %, humidity 50.55 %, button press, temperature -2.5 °C
+        let data = [
+            0x44, 0x00, 0x2A, 0x01, 0x5D, 0x03, 0xBF, 0x13, 0x3A, 0x01, 0x45, 0xE7, 0xFF,
+        ];
+        let reading = parse(mac, &data).unwrap();
+        assert_eq!(reading.temperature, -2.5);
+        assert_eq!(reading.humidity, 50.55);
+        assert_eq!(reading.battery_level, 93.0);
+        assert!(reading.button_pressed);
+
+        // Pressure 1008.83 hPa, temperature 25.06 °C, followed by an unknown object
+        let data = [0x40, 0x04, 0x13, 0x8A, 0x01, 0x02, 0xCA, 0x09, 0xEE, 0x01];
+        let reading = parse(mac, &data).unwrap();
+        assert_eq!(reading.pressure, Some(1008.83));
+        assert_eq!(reading.temperature, 25.06);
+
+        // Encrypted
+        assert!(parse(mac, &[0x41, 0x02, 0xCA, 0x09]).is_err());
+        // Without temperature
+        assert!(parse(mac, &[0x40, 0x01, 0x5D]).is_err());
+        // Truncated
+        assert!(parse(mac, &[0x40, 0x02, 0xCA]).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use btleplug::api::BDAddr;
+
+/// Last known button state of each device
+static BUTTON_STATES: Mutex<Option<HashMap<BDAddr, bool>>> = Mutex::new(None);
+
+/// Payload of the event published for a button press
+pub static PRESS_EVENT: &str = r#"{"event_type":"press"}"#;
+
+/// Returns true if the button was newly pressed since the last recorded reading of the device. The first reading of a device never
+/// counts as press, since it might be stale. The state is only updated by [`commit`], so a press which could not be published is
+/// detected again with the next reading.
+pub fn pressed(mac: BDAddr, button_pressed: bool) -> bool {
+    let states = BUTTON_STATES.lock().unwrap();
+    let previous = states.as_ref().and_then(|states| states.get(&mac));
+    button_pressed && previous == Some(&false)
+}
+
+/// Records the button state of a published reading of the device
+pub fn commit(mac: BDAddr, button_pressed: bool) {
+    BUTTON_STATES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(mac, button_pressed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_release_to_press_transitions() {
+        let mac: BDAddr = "11:22:33:44:55:77".parse().unwrap();
+        let press = |button_pressed| {
+            let result = pressed(mac, button_pressed);
+            commit(mac, button_pressed);
+            result
+        };
+        assert!(!press(true));
+        assert!(!press(true));
+        assert!(!press(false));
+        assert!(press(true));
+        assert!(!press(true));
+    }
+
+    #[test]
+    fn detects_unpublished_presses_again() {
+        let mac: BDAddr = "11:22:33:44:55:78".parse().unwrap();
+        commit(mac, false);
+        assert!(pressed(mac, true));
+        // Not published, so the press is still detected with the next reading
+        assert!(pressed(mac, true));
+        commit(mac, true);
+        assert!(!pressed(mac, true));
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use chrono::NaiveDate;
+
+/// Time between two downloads of iCal calendars
+const ICAL_REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Inclusive range of dates, parsed from '2024-12-24' or '2024-12-24..2025-01-06'
+#[derive(Debug, Clone, Copy, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl TryFrom<String> for DateRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |s: &str| {
+            s.trim()
+                .parse::<NaiveDate>()
+                .map_err(|e| format!("Invalid date '{}': {}", s, e))
+        };
+        match value.split_once("..") {
+            Some((from, to)) => Ok(DateRange {
+                from: parse(from)?,
+                to: parse(to)?,
+            }),
+            None => {
+                let date = parse(&value)?;
+                Ok(DateRange {
+                    from: date,
+                    to: date,
+                })
+            }
+        }
+    }
+}
+
+impl DateRange {
+    fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+}
+
+/// Dates on which the schedule is modified (e.g. vacation mode)
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct ScheduleException {
+    /// Human-readable name of the exception (for the logs)
+    pub name: Option<String>,
+    /// Dates or date ranges of the exception
+    #[serde(default)]
+    pub dates: Vec<DateRange>,
+    /// URL of an iCal calendar, each (non-recurring) event is an exception date
+    pub ical_url: Option<String>,
+    /// CRON expression replacing the regular one on these dates
+    pub cron: Option<String>,
+}
+
+/// Evaluates the configured schedule exceptions, including the dates of downloaded iCal calendars
+pub struct ScheduleExceptions {
+    exceptions: Vec<ScheduleException>,
+    /// Dates downloaded from the iCal URL of each exception
+    ical_dates: Vec<Vec<DateRange>>,
+    last_refresh: Option<Instant>,
+}
+
+impl ScheduleExceptions {
+    pub fn new(exceptions: Vec<ScheduleException>) -> Self {
+        let ical_dates = vec![vec![]; exceptions.len()];
+        ScheduleExceptions {
+            exceptions,
+            ical_dates,
+            last_refresh: None,
+        }
+    }
+
+    /// Downloads the configured iCal calendars, if they are outdated. Failed downloads keep the previous dates.
+    pub async fn refresh(&mut self) {
+        if self
+            .last_refresh
+            .map(|r| r.elapsed() < ICAL_REFRESH_INTERVAL)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        for (i, exception) in self.exceptions.iter().enumerate() {
+            if let Some(url) = &exception.ical_url {
+                match fetch_ical_dates(url).await {
+                    Ok(dates) => {
+                        debug!("Loaded {} exception dates from {}", dates.len(), url);
+                        self.ical_dates[i] = dates;
+                    }
+                    Err(e) => warn!("Failed to load calendar {}: {}", url, e),
+                }
+            }
+        }
+        self.last_refresh = Some(Instant::now());
+    }
+
+    /// Returns the first exception active on the given date (in the configured timezone)
+    pub fn active(&self, date: NaiveDate) -> Option<&ScheduleException> {
+        self.exceptions
+            .iter()
+            .zip(self.ical_dates.iter())
+            .find(|(exception, ical_dates)| {
+                exception
+                    .dates
+                    .iter()
+                    .chain(ical_dates.iter())
+                    .any(|d| d.contains(date))
+            })
+            .map(|(exception, _)| exception)
+    }
+}
+
+/// Downloads an iCal calendar and returns the dates of all its events
+async fn fetch_ical_dates(url: &str) -> Result<Vec<DateRange>, Box<dyn Error + Send + Sync>> {
+    let body = reqwest::get(url).await?.error_for_status()?.text().await?;
+    Ok(parse_ical_dates(&body))
+}
+
+/// Parses the dates of all events of an iCal calendar. Recurrence rules are not supported.
+fn parse_ical_dates(ical: &str) -> Vec<DateRange> {
+    // Continuation lines start with a space or tab
+    let unfolded = ical
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "");
+
+    let parse_date = |line: &str| -> Option<(NaiveDate, bool)> {
+        let (params, value) = line.split_once(':')?;
+        let date = NaiveDate::parse_from_str(value.get(0..8)?, "%Y%m%d").ok()?;
+        // All-day events have an exclusive end date
+        Some((date, params.contains("VALUE=DATE") && !value.contains('T')))
+    };
+
+    let mut dates = vec![];
+    let mut start: Option<NaiveDate> = None;
+    let mut end: Option<NaiveDate> = None;
+    for line in unfolded.lines() {
+        let line = line.trim_end();
+        if line == "BEGIN:VEVENT" {
+            start = None;
+            end = None;
+        } else if line.starts_with("DTSTART") {
+            start = parse_date(line).map(|(d, _)| d);
+        } else if line.starts_with("DTEND") {
+            end = parse_date(line).map(|(d, all_day)| {
+                if all_day {
+                    d.pred_opt().unwrap_or(d)
+                } else {
+                    d
+                }
+            });
+        } else if line == "END:VEVENT" {
+            if let Some(from) = start {
+                let to = end.unwrap_or(from).max(from);
+                dates.push(DateRange { from, to });
+            }
+        }
+    }
+    dates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_date_ranges() {
+        let range = DateRange::try_from("2024-12-24..2025-01-06".to_string()).unwrap();
+        assert!(range.contains(date("2024-12-31")));
+        assert!(!range.contains(date("2025-01-07")));
+        assert!(DateRange::try_from("24.12.2024".to_string()).is_err());
+    }
+
+    #[test]
+    fn parses_ical_events() {
+        let ical = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20240801\r\nDTEND;VALUE=DATE:20240815\r\nSUMMARY:Vacation\r\nEND:VEVENT\r\nBEGIN:VEVENT\r\nDTSTART;TZID=Europe/Berlin:20240901T100000\r\nSUMMARY:Trip\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        assert_eq!(
+            parse_ical_dates(ical),
+            vec![
+                DateRange {
+                    from: date("2024-08-01"),
+                    to: date("2024-08-14")
+                },
+                DateRange {
+                    from: date("2024-09-01"),
+                    to: date("2024-09-01")
+                }
+            ]
+        );
+    }
+}
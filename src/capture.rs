@@ -0,0 +1,180 @@
+//! Capture of raw advertisements for protocol debugging, e.g. to reverse-engineer new device variants or to attach to bug reports.
+//!
+//! Each advertisement is stored as a single JSON line (NDJSON) with the time of reception and the payloads as hex strings:
+//!
+//! ```json
+//! {"time":"2024-05-01T12:00:00Z","mac":"11:22:33:44:55:66","name":"ThermoBeacon","rssi":-70,"manufacturer_data":{"16":"0000..."},"service_data":{}}
+//! ```
+
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, Manager as _, Peripheral as _, PeripheralProperties, ScanFilter,
+};
+use btleplug::platform::Manager;
+use chrono::{DateTime, Utc};
+use futures::stream::{Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use tokio::sync::mpsc::unbounded_channel;
+use uuid::Uuid;
+
+/// A single received advertisement
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct CapturedAdvertisement {
+    /// Time of reception
+    pub time: DateTime<Utc>,
+    /// Address of the advertising peripheral
+    pub mac: BDAddr,
+    /// Local name of the peripheral, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Signal strength (dBm), if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i16>,
+    /// Transmission power (dBm), if advertised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_power: Option<i16>,
+    /// Manufacturer data (hex) by manufacturer key
+    #[serde(default)]
+    pub manufacturer_data: BTreeMap<u16, String>,
+    /// Service data (hex) by service UUID
+    #[serde(default)]
+    pub service_data: BTreeMap<Uuid, String>,
+}
+
+impl CapturedAdvertisement {
+    /// Captures the given payloads of an advertisement received now
+    pub fn new(
+        mac: BDAddr,
+        props: Option<&PeripheralProperties>,
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Self {
+        CapturedAdvertisement {
+            time: Utc::now(),
+            mac,
+            name: props.and_then(|p| p.local_name.clone()),
+            rssi: props.and_then(|p| p.rssi),
+            tx_power: props.and_then(|p| p.tx_power_level),
+            manufacturer_data: manufacturer_data
+                .iter()
+                .map(|(key, data)| (*key, hex::encode(data)))
+                .collect(),
+            service_data: service_data
+                .iter()
+                .map(|(uuid, data)| (*uuid, hex::encode(data)))
+                .collect(),
+        }
+    }
+
+    /// Properties of the peripheral as seen by the scan when receiving the advertisement
+    pub fn properties(&self) -> Result<PeripheralProperties, Box<dyn Error + Send + Sync>> {
+        let mut manufacturer_data = HashMap::new();
+        for (key, data) in &self.manufacturer_data {
+            manufacturer_data.insert(*key, hex::decode(data)?);
+        }
+        let mut service_data = HashMap::new();
+        for (uuid, data) in &self.service_data {
+            service_data.insert(*uuid, hex::decode(data)?);
+        }
+        Ok(PeripheralProperties {
+            address: self.mac,
+            local_name: self.name.clone(),
+            rssi: self.rssi,
+            tx_power_level: self.tx_power,
+            manufacturer_data,
+            service_data,
+            ..Default::default()
+        })
+    }
+}
+
+/// Continuously listens for advertisements on all adapters and returns them as a stream, optionally only the ones of the given devices.
+/// Must be called within a tokio runtime, the listeners stop when the stream is dropped.
+pub async fn capture_stream(
+    manager: &Manager,
+    devices: Option<Vec<BDAddr>>,
+) -> Result<impl Stream<Item = CapturedAdvertisement>, Box<dyn Error + Send + Sync>> {
+    let adapter_list = manager.adapters().await?;
+    if adapter_list.is_empty() {
+        error!("No Bluetooth adapters found");
+        return Err("No Bluetooth adapters found".into());
+    }
+
+    let (sender, receiver) = unbounded_channel();
+    for adapter in adapter_list.into_iter() {
+        let adapter_info = adapter.adapter_info().await?;
+        let mut events = adapter.events().await?;
+        adapter.start_scan(ScanFilter::default()).await?;
+        info!("Capturing advertisements on {}", adapter_info);
+
+        let devices = devices.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                let (id, manufacturer_data, service_data) = match event {
+                    CentralEvent::ManufacturerDataAdvertisement {
+                        id,
+                        manufacturer_data,
+                    } => (id, manufacturer_data, HashMap::new()),
+                    CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                        (id, HashMap::new(), service_data)
+                    }
+                    _ => continue,
+                };
+                let peripheral = match adapter.peripheral(&id).await {
+                    Ok(peripheral) => peripheral,
+                    Err(_) => continue,
+                };
+                let mac = peripheral.address();
+                if devices.as_ref().is_some_and(|d| !d.contains(&mac)) {
+                    continue;
+                }
+                let props = peripheral.properties().await.ok().flatten();
+                let advertisement = CapturedAdvertisement::new(
+                    mac,
+                    props.as_ref(),
+                    &manufacturer_data,
+                    &service_data,
+                );
+                if sender.send(advertisement).is_err() {
+                    // Stream was dropped
+                    break;
+                }
+            }
+            warn!("Stopped capturing on {}", adapter_info);
+            let _ = adapter.stop_scan().await;
+        });
+    }
+
+    Ok(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move { receiver.recv().await.map(|ad| (ad, receiver)) },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btleplug::api::bleuuid::uuid_from_u16;
+
+    #[test]
+    fn roundtrips_advertisements_as_json_lines() {
+        let mac: BDAddr = "11:22:33:44:55:66".parse().unwrap();
+        let advertisement = CapturedAdvertisement::new(
+            mac,
+            None,
+            &HashMap::from([(0x10, vec![0x00, 0x80, 0xAB])]),
+            &HashMap::from([(uuid_from_u16(0xFCD2), vec![0x40, 0x02])]),
+        );
+        let line = serde_json::to_string(&advertisement).unwrap();
+        assert!(!line.contains('\n'));
+        assert!(line.contains(r#""manufacturer_data":{"16":"0080ab"}"#));
+
+        let parsed: CapturedAdvertisement = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed, advertisement);
+        let props = parsed.properties().unwrap();
+        assert_eq!(props.address, mac);
+        assert_eq!(props.manufacturer_data[&0x10], vec![0x00, 0x80, 0xAB]);
+        assert_eq!(props.service_data[&uuid_from_u16(0xFCD2)], vec![0x40, 0x02]);
+    }
+}
@@ -0,0 +1,60 @@
+use btleplug::{api::BDAddr, platform::Manager};
+use futures::StreamExt;
+use std::{error::Error, fs::OpenOptions, io::Write, path::Path, time::Duration};
+
+use crate::{capture::capture_stream, configuration::AppConfig};
+
+/// Captures the advertisements of the configured devices (or of all peripherals in range) and appends them to the given NDJSON file,
+/// for the given number of seconds or until stopped
+pub async fn run(
+    config: &AppConfig,
+    manager: &Manager,
+    file: &Path,
+    seconds: Option<u64>,
+    all: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let devices = if all {
+        None
+    } else {
+        let devices: Vec<BDAddr> = config
+            .devices
+            .iter()
+            .filter_map(|d| d.mac.parse().ok())
+            .collect();
+        if devices.is_empty() {
+            return Err("No devices configured, capture all advertisements with --all".into());
+        }
+        Some(devices)
+    };
+    let mut output = OpenOptions::new().create(true).append(true).open(file)?;
+    match &devices {
+        Some(devices) => println!(
+            "Capturing advertisements of {} devices to {} ...",
+            devices.len(),
+            file.display()
+        ),
+        None => println!("Capturing all advertisements to {} ...", file.display()),
+    }
+
+    let mut advertisements = Box::pin(capture_stream(manager, devices).await?);
+    let mut count = 0;
+    let capture = async {
+        while let Some(advertisement) = advertisements.next().await {
+            // A line per advertisement, so an interrupted capture stays readable
+            writeln!(output, "{}", serde_json::to_string(&advertisement)?)?;
+            count += 1;
+        }
+        Ok::<_, Box<dyn Error + Send + Sync>>(())
+    };
+    match seconds {
+        // The capture ends with the timeout
+        Some(seconds) => {
+            if let Ok(result) = tokio::time::timeout(Duration::from_secs(seconds), capture).await {
+                result?;
+            }
+        }
+        None => capture.await?,
+    }
+    println!("Captured {} advertisements", count);
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use btleplug::api::BDAddr;
+
+use crate::{
+    configuration::{AppConfig, PublishOnChangeConfig},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Last published reading of each device and the time it was published
+static LAST_PUBLISHED: Mutex<Option<HashMap<BDAddr, (Instant, ThermoBeaconFullReadResult)>>> =
+    Mutex::new(None);
+
+/// Checks if a value changed at least by the given threshold. Without threshold, changes of the value are ignored.
+fn exceeds(threshold: Option<f32>, last: f32, current: f32) -> bool {
+    threshold
+        .map(|t| (current - last).abs() >= t)
+        .unwrap_or(false)
+}
+
+/// Checks if a reading differs significantly from the last published one
+fn changed(
+    config: &PublishOnChangeConfig,
+    last: &ThermoBeaconFullReadResult,
+    elapsed: Duration,
+    current: &ThermoBeaconFullReadResult,
+) -> bool {
+    elapsed >= config.min_publish_interval
+        || current.button_pressed != last.button_pressed
+        || exceeds(config.temperature, last.temperature, current.temperature)
+        || exceeds(config.humidity, last.humidity, current.humidity)
+        || exceeds(config.battery, last.battery_level, current.battery_level)
+}
+
+/// Checks if the reading should be published. Always true if publish-on-change is not configured or nothing was published for the device yet.
+pub fn should_publish(config: &AppConfig, data: &ThermoBeaconFullReadResult) -> bool {
+    let publish_on_change = match &config.publish_on_change {
+        Some(c) => c,
+        None => return true,
+    };
+    let last_published = LAST_PUBLISHED.lock().unwrap();
+    match last_published.as_ref().and_then(|l| l.get(&data.mac)) {
+        Some((time, last)) => changed(publish_on_change, last, time.elapsed(), data),
+        None => true,
+    }
+}
+
+/// Remembers the reading as last published reading of its device
+pub fn published(data: &ThermoBeaconFullReadResult) {
+    LAST_PUBLISHED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(data.mac, (Instant::now(), data.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishes_significant_changes_or_after_interval() {
+        let config = PublishOnChangeConfig {
+            min_publish_interval: Duration::from_secs(900),
+            temperature: Some(0.2),
+            humidity: None,
+            battery: None,
+        };
+        let last = ThermoBeaconFullReadResult {
+            temperature: 21.0,
+            humidity: 45.0,
+            ..Default::default()
+        };
+        let reading = |temperature, humidity| ThermoBeaconFullReadResult {
+            temperature,
+            humidity,
+            ..Default::default()
+        };
+        let minute = Duration::from_secs(60);
+
+        assert!(!changed(&config, &last, minute, &reading(21.1, 45.0)));
+        assert!(changed(&config, &last, minute, &reading(20.75, 45.0)));
+        // Humidity has no threshold
+        assert!(!changed(&config, &last, minute, &reading(21.0, 60.0)));
+        assert!(changed(&config, &last, 15 * minute, &reading(21.0, 45.0)));
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Result of the last clock check
+static CLOCK_RELIABLE: AtomicBool = AtomicBool::new(true);
+
+/// Id of the current run, increased with each run
+static RUN_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Earliest plausible time: Raspberry Pis without RTC start at 1970 (or the last shutdown time) after a power loss
+fn earliest_plausible_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// Checks if the system clock is plausible and (optionally) synchronized by NTP. The result is kept for `is_reliable()`.
+pub async fn check(check_ntp_sync: bool) -> bool {
+    let now = Utc::now();
+    let mut reliable = now >= earliest_plausible_time();
+    if !reliable {
+        warn!(
+            "System clock {} is implausible, timestamps and schedules are not reliable",
+            now
+        );
+    } else if check_ntp_sync {
+        match tokio::task::spawn_blocking(ntp_synchronized).await {
+            Ok(Some(true)) => debug!("System clock is synchronized by NTP"),
+            Ok(Some(false)) => {
+                warn!("System clock is not (yet) synchronized by NTP, timestamps are not reliable");
+                reliable = false;
+            }
+            _ => debug!("NTP synchronization state of the system clock is unknown"),
+        }
+    }
+
+    CLOCK_RELIABLE.store(reliable, Ordering::Relaxed);
+    reliable
+}
+
+/// Result of the last clock check
+pub fn is_reliable() -> bool {
+    CLOCK_RELIABLE.load(Ordering::Relaxed)
+}
+
+/// Starts a new run and returns its id. Ids start at 1 and increase monotonically while the server is running.
+pub fn start_run() -> u64 {
+    RUN_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Id of the current run, 0 before the first run
+pub fn run_id() -> u64 {
+    RUN_ID.load(Ordering::Relaxed)
+}
+
+/// Queries the NTP synchronization state from systemd-timedated on the system D-Bus
+#[cfg(target_os = "linux")]
+fn ntp_synchronized() -> Option<bool> {
+    use dbus::blocking::{stdintf::org_freedesktop_dbus::Properties, Connection};
+    use std::time::Duration;
+
+    let connection = Connection::new_system()
+        .map_err(|e| debug!("Failed to connect to system D-Bus: {}", e))
+        .ok()?;
+    let proxy = connection.with_proxy(
+        "org.freedesktop.timedate1",
+        "/org/freedesktop/timedate1",
+        Duration::from_secs(5),
+    );
+    proxy
+        .get::<bool>("org.freedesktop.timedate1", "NTPSynchronized")
+        .map_err(|e| debug!("Failed to read NTP synchronization state: {}", e))
+        .ok()
+}
+
+/// NTP synchronization state is only available on Linux
+#[cfg(not(target_os = "linux"))]
+fn ntp_synchronized() -> Option<bool> {
+    None
+}
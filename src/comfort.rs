@@ -0,0 +1,98 @@
+/// Comfort values derived from the temperature and the relative humidity of a reading
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
+pub struct ComputedFields {
+    /// Dew point (°C)
+    pub dew_point: f32,
+    /// Absolute humidity (g/m³)
+    pub absolute_humidity: f32,
+    /// Heat index, the temperature perceived by humans (°C)
+    pub heat_index: f32,
+}
+
+/// Coefficients of the Magnus formula (over water, -45°C to 60°C)
+const MAGNUS_A: f32 = 17.62;
+const MAGNUS_B: f32 = 243.12;
+
+/// Rounds to two decimal places, more precision is not backed by the sensor
+fn round(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Saturation vapour pressure (hPa) at the given temperature (°C)
+fn saturation_vapour_pressure(temperature: f32) -> f32 {
+    6.112 * (MAGNUS_A * temperature / (MAGNUS_B + temperature)).exp()
+}
+
+/// Dew point (°C) using the Magnus formula
+fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    let gamma = (humidity / 100.0).ln() + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    MAGNUS_B * gamma / (MAGNUS_A - gamma)
+}
+
+/// Absolute humidity (g/m³) from the vapour pressure and the ideal gas law
+fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let vapour_pressure = humidity / 100.0 * saturation_vapour_pressure(temperature);
+    216.7 * vapour_pressure / (273.15 + temperature)
+}
+
+/// Heat index (°C) using the algorithm of the US National Weather Service (https://www.wpc.ncep.noaa.gov/html/heatindex_equation.shtml)
+fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let hi = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            hi += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+        }
+        hi
+    };
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+impl ComputedFields {
+    /// Computes the comfort values of the given temperature (°C) and relative humidity (%). Returns None for implausible humidities.
+    pub fn new(temperature: f32, humidity: f32) -> Option<Self> {
+        if humidity <= 0.0 || humidity > 100.0 {
+            return None;
+        }
+        Some(ComputedFields {
+            dew_point: round(dew_point(temperature, humidity)),
+            absolute_humidity: round(absolute_humidity(temperature, humidity)),
+            heat_index: round(heat_index(temperature, humidity)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_comfort_values() {
+        let fields = ComputedFields::new(20.0, 50.0).unwrap();
+        assert_eq!(fields.dew_point, 9.26);
+        assert_eq!(fields.absolute_humidity, 8.62);
+        // Below 80°F the simple formula is used
+        assert_eq!(fields.heat_index, 19.36);
+
+        // Hot and humid: Rothfusz regression
+        assert_eq!(ComputedFields::new(30.0, 70.0).unwrap().heat_index, 35.04);
+    }
+
+    #[test]
+    fn rejects_implausible_humidity() {
+        assert_eq!(ComputedFields::new(20.0, 0.0), None);
+        assert_eq!(ComputedFields::new(20.0, 101.0), None);
+    }
+}
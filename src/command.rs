@@ -0,0 +1,190 @@
+use std::time::Duration;
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    brokers,
+    configuration::{self, AppConfig},
+    device_actions::{self, DeviceAction},
+    homeassistant,
+    mqtt_router::MessageRouter,
+    remote_devices, scan_trigger,
+};
+
+/// Command received on the command topic, e.g. '{"action": "scan"}'
+#[derive(Debug, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Command {
+    /// Immediate run of the scheduled job
+    Scan,
+    /// Publish the Home Assistant discovery messages again
+    Rediscover,
+    /// Read the device list of the configuration again
+    ReloadConfig,
+    /// Reset the min/max values of the given ThermoBeacon (name or MAC)
+    ResetMinMax { device: String },
+    /// Set the clock of the given ThermoBeacon (name or MAC) to the time of the gateway
+    SyncTime { device: String },
+    /// Read the firmware and hardware revision of the given ThermoBeacon (name or MAC) again
+    ReadDeviceInfo { device: String },
+}
+
+/// Parses a command message. Returns None for invalid or empty (deleted retained) messages.
+fn parse(msg: &mqtt::Message) -> Option<Command> {
+    if msg.payload().is_empty() {
+        return None;
+    }
+    serde_json::from_slice(msg.payload())
+        .map_err(|e| {
+            warn!(
+                "Invalid command on {}: {} ({})",
+                msg.topic(),
+                msg.payload_str(),
+                e
+            )
+        })
+        .ok()
+}
+
+/// Subscribes to the command topic of this gateway and acts on the received commands
+pub async fn start(config: &AppConfig, cli: &AsyncClient, router: &MessageRouter) {
+    let topic = config.command_topic();
+    let receiver = router.route(&topic);
+    if let Err(e) = cli.subscribe(&topic, 1).await {
+        error!("Failed to subscribe to command topic {}: {}", topic, e);
+        return;
+    }
+    info!("Listening for commands on {}", topic);
+    tokio::spawn(watch(config.clone(), cli.clone(), receiver));
+}
+
+async fn watch(
+    config: AppConfig,
+    cli: AsyncClient,
+    mut receiver: UnboundedReceiver<mqtt::Message>,
+) {
+    while let Some(msg) = receiver.recv().await {
+        // Retained commands would be executed again at each start
+        if msg.retained() {
+            warn!("Ignoring retained command on {}", msg.topic());
+            continue;
+        }
+        match parse(&msg) {
+            Some(Command::Scan) => {
+                if config.listen.is_some() {
+                    warn!("Scan command ignored, devices are read continuously while listening");
+                } else {
+                    info!("Scan requested by command");
+                    scan_trigger::request();
+                }
+            }
+            Some(Command::Rediscover) => {
+                info!("Home Assistant discovery requested by command");
+                rediscover(&config, &cli).await;
+            }
+            Some(Command::ReloadConfig) => {
+                if config
+                    .mqtt
+                    .as_ref()
+                    .and_then(|c| c.devices_topic.as_ref())
+                    .is_some()
+                {
+                    warn!("Reload command ignored, the device list is managed on the broker");
+                    continue;
+                }
+                // The reloaded devices are validated like a device list received from the broker
+                match configuration::read_devices()
+                    .map_err(|e| e.to_string())
+                    .and_then(|devices| {
+                        remote_devices::validate(&config, &devices).map(|()| devices)
+                    }) {
+                    Ok(devices) => {
+                        info!("Reloaded the configuration with {} devices", devices.len());
+                        if remote_devices::update(devices) {
+                            rediscover(&config, &cli).await;
+                        }
+                    }
+                    Err(e) => error!("Failed to reload the configuration: {}", e),
+                }
+            }
+            Some(Command::ResetMinMax { device }) => {
+                request_action(&config, &device, DeviceAction::ResetMinMax)
+            }
+            Some(Command::SyncTime { device }) => {
+                request_action(&config, &device, DeviceAction::SyncTime)
+            }
+            Some(Command::ReadDeviceInfo { device }) => {
+                request_action(&config, &device, DeviceAction::ReadDeviceInfo)
+            }
+            None => {}
+        }
+    }
+}
+
+/// Time to wait for a requested device action, e.g. for a run in progress
+const ACTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Requests the action on the given device, without blocking the following commands
+fn request_action(config: &AppConfig, device: &str, action: DeviceAction) {
+    let device = match device_actions::find_device(config, device) {
+        Some(device) => device,
+        None => {
+            warn!(
+                "{} command ignored, unknown ThermoBeacon {}",
+                action, device
+            );
+            return;
+        }
+    };
+    let mac = device.mac.parse().unwrap();
+    info!("{} of {} requested by command", action, device.name);
+    tokio::spawn(async move {
+        if let Err(e) = device_actions::run(mac, action, ACTION_TIMEOUT).await {
+            error!("Failed to execute {} on {}: {}", action, device.name, e);
+        }
+    });
+}
+
+/// Publishes the Home Assistant discovery messages of the current devices to all brokers with auto-discovery enabled
+pub async fn rediscover(config: &AppConfig, cli: &AsyncClient) {
+    let config = remote_devices::apply(config);
+    if config
+        .mqtt
+        .as_ref()
+        .map(|c| c.homeassistant)
+        .unwrap_or(false)
+    {
+        if let Err(e) =
+            homeassistant::publish_homeassistant_device_discovery_messages(&config, cli, None).await
+        {
+            warn!("Failed to publish Home Assistant discovery messages: {}", e);
+        }
+    }
+    brokers::publish_homeassistant_discovery(&config).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        let command = |payload: &str| parse(&mqtt::Message::new("topic", payload, 1));
+
+        assert_eq!(command(r#"{"action": "scan"}"#), Some(Command::Scan));
+        assert_eq!(
+            command(r#"{"action": "reload_config"}"#),
+            Some(Command::ReloadConfig)
+        );
+        assert_eq!(
+            command(r#"{"action": "reset_min_max", "device": "Basement"}"#),
+            Some(Command::ResetMinMax {
+                device: "Basement".to_string()
+            })
+        );
+        assert_eq!(command(r#"{"action": "sync_time"}"#), None);
+        assert_eq!(command(r#"{"action": "reboot"}"#), None);
+        assert_eq!(command(""), None);
+    }
+}
@@ -1,4 +1,24 @@
-use config::Config;
+use btleplug::api::BDAddr;
+use chrono::Utc;
+use config::{Config, ConfigError};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use crate::{
+    alerts, aws_iot, azure_iot,
+    calendar::ScheduleException,
+    icinga::Thresholds,
+    notifications,
+    number_format::NumberFormat,
+    publish_policy,
+    scanner::{AdvertisementId, ScanParameters},
+    sensors::DeviceType,
+    sink, thermobeacon_models,
+    thermobeacon_protocol::{Aggregation, ScanOptions},
+    time_window::TimeWindow,
+};
 use dotenv::dotenv;
 use std::env;
 
@@ -7,9 +27,25 @@ use std::env;
 pub struct MqttConfig {
     /// URL of the MQTT server
     pub url: Option<String>,
+    /// URLs of standby MQTT servers, tried in order if the server of `url` is not reachable
+    #[serde(default)]
+    pub failover_urls: Vec<String>,
     #[serde(rename(deserialize = "keepAlive"), default = "default_keep_alive")]
     /// Keep alive time of the connection to the server
     pub keep_alive: u64,
+    /// MQTT protocol version, MQTT 5 falls back to 3.1.1 if rejected by the broker
+    #[serde(default)]
+    pub protocol: MqttProtocol,
+    /// Client id of the connection, a random id if not set. Required to resume sessions.
+    pub client_id: Option<String>,
+    /// Start a new session on each connect, discarding the session kept by the broker
+    #[serde(default = "default_clean_start")]
+    pub clean_start: bool,
+    /// MQTT 5: Time the broker keeps the session after a disconnect, the session ends with the connection if not set
+    #[serde(default, with = "humantime_serde")]
+    pub session_expiry: Option<Duration>,
+    /// MQTT 5: Maximum number of unacknowledged QoS 1 and 2 messages the broker sends at once
+    pub receive_maximum: Option<u16>,
     #[serde(rename(deserialize = "username"))]
     /// Optional username for the mqtt server
     pub username: Option<String>,
@@ -17,9 +53,169 @@ pub struct MqttConfig {
     pub password: Option<String>,
     /// Optional password file for the mqtt server password
     pub password_file: Option<String>,
+    /// Optional CA certificate file (PEM) to verify the server certificate. Enables TLS.
+    pub ca_cert: Option<String>,
+    /// Optional client certificate file (PEM) for mutual TLS. Enables TLS.
+    pub client_cert: Option<String>,
+    /// Optional private key file (PEM) of the client certificate, if not contained in the certificate file
+    pub client_key: Option<String>,
+    /// Do not verify the server certificate and host name. Enables TLS.
+    #[serde(default)]
+    pub tls_insecure: bool,
+    /// ALPN protocols offered in the TLS handshake (e.g. 'x-amzn-mqtt-ca' for AWS IoT Core on port 443)
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    /// Optional AWS IoT Core profile, filling in the URL, ALPN, client id and keep alive
+    pub aws_iot: Option<AwsIotConfig>,
+    /// Optional Azure IoT Hub dialect: username, SAS tokens and the device-to-cloud topic
+    pub azure_iot_hub: Option<AzureIotHubConfig>,
+    /// Prefix of the default topics, defaults to 'ThermoBeacon'
+    pub topic_prefix: Option<String>,
+    /// QoS level of the messages of all devices without a QoS of their own, defaults to 1
+    pub default_qos: Option<i32>,
+    /// Should the messages of all devices without a setting of their own be retained by the broker? Defaults to false
+    pub default_retained: Option<bool>,
+    /// MQTT 5: User properties (e.g. gateway id, site) added to the state messages
+    #[serde(default)]
+    pub user_properties: BTreeMap<String, String>,
+    /// MQTT 5: Optional expiry of the state messages: 'schedule' for the schedule period of the device or a duration
+    #[serde(default, deserialize_with = "message_expiry")]
+    pub message_expiry_interval: Option<MessageExpiry>,
     /// Optional support for Home assistant
     #[serde(default)]
     pub homeassistant: bool,
+    /// Home Assistant marks the sensors of a device unavailable if no reading arrived within this multiple of its schedule period (0 disables the expiry)
+    #[serde(default = "default_expire_after_factor")]
+    pub expire_after_factor: u32,
+    /// Optional URL linked from the devices in Home Assistant (e.g. the status page of this gateway)
+    pub configuration_url: Option<String>,
+    /// Optional directory remembering the announced Home Assistant entities, to remove the entities of deleted devices
+    pub homeassistant_state: Option<String>,
+    /// Publish the readings as JSON document, as scalar value per field or both
+    #[serde(default)]
+    pub publish_mode: PublishMode,
+    /// Test-publish to all topics at startup to detect ACL denials of the broker
+    #[serde(default)]
+    pub permission_check: bool,
+    /// Measure the publish -> receive round-trip latency of the broker in each run
+    #[serde(default)]
+    pub latency_check: bool,
+    /// Optional coordination of redundant gateways
+    pub leader_election: Option<LeaderElectionConfig>,
+    /// Optional topic of a retained JSON device list, which replaces the configured devices
+    pub devices_topic: Option<String>,
+    /// Act on the commands (e.g. an immediate scan) received on the command topic
+    #[serde(default)]
+    pub commands: bool,
+    /// Optional interval of the bridge info messages. Enables the bridge state and info topics.
+    #[serde(default, with = "humantime_serde")]
+    pub bridge_info_interval: Option<Duration>,
+}
+
+/// Connection to AWS IoT Core as a thing
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct AwsIotConfig {
+    /// Device data endpoint of the account (e.g. 'abc123-ats.iot.eu-central-1.amazonaws.com')
+    pub endpoint: String,
+    /// Name of the thing of this gateway, used as client id
+    pub thing_name: String,
+    /// Publish the readings as reported state of a named shadow (the device name) of the thing instead of the state topic
+    #[serde(default)]
+    pub shadow: bool,
+}
+
+/// Connection to Azure IoT Hub as a device
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct AzureIotHubConfig {
+    /// Host name of the hub (e.g. 'my-hub.azure-devices.net')
+    pub host_name: String,
+    /// Id of the device of this gateway in the hub
+    pub device_id: String,
+    /// Symmetric key (base64) of the device, signing the SAS tokens. Without it, the device authenticates with the 'client_cert'.
+    pub shared_access_key: Option<String>,
+    /// File containing the symmetric key (to use docker secrets)
+    pub shared_access_key_file: Option<String>,
+    /// Validity of the SAS tokens, which are renewed in the background, defaults to 1h
+    #[serde(default = "default_sas_token_validity", with = "humantime_serde")]
+    pub token_validity: Duration,
+}
+
+fn default_sas_token_validity() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+/// Version of the MQTT protocol
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MqttProtocol {
+    /// MQTT 5
+    #[default]
+    V5,
+    /// MQTT 3.1.1
+    V311,
+}
+
+/// Format of the MQTT messages of the readings
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishMode {
+    /// Single JSON document to the state topic of the device
+    #[default]
+    Json,
+    /// Scalar value of each field to '{state topic}/{field}'
+    PerField,
+    /// JSON document and scalar values
+    Both,
+}
+
+/// Encoding of the messages of the readings
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// JSON document
+    #[default]
+    Json,
+    /// CBOR (RFC 8949)
+    Cbor,
+    /// MessagePack, maps with field names
+    Msgpack,
+    /// Protobuf, schema in thermobeacon.proto
+    Protobuf,
+}
+
+impl PayloadFormat {
+    /// Is the encoding binary (not text)?
+    pub fn is_binary(self) -> bool {
+        self != PayloadFormat::Json
+    }
+}
+
+/// Configuration of the leader election between redundant gateways
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct LeaderElectionConfig {
+    /// Lock topic shared by all gateways, defaults to 'ThermoBeacon/leader'
+    #[serde(default = "default_leader_topic")]
+    pub topic: String,
+    /// Unique id of this gateway, defaults to the instance name or a random id
+    pub node_id: Option<String>,
+    /// Seconds between two heartbeats of the leader, defaults to 30
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// Seconds without heartbeat until a standby gateway takes over, defaults to 90
+    #[serde(default = "default_heartbeat_timeout")]
+    pub heartbeat_timeout: u64,
+}
+
+fn default_leader_topic() -> String {
+    "ThermoBeacon/leader".to_string()
+}
+
+fn default_heartbeat_interval() -> u64 {
+    30
+}
+
+fn default_heartbeat_timeout() -> u64 {
+    90
 }
 
 /// Default keep_alive value
@@ -27,6 +223,44 @@ fn default_keep_alive() -> u64 {
     60
 }
 
+fn default_clean_start() -> bool {
+    true
+}
+
+fn default_expire_after_factor() -> u32 {
+    3
+}
+
+/// Expiry of the state messages at the broker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageExpiry {
+    /// Schedule period of the device, so a reading expires when the next one is due
+    Schedule,
+    /// Fixed interval
+    Interval(Duration),
+}
+
+/// Deserializes a message expiry: 'schedule' or a duration like '10m'
+fn message_expiry<'de, D>(deserializer: D) -> Result<Option<MessageExpiry>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    value
+        .map(|value| match value.trim() {
+            "schedule" => Ok(MessageExpiry::Schedule),
+            interval => humantime::parse_duration(interval)
+                .map(MessageExpiry::Interval)
+                .map_err(|e| {
+                    serde::de::Error::custom(format!(
+                        "message_expiry_interval '{}' is neither 'schedule' nor a duration: {}",
+                        interval, e
+                    ))
+                }),
+        })
+        .transpose()
+}
+
 /// Configuration of a single known ThermoBeacon device
 #[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
 pub struct AppDevice {
@@ -34,15 +268,100 @@ pub struct AppDevice {
     pub mac: String,
     /// Human-readable name of the device (for the MQTT message)
     pub name: String,
+    /// Type of the sensor, defaults to 'thermobeacon'
+    #[serde(default, rename = "type")]
+    pub device_type: DeviceType,
     /// Topic of the MQTT message
     pub topic: Option<String>,
-    /// QOS level of the MQTT message
+    /// QOS level of the MQTT message, overrides the global default
     pub qos: Option<i32>,
-    /// Should  the message be retained by the broker?
-    #[serde(default)]
-    pub retained: bool,
+    /// Should  the message be retained by the broker? Overrides the global default
+    pub retained: Option<bool>,
     pub manufacturer: Option<String>,
+    /// Model for Home Assistant. If it names a known ThermoBeacon model (e.g. 'WS08'), the beacon is decoded as this model instead of the detected one.
     pub model: Option<String>,
+    /// Optional hardware version of the device for Home Assistant
+    pub hw_version: Option<String>,
+    /// Optional firmware version of the device for Home Assistant
+    pub sw_version: Option<String>,
+    /// Name of the broker (from 'brokers') to publish to instead of the default one
+    pub broker: Option<String>,
+    /// Units of the messages of this device, overrides the global units
+    pub units: Option<Units>,
+    /// CRON expression to read this device, overrides the global schedule
+    pub cron: Option<String>,
+    /// Fixed interval to read this device, overrides the global schedule and the cron expression of the device
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// Template of the message payload of this device, overrides the global template
+    pub payload_template: Option<String>,
+    /// Encoding of the messages of this device, overrides the global format
+    pub payload_format: Option<PayloadFormat>,
+    /// Download the log of the device over GATT to recover readings missed since the last known reading
+    #[serde(default)]
+    pub download_history: bool,
+    /// Name of the Bluetooth adapter (e.g. 'hci1') the device is only read with during the scheduled scans
+    pub adapter: Option<String>,
+}
+
+/// Schedule devices are read on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Fixed interval, starting immediately
+    Interval(Duration),
+    /// CRON expression, evaluated in the configured timezone
+    Cron(String),
+}
+
+impl Schedule {
+    /// Time between two runs. For CRON expressions the longest gap between the next runs is used. None if the CRON expression is invalid.
+    pub fn period(&self) -> Option<Duration> {
+        match self {
+            Schedule::Interval(interval) => Some(*interval),
+            Schedule::Cron(cron) => {
+                let mut run = cron_parser::parse(cron, &Utc::now()).ok()?;
+                let mut period = Duration::ZERO;
+                for _ in 0..CRON_PERIOD_SAMPLES {
+                    let next = cron_parser::parse(cron, &run).ok()?;
+                    period = period.max((next - run).to_std().ok()?);
+                    run = next;
+                }
+                Some(period)
+            }
+        }
+    }
+}
+
+/// Number of consecutive CRON runs inspected to determine the period of a schedule
+const CRON_PERIOD_SAMPLES: usize = 24;
+
+/// Units of the temperatures in the messages
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// °C
+    #[default]
+    Metric,
+    /// °F
+    Imperial,
+}
+
+impl Units {
+    /// Unit of temperatures, e.g. for Home Assistant
+    pub fn temperature_unit(&self) -> &'static str {
+        match self {
+            Units::Metric => "°C",
+            Units::Imperial => "°F",
+        }
+    }
+
+    /// Converts a temperature (°C) into this units, rounded to two decimal places
+    pub fn temperature(&self, celsius: f32) -> f32 {
+        match self {
+            Units::Metric => celsius,
+            Units::Imperial => ((celsius * 1.8 + 32.0) * 100.0).round() / 100.0,
+        }
+    }
 }
 
 /// Configuration of the health check
@@ -57,6 +376,9 @@ pub struct HealthCheckConfig {
     /// Port of the health check service,defaults to 8080
     #[serde(default = "default_server_port")]
     pub port: u16,
+    /// Optional maximum age of the newest reading, older data turns the health check unhealthy
+    #[serde(default, with = "humantime_serde")]
+    pub max_data_age: Option<Duration>,
 }
 
 /// Default server ip
@@ -75,10 +397,716 @@ impl Default for HealthCheckConfig {
             active: Default::default(),
             ip: default_server_ip(),
             port: default_server_port(),
+            max_data_age: None,
+        }
+    }
+}
+
+/// Configuration of the download of the logs of the devices
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct HistoryDownloadConfig {
+    /// Interval the devices log their readings in, defaults to 10 minutes
+    #[serde(default = "default_log_interval", with = "humantime_serde")]
+    pub log_interval: Duration,
+    /// Maximum number of log entries to download per device and run, defaults to 144 (one day)
+    #[serde(default = "default_max_log_entries")]
+    pub max_entries: u32,
+}
+
+fn default_log_interval() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_max_log_entries() -> u32 {
+    144
+}
+
+impl Default for HistoryDownloadConfig {
+    fn default() -> Self {
+        HistoryDownloadConfig {
+            log_interval: default_log_interval(),
+            max_entries: default_max_log_entries(),
+        }
+    }
+}
+
+/// Parameters of the scheduled scans
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct ScanConfig {
+    /// Scan passively without scan requests (bluer backend with 'filter' only)
+    #[serde(default)]
+    pub passive: bool,
+    /// Report each advertisement, even if its content did not change (bluer backend only), defaults to true
+    #[serde(default = "default_duplicate_data")]
+    pub duplicate_data: bool,
+    /// Restrict the scan to the advertisements of the configured device types
+    #[serde(default)]
+    pub filter: bool,
+    /// Interval to poll the properties of the peripherals while scanning, defaults to 1s
+    #[serde(default = "default_poll_interval", with = "humantime_serde")]
+    pub poll_interval: Duration,
+}
+
+fn default_duplicate_data() -> bool {
+    true
+}
+
+fn default_poll_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            passive: false,
+            duplicate_data: default_duplicate_data(),
+            filter: false,
+            poll_interval: default_poll_interval(),
         }
     }
 }
 
+/// Configuration of the tamper-evident record log
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct RecordLogConfig {
+    /// Directory containing one hash-chained log file per device
+    pub path: String,
+    /// Optional secret key to sign each record (HMAC-SHA256)
+    pub key: Option<String>,
+    /// Optional file containing the secret key (to use docker secrets)
+    pub key_file: Option<String>,
+}
+
+/// Configuration of the local history store (SQLite)
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct StoreConfig {
+    /// Directory containing the database
+    pub path: String,
+    /// Days to keep the readings, defaults to 30. 0 keeps them forever
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+}
+
+fn default_retention_days() -> u32 {
+    30
+}
+
+/// Configuration of the spool of messages which could not be published
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct SpoolConfig {
+    /// Directory containing the spooled messages
+    pub path: String,
+    /// Maximum total size of the spooled messages in bytes, the oldest messages are dropped first. Defaults to 10 MiB
+    #[serde(default = "default_spool_max_size")]
+    pub max_size: u64,
+}
+
+fn default_spool_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+/// Configuration of the InfluxDB line protocol output over UDP
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct InfluxUdpConfig {
+    /// Host of the UDP listener (e.g. Telegraf socket_listener)
+    pub host: String,
+    /// Port of the UDP listener
+    pub port: u16,
+    /// Name of the measurement, defaults to 'thermobeacon'
+    #[serde(default = "default_influx_measurement")]
+    pub measurement: String,
+    /// Additional tags added to each line
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+fn default_influx_measurement() -> String {
+    "thermobeacon".to_string()
+}
+
+/// Configuration of the Graphite plaintext output
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct GraphiteConfig {
+    /// Host of the Graphite server (carbon)
+    pub host: String,
+    /// Port of the plaintext protocol, defaults to 2003
+    #[serde(default = "default_graphite_port")]
+    pub port: u16,
+    /// Prefix of the metric paths, defaults to 'thermobeacon'
+    #[serde(default = "default_graphite_prefix")]
+    pub prefix: String,
+}
+
+fn default_graphite_port() -> u16 {
+    2003
+}
+
+fn default_graphite_prefix() -> String {
+    "thermobeacon".to_string()
+}
+
+/// Configuration of the StatsD output over UDP
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct StatsdConfig {
+    /// Host of the StatsD daemon
+    pub host: String,
+    /// Port of the StatsD daemon, defaults to 8125
+    #[serde(default = "default_statsd_port")]
+    pub port: u16,
+    /// Prefix of the gauge names, defaults to 'thermobeacon'
+    #[serde(default = "default_graphite_prefix")]
+    pub prefix: String,
+}
+
+fn default_statsd_port() -> u16 {
+    8125
+}
+
+/// Configuration of the Zabbix sender output
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct ZabbixConfig {
+    /// Host of the Zabbix server (or proxy)
+    pub server: String,
+    /// Trapper port of the Zabbix server, defaults to 10051
+    #[serde(default = "default_zabbix_port")]
+    pub port: u16,
+    /// Name of the host in Zabbix the items belong to
+    pub host: String,
+    /// Prefix of the item keys, defaults to 'thermobeacon'
+    #[serde(default = "default_zabbix_key_prefix")]
+    pub key_prefix: String,
+}
+
+fn default_zabbix_port() -> u16 {
+    10051
+}
+
+fn default_zabbix_key_prefix() -> String {
+    "thermobeacon".to_string()
+}
+
+/// Configuration of the Icinga 2 passive check output
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct IcingaConfig {
+    /// Base URL of the Icinga 2 API (e.g. https://icinga.local:5665)
+    pub url: String,
+    /// API user with permission for 'actions/process-check-result'
+    pub username: String,
+    /// Password of the API user
+    pub password: Option<String>,
+    /// File containing the password of the API user (to use docker secrets)
+    pub password_file: Option<String>,
+    /// Accept invalid (e.g. self-signed) TLS certificates of the API
+    #[serde(default)]
+    pub insecure: bool,
+    /// Name of the host in Icinga the services belong to
+    pub host: String,
+    /// Prefix of the service names, followed by the device name
+    #[serde(default)]
+    pub service_prefix: String,
+    /// Thresholds for the check states
+    #[serde(default)]
+    pub thresholds: IcingaThresholds,
+}
+
+/// Thresholds of the Icinga 2 passive checks for each metric
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct IcingaThresholds {
+    #[serde(default)]
+    pub temperature: Thresholds,
+    #[serde(default)]
+    pub humidity: Thresholds,
+    #[serde(default)]
+    pub battery_level: Thresholds,
+}
+
+fn default_battery_low() -> u8 {
+    20
+}
+
+/// Configuration of the alert notifications
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct NotificationsConfig {
+    /// Named notification services the rules send to
+    #[serde(default)]
+    pub notifiers: HashMap<String, NotifierConfig>,
+    /// Rules raising the alerts
+    #[serde(default)]
+    pub rules: Vec<NotificationRule>,
+}
+
+/// Notification service sending the alerts to a phone
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    Ntfy(NtfyConfig),
+    Pushover(PushoverConfig),
+    Telegram(TelegramConfig),
+}
+
+/// Configuration of a ntfy topic
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct NtfyConfig {
+    /// URL of the ntfy server, defaults to https://ntfy.sh
+    #[serde(default = "default_ntfy_url")]
+    pub url: String,
+    /// Topic the alerts are published to
+    pub topic: String,
+    /// Optional access token
+    pub token: Option<String>,
+    /// File containing the access token (to use docker secrets)
+    pub token_file: Option<String>,
+    /// Optional priority of the alerts (1 - 5)
+    pub priority: Option<u8>,
+}
+
+fn default_ntfy_url() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+/// Configuration of a Pushover application
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct PushoverConfig {
+    /// API token of the application
+    pub token: Option<String>,
+    /// File containing the API token (to use docker secrets)
+    pub token_file: Option<String>,
+    /// User (or group) key of the recipients
+    pub user: String,
+    /// Optional priority of the alerts (-2 - 1)
+    pub priority: Option<i8>,
+}
+
+/// Configuration of a Telegram bot
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct TelegramConfig {
+    /// Token of the bot
+    pub bot_token: Option<String>,
+    /// File containing the token of the bot (to use docker secrets)
+    pub bot_token_file: Option<String>,
+    /// Chat the alerts are sent to
+    pub chat_id: String,
+}
+
+/// Condition of a notification rule
+#[derive(Debug, Clone, Copy, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationCondition {
+    /// The device is reported offline (see `offline_after_missing_runs`)
+    Offline,
+    /// The battery level falls below `battery_low`
+    BatteryLow,
+}
+
+/// Metric of a reading compared by an alert rule
+#[derive(
+    Debug, Clone, Copy, Default, serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Temperature in the configured units
+    #[default]
+    Temperature,
+    Humidity,
+    BatteryLevel,
+}
+
+/// Rule sending a notification when its condition becomes true for a device, and again once it is resolved
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct NotificationRule {
+    /// Name of the rule, the title of the notifications
+    pub name: String,
+    /// Names of the devices the rule applies to, defaults to all devices
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub devices: Vec<String>,
+    pub condition: NotificationCondition,
+    /// Names of the notifiers to send to
+    #[serde(deserialize_with = "one_or_many")]
+    pub notify: Vec<String>,
+    /// Only notify within these time windows (e.g. not at night). Defaults to always
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+}
+
+/// Configuration of the threshold alerts
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct AlertsConfig {
+    /// Optional file keeping the state of the rules (pending and raised alerts) across restarts
+    pub state_file: Option<String>,
+    /// Rules raising the alerts
+    #[serde(default)]
+    pub rules: Vec<AlertRule>,
+}
+
+/// Comparison of an alert rule
+#[derive(Debug, Clone, Copy, serde_derive::Deserialize, PartialEq, Eq)]
+pub enum Operator {
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = ">=")]
+    GreaterOrEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessOrEqual,
+}
+
+/// Rule raising an alert for a device while its metric compares to the value, cleared once the metric is back beyond the hysteresis
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq)]
+pub struct AlertRule {
+    /// Unique name of the rule, part of the alert topic
+    pub name: String,
+    /// Names of the devices the rule applies to, defaults to all devices
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub devices: Vec<String>,
+    /// Compared metric, defaults to the temperature
+    #[serde(default)]
+    pub metric: Metric,
+    pub operator: Operator,
+    pub value: f32,
+    /// Distance the metric must be back from the value to clear the alert, defaults to 0
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Time the condition must hold before the alert is raised, defaults to immediately
+    #[serde(rename = "for", default, with = "humantime_serde")]
+    pub duration: Option<Duration>,
+    /// Names of the notifiers (see `notifications`) to send the alert to
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub notify: Vec<String>,
+    /// Only notify within these time windows (e.g. not at night). Defaults to always
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+}
+
+impl Eq for AlertRule {}
+
+/// Configuration of the SNMP agent
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct SnmpConfig {
+    /// IP bind of the agent, defaults to "0.0.0.0"
+    #[serde(default = "default_snmp_ip")]
+    pub ip: String,
+    /// UDP port of the agent, defaults to 161
+    #[serde(default = "default_snmp_port")]
+    pub port: u16,
+    /// Community required for read access
+    pub community: String,
+    /// OID of the private MIB subtree, defaults to the Net-SNMP experimental subtree (1.3.6.1.4.1.8072.9999.9999.1)
+    #[serde(default = "default_snmp_base_oid")]
+    pub base_oid: String,
+}
+
+fn default_snmp_ip() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_snmp_port() -> u16 {
+    161
+}
+
+fn default_snmp_base_oid() -> String {
+    "1.3.6.1.4.1.8072.9999.9999.1".to_string()
+}
+
+/// Message bus of the D-Bus service
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DbusBus {
+    Session,
+    #[default]
+    System,
+}
+
+/// Configuration of the D-Bus service
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct DbusConfig {
+    /// Bus to register the service on, defaults to the system bus
+    #[serde(default)]
+    pub bus: DbusBus,
+    /// Well-known name of the service, also used as interface name
+    #[serde(default = "default_dbus_name")]
+    pub name: String,
+}
+
+fn default_dbus_name() -> String {
+    "io.github.StefanRichterHuber.ThermoBeacon".to_string()
+}
+
+/// Configuration of the continuous listening mode
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct ListenConfig {
+    /// Minimum number of seconds between two delivered readings of the same device, defaults to 0 (every advertisement)
+    #[serde(default)]
+    pub min_interval: u64,
+}
+
+/// Range of simulated values
+#[derive(Debug, Clone, Copy, serde_derive::Deserialize, PartialEq)]
+pub struct ValueRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Eq for ValueRange {}
+
+/// Configuration of the simulated devices
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct SimulateConfig {
+    /// Interval of the readings of each device, defaults to 1 minute
+    #[serde(default = "default_simulate_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// Number of devices generated if no devices are configured, defaults to 3
+    #[serde(default = "default_simulated_devices")]
+    pub count: u8,
+    /// Range of the temperature (°C), defaults to 18 - 24 °C
+    #[serde(default = "default_simulated_temperature")]
+    pub temperature: ValueRange,
+    /// Range of the humidity (%), defaults to 40 - 60 %
+    #[serde(default = "default_simulated_humidity")]
+    pub humidity: ValueRange,
+    /// Range of the battery level (%), defaults to 80 - 100 %
+    #[serde(default = "default_simulated_battery")]
+    pub battery: ValueRange,
+}
+
+fn default_simulate_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_simulated_devices() -> u8 {
+    3
+}
+
+fn default_simulated_temperature() -> ValueRange {
+    ValueRange {
+        min: 18.0,
+        max: 24.0,
+    }
+}
+
+fn default_simulated_humidity() -> ValueRange {
+    ValueRange {
+        min: 40.0,
+        max: 60.0,
+    }
+}
+
+fn default_simulated_battery() -> ValueRange {
+    ValueRange {
+        min: 80.0,
+        max: 100.0,
+    }
+}
+
+/// Configuration of publishing readings only if they changed
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq)]
+pub struct PublishOnChangeConfig {
+    /// Unchanged readings are published again once this interval elapsed since the last published reading of the device, defaults to 15 minutes
+    #[serde(default = "default_min_publish_interval", with = "humantime_serde")]
+    pub min_publish_interval: Duration,
+    /// Minimum change of the temperature (°C) to publish a reading
+    pub temperature: Option<f32>,
+    /// Minimum change of the humidity (%) to publish a reading
+    pub humidity: Option<f32>,
+    /// Minimum change of the battery level (%) to publish a reading
+    pub battery: Option<f32>,
+}
+
+impl Eq for PublishOnChangeConfig {}
+
+fn default_min_publish_interval() -> Duration {
+    Duration::from_secs(15 * 60)
+}
+
+/// Output (sink) readings are delivered to
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OutputConfig {
+    /// Publish to the state topic of each device on the MQTT broker
+    Mqtt,
+    /// Print to the console
+    Stdout,
+    /// Publish Sparkplug B messages (e.g. for Ignition) to the MQTT broker
+    Sparkplug(SparkplugConfig),
+    /// POST the readings as JSON to an HTTP endpoint
+    Webhook(WebhookConfig),
+    /// Produce the readings to a Kafka topic (Cargo feature `kafka`)
+    Kafka(KafkaConfig),
+    /// Publish the readings to NATS subjects, optionally through JetStream (Cargo feature `nats`)
+    Nats(NatsConfig),
+    /// Append the readings to a local CSV or NDJSON file
+    File(FileConfig),
+}
+
+/// Configuration of the file output
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct FileConfig {
+    /// Path of the file the readings are appended to
+    pub path: String,
+    /// Format of the file, defaults to CSV
+    #[serde(default)]
+    pub format: FileFormat,
+    /// Rotate the file once it reached this size in bytes
+    pub max_size: Option<u64>,
+    /// Rotate the file on the first reading of a new day (in the configured timezone)
+    #[serde(default)]
+    pub daily: bool,
+    /// Compress rotated files with gzip
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+/// Format of the file output
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    /// One line per reading, numbers formatted according to the 'number_format'
+    #[default]
+    Csv,
+    /// One JSON message per line
+    Ndjson,
+}
+
+/// Configuration of the NATS output
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct NatsConfig {
+    /// Server URLs (e.g. 'nats://nats1:4222'), a single one or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub servers: Vec<String>,
+    /// Prefix of the subjects, defaults to 'thermobeacon'. The readings are published to '<prefix>.[<instance_name>.]<device name>'.
+    #[serde(default = "default_nats_subject_prefix")]
+    pub subject_prefix: String,
+    /// Publish through JetStream and wait for the acknowledgement of the stream. Requires a stream capturing the subjects.
+    #[serde(default)]
+    pub jetstream: bool,
+    /// Optional credentials file (JWT and NKey seed, e.g. of NGS or an operator setup)
+    pub credentials_file: Option<String>,
+    /// Optional token authentication
+    pub token: Option<String>,
+    /// File containing the token (to use docker secrets)
+    pub token_file: Option<String>,
+    /// Require TLS
+    #[serde(default)]
+    pub tls: bool,
+    /// Optional CA certificate file (PEM) to verify the servers. Enables TLS.
+    pub ca_cert: Option<String>,
+    /// Timeout of connecting and of the acknowledgement of a publish, defaults to 10s
+    #[serde(default = "default_nats_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+fn default_nats_subject_prefix() -> String {
+    "thermobeacon".to_string()
+}
+
+fn default_nats_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Configuration of the Kafka output
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct KafkaConfig {
+    /// Bootstrap servers (host:port), a single one or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub brokers: Vec<String>,
+    /// Topic of the readings, defaults to 'thermobeacon'
+    #[serde(default = "default_kafka_topic")]
+    pub topic: String,
+    /// Key of the records, defaults to the MAC address of the device
+    #[serde(default)]
+    pub key: KafkaKey,
+    /// Connect with TLS
+    #[serde(default)]
+    pub tls: bool,
+    /// Optional CA certificate file (PEM) to verify the brokers. Enables TLS.
+    pub ca_cert: Option<String>,
+    /// Optional SASL authentication
+    pub sasl: Option<KafkaSaslConfig>,
+    /// Timeout of the delivery of a record, defaults to 10s
+    #[serde(default = "default_kafka_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Additional librdkafka producer properties (e.g. 'compression.type'), applied last
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+}
+
+fn default_kafka_topic() -> String {
+    "thermobeacon".to_string()
+}
+
+fn default_kafka_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Key of the Kafka records, which selects their partition
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaKey {
+    /// MAC address of the device
+    #[default]
+    Mac,
+    /// Name of the device
+    Name,
+}
+
+/// SASL authentication of the Kafka output
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct KafkaSaslConfig {
+    /// Mechanism: 'PLAIN', 'SCRAM-SHA-256' or 'SCRAM-SHA-512', defaults to 'PLAIN'
+    #[serde(default = "default_kafka_sasl_mechanism")]
+    pub mechanism: String,
+    pub username: String,
+    pub password: Option<String>,
+    /// File containing the password (to use docker secrets)
+    pub password_file: Option<String>,
+}
+
+fn default_kafka_sasl_mechanism() -> String {
+    "PLAIN".to_string()
+}
+
+/// Configuration of the webhook output
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct WebhookConfig {
+    /// URL the readings are POSTed to
+    pub url: String,
+    /// Optional bearer token sent in the Authorization header
+    pub bearer_token: Option<String>,
+    /// File containing the bearer token (to use docker secrets)
+    pub bearer_token_file: Option<String>,
+    /// POST the readings of each run as one JSON array instead of each reading on its own
+    #[serde(default)]
+    pub batch: bool,
+    /// Number of retries of a failed request, defaults to 3
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    /// Timeout of each request, defaults to 10s
+    #[serde(default = "default_webhook_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Sparkplug B identity of this gateway (the edge node)
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct SparkplugConfig {
+    /// Group of the edge node, defaults to 'ThermoBeacon'
+    #[serde(default = "default_sparkplug_group_id")]
+    pub group_id: String,
+    /// Id of the edge node, defaults to the instance name or 'gateway'
+    pub edge_node_id: Option<String>,
+}
+
+fn default_sparkplug_group_id() -> String {
+    "ThermoBeacon".to_string()
+}
+
 /// Main configuration structure
 #[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
 pub struct AppConfig {
@@ -87,36 +1115,440 @@ pub struct AppConfig {
     pub devices: Vec<AppDevice>,
     /// CRON expression for the poll interval
     pub cron: Option<String>,
+    /// Fixed poll interval (e.g. '60s', '5min'), alternative to the CRON expression
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
     /// Timezone for the CRON expression
     pub timezone: Option<String>,
+    /// Dates on which the schedule is modified
+    #[serde(default)]
+    pub exceptions: Vec<ScheduleException>,
     /// MQTT client configuration
     pub mqtt: Option<MqttConfig>,
+    /// Outputs the readings are delivered to. Defaults to MQTT (if configured) or the console
+    #[serde(default)]
+    pub outputs: Vec<OutputConfig>,
+    /// Additional named MQTT brokers, devices can publish to instead of the default one
+    #[serde(default)]
+    pub brokers: HashMap<String, MqttConfig>,
+    /// Continuously listen for advertisements instead of scanning on the cron schedule
+    pub listen: Option<ListenConfig>,
+    /// Publish readings of simulated devices instead of reading the configured devices, e.g. to develop dashboards without hardware
+    pub simulate: Option<SimulateConfig>,
+    /// Only publish readings which changed significantly (or after an interval)
+    pub publish_on_change: Option<PublishOnChangeConfig>,
+    /// Names of the Bluetooth adapters (e.g. 'hci1') to use for the scheduled scans, a single name or a list. Defaults to all adapters
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub adapter: Vec<String>,
+    /// Parameters of the scheduled scans
+    #[serde(default)]
+    pub scan: ScanConfig,
     /// Time in seconds to scan for devices
     #[serde(default = "default_seconds_to_scan")]
     pub seconds_to_scan: u64,
+    /// Seconds to wait for the missing frame of a device after the first one was received, before a partial result is published
+    #[serde(default = "default_device_read_timeout")]
+    pub device_read_timeout: u64,
+    /// Number of distinct temperature / humidity frames to collect from each device during a scan, defaults to 1
+    #[serde(default = "default_samples")]
+    pub samples: usize,
+    /// Aggregation of the collected frames: 'last', 'mean' or 'median', defaults to 'last'
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    /// Number of consecutive runs a device must be missing before it is searched for less often (0 disables the backoff)
+    #[serde(default = "default_backoff_after_missing_runs")]
+    pub backoff_after_missing_runs: u32,
+    /// Number of consecutive runs a device must be missing before it is reported offline (0 disables the availability of the devices)
+    #[serde(default)]
+    pub offline_after_missing_runs: u32,
+    /// Battery level (%) below which the battery of a device is low, for the device events and 'battery_low' notifications. Defaults to 20
+    #[serde(default = "default_battery_low")]
+    pub battery_low: u8,
+    /// Publish battery-low and reboot events of the devices
+    #[serde(default)]
+    pub device_events: bool,
     /// Health check options
     #[serde(default)]
     pub health: HealthCheckConfig,
+    /// Optional tamper-evident log of all readings
+    pub record_log: Option<RecordLogConfig>,
+    /// Optional spool of MQTT messages which could not be published, replayed once the broker is reachable again
+    pub spool: Option<SpoolConfig>,
+    /// Optional local history store of all readings
+    pub store: Option<StoreConfig>,
+    /// Read the firmware and hardware revision of each ThermoBeacon once over GATT, announced to Home Assistant and in the bridge info
+    #[serde(default)]
+    pub read_device_info: bool,
+    /// Download of the logs of devices with 'download_history'
+    #[serde(default)]
+    pub history_download: HistoryDownloadConfig,
+    /// Optional InfluxDB line protocol output over UDP
+    pub influx_udp: Option<InfluxUdpConfig>,
+    /// Optional Graphite plaintext output
+    pub graphite: Option<GraphiteConfig>,
+    /// Optional StatsD output over UDP
+    pub statsd: Option<StatsdConfig>,
+    /// Optional Zabbix sender output
+    pub zabbix: Option<ZabbixConfig>,
+    /// Optional Icinga 2 passive check output
+    pub icinga: Option<IcingaConfig>,
+    /// Optional alert notifications (ntfy, Pushover, Telegram)
+    pub notifications: Option<NotificationsConfig>,
+    /// Optional threshold alerts, published to the alert topics and the notifiers
+    pub alerts: Option<AlertsConfig>,
+    /// Optional SNMP agent exposing the latest readings
+    pub snmp: Option<SnmpConfig>,
+    /// Optional D-Bus service exposing the latest readings
+    pub dbus: Option<DbusConfig>,
+    /// Optional name of this gateway instance, used to namespace topics and ids of multiple gateways
+    pub instance_name: Option<String>,
+    /// Formatting of numbers in CSV and table outputs
+    #[serde(default)]
+    pub number_format: NumberFormat,
+    /// Check if the system clock is synchronized by NTP (using systemd-timedated) before each run
+    #[serde(default)]
+    pub check_ntp_sync: bool,
+    /// Add comfort values (dew point, absolute humidity, heat index) computed from temperature and humidity to the messages
+    #[serde(default)]
+    pub computed_fields: bool,
+    /// Units of the temperatures in the messages, defaults to metric (°C)
+    #[serde(default)]
+    pub units: Units,
+    /// Optional template (Jinja2 syntax) of the message payload, replaces the JSON document
+    pub payload_template: Option<String>,
+    /// Encoding of the messages of the readings (without template), defaults to JSON
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+}
+
+/// Accepts a single value or a list of values
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde_derive::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+impl AppConfig {
+    /// Base of the default topics: '{topic_prefix}/{instance_name}' or '{topic_prefix}' without instance name. The prefix defaults to 'ThermoBeacon'.
+    pub fn topic_base(&self) -> String {
+        let prefix = self
+            .mqtt
+            .as_ref()
+            .and_then(|m| m.topic_prefix.as_deref())
+            .unwrap_or("ThermoBeacon");
+        match &self.instance_name {
+            Some(instance) => format!("{}/{}", prefix, instance),
+            None => prefix.to_string(),
+        }
+    }
+
+    /// MQTT state topic of the given device. Defaults to '{topic base}/{name}'.
+    pub fn device_topic(&self, device: &AppDevice) -> String {
+        match &device.topic {
+            Some(topic) => topic.clone(),
+            None => format!("{}/{}", self.topic_base(), device.name),
+        }
+    }
+
+    /// QoS level of the messages of the given device: its own, the global default or 1
+    pub fn device_qos(&self, device: &AppDevice) -> i32 {
+        device
+            .qos
+            .or(self.mqtt.as_ref().and_then(|m| m.default_qos))
+            .unwrap_or(1)
+    }
+
+    /// Are the messages of the given device retained: its own setting, the global default or false
+    pub fn device_retained(&self, device: &AppDevice) -> bool {
+        device
+            .retained
+            .or(self.mqtt.as_ref().and_then(|m| m.default_retained))
+            .unwrap_or(false)
+    }
+
+    /// MQTT 5 user properties of the state messages
+    pub fn user_properties(&self) -> Vec<(String, String)> {
+        self.mqtt
+            .as_ref()
+            .map(|m| {
+                m.user_properties
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// MQTT 5 message expiry of the state messages of the given device, None if they never expire (or the device is only read once)
+    pub fn message_expiry(&self, device: &AppDevice) -> Option<Duration> {
+        match self.mqtt.as_ref()?.message_expiry_interval? {
+            MessageExpiry::Schedule => self.schedule(device)?.period(),
+            MessageExpiry::Interval(interval) => Some(interval),
+        }
+    }
+
+    /// MQTT topic of the button press events of the given device: '{state topic}/button'
+    pub fn button_topic(&self, device: &AppDevice) -> String {
+        format!("{}/button", self.device_topic(device))
+    }
+
+    /// Topic of the readings recovered from the log of the given device: '{state topic}/history'
+    pub fn history_topic(&self, device: &AppDevice) -> String {
+        format!("{}/history", self.device_topic(device))
+    }
+
+    /// Topic announcing the availability ('online' / 'offline') of the given device: '{state topic}/availability'
+    pub fn device_availability_topic(&self, device: &AppDevice) -> String {
+        format!("{}/availability", self.device_topic(device))
+    }
+
+    /// Topic of the battery-low and reboot events of the given device: '{state topic}/event'
+    pub fn device_event_topic(&self, device: &AppDevice) -> String {
+        format!("{}/event", self.device_topic(device))
+    }
+
+    /// Topic of the battery-low state ('ON' / 'OFF') of the given device: '{state topic}/battery_low'
+    pub fn battery_low_topic(&self, device: &AppDevice) -> String {
+        format!("{}/battery_low", self.device_topic(device))
+    }
+
+    /// Topic of the state of the given alert rule for the given device: '{state topic}/alerts/{rule name}'
+    pub fn alert_topic(&self, device: &AppDevice, rule: &str) -> String {
+        format!("{}/alerts/{}", self.device_topic(device), rule)
+    }
+
+    /// Topic announcing the availability ('online' / 'offline') of this gateway: '{topic base}/availability'
+    pub fn availability_topic(&self) -> String {
+        format!("{}/availability", self.topic_base())
+    }
+
+    /// Topic of the commands to this gateway: '{topic base}/gateway/command'
+    pub fn command_topic(&self) -> String {
+        format!("{}/gateway/command", self.topic_base())
+    }
+
+    /// Base topic of the bridge state and info messages of this gateway: '{topic base}/bridge'
+    pub fn bridge_topic(&self) -> String {
+        format!("{}/bridge", self.topic_base())
+    }
+
+    /// Global schedule of all devices without a schedule of their own. An interval takes precedence over the cron expression.
+    pub fn global_schedule(&self) -> Option<Schedule> {
+        match (self.interval, &self.cron) {
+            (Some(interval), _) => Some(Schedule::Interval(interval)),
+            (None, Some(cron)) => Some(Schedule::Cron(cron.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// Schedule of the given device, None if it is only read once
+    pub fn schedule(&self, device: &AppDevice) -> Option<Schedule> {
+        match (device.interval, &device.cron) {
+            (Some(interval), _) => Some(Schedule::Interval(interval)),
+            (None, Some(cron)) => Some(Schedule::Cron(cron.clone())),
+            (None, None) => self.global_schedule(),
+        }
+    }
+
+    /// Checks that the global and the device specific intervals are not zero
+    pub fn validate_intervals(&self) -> Result<(), String> {
+        if self.interval == Some(Duration::ZERO) {
+            return Err("'interval' must not be zero".to_string());
+        }
+        validate_device_intervals(&self.devices)
+    }
+
+    /// Checks that the global and the device specific cron expressions are valid
+    pub fn validate_crons(&self) -> Result<(), String> {
+        if let Some(cron) = &self.cron {
+            if let Err(e) = cron_parser::parse(cron, &Utc::now()) {
+                return Err(format!("Invalid cron expression '{}': {:?}", cron, e));
+            }
+        }
+        validate_device_crons(&self.devices)
+    }
+
+    /// All distinct schedules of the configured devices
+    pub fn schedules(&self) -> Vec<Schedule> {
+        let mut schedules: Vec<Schedule> = vec![];
+        for schedule in self.devices.iter().filter_map(|d| self.schedule(d)) {
+            if !schedules.contains(&schedule) {
+                schedules.push(schedule);
+            }
+        }
+        schedules
+    }
+
+    /// Options of the scans for the configured devices
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            scan_duration: Duration::from_secs(self.seconds_to_scan),
+            device_read_timeout: Duration::from_secs(self.device_read_timeout),
+            samples: self.samples.max(1),
+            aggregation: self.aggregation,
+            device_types: self
+                .devices
+                .iter()
+                .filter_map(|d| Some((d.mac.parse::<BDAddr>().ok()?, d.device_type)))
+                .collect(),
+            models: self
+                .devices
+                .iter()
+                .filter(|d| d.device_type == DeviceType::ThermoBeacon)
+                .filter_map(|d| {
+                    let model = thermobeacon_models::by_name(d.model.as_deref()?)?;
+                    Some((d.mac.parse::<BDAddr>().ok()?, model))
+                })
+                .collect(),
+            adapters: self.adapter.clone(),
+            device_adapters: self
+                .devices
+                .iter()
+                .filter_map(|d| Some((d.mac.parse::<BDAddr>().ok()?, d.adapter.clone()?)))
+                .collect(),
+            poll_interval: self.scan.poll_interval,
+            parameters: ScanParameters {
+                passive: self.scan.passive,
+                duplicate_data: self.scan.duplicate_data,
+                filter: self.scan_filter(),
+            },
+        }
+    }
+
+    /// Advertisements of the configured device types to restrict the scans to. None if the filter is disabled or a configured type can't be filtered.
+    fn scan_filter(&self) -> Option<Vec<AdvertisementId>> {
+        if !self.scan.filter {
+            return None;
+        }
+        let mut filter = vec![];
+        for device in &self.devices {
+            let ids = device.device_type.advertisement_ids();
+            if ids.is_empty() {
+                return None;
+            }
+            for id in ids {
+                if !filter.contains(&id) {
+                    filter.push(id);
+                }
+            }
+        }
+        Some(filter)
+    }
+
+    /// Configured timezone, UTC if invalid
+    pub fn tz(&self) -> chrono_tz::Tz {
+        self.timezone
+            .as_deref()
+            .unwrap_or(DEFAULT_TIMEZONE)
+            .parse()
+            .unwrap_or(chrono_tz::UTC)
+    }
+
+    /// Payload template of the given device, if any
+    pub fn payload_template<'a>(&'a self, device: &'a AppDevice) -> Option<&'a str> {
+        device
+            .payload_template
+            .as_deref()
+            .or(self.payload_template.as_deref())
+    }
+
+    /// Encoding of the messages of the given device: its own or the global format
+    pub fn payload_format(&self, device: &AppDevice) -> PayloadFormat {
+        device.payload_format.unwrap_or(self.payload_format)
+    }
+
+    /// Units of the messages of the given device
+    pub fn units(&self, device: &AppDevice) -> Units {
+        device.units.unwrap_or(self.units)
+    }
+
+    /// Prefix of all unique ids (e.g. for Home Assistant) to avoid collisions between multiple gateways
+    pub fn id_prefix(&self) -> String {
+        match &self.instance_name {
+            Some(instance) => format!("{}_", instance),
+            None => String::new(),
+        }
+    }
 }
 
 fn default_seconds_to_scan() -> u64 {
     45
 }
 
+fn default_device_read_timeout() -> u64 {
+    15
+}
+
+fn default_samples() -> usize {
+    1
+}
+
+fn default_backoff_after_missing_runs() -> u32 {
+    5
+}
+
 /// Timezone assumed if none configured
 pub static DEFAULT_TIMEZONE: &str = "UTC";
 
-/// Read the configuration
-pub fn read_configuration() -> AppConfig {
-    dotenv().ok();
-    let settings = Config::builder()
+/// Sources of the configuration
+fn settings() -> Result<Config, ConfigError> {
+    Config::builder()
         // Add optional file source `./config.yml"
         .add_source(config::File::with_name("config").required(false))
         // Add in settings from the environment (with a prefix of APP)
         // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key.  APP_MQTT_PASSWORD would set mqtt.password key.
         .add_source(config::Environment::with_prefix("APP").separator("_"))
         .build()
-        .unwrap();
+}
+
+/// Checks that the device specific intervals are not zero
+pub fn validate_device_intervals(devices: &[AppDevice]) -> Result<(), String> {
+    match devices.iter().find(|d| d.interval == Some(Duration::ZERO)) {
+        Some(device) => Err(format!(
+            "'interval' of device {} must not be zero",
+            device.name
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the device specific cron expressions are valid
+pub fn validate_device_crons(devices: &[AppDevice]) -> Result<(), String> {
+    for device in devices {
+        if let Some(cron) = &device.cron {
+            if let Err(e) = cron_parser::parse(cron, &Utc::now()) {
+                return Err(format!(
+                    "Invalid cron expression '{}' of device {}: {:?}",
+                    cron, device.name, e
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the device list of the configuration again (e.g. after the configuration file changed). Unlike read_configuration, invalid configurations are returned as error.
+pub fn read_devices() -> Result<Vec<AppDevice>, ConfigError> {
+    match settings()?.get("devices") {
+        Err(ConfigError::NotFound(_)) => Ok(vec![]),
+        result => result,
+    }
+}
+
+/// Read the configuration
+pub fn read_configuration() -> AppConfig {
+    dotenv().ok();
+    let settings = settings().unwrap();
 
     let mut config: AppConfig = settings.try_deserialize().unwrap();
 
@@ -150,6 +1582,221 @@ pub fn read_configuration() -> AppConfig {
         };
     }
 
+    // Check if we have to load the record log key file. If it is present, load its content and place it into the key field of the record log config
+    if config
+        .record_log
+        .as_ref()
+        .map(|c| c.key.is_none() && c.key_file.is_some())
+        .unwrap_or(false)
+    {
+        let record_log_config = config.record_log.unwrap();
+        let file = record_log_config.key_file.as_ref().unwrap();
+
+        config = match std::fs::read_to_string(file) {
+            Ok(key) => AppConfig {
+                record_log: Some(RecordLogConfig {
+                    key: Some(key.trim_end().to_string()),
+                    ..record_log_config
+                }),
+                ..config
+            },
+            Err(e) => {
+                error!(
+                    "record_log.key_file {} configured, but not readable!: {:?}",
+                    file, e
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // Check if we have to load the Icinga API password file
+    if config
+        .icinga
+        .as_ref()
+        .map(|c| c.password.is_none() && c.password_file.is_some())
+        .unwrap_or(false)
+    {
+        let icinga_config = config.icinga.unwrap();
+        let file = icinga_config.password_file.as_ref().unwrap();
+
+        config = match std::fs::read_to_string(file) {
+            Ok(pw) => AppConfig {
+                icinga: Some(IcingaConfig {
+                    password: Some(pw.trim_end().to_string()),
+                    ..icinga_config
+                }),
+                ..config
+            },
+            Err(e) => {
+                error!(
+                    "icinga.password_file {} configured, but not readable!: {:?}",
+                    file, e
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // Load the password files of the named brokers
+    for (name, broker) in config.brokers.iter_mut() {
+        if let (None, Some(file)) = (&broker.password, &broker.password_file) {
+            match std::fs::read_to_string(file) {
+                Ok(pw) => broker.password = Some(pw),
+                Err(e) => {
+                    error!(
+                        "brokers.{}.password_file {} configured, but not readable!: {:?}",
+                        name, file, e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Load the secret files of the outputs
+    for output in config.outputs.iter_mut() {
+        let (secret, file, setting) = match output {
+            OutputConfig::Webhook(webhook) => (
+                &mut webhook.bearer_token,
+                webhook.bearer_token_file.as_ref(),
+                "webhook bearer_token_file",
+            ),
+            OutputConfig::Kafka(KafkaConfig {
+                sasl: Some(sasl), ..
+            }) => (
+                &mut sasl.password,
+                sasl.password_file.as_ref(),
+                "kafka sasl.password_file",
+            ),
+            OutputConfig::Nats(nats) => {
+                (&mut nats.token, nats.token_file.as_ref(), "nats token_file")
+            }
+            _ => continue,
+        };
+        if let (true, Some(file)) = (secret.is_none(), file) {
+            match std::fs::read_to_string(file) {
+                Ok(value) => *secret = Some(value.trim_end().to_string()),
+                Err(e) => {
+                    error!(
+                        "{} {} configured, but not readable!: {:?}",
+                        setting, file, e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Load the secret files of the notifiers
+    if let Some(notifications) = config.notifications.as_mut() {
+        for (name, notifier) in notifications.notifiers.iter_mut() {
+            let (secret, file) = match notifier {
+                NotifierConfig::Ntfy(ntfy) => (&mut ntfy.token, ntfy.token_file.as_ref()),
+                NotifierConfig::Pushover(pushover) => {
+                    (&mut pushover.token, pushover.token_file.as_ref())
+                }
+                NotifierConfig::Telegram(telegram) => {
+                    (&mut telegram.bot_token, telegram.bot_token_file.as_ref())
+                }
+            };
+            if let (true, Some(file)) = (secret.is_none(), file) {
+                match std::fs::read_to_string(file) {
+                    Ok(value) => *secret = Some(value.trim_end().to_string()),
+                    Err(e) => {
+                        error!(
+                            "notifications.notifiers.{} secret file {} configured, but not readable!: {:?}",
+                            name, file, e
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(hub) = config
+        .mqtt
+        .as_mut()
+        .and_then(|mqtt| mqtt.azure_iot_hub.as_mut())
+    {
+        if let (None, Some(file)) = (&hub.shared_access_key, &hub.shared_access_key_file) {
+            match std::fs::read_to_string(file) {
+                Ok(key) => hub.shared_access_key = Some(key.trim_end().to_string()),
+                Err(e) => {
+                    error!(
+                        "azure_iot_hub.shared_access_key_file {} configured, but not readable!: {:?}",
+                        file, e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    if let Some(mqtt) = config.mqtt.as_mut() {
+        if let Err(e) = aws_iot::apply_profile(mqtt) {
+            error!("Invalid aws_iot configuration: {}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = azure_iot::apply_profile(mqtt) {
+            error!("Invalid azure_iot_hub configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = config.validate_intervals() {
+        error!("Invalid schedule: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = config.validate_crons() {
+        error!("Invalid schedule: {}", e);
+        std::process::exit(1);
+    }
+
+    for exception in config.exceptions.iter() {
+        if let Some(cron) = &exception.cron {
+            if let Err(e) = cron_parser::parse(cron, &Utc::now()) {
+                error!(
+                    "Invalid cron expression '{}' of schedule exception {}: {:?}",
+                    cron,
+                    exception.name.as_deref().unwrap_or("(unnamed)"),
+                    e
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let templates = config.payload_template.iter().chain(
+        config
+            .devices
+            .iter()
+            .filter_map(|d| d.payload_template.as_ref()),
+    );
+    if let Err(e) = sink::compile_templates(templates.map(String::as_str)) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(notifications) = &config.notifications {
+        if let Err(e) = notifications::validate(notifications) {
+            error!("Invalid notifications configuration: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = alerts::validate(&config) {
+        error!("Invalid alerts configuration: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = publish_policy::validate(&config) {
+        error!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    }
+
     // Check if timezone for chron is configured. If not, read environment variable TZ. If no value found, use default timezone UTC to set config variable timezone.
     if config.timezone.is_none() {
         let timezone = env::var("TZ").unwrap_or(DEFAULT_TIMEZONE.to_string());
@@ -162,3 +1809,73 @@ pub fn read_configuration() -> AppConfig {
 
     config
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_zero_intervals() {
+        let device = AppDevice {
+            mac: "11:22:33:44:55:66".to_string(),
+            name: "Fridge".to_string(),
+            interval: Some(Duration::from_secs(120)),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            interval: Some(Duration::from_secs(60)),
+            devices: vec![device.clone()],
+            ..Default::default()
+        };
+        assert!(config.validate_intervals().is_ok());
+
+        let global = AppConfig {
+            interval: Some(Duration::ZERO),
+            ..config.clone()
+        };
+        assert!(global.validate_intervals().is_err());
+
+        let per_device = AppConfig {
+            devices: vec![AppDevice {
+                interval: Some(Duration::ZERO),
+                ..device
+            }],
+            ..config
+        };
+        assert_eq!(
+            per_device.validate_intervals(),
+            Err("'interval' of device Fridge must not be zero".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_crons() {
+        let device = AppDevice {
+            mac: "11:22:33:44:55:66".to_string(),
+            name: "Fridge".to_string(),
+            cron: Some("*/10 * * * *".to_string()),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            cron: Some("*/5 * * * *".to_string()),
+            devices: vec![device.clone()],
+            ..Default::default()
+        };
+        assert!(config.validate_crons().is_ok());
+
+        let global = AppConfig {
+            cron: Some("every five minutes".to_string()),
+            ..config.clone()
+        };
+        assert!(global.validate_crons().is_err());
+
+        let per_device = AppConfig {
+            devices: vec![AppDevice {
+                cron: Some("*/70 * * *".to_string()),
+                ..device
+            }],
+            ..config
+        };
+        assert!(per_device.validate_crons().is_err());
+    }
+}
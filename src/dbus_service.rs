@@ -0,0 +1,77 @@
+use std::error::Error;
+
+use crate::configuration::DbusConfig;
+
+/// Object path of the service
+#[cfg(target_os = "linux")]
+static OBJECT_PATH: &str = "/io/github/StefanRichterHuber/ThermoBeacon";
+
+/// Starts the D-Bus service in its own thread. It provides the interface 'io.github.StefanRichterHuber.ThermoBeacon' with the methods
+/// - `GetReadings() -> a(ssddds)`: latest reading of each device (name, MAC, temperature, humidity, battery level, RFC 3339 timestamp)
+/// - `Scan()`: requests an immediate run
+#[cfg(target_os = "linux")]
+pub fn start(config: DbusConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    use crate::{configuration::DbusBus, readings, scan_trigger};
+    use dbus::blocking::Connection;
+    use dbus_crossroads::Crossroads;
+
+    // The connection is not Send, so it is established within the service thread. Setup errors are reported back.
+    let (setup_tx, setup_rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let connection = match config.bus {
+            DbusBus::Session => Connection::new_session(),
+            DbusBus::System => Connection::new_system(),
+        }
+        .and_then(|c| c.request_name(&config.name, false, true, false).map(|_| c));
+        let connection = match connection {
+            Ok(c) => {
+                let _ = setup_tx.send(Ok(()));
+                c
+            }
+            Err(e) => {
+                let _ = setup_tx.send(Err(e.to_string()));
+                return;
+            }
+        };
+
+        let mut cr = Crossroads::new();
+        let iface = cr.register(config.name.clone(), |b| {
+            b.method("GetReadings", (), ("readings",), |_, _, ()| {
+                let readings: Vec<(String, String, f64, f64, f64, String)> = readings::all()
+                    .into_iter()
+                    .map(|r| {
+                        (
+                            r.name,
+                            r.data.mac.to_string(),
+                            r.data.temperature as f64,
+                            r.data.humidity as f64,
+                            r.data.battery_level as f64,
+                            r.timestamp.to_rfc3339(),
+                        )
+                    })
+                    .collect();
+                Ok((readings,))
+            });
+            b.method("Scan", (), (), |_, _, ()| {
+                info!("Immediate run requested over D-Bus");
+                scan_trigger::request();
+                Ok(())
+            });
+        });
+        cr.insert(OBJECT_PATH, &[iface], ());
+        if let Err(e) = cr.serve(&connection) {
+            error!("D-Bus service stopped: {}", e);
+        }
+    });
+
+    setup_rx
+        .recv()?
+        .map_err(|e| format!("Failed to start D-Bus service: {}", e))?;
+    Ok(())
+}
+
+/// D-Bus is only available on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn start(_config: DbusConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Err("The D-Bus service is only available on Linux".into())
+}
@@ -0,0 +1,166 @@
+//! Actions executed on a ThermoBeacon over a GATT connection on request (MQTT command, REST API).
+//! They are queued and executed by the scheduler between its runs, as a connection must not overlap a scan.
+
+use std::{error::Error, fmt, sync::OnceLock, time::Duration};
+
+use btleplug::{api::BDAddr, platform::Manager};
+use chrono::Utc;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+
+use crate::{
+    configuration::{AppConfig, AppDevice},
+    device_info, remote_devices,
+    sensors::DeviceType,
+    thermobeacon_gatt::{self, Connection},
+};
+
+/// Action executed on a ThermoBeacon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceAction {
+    /// Reset the min/max values tracked by the device
+    ResetMinMax,
+    /// Set the clock of the device to the current time of the gateway (in the configured timezone)
+    SyncTime,
+    /// Read the firmware and hardware revision of the device again
+    ReadDeviceInfo,
+}
+
+impl DeviceAction {
+    /// All actions, e.g. for the Home Assistant buttons
+    pub const ALL: [DeviceAction; 3] = [
+        DeviceAction::ResetMinMax,
+        DeviceAction::SyncTime,
+        DeviceAction::ReadDeviceInfo,
+    ];
+
+    /// Name of the action in commands and URLs
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceAction::ResetMinMax => "reset_min_max",
+            DeviceAction::SyncTime => "sync_time",
+            DeviceAction::ReadDeviceInfo => "read_device_info",
+        }
+    }
+
+    /// Name of the action in Home Assistant
+    pub fn title(&self) -> &'static str {
+        match self {
+            DeviceAction::ResetMinMax => "Reset min/max",
+            DeviceAction::SyncTime => "Sync time",
+            DeviceAction::ReadDeviceInfo => "Read device info",
+        }
+    }
+
+    /// Entity category of the button in Home Assistant
+    pub fn entity_category(&self) -> &'static str {
+        match self {
+            DeviceAction::ReadDeviceInfo => "diagnostic",
+            _ => "config",
+        }
+    }
+
+    /// Action of the given name, if any
+    pub fn by_name(name: &str) -> Option<DeviceAction> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
+}
+
+impl fmt::Display for DeviceAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Queued action with the channel for its result
+pub struct Request {
+    mac: BDAddr,
+    action: DeviceAction,
+    result: oneshot::Sender<Result<(), String>>,
+}
+
+/// Queue of the requested actions, only available while the scheduler executes them
+static REQUESTS: OnceLock<UnboundedSender<Request>> = OnceLock::new();
+
+/// Creates the queue of the requested actions. Must be called once by the executor of the actions.
+pub fn start() -> UnboundedReceiver<Request> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if REQUESTS.set(sender).is_err() {
+        panic!("Device actions are executed twice");
+    }
+    receiver
+}
+
+/// Configured ThermoBeacon of the given name or MAC, if any. Only ThermoBeacons support the actions.
+pub fn find_device(config: &AppConfig, device: &str) -> Option<AppDevice> {
+    remote_devices::apply(config)
+        .devices
+        .into_iter()
+        .filter(|d| d.device_type == DeviceType::ThermoBeacon)
+        .find(|d| d.name == device || d.mac.eq_ignore_ascii_case(device))
+}
+
+/// Requests the action on the given device and waits (at most for the given timeout) for its execution
+pub async fn run(mac: BDAddr, action: DeviceAction, timeout: Duration) -> Result<(), String> {
+    let requests = REQUESTS
+        .get()
+        .ok_or("Device actions are only executed with a schedule")?;
+    let (sender, receiver) = oneshot::channel();
+    requests
+        .send(Request {
+            mac,
+            action,
+            result: sender,
+        })
+        .map_err(|e| e.to_string())?;
+    match tokio::time::timeout(timeout, receiver).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("Action not executed within {:?}", timeout)),
+    }
+}
+
+/// Current time in the configured timezone, as shown by the display of the device
+fn local_time(config: &AppConfig) -> u32 {
+    let now = Utc::now().with_timezone(&config.tz()).naive_local();
+    now.and_utc().timestamp().clamp(0, i64::from(u32::MAX)) as u32
+}
+
+async fn execute(
+    config: &AppConfig,
+    manager: &Manager,
+    mac: BDAddr,
+    action: DeviceAction,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let peripheral = thermobeacon_gatt::find_peripheral(manager, mac).await?;
+    let connection = Connection::open(peripheral).await?;
+    let result = match action {
+        DeviceAction::ResetMinMax => connection.reset_min_max().await,
+        DeviceAction::SyncTime => connection.set_time(local_time(config)).await,
+        DeviceAction::ReadDeviceInfo => connection.device_info().await.map(|info| {
+            info!("Read device information of {}: {:?}", mac, info);
+            device_info::set(mac, info);
+        }),
+    };
+    if let Err(e) = connection.disconnect().await {
+        debug!("Failed to disconnect from {}: {}", mac, e);
+    }
+    result
+}
+
+impl Request {
+    /// Executes the action and reports the result to the requester
+    pub async fn execute(self, config: &AppConfig, manager: &Manager) {
+        info!("Executing {} on {}", self.action, self.mac);
+        let result = execute(config, manager, self.mac, self.action)
+            .await
+            .map_err(|e| {
+                warn!("Failed to execute {} on {}: {}", self.action, self.mac, e);
+                e.to_string()
+            });
+        // The requester might have given up waiting
+        let _ = self.result.send(result);
+    }
+}
@@ -0,0 +1,42 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use btleplug::api::BDAddr;
+
+/// Last published availability of each device
+static DEVICE_ONLINE: Mutex<Option<HashMap<BDAddr, bool>>> = Mutex::new(None);
+
+/// Payload of the availability topic of a device found recently
+pub static ONLINE: &str = "online";
+
+/// Payload of the availability topic of a device missing for several runs
+pub static OFFLINE: &str = "offline";
+
+/// Records the availability of a device. Returns true if it has to be published, i.e. it changed or is the first one since start (the retained state on the broker might be outdated).
+pub fn changed(mac: BDAddr, online: bool) -> bool {
+    let mut states = DEVICE_ONLINE.lock().unwrap();
+    let previous = states.get_or_insert_with(HashMap::new).insert(mac, online);
+    previous != Some(online)
+}
+
+/// Forgets the availability of a device, e.g. after a failed publication, so it is published again in the next run
+pub fn reset(mac: BDAddr) {
+    if let Some(states) = DEVICE_ONLINE.lock().unwrap().as_mut() {
+        states.remove(&mac);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishes_first_and_changed_availability() {
+        let mac: BDAddr = "11:22:33:44:55:88".parse().unwrap();
+        assert!(changed(mac, true));
+        assert!(!changed(mac, true));
+        assert!(changed(mac, false));
+        assert!(!changed(mac, false));
+        reset(mac);
+        assert!(changed(mac, false));
+    }
+}
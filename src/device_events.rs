@@ -0,0 +1,140 @@
+//! Battery-low and reboot detection: derived from consecutive readings of each device and published as discrete events and as
+//! the state of a battery-low binary sensor, as the raw battery level and uptime counter make this awkward to do downstream.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use btleplug::api::BDAddr;
+
+/// Percentage points the battery level has to rise above the threshold to clear a low battery, as the level fluctuates with
+/// the temperature
+const BATTERY_HYSTERESIS: f32 = 5.0;
+
+/// Payload of the event published when the battery of a device became low
+pub static BATTERY_LOW_EVENT: &str = r#"{"event_type":"battery_low"}"#;
+
+/// Payload of the event published when the uptime of a device was reset (rebooted, e.g. after a battery change)
+pub static REBOOT_EVENT: &str = r#"{"event_type":"reboot"}"#;
+
+/// Payload of the battery-low topic of a device with a low battery
+pub static ON: &str = "ON";
+
+/// Payload of the battery-low topic of a device with a sufficient battery
+pub static OFF: &str = "OFF";
+
+/// Last published battery state and uptime of a device
+#[derive(Debug, Default)]
+struct DeviceState {
+    battery_low: Option<bool>,
+    uptime: Option<u32>,
+}
+
+/// Last published state of each device
+static DEVICE_STATES: Mutex<Option<HashMap<BDAddr, DeviceState>>> = Mutex::new(None);
+
+/// Changes detected in a reading
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    /// New battery-low state, if it changed or is the first one since start (the retained state on the broker might be outdated)
+    pub battery_low: Option<bool>,
+    /// Payloads of the events of the reading
+    pub events: Vec<&'static str>,
+}
+
+/// Detects the changes of the battery level (below the given threshold in %) and uptime (None if the device does not report it)
+/// of a reading against the last published state. The first reading of a device never raises an event, as the battery might have
+/// been low or the device rebooted before the start. The state is only updated by [`commit`], so the changes of a reading which
+/// could not be published are detected again with the next one.
+pub fn detect(threshold: u8, mac: BDAddr, battery_level: f32, uptime: Option<u32>) -> Changes {
+    let states = DEVICE_STATES.lock().unwrap();
+    let unknown = DeviceState::default();
+    let state = states
+        .as_ref()
+        .and_then(|states| states.get(&mac))
+        .unwrap_or(&unknown);
+    let threshold = f32::from(threshold);
+    let low = match state.battery_low {
+        Some(true) => battery_level < threshold + BATTERY_HYSTERESIS,
+        _ => battery_level < threshold,
+    };
+
+    let mut changes = Changes::default();
+    if state.battery_low != Some(low) {
+        if low && state.battery_low.is_some() {
+            changes.events.push(BATTERY_LOW_EVENT);
+        }
+        changes.battery_low = Some(low);
+    }
+    if let Some(uptime) = uptime {
+        if state.uptime.is_some_and(|previous| uptime < previous) {
+            changes.events.push(REBOOT_EVENT);
+        }
+    }
+    changes
+}
+
+/// Records the published changes and uptime of a reading of the device
+pub fn commit(mac: BDAddr, changes: &Changes, uptime: Option<u32>) {
+    let mut states = DEVICE_STATES.lock().unwrap();
+    let state = states
+        .get_or_insert_with(HashMap::new)
+        .entry(mac)
+        .or_default();
+    if let Some(low) = changes.battery_low {
+        state.battery_low = Some(low);
+    }
+    if let Some(uptime) = uptime {
+        state.uptime = Some(uptime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Detects and commits the changes of a published reading
+    fn published(mac: BDAddr, battery_level: f32, uptime: Option<u32>) -> Changes {
+        let changes = detect(20, mac, battery_level, uptime);
+        commit(mac, &changes, uptime);
+        changes
+    }
+
+    #[test]
+    fn detects_low_battery_and_reboots() {
+        let mac: BDAddr = "11:22:33:44:55:66".parse().unwrap();
+        assert_eq!(
+            published(mac, 50.0, Some(1000)),
+            Changes {
+                battery_low: Some(false),
+                events: vec![],
+            }
+        );
+        assert_eq!(
+            published(mac, 19.0, Some(1100)),
+            Changes {
+                battery_low: Some(true),
+                events: vec![BATTERY_LOW_EVENT],
+            }
+        );
+        // Still low within the hysteresis
+        assert_eq!(published(mac, 22.0, Some(1200)), Changes::default());
+        // New battery
+        assert_eq!(
+            published(mac, 100.0, Some(5)),
+            Changes {
+                battery_low: Some(false),
+                events: vec![REBOOT_EVENT],
+            }
+        );
+    }
+
+    #[test]
+    fn detects_unpublished_changes_again() {
+        let mac: BDAddr = "11:22:33:44:55:77".parse().unwrap();
+        published(mac, 50.0, Some(1000));
+
+        // Publishing the reading failed
+        let changes = detect(20, mac, 10.0, Some(5));
+        assert_eq!(changes.events, vec![BATTERY_LOW_EVENT, REBOOT_EVENT]);
+        assert_eq!(detect(20, mac, 10.0, Some(65)), changes);
+    }
+}
@@ -0,0 +1,99 @@
+//! Firmware and hardware revisions of the ThermoBeacons, read once over a GATT connection (or on demand by the device action)
+//! and announced with the device in the Home Assistant discovery messages and the bridge info.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::mqtt::AsyncClient;
+use btleplug::{api::BDAddr, platform::Manager};
+use tokio::sync::Notify;
+
+use crate::{
+    command,
+    configuration::{AppConfig, AppDevice},
+    sensors::DeviceType,
+    sink::Message,
+    thermobeacon_gatt::{self, Connection, DeviceInfo},
+};
+
+/// Revisions read so far
+static DEVICE_INFO: Mutex<Option<HashMap<BDAddr, DeviceInfo>>> = Mutex::new(None);
+
+/// Signals changed revisions to the task announcing them
+static CHANGED: OnceLock<Notify> = OnceLock::new();
+
+fn changed() -> &'static Notify {
+    CHANGED.get_or_init(Notify::new)
+}
+
+/// Revisions of the given device, if read before
+pub fn get(mac: BDAddr) -> Option<DeviceInfo> {
+    DEVICE_INFO
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|i| i.get(&mac).cloned())
+}
+
+/// Remembers the revisions of the given device and triggers their announcement if they changed
+pub fn set(mac: BDAddr, info: DeviceInfo) {
+    let previous = DEVICE_INFO
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(mac, info.clone());
+    if previous.as_ref() != Some(&info) {
+        changed().notify_one();
+    }
+}
+
+/// Connects to the device and reads its revisions
+async fn read(manager: &Manager, mac: BDAddr) -> Result<DeviceInfo, Box<dyn Error + Send + Sync>> {
+    let peripheral = thermobeacon_gatt::find_peripheral(manager, mac).await?;
+    let connection = Connection::open(peripheral).await?;
+    let result = connection.device_info().await;
+    if let Err(e) = connection.disconnect().await {
+        debug!("Failed to disconnect from {}: {}", mac, e);
+    }
+    result
+}
+
+/// Reads the revisions of all ThermoBeacons found in this run, which were not read before.
+/// Failures are only logged, the read is retried in the next run.
+pub async fn read_missing(devices: &[AppDevice], messages: &[Message], manager: &Manager) {
+    for device in devices
+        .iter()
+        .filter(|d| d.device_type == DeviceType::ThermoBeacon)
+    {
+        let mac = device.mac.parse::<BDAddr>().unwrap();
+        if get(mac).is_some() || !messages.iter().any(|msg| msg.data.mac == mac) {
+            continue;
+        }
+        match read(manager, mac).await {
+            Ok(info) => {
+                info!("Read device information of {}: {:?}", device.name, info);
+                set(mac, info);
+            }
+            Err(e) => warn!(
+                "Failed to read the device information of {}: {}",
+                device.name, e
+            ),
+        }
+    }
+}
+
+/// Announces the devices to Home Assistant again whenever revisions changed
+pub fn start(config: &AppConfig, cli: &AsyncClient) {
+    let config = config.clone();
+    let cli = cli.clone();
+    tokio::spawn(async move {
+        loop {
+            changed().notified().await;
+            debug!("Device information changed, announcing the devices again");
+            command::rediscover(&config, &cli).await;
+        }
+    });
+}
@@ -0,0 +1,77 @@
+use btleplug::platform::Manager;
+use std::error::Error;
+
+use crate::{configuration::AppConfig, thermobeacon_protocol};
+
+/// Formats an optional reading, '-' if not available
+fn format_reading(config: &AppConfig, value: Option<f32>, precision: usize) -> String {
+    match value {
+        Some(v) => config.number_format.format(v, precision),
+        None => "-".to_string(),
+    }
+}
+
+/// Scans for the given time and prints a table of all ThermoBeacons in range, so their MACs can be copied into the configuration
+pub async fn run(
+    config: &AppConfig,
+    manager: &Manager,
+    seconds_to_scan: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    println!("Scanning for ThermoBeacons for {}s ...", seconds_to_scan);
+    let mut beacons = thermobeacon_protocol::discover(manager, seconds_to_scan).await?;
+    if beacons.is_empty() {
+        println!("No ThermoBeacons found");
+        return Ok(());
+    }
+    // Strongest signal first
+    beacons.sort_by_key(|b| std::cmp::Reverse(b.rssi.unwrap_or(i16::MIN)));
+
+    println!(
+        "{:<17}  {:>5}  {:>7}  {:>8}  {:>7}  {:>6}  {:<7}  Configured as",
+        "MAC", "RSSI", "Temp °C", "Humidity", "Battery", "Key", "Model"
+    );
+    for beacon in beacons.iter() {
+        let mac = beacon.mac.to_string();
+        let configured = config
+            .devices
+            .iter()
+            .find(|d| d.mac.eq_ignore_ascii_case(&mac))
+            .map(|d| d.name.as_str())
+            .unwrap_or("-");
+        println!(
+            "{:<17}  {:>5}  {:>7}  {:>8}  {:>7}  {:>#6x}  {:<7}  {}",
+            mac,
+            beacon
+                .rssi
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            format_reading(config, beacon.temperature, 1),
+            format_reading(config, beacon.humidity, 1),
+            format_reading(config, beacon.battery_level, 0),
+            beacon.manufacturer_key,
+            beacon.model,
+            configured
+        );
+    }
+    println!("Readings are '-' if only the min/max data was received during the scan");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number_format::NumberFormat;
+
+    #[test]
+    fn formats_readings_with_the_locale() {
+        let config = AppConfig {
+            number_format: NumberFormat {
+                locale: Some("de_DE".to_string()),
+                decimal_separator: None,
+            },
+            ..Default::default()
+        };
+        assert_eq!(format_reading(&config, Some(21.56), 1), "21,6");
+        assert_eq!(format_reading(&config, None, 1), "-");
+    }
+}
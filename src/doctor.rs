@@ -0,0 +1,360 @@
+use btleplug::api::{BDAddr, Central, CentralState, Manager as _};
+use btleplug::platform::Manager;
+use chrono::Utc;
+
+use crate::{configuration::AppConfig, mqtt::MqttClient, permission_check};
+
+/// Result of a single check
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// A single diagnostic check with its result and an optional remediation hint
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+    hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            outcome: Outcome::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Check {
+            name,
+            outcome: Outcome::Warn,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Check {
+            name,
+            outcome: Outcome::Fail,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+/// Checks the configuration for values that are only detected at runtime
+fn check_configuration(config: &AppConfig) -> Vec<Check> {
+    let mut checks = vec![];
+
+    let invalid: Vec<_> = config
+        .devices
+        .iter()
+        .filter(|d| d.mac.parse::<BDAddr>().is_err())
+        .map(|d| d.mac.as_str())
+        .collect();
+    checks.push(if config.devices.is_empty() {
+        Check::warn(
+            "Devices",
+            "No devices configured",
+            "Add the ThermoBeacons to 'devices' (use a BLE scanner app to find their MACs)",
+        )
+    } else if !invalid.is_empty() {
+        Check::fail(
+            "Devices",
+            format!("Invalid MACs: {}", invalid.join(", ")),
+            "MACs must be formatted like 'xx:xx:xx:xx:xx:xx'",
+        )
+    } else {
+        Check::pass(
+            "Devices",
+            format!("{} devices configured", config.devices.len()),
+        )
+    });
+
+    if let Some(timezone) = &config.timezone {
+        checks.push(match timezone.parse::<chrono_tz::Tz>() {
+            Ok(_) => Check::pass("Timezone", timezone.clone()),
+            Err(e) => Check::fail(
+                "Timezone",
+                format!("{}: {}", timezone, e),
+                "Use an IANA timezone name like 'Europe/Berlin'",
+            ),
+        });
+    }
+
+    let crons = config
+        .cron
+        .iter()
+        .chain(config.exceptions.iter().filter_map(|e| e.cron.as_ref()))
+        .chain(config.devices.iter().filter_map(|d| d.cron.as_ref()));
+    for cron in crons {
+        checks.push(match cron_parser::parse(cron, &Utc::now()) {
+            Ok(next) => Check::pass("Schedule", format!("'{}', next run {}", cron, next)),
+            Err(e) => Check::fail(
+                "Schedule",
+                format!("'{}': {:?}", cron, e),
+                "Use a five field CRON expression like '*/5 * * * *'",
+            ),
+        });
+    }
+
+    let templates = config.payload_template.iter().chain(
+        config
+            .devices
+            .iter()
+            .filter_map(|d| d.payload_template.as_ref()),
+    );
+    for template in templates {
+        checks.push(
+            match minijinja::Environment::new().template_from_str(template) {
+                Ok(_) => Check::pass("Payload template", "Valid"),
+                Err(e) => Check::fail(
+                    "Payload template",
+                    format!("'{}': {}", template, e),
+                    "Check the template syntax, see https://docs.rs/minijinja",
+                ),
+            },
+        );
+    }
+
+    let homeassistant = config
+        .mqtt
+        .as_ref()
+        .map(|m| m.homeassistant)
+        .unwrap_or(false);
+    let binary = config
+        .devices
+        .iter()
+        .map(|d| config.payload_format(d))
+        .chain([config.payload_format])
+        .find(|format| format.is_binary());
+    if let (true, Some(format)) = (homeassistant, binary) {
+        checks.push(Check::warn(
+            "Payload format",
+            format!("{:?} with Home Assistant auto-discovery", format),
+            "Home Assistant only reads JSON state messages, use 'payload_format: json' or 'publish_mode: per_field'",
+        ));
+    }
+    checks
+}
+
+/// Checks the rfkill state of the Bluetooth devices
+#[cfg(target_os = "linux")]
+fn check_rfkill() -> Vec<Check> {
+    let read = |path: std::path::PathBuf| {
+        std::fs::read_to_string(path)
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    let entries = match std::fs::read_dir("/sys/class/rfkill") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|path| read(path.join("type")) == "bluetooth")
+        .map(|path| {
+            let name = read(path.join("name"));
+            if read(path.join("hard")) == "1" {
+                Check::fail(
+                    "rfkill",
+                    format!("{} is blocked by a hardware switch", name),
+                    "Enable Bluetooth with the hardware switch of the device",
+                )
+            } else if read(path.join("soft")) == "1" {
+                Check::fail(
+                    "rfkill",
+                    format!("{} is soft blocked", name),
+                    "Run 'rfkill unblock bluetooth'",
+                )
+            } else {
+                Check::pass("rfkill", format!("{} is not blocked", name))
+            }
+        })
+        .collect()
+}
+
+/// rfkill is only available on Linux
+#[cfg(not(target_os = "linux"))]
+fn check_rfkill() -> Vec<Check> {
+    vec![]
+}
+
+/// Checks if BlueZ is reachable on the system D-Bus
+#[cfg(target_os = "linux")]
+fn check_dbus() -> Check {
+    use dbus::blocking::Connection;
+    use std::time::Duration;
+
+    let connection = match Connection::new_system() {
+        Ok(c) => c,
+        Err(e) => {
+            return Check::fail(
+                "D-Bus",
+                format!("Failed to connect to the system bus: {}", e),
+                "Mount /run/dbus into the container or start the dbus service",
+            )
+        }
+    };
+    let proxy = connection.with_proxy("org.bluez", "/", Duration::from_secs(5));
+    let result: Result<(), dbus::Error> =
+        proxy.method_call("org.freedesktop.DBus.Peer", "Ping", ());
+    match result {
+        Ok(()) => Check::pass("D-Bus", "BlueZ is reachable on the system bus"),
+        Err(e) => Check::fail(
+            "D-Bus",
+            format!("BlueZ is not reachable: {}", e),
+            "Start the bluetooth service and check the D-Bus policy of BlueZ (/etc/dbus-1/system.d/bluetooth.conf) for this user",
+        ),
+    }
+}
+
+/// BlueZ is only used on Linux
+#[cfg(not(target_os = "linux"))]
+fn check_dbus() -> Check {
+    Check::pass("D-Bus", "Not required on this platform")
+}
+
+/// Checks if a Bluetooth adapter is present and powered
+async fn check_adapter() -> Check {
+    let manager = match Manager::new().await {
+        Ok(m) => m,
+        Err(e) => {
+            return Check::fail(
+                "Bluetooth adapter",
+                format!("Bluetooth not available: {}", e),
+                "Check the D-Bus and rfkill results above",
+            )
+        }
+    };
+    let adapters = match manager.adapters().await {
+        Ok(adapters) => adapters,
+        Err(e) => {
+            return Check::fail(
+                "Bluetooth adapter",
+                format!("Failed to list adapters: {}", e),
+                "Check the D-Bus and rfkill results above",
+            )
+        }
+    };
+    let adapter = match adapters.first() {
+        Some(adapter) => adapter,
+        None => {
+            return Check::fail(
+                "Bluetooth adapter",
+                "No adapter found",
+                "Connect a Bluetooth adapter (or pass it into the container) and check 'bluetoothctl list'",
+            )
+        }
+    };
+    let info = adapter.adapter_info().await.unwrap_or_default();
+    match adapter.adapter_state().await {
+        Ok(CentralState::PoweredOn) => {
+            Check::pass("Bluetooth adapter", format!("{} is powered on", info))
+        }
+        Ok(CentralState::PoweredOff) => Check::fail(
+            "Bluetooth adapter",
+            format!("{} is powered off", info),
+            "Run 'bluetoothctl power on'",
+        ),
+        Ok(CentralState::Unknown) | Err(_) => Check::warn(
+            "Bluetooth adapter",
+            format!("{}: power state unknown", info),
+            "Check 'bluetoothctl show'",
+        ),
+    }
+}
+
+/// Checks if the MQTT broker is reachable and permits publishing to all topics
+async fn check_mqtt(config: &AppConfig) -> Vec<Check> {
+    let mqtt_config = match &config.mqtt {
+        Some(c) => c,
+        None => return vec![Check::pass("MQTT", "Not configured, readings are printed")],
+    };
+    if mqtt_config.url.is_none() {
+        return vec![Check::fail(
+            "MQTT",
+            "No URL configured",
+            "Set 'mqtt.url', e.g. 'tcp://localhost:1883'",
+        )];
+    }
+    let cli = match crate::connect_to_mqtt(mqtt_config, None).await {
+        Ok(cli) => cli,
+        Err(e) => {
+            return vec![Check::fail(
+                "MQTT",
+                format!("Failed to connect: {}", e),
+                "Check the URL, the credentials and if the broker is reachable from this host",
+            )]
+        }
+    };
+
+    let server = cli
+        .active_server()
+        .or_else(|| mqtt_config.url.clone())
+        .unwrap_or_default();
+    let mut checks = vec![Check::pass("MQTT", format!("Connected to {}", server))];
+    let denied = permission_check::check_publish_permissions(config, &cli).await;
+    let discovery_denied = denied.iter().any(|t| t.starts_with("homeassistant/"));
+    let state_denied: Vec<_> = denied
+        .iter()
+        .filter(|t| !t.starts_with("homeassistant/"))
+        .cloned()
+        .collect();
+    checks.push(if state_denied.is_empty() {
+        Check::pass("MQTT ACL", "Publishing to all state topics permitted")
+    } else {
+        Check::fail(
+            "MQTT ACL",
+            format!("Publishing denied to {}", state_denied.join(", ")),
+            "Grant write access to these topics in the ACL of the broker (denials are only reported by MQTT 5 brokers)",
+        )
+    });
+    if mqtt_config.homeassistant {
+        checks.push(if discovery_denied {
+            Check::fail(
+                "Home Assistant",
+                "Publishing to the discovery prefix 'homeassistant/' denied",
+                "Grant write access to 'homeassistant/#' in the ACL of the broker",
+            )
+        } else {
+            Check::pass(
+                "Home Assistant",
+                "Publishing to the discovery prefix 'homeassistant/' permitted",
+            )
+        });
+    }
+    let _ = cli.disconnect().await;
+    checks
+}
+
+/// Checks the whole environment and prints a report. Returns true if no check failed.
+pub async fn run(config: &AppConfig) -> bool {
+    let mut checks = check_configuration(config);
+    checks.extend(check_rfkill());
+    checks.push(check_dbus());
+    checks.push(check_adapter().await);
+    checks.extend(check_mqtt(config).await);
+
+    for check in checks.iter() {
+        let label = match check.outcome {
+            Outcome::Pass => "PASS",
+            Outcome::Warn => "WARN",
+            Outcome::Fail => "FAIL",
+        };
+        println!("[{}] {}: {}", label, check.name, check.detail);
+        if let Some(hint) = check.hint {
+            println!("       -> {}", hint);
+        }
+    }
+    !checks.iter().any(|c| c.outcome == Outcome::Fail)
+}
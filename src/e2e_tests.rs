@@ -0,0 +1,244 @@
+//! End-to-end tests against an embedded MQTT broker (rumqttd). Only built with the `e2e` feature: `cargo test --features e2e`
+//! They cover publishing, Home Assistant discovery, availability, leader election and the remote device list. Reading devices
+//! is covered by the tests of the protocol against a mocked scanner.
+
+use std::{
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use btleplug::api::BDAddr;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    configuration::{AppConfig, AppDevice, LeaderElectionConfig, MqttConfig},
+    connect_to_mqtt, homeassistant,
+    leader_election::LeaderElection,
+    mqtt_router::MessageRouter,
+    remote_devices,
+    sink::{MqttSink, Sink},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Time to wait for an expected message
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts an embedded MQTT 5 broker on a free local port and returns the port
+fn start_broker() -> u16 {
+    // Find a free port
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let broker_config = format!(
+        r#"
+        id = 0
+
+        [router]
+        id = 0
+        max_connections = 100
+        max_outgoing_packet_count = 200
+        max_segment_size = 104857600
+        max_segment_count = 10
+
+        [v5.1]
+        name = "v5-1"
+        listen = "127.0.0.1:{}"
+        next_connection_delay_ms = 1
+
+        [v5.1.connections]
+        connection_timeout_ms = 60000
+        max_payload_size = 20480
+        max_inflight_count = 100
+        "#,
+        port
+    );
+    let broker_config: rumqttd::Config = config::Config::builder()
+        .add_source(config::File::from_str(
+            &broker_config,
+            config::FileFormat::Toml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    std::thread::spawn(move || {
+        let mut broker = rumqttd::Broker::new(broker_config);
+        broker.start().unwrap();
+    });
+
+    // Wait until the broker accepts connections
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return port;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    panic!("Embedded MQTT broker did not start");
+}
+
+fn mqtt_config(port: u16) -> MqttConfig {
+    MqttConfig {
+        url: Some(format!("tcp://127.0.0.1:{}", port)),
+        keep_alive: 60,
+        clean_start: true,
+        ..Default::default()
+    }
+}
+
+fn device() -> AppDevice {
+    AppDevice {
+        mac: "11:22:33:44:55:66".to_string(),
+        name: "Basement".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Connects a client to the embedded broker and subscribes to the given topic filter
+async fn subscriber(port: u16, filter: &str) -> (AsyncClient, UnboundedReceiver<mqtt::Message>) {
+    let cli = connect_to_mqtt(&mqtt_config(port), None).await.unwrap();
+    let router = MessageRouter::new(&cli);
+    let receiver = router.route(filter);
+    cli.subscribe(filter, 1).await.unwrap();
+    (cli, receiver)
+}
+
+async fn receive(receiver: &mut UnboundedReceiver<mqtt::Message>) -> mqtt::Message {
+    tokio::time::timeout(RECEIVE_TIMEOUT, receiver.recv())
+        .await
+        .expect("No message received")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn publishes_readings_to_device_topic() {
+    let port = start_broker();
+    let (_subscriber, mut receiver) = subscriber(port, "ThermoBeacon/#").await;
+
+    let config = AppConfig {
+        devices: vec![device()],
+        mqtt: Some(mqtt_config(port)),
+        ..Default::default()
+    };
+    let publisher = connect_to_mqtt(config.mqtt.as_ref().unwrap(), None)
+        .await
+        .unwrap();
+    let data = ThermoBeaconFullReadResult {
+        temperature: 21.5,
+        humidity: 45.0,
+        battery_level: 100.0,
+        mac: "11:22:33:44:55:66".parse::<BDAddr>().unwrap(),
+        ..Default::default()
+    };
+    MqttSink::new(publisher, &config)
+        .publish(&data, &config.devices[0])
+        .await
+        .unwrap();
+
+    let received = receive(&mut receiver).await;
+    assert_eq!(received.topic(), "ThermoBeacon/Basement");
+    let payload: serde_json::Value = serde_json::from_slice(received.payload()).unwrap();
+    assert_eq!(payload["name"], "Basement");
+    assert_eq!(payload["data"]["temperature"], 21.5);
+}
+
+#[tokio::test]
+async fn publishes_homeassistant_discovery() {
+    let port = start_broker();
+    let (_subscriber, mut receiver) = subscriber(port, "homeassistant/#").await;
+
+    let config = AppConfig {
+        devices: vec![device()],
+        ..Default::default()
+    };
+    let publisher = connect_to_mqtt(&mqtt_config(port), None).await.unwrap();
+    homeassistant::publish_homeassistant_device_discovery_messages(&config, &publisher, None)
+        .await
+        .unwrap();
+
+    let mut topics = vec![];
+    for _ in 0..13 {
+        topics.push(receive(&mut receiver).await.topic().to_string());
+    }
+    topics.sort();
+    assert_eq!(
+        topics,
+        vec![
+            "homeassistant/binary_sensor/thermobeacon/11_22_33_44_55_66_button/config",
+            "homeassistant/device_automation/thermobeacon/11_22_33_44_55_66_button_press/config",
+            "homeassistant/event/thermobeacon/11_22_33_44_55_66_button_press/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_battery/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_humidity/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_last_seen/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_max_temperature/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_max_temperature_time/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_min_temperature/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_min_temperature_time/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_rssi/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_temperature/config",
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_uptime/config",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn announces_availability() {
+    let port = start_broker();
+    let topic = "ThermoBeacon/availability";
+    let _publisher = connect_to_mqtt(&mqtt_config(port), Some(topic))
+        .await
+        .unwrap();
+
+    // Retained, so a late subscriber still receives it
+    let (_subscriber, mut receiver) = subscriber(port, topic).await;
+    let received = receive(&mut receiver).await;
+    assert!(received.retained());
+    assert_eq!(received.payload_str(), "online");
+}
+
+#[tokio::test]
+async fn single_node_becomes_leader() {
+    let port = start_broker();
+    let cli = connect_to_mqtt(&mqtt_config(port), None).await.unwrap();
+    let router = MessageRouter::new(&cli);
+
+    let election = LeaderElection::new(
+        "node-a".to_string(),
+        LeaderElectionConfig {
+            topic: "ThermoBeacon/leader".to_string(),
+            node_id: None,
+            heartbeat_interval: 1,
+            heartbeat_timeout: 2,
+        },
+    );
+    election.start(cli.clone(), &router);
+    assert!(!election.is_leader());
+
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    assert!(election.is_leader());
+}
+
+#[tokio::test]
+async fn reads_retained_device_list() {
+    let port = start_broker();
+    let topic = "ThermoBeacon/devices";
+
+    let publisher = connect_to_mqtt(&mqtt_config(port), None).await.unwrap();
+    let devices = r#"[{"mac": "11:22:33:44:55:66", "name": "Attic"}]"#;
+    publisher
+        .publish(mqtt::Message::new_retained(topic, devices, 1))
+        .await
+        .unwrap();
+
+    let cli = connect_to_mqtt(&mqtt_config(port), None).await.unwrap();
+    let router = MessageRouter::new(&cli);
+    remote_devices::start(&AppConfig::default(), &cli, &router, topic).await;
+
+    let devices = remote_devices::current().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name, "Attic");
+}
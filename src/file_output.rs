@@ -0,0 +1,278 @@
+//! File output: appends the readings to a local CSV or NDJSON file (e.g. for air-gapped sites, collected by USB stick).
+//! The file is rotated by size and / or daily, rotated files are named after the day of their last reading and optionally gzipped.
+
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{
+    configuration::{AppConfig, AppDevice, FileConfig, FileFormat},
+    number_format::NumberFormat,
+    sink::{Message, Sink},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Columns of the CSV files
+const CSV_COLUMNS: [&str; 10] = [
+    "measured_at",
+    "name",
+    "mac",
+    "temperature",
+    "humidity",
+    "battery_level",
+    "uptime",
+    "max_temperature",
+    "min_temperature",
+    "rssi",
+];
+
+/// Quotes a CSV field containing the separator, quotes or line breaks
+fn csv_field(value: &str, separator: char) -> String {
+    if value.contains([separator, '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Header line of the CSV files
+fn csv_header(number_format: &NumberFormat) -> String {
+    let separator = number_format.field_separator();
+    format!("{}\n", CSV_COLUMNS.join(&separator.to_string()))
+}
+
+/// CSV line of the message, numbers formatted with the decimal separator of the number format. Missing values are empty.
+fn csv_line(msg: &Message, number_format: &NumberFormat) -> String {
+    let separator = number_format.field_separator();
+    let data = &msg.data;
+    let optional = |value: Option<f32>, precision| {
+        value
+            .map(|v| number_format.format(v, precision))
+            .unwrap_or_default()
+    };
+    let fields = [
+        msg.measured_at.clone(),
+        msg.name.clone(),
+        data.mac.to_string(),
+        number_format.format(data.temperature, 2),
+        number_format.format(data.humidity, 2),
+        number_format.format(data.battery_level, 0),
+        data.uptime.to_string(),
+        optional(data.max_temperature, 2),
+        optional(data.min_temperature, 2),
+        data.rssi.map(|r| r.to_string()).unwrap_or_default(),
+    ];
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| csv_field(field, separator))
+        .collect();
+    format!("{}\n", fields.join(&separator.to_string()))
+}
+
+/// Path of a rotated file: the day of its last reading inserted before the extension, and a counter if that name is taken
+/// ('readings.csv' -> 'readings-2024-05-01.csv', 'readings-2024-05-01-1.csv', ...)
+fn rotated_path(path: &Path, day: NaiveDate, gzip: bool) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let gz = if gzip { ".gz" } else { "" };
+    let mut counter = 0;
+    loop {
+        let suffix = match counter {
+            0 => String::new(),
+            n => format!("-{}", n),
+        };
+        let candidate =
+            path.with_file_name(format!("{}-{}{}{}{}", stem, day, suffix, extension, gz));
+        if !candidate.exists() {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Compresses the file with gzip and removes the uncompressed file
+fn gzip_file(from: &Path, to: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    std::io::copy(&mut BufReader::new(File::open(from)?), &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Appends the readings to a CSV or NDJSON file
+pub struct FileSink {
+    writer: Arc<FileWriter>,
+}
+
+/// Blocking file operations of the sink, run with `spawn_blocking`
+struct FileWriter {
+    config: AppConfig,
+    file: FileConfig,
+    /// Serializes the writes (and rotations) of the readings of parallel runs
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new(config: &AppConfig, file: &FileConfig) -> Self {
+        FileSink {
+            writer: Arc::new(FileWriter {
+                config: config.clone(),
+                file: file.clone(),
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Appends the message to the file without blocking the runtime (rotation and gzip may take a while)
+    async fn write(&self, msg: Message) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let writer = self.writer.clone();
+        tokio::task::spawn_blocking(move || writer.append(&msg)).await?
+    }
+}
+
+impl FileWriter {
+    /// Rotates the file if it exceeds the maximum size or (with daily rotation) was last written on another day
+    fn rotate_if_due(
+        &self,
+        path: &Path,
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return Ok(());
+        };
+        let tz = self.config.tz();
+        let last_day = DateTime::<Utc>::from(metadata.modified()?)
+            .with_timezone(&tz)
+            .date_naive();
+        let too_large = self
+            .file
+            .max_size
+            .is_some_and(|max_size| metadata.len() >= max_size);
+        let new_day = self.file.daily && last_day != now.with_timezone(&tz).date_naive();
+        if !too_large && !new_day {
+            return Ok(());
+        }
+
+        let rotated = rotated_path(path, last_day, self.file.gzip);
+        debug!("Rotating {} to {}", path.display(), rotated.display());
+        if self.file.gzip {
+            gzip_file(path, &rotated)
+        } else {
+            std::fs::rename(path, &rotated)?;
+            Ok(())
+        }
+    }
+
+    /// Appends the message to the file, rotating it first if due. New CSV files start with the header.
+    fn append(&self, msg: &Message) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _guard = self.lock.lock().unwrap();
+        let path = Path::new(&self.file.path);
+        self.rotate_if_due(path, Utc::now())?;
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut content = String::new();
+        match self.file.format {
+            FileFormat::Csv => {
+                if file.metadata()?.len() == 0 {
+                    content.push_str(&csv_header(&self.config.number_format));
+                }
+                content.push_str(&csv_line(msg, &self.config.number_format));
+            }
+            FileFormat::Ndjson => {
+                content.push_str(&serde_json::to_string(msg)?);
+                content.push('\n');
+            }
+        }
+        // One write per reading, so readers of the file (e.g. tail -f) see complete lines
+        file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::new(&self.writer.config, device, data.clone());
+        self.write(msg).await
+    }
+
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::at(&self.writer.config, device, data.clone(), measured_at);
+        self.write(msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_csv_lines() {
+        let msg = Message {
+            data: ThermoBeaconFullReadResult {
+                temperature: 21.5625,
+                humidity: 45.0,
+                battery_level: 90.0,
+                uptime: 42,
+                min_temperature: Some(-1.5),
+                ..Default::default()
+            },
+            name: "Kitchen; north".to_string(),
+            measured_at: "2024-05-01T12:00:00+02:00".to_string(),
+            ..Default::default()
+        };
+        let german = NumberFormat {
+            locale: Some("de_DE".to_string()),
+            decimal_separator: None,
+        };
+        assert_eq!(
+            csv_line(&msg, &german),
+            "2024-05-01T12:00:00+02:00;\"Kitchen; north\";00:00:00:00:00:00;21,56;45,00;90;42;;-1,50;\n"
+        );
+        assert!(csv_header(&german).starts_with("measured_at;name;mac;"));
+    }
+
+    #[test]
+    fn names_rotated_files_after_the_day() {
+        let dir = std::env::temp_dir().join(format!("thermobeacon-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("readings.csv");
+        let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+
+        let rotated = rotated_path(&path, day, true);
+        assert_eq!(rotated, dir.join("readings-2024-05-01.csv.gz"));
+        std::fs::write(&rotated, b"").unwrap();
+        assert_eq!(
+            rotated_path(&path, day, true),
+            dir.join("readings-2024-05-01-1.csv.gz")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+//! Decoder for the advertisements of Govee H5072 / H5075 hygrometers
+//!
+//! Message length: 6 bytes (manufacturer data of the company id 0xEC88)
+//! bytes | content
+//! ========================================================
+//! 00-00 | 00 ?
+//! 01-03 | temperature and humidity (big endian): temperature * 10000 + humidity * 10, bit 23 is set for negative temperatures
+//! 04-04 | battery level (0 - 100%)
+//! 05-05 | 00 ?
+
+use btleplug::api::BDAddr;
+use std::error::Error;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// Key of the manufacturer data (company id) of the Govee advertisements
+pub const MANUFACTURER_KEY: u16 = 0xEC88;
+
+/// Decodes the manufacturer data of a Govee H5072 / H5075 advertisement. The advertisement does not contain the MAC, so it has to be given.
+pub fn parse(
+    mac: BDAddr,
+    data: &[u8],
+) -> Result<ThermoBeaconFullReadResult, Box<dyn Error + Send + Sync>> {
+    if data.len() != 6 {
+        return Err(format!("Unknown Govee frame length {}", data.len()).into());
+    }
+    let packet = u32::from_be_bytes([0, data[1], data[2], data[3]]);
+    let negative = packet & 0x80_0000 != 0;
+    let packet = packet & 0x7F_FFFF;
+    let temperature = (packet / 1000) as f32 / 10.0;
+
+    Ok(ThermoBeaconFullReadResult {
+        temperature: if negative { -temperature } else { temperature },
+        humidity: (packet % 1000) as f32 / 10.0,
+        battery_level: f32::from(data[4]),
+        mac,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_positive_and_negative_temperatures() {
+        let mac: BDAddr = "a4:c1:38:00:00:01".parse().unwrap();
+        // 21.5 °C, 45.3 %
+        let reading = parse(mac, &[0x00, 0x03, 0x49, 0x9D, 0x5A, 0x00]).unwrap();
+        assert_eq!(reading.temperature, 21.5);
+        assert_eq!(reading.humidity, 45.3);
+        assert_eq!(reading.battery_level, 90.0);
+        assert_eq!(reading.mac, mac);
+
+        // -5.2 °C, 80.0 %
+        let reading = parse(mac, &[0x00, 0x80, 0xCE, 0x40, 0x64, 0x00]).unwrap();
+        assert_eq!(reading.temperature, -5.2);
+        assert_eq!(reading.humidity, 80.0);
+
+        assert!(parse(mac, &[0x00, 0x03, 0x4A]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{error::Error, net::UdpSocket, time::Duration};
+
+use chrono::Utc;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+use crate::{
+    configuration::{GraphiteConfig, StatsdConfig},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Timeout for the whole exchange with the Graphite server
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Replaces all characters but letters, digits, '-' and '_', since '.' separates the path and ':', '|', '@' are part of the StatsD syntax
+fn path_segment(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Converts a reading into metrics with the paths '{prefix}.[{instance}.]{device name}.{metric}'
+fn to_metrics(
+    prefix: &str,
+    instance: Option<&str>,
+    name: &str,
+    data: &ThermoBeaconFullReadResult,
+) -> Vec<(String, String)> {
+    let base = match instance {
+        Some(instance) => format!(
+            "{}.{}.{}",
+            prefix,
+            path_segment(instance),
+            path_segment(name)
+        ),
+        None => format!("{}.{}", prefix, path_segment(name)),
+    };
+    // The min/max data is missing in partial results
+    let metrics = [
+        ("temperature", Some(data.temperature.to_string())),
+        ("humidity", Some(data.humidity.to_string())),
+        ("battery_level", Some(data.battery_level.to_string())),
+        (
+            "max_temperature",
+            data.max_temperature.map(|t| t.to_string()),
+        ),
+        (
+            "min_temperature",
+            data.min_temperature.map(|t| t.to_string()),
+        ),
+        ("uptime", Some(data.uptime.to_string())),
+        ("rssi", data.rssi.map(|r| r.to_string())),
+    ];
+    metrics
+        .into_iter()
+        .filter_map(|(metric, value)| value.map(|v| (format!("{}.{}", base, metric), v)))
+        .collect()
+}
+
+/// Sends the readings (name of the device and data) in the plaintext protocol to the Graphite server (carbon)
+pub async fn send<'a>(
+    config: &GraphiteConfig,
+    instance: Option<&str>,
+    readings: impl Iterator<Item = (&'a str, &'a ThermoBeaconFullReadResult)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let timestamp = Utc::now().timestamp();
+    let lines: String = readings
+        .flat_map(|(name, data)| to_metrics(&config.prefix, instance, name, data))
+        .map(|(path, value)| format!("{} {} {}\n", path, value, timestamp))
+        .collect();
+    if lines.is_empty() {
+        return Ok(());
+    }
+    trace!("Sending to Graphite:\n{}", lines);
+
+    tokio::time::timeout(TIMEOUT, async {
+        let mut stream = TcpStream::connect((config.host.as_str(), config.port)).await?;
+        stream.write_all(lines.as_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await
+    .map_err(|_| "Timeout while sending metrics to Graphite")??;
+    Ok(())
+}
+
+/// Sends the readings as StatsD gauges, one datagram per reading. Delivery is not confirmed.
+pub fn send_statsd<'a>(
+    config: &StatsdConfig,
+    instance: Option<&str>,
+    readings: impl Iterator<Item = (&'a str, &'a ThermoBeaconFullReadResult)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((config.host.as_str(), config.port))?;
+
+    for (name, data) in readings {
+        let gauges: Vec<String> = to_metrics(&config.prefix, instance, name, data)
+            .into_iter()
+            .map(|(path, value)| format!("{}:{}|g", path, value))
+            .collect();
+        let packet = gauges.join("\n");
+        trace!("Sending StatsD gauges {}", packet);
+        socket.send(packet.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_metric_paths() {
+        let data = ThermoBeaconFullReadResult {
+            temperature: -2.5,
+            humidity: 40.0,
+            battery_level: 90.0,
+            uptime: 42,
+            rssi: Some(-70),
+            ..Default::default()
+        };
+        let metrics = to_metrics("thermobeacon", Some("site a"), "Freezer.top", &data);
+        assert_eq!(
+            metrics,
+            vec![
+                (
+                    "thermobeacon.site_a.Freezer_top.temperature".to_string(),
+                    "-2.5".to_string()
+                ),
+                (
+                    "thermobeacon.site_a.Freezer_top.humidity".to_string(),
+                    "40".to_string()
+                ),
+                (
+                    "thermobeacon.site_a.Freezer_top.battery_level".to_string(),
+                    "90".to_string()
+                ),
+                (
+                    "thermobeacon.site_a.Freezer_top.uptime".to_string(),
+                    "42".to_string()
+                ),
+                (
+                    "thermobeacon.site_a.Freezer_top.rssi".to_string(),
+                    "-70".to_string()
+                ),
+            ]
+        );
+    }
+}
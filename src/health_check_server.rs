@@ -1,55 +1,243 @@
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
 use serde_derive::Serialize;
 
-use std::{error::Error, sync::Mutex};
+use crate::{
+    clock, configuration::AppConfig, readings, remote_devices, rest_api,
+    scanner::AdapterUnavailable,
+};
+
+use btleplug::api::BDAddr;
+use chrono::{DateTime, Utc};
+use std::{
+    error::Error,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
 
 #[derive(Serialize)]
 pub struct Response {
     pub message: String,
+    /// Name of the gateway instance, if configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Last measured publish -> receive latency of the MQTT broker in ms, if measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt_latency_ms: Option<u128>,
+    /// URL of the MQTT server the gateway is connected to, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt_server: Option<String>,
+    /// Last read of each configured device
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceStatus>,
+    /// Adapters whose scan failed in the last run
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_adapters: Vec<String>,
+}
+
+/// Last successful read of a configured device
+#[derive(Serialize)]
+pub struct DeviceStatus {
+    pub name: String,
+    pub mac: String,
+    /// Time of the last reading (RFC 3339), None if not read since start
+    pub last_read: Option<String>,
 }
 
 pub enum HealthStatus {
     WaitingForFirstRun,
     LastRunFailed(String),
+    /// The last run failed, because no Bluetooth adapter could scan (e.g. powered off or blocked by rfkill)
+    AdapterUnavailable(String),
     Ok,
 }
 
+impl HealthStatus {
+    /// Status of a run failed with the given error
+    pub fn failed(e: &(dyn Error + Send + Sync)) -> Self {
+        match e.downcast_ref::<AdapterUnavailable>() {
+            Some(e) => HealthStatus::AdapterUnavailable(e.to_string()),
+            None => HealthStatus::LastRunFailed(e.to_string()),
+        }
+    }
+}
+
 /// Global flag for current health status
 static SYSTEM_STATUS: Mutex<HealthStatus> = Mutex::new(HealthStatus::WaitingForFirstRun);
 
-#[get("/health")]
-async fn healthcheck() -> impl Responder {
+/// Last measured round-trip latency of the MQTT broker
+static MQTT_LATENCY: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// URL of the MQTT server of the current connection
+static MQTT_SERVER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Topics the MQTT broker denied publishing to during the startup permission check
+static PERMISSION_DENIALS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Adapters whose scan failed in the last run, while other adapters still scanned
+static FAILED_ADAPTERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Start of the health check server, the reference for the data age until the first reading
+static STARTED: OnceLock<DateTime<Utc>> = OnceLock::new();
+
+/// Last read of each configured device
+fn device_status(config: &AppConfig) -> Vec<DeviceStatus> {
+    remote_devices::apply(config)
+        .devices
+        .into_iter()
+        .map(|device| {
+            let last_read = device
+                .mac
+                .parse::<BDAddr>()
+                .ok()
+                .and_then(readings::get)
+                .map(|r| r.timestamp.to_rfc3339());
+            DeviceStatus {
+                name: device.name,
+                mac: device.mac,
+                last_read,
+            }
+        })
+        .collect()
+}
+
+/// Returns the age of the newest reading (or the time since start without any reading), if it exceeds the given maximum
+fn stale_data_age(max_data_age: Duration) -> Option<Duration> {
+    let newest = readings::all()
+        .into_iter()
+        .map(|r| r.timestamp)
+        .max()
+        .or(STARTED.get().copied())?;
+    let age = (Utc::now() - newest).to_std().unwrap_or_default();
+    (age > max_data_age).then_some(age)
+}
+
+async fn healthcheck(State(config): State<Arc<AppConfig>>) -> (StatusCode, Json<Response>) {
     let status = SYSTEM_STATUS.lock().unwrap();
+    let instance = config.instance_name.clone();
+    let mqtt_latency_ms = MQTT_LATENCY.lock().unwrap().map(|l| l.as_millis());
+    let mqtt_server = MQTT_SERVER.lock().unwrap().clone();
+    let devices = device_status(&config);
+    let failed_adapters = FAILED_ADAPTERS.lock().unwrap().clone();
+
+    let denials = PERMISSION_DENIALS.lock().unwrap();
+    if !denials.is_empty() {
+        debug!("Checked health of service: Publishing denied by MQTT broker");
+        let response = Response {
+            message: format!(
+                "MQTT broker denied publishing to topics: {}",
+                denials.join(", ")
+            ),
+            instance,
+            mqtt_latency_ms,
+            mqtt_server: mqtt_server.clone(),
+            devices,
+            failed_adapters,
+        };
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(response));
+    }
+
+    if !clock::is_reliable() {
+        debug!("Checked health of service: System clock not reliable");
+        let response = Response {
+            message: "System clock is not reliable".to_string(),
+            instance,
+            mqtt_latency_ms,
+            mqtt_server: mqtt_server.clone(),
+            devices,
+            failed_adapters,
+        };
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(response));
+    }
 
     match &*status {
         HealthStatus::WaitingForFirstRun => {
             debug!("Checked health of service: Waiting for the first run");
             let response = Response {
                 message: "Waiting for the first run".to_string(),
+                instance,
+                mqtt_latency_ms,
+                mqtt_server: mqtt_server.clone(),
+                devices,
+                failed_adapters,
             };
-            HttpResponse::NotFound().json(response)
+            (StatusCode::NOT_FOUND, Json(response))
         }
         HealthStatus::LastRunFailed(msg) => {
             debug!("Checked health of service: Last run failed");
             let response = Response {
                 message: msg.clone(),
+                instance,
+                mqtt_latency_ms,
+                mqtt_server: mqtt_server.clone(),
+                devices,
+                failed_adapters,
             };
-            HttpResponse::InternalServerError().json(response)
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+        }
+        HealthStatus::AdapterUnavailable(msg) => {
+            debug!("Checked health of service: Bluetooth adapter unavailable");
+            let response = Response {
+                message: msg.clone(),
+                instance,
+                mqtt_latency_ms,
+                mqtt_server: mqtt_server.clone(),
+                devices,
+                failed_adapters,
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(response))
         }
         HealthStatus::Ok => {
+            // The BLE adapter might have died quietly
+            if let Some(age) = config.health.max_data_age.and_then(stale_data_age) {
+                debug!("Checked health of service: No recent readings");
+                let response = Response {
+                    message: format!(
+                        "No readings received for {}",
+                        humantime::format_duration(Duration::from_secs(age.as_secs()))
+                    ),
+                    instance,
+                    mqtt_latency_ms,
+                    mqtt_server: mqtt_server.clone(),
+                    devices,
+                    failed_adapters,
+                };
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(response));
+            }
+            // The other adapters still scan, so a failed adapter does not fail the health check
+            let message = if failed_adapters.is_empty() {
+                "Everything is working fine".to_string()
+            } else {
+                format!("Scan failed on adapters: {}", failed_adapters.join(", "))
+            };
             let response = Response {
-                message: "Everything is working fine".to_string(),
+                message,
+                instance,
+                mqtt_latency_ms,
+                mqtt_server: mqtt_server.clone(),
+                devices,
+                failed_adapters,
             };
-            HttpResponse::Ok().json(response)
+            (StatusCode::OK, Json(response))
         }
     }
 }
 
-async fn not_found() -> actix_web::Result<HttpResponse> {
+async fn not_found(State(config): State<Arc<AppConfig>>) -> impl IntoResponse {
     let response = Response {
         message: "Resource not found".to_string(),
+        instance: config.instance_name.clone(),
+        mqtt_latency_ms: None,
+        mqtt_server: None,
+        devices: vec![],
+        failed_adapters: vec![],
     };
-    Ok(HttpResponse::NotFound().json(response))
+    (StatusCode::NOT_FOUND, Json(response))
 }
 
 /// Sets the current health status of the service
@@ -58,22 +246,49 @@ pub fn set_health_status(next_status: HealthStatus) {
     *status = next_status;
 }
 
-/// Starts an actix web server for the health check endpoint
+/// Sets the last measured round-trip latency of the MQTT broker
+pub fn set_mqtt_latency(latency: Option<Duration>) {
+    let mut last_latency = MQTT_LATENCY.lock().unwrap();
+    *last_latency = latency;
+}
+
+/// Sets the URL of the MQTT server of the current connection, None if not connected
+pub fn set_mqtt_server(server: Option<String>) {
+    let mut mqtt_server = MQTT_SERVER.lock().unwrap();
+    *mqtt_server = server;
+}
+
+/// Sets the adapters whose scan failed in the last run
+pub fn set_failed_adapters(adapters: Vec<String>) {
+    let mut failed = FAILED_ADAPTERS.lock().unwrap();
+    *failed = adapters;
+}
+
+/// Sets the topics the MQTT broker denied publishing to
+pub fn set_permission_denials(topics: Vec<String>) {
+    let mut denials = PERMISSION_DENIALS.lock().unwrap();
+    *denials = topics;
+}
+
+/// Starts an HTTP server (axum on the existing tokio runtime) for the health check endpoint and the REST API
 pub async fn start_healthcheck_server(
     ip: String,
     port: u16,
+    config: &AppConfig,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let srv = HttpServer::new(|| {
-        App::new()
-            .service(healthcheck)
-            .default_service(web::route().to(not_found))
-    })
-    .bind((ip, port))?
-    .workers(1)
-    .disable_signals()
-    .run();
-
-    tokio::spawn(srv);
+    STARTED.get_or_init(Utc::now);
+    let app = Router::new()
+        .route("/health", get(healthcheck))
+        .merge(rest_api::router())
+        .fallback(not_found)
+        .with_state(Arc::new(config.clone()));
+    let listener = tokio::net::TcpListener::bind((ip.as_str(), port)).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Health check server failed: {}", e);
+        }
+    });
 
     Ok(())
 }
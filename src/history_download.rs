@@ -0,0 +1,197 @@
+//! Recovers the readings missed by the gateway (e.g. during an outage) from the logs of the ThermoBeacons, downloaded over a GATT connection.
+//! The log entries carry no time, so their times are derived from the log interval, assuming the newest entry was just logged.
+
+use std::{collections::HashMap, error::Error, sync::Mutex, time::Duration};
+
+use btleplug::{api::BDAddr, platform::Manager};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    configuration::{AppConfig, AppDevice, HistoryDownloadConfig},
+    sink::{Message, Sink},
+    store,
+    thermobeacon_gatt::{self, Connection, LogEntry},
+    thermobeacon_models,
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Time of the last known reading of each device with history download
+static LAST_READING: Mutex<Option<HashMap<BDAddr, DateTime<Utc>>>> = Mutex::new(None);
+
+/// Number of log entries logged after the last known reading, without the newest entry (which is covered by the current reading)
+fn missing_entries(
+    last_reading: DateTime<Utc>,
+    now: DateTime<Utc>,
+    config: &HistoryDownloadConfig,
+) -> u32 {
+    let gap = (now - last_reading).to_std().unwrap_or_default();
+    let intervals = gap.as_secs() / config.log_interval.as_secs().max(1);
+    (intervals
+        .saturating_sub(1)
+        .min(u64::from(config.max_entries))) as u32
+}
+
+/// Time the log entry of the given index was taken, given the index of the newest entry taken now
+fn logged_at(now: DateTime<Utc>, index: u32, newest: u32, log_interval: Duration) -> DateTime<Utc> {
+    let age = log_interval * newest.saturating_sub(index);
+    now - chrono::Duration::from_std(age).unwrap_or_default()
+}
+
+/// Time of the last known reading of the device, from this run of the gateway or the local history store
+fn last_reading(config: &AppConfig, mac: BDAddr) -> Option<DateTime<Utc>> {
+    let known = LAST_READING
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|l| l.get(&mac).copied());
+    known.or_else(|| match store::latest(config.store.as_ref()?, mac) {
+        Ok(latest) => latest,
+        Err(e) => {
+            warn!("Failed to read the latest reading of {}: {}", mac, e);
+            None
+        }
+    })
+}
+
+fn set_last_reading(mac: BDAddr, time: DateTime<Utc>) {
+    LAST_READING
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(mac, time);
+}
+
+/// Downloads the given number of log entries before the newest one. Returns the index of the newest entry and the downloaded entries.
+async fn download(
+    manager: &Manager,
+    device: &AppDevice,
+    mac: BDAddr,
+    count: u32,
+) -> Result<(u32, Vec<LogEntry>), Box<dyn Error + Send + Sync>> {
+    let model = device
+        .model
+        .as_deref()
+        .and_then(thermobeacon_models::by_name)
+        .unwrap_or(thermobeacon_models::DEFAULT);
+    let peripheral = thermobeacon_gatt::find_peripheral(manager, mac).await?;
+    let mut connection = Connection::open(peripheral).await?;
+    let result = async {
+        let newest = connection.log_length().await?.saturating_sub(1);
+        let entries = connection
+            .download_log(newest.saturating_sub(count), newest, model)
+            .await?;
+        Ok::<_, Box<dyn Error + Send + Sync>>((newest, entries))
+    }
+    .await;
+    if let Err(e) = connection.disconnect().await {
+        debug!("Failed to disconnect from {}: {}", device.name, e);
+    }
+    result
+}
+
+/// Downloads the log entries missed since the last known reading of each device with history download found in this run,
+/// and delivers them with their original times to the sinks and the local history store. Failures are only logged, the download is retried in the next run.
+pub async fn recover(
+    config: &AppConfig,
+    devices: &[AppDevice],
+    messages: &[Message],
+    manager: &Manager,
+    sinks: &[Box<dyn Sink>],
+) {
+    let now = Utc::now();
+    for device in devices.iter().filter(|d| d.download_history) {
+        let mac = device.mac.parse::<BDAddr>().unwrap();
+        let current = match messages.iter().find(|msg| msg.data.mac == mac) {
+            Some(msg) => &msg.data,
+            None => continue,
+        };
+        let last = last_reading(config, mac);
+        let missing = last
+            .map(|last| missing_entries(last, now, &config.history_download))
+            .unwrap_or(0);
+        if missing > 0 {
+            info!(
+                "Downloading up to {} missed log entries of {} ...",
+                missing, device.name
+            );
+            match download(manager, device, mac, missing).await {
+                Ok((newest, entries)) => {
+                    info!("Recovered {} log entries of {}", entries.len(), device.name);
+                    for entry in entries {
+                        let time = logged_at(
+                            now,
+                            entry.index,
+                            newest,
+                            config.history_download.log_interval,
+                        );
+                        // The log contains no battery level, it changes slowly anyway
+                        let data = ThermoBeaconFullReadResult {
+                            temperature: entry.temperature,
+                            humidity: entry.humidity,
+                            battery_level: current.battery_level,
+                            mac,
+                            ..Default::default()
+                        };
+                        deliver(config, device, &data, time, sinks).await;
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to download the log of {}: {}", device.name, e);
+                    // Keep the last known reading, so the download is retried in the next run
+                    if let Some(last) = last {
+                        set_last_reading(mac, last);
+                    }
+                    continue;
+                }
+            }
+        }
+        set_last_reading(mac, now);
+    }
+}
+
+/// Delivers a recovered reading to all sinks and the local history store
+async fn deliver(
+    config: &AppConfig,
+    device: &AppDevice,
+    data: &ThermoBeaconFullReadResult,
+    time: DateTime<Utc>,
+    sinks: &[Box<dyn Sink>],
+) {
+    for sink in sinks {
+        if let Err(e) = sink.publish_history(data, device, time).await {
+            error!(
+                "Failed to deliver log entry of {} to {}: {}",
+                device.name,
+                sink.name(),
+                e
+            );
+        }
+    }
+    if let Some(store_config) = &config.store {
+        if let Err(e) = store::insert_at(store_config, time, &device.name, data) {
+            error!("Failed to store log entry of {}: {}", device.name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_missing_entries_and_their_times() {
+        let config = HistoryDownloadConfig::default();
+        let now = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        // Six 10 minute intervals, the newest entry is covered by the current reading
+        assert_eq!(missing_entries(now - hour, now, &config), 5);
+        assert_eq!(
+            missing_entries(now - chrono::Duration::minutes(15), now, &config),
+            0
+        );
+        assert_eq!(missing_entries(now - hour * 48, now, &config), 144);
+
+        assert_eq!(logged_at(now, 10, 10, config.log_interval), now);
+        assert_eq!(logged_at(now, 4, 10, config.log_interval), now - hour);
+    }
+}
@@ -1,6 +1,18 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
 
-use paho_mqtt::AsyncClient;
+use crate::mqtt::{AsyncClient, MqttClient};
+
+use crate::{
+    button,
+    configuration::{AppConfig, AppDevice},
+    device_actions::DeviceAction,
+    device_availability, device_info,
+    publish_policy::PublishPolicy,
+    sensors::DeviceType,
+};
 
 /// Describes a device for automatic discovery of device topics
 #[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
@@ -9,46 +21,430 @@ pub struct MQTTDiscoveryDevice {
     pub name: String,
     pub manufacturer: String,
     pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hw_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sw_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub configuration_url: Option<String>,
 }
 
 /// Describes the message send to 'homeassistant' topic for automatic discovery of device topics
 #[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
 pub struct MQTTDiscovery {
-    pub device_class: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_category: Option<String>,
     pub state_topic: String,
-    pub unit_of_measurement: String,
+    pub availability_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_display_precision: Option<u8>,
     pub value_template: String,
     pub unique_id: String,
     pub device: MQTTDiscoveryDevice,
 }
 
-/// Sends the Home assistant auto discovery messages for all configured devices
+/// Describes the event entity of the button presses of a device
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
+pub struct MQTTEventDiscovery {
+    pub name: String,
+    pub state_topic: String,
+    pub availability_topic: String,
+    pub event_types: Vec<String>,
+    pub unique_id: String,
+    pub device: MQTTDiscoveryDevice,
+}
+
+/// Describes a button entity executing a device action by a command
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
+pub struct MQTTButtonDiscovery {
+    pub name: String,
+    pub command_topic: String,
+    pub payload_press: String,
+    pub availability_topic: String,
+    pub entity_category: String,
+    pub unique_id: String,
+    pub device: MQTTDiscoveryDevice,
+}
+
+/// Describes a device trigger, usable in Home Assistant automations of the device
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
+pub struct MQTTDeviceTrigger {
+    pub automation_type: String,
+    pub topic: String,
+    pub payload: String,
+    #[serde(rename = "type")]
+    pub trigger_type: String,
+    pub subtype: String,
+    pub device: MQTTDiscoveryDevice,
+}
+
+/// A single Home Assistant entity announced for each device
+struct Entity {
+    /// Home Assistant component, 'sensor' or 'binary_sensor'
+    component: &'static str,
+    /// Suffix of the config topic
+    topic_suffix: &'static str,
+    /// Suffix of the unique id
+    id_suffix: &'static str,
+    /// Entity name. Without a name Home Assistant names the entity after its device class.
+    name: Option<&'static str>,
+    device_class: Option<&'static str>,
+    /// State class 'measurement' or 'total_increasing' enables long-term statistics
+    state_class: Option<&'static str>,
+    /// Number of decimals shown by Home Assistant
+    precision: Option<u8>,
+    /// Diagnostic entities are shown separately from the measurements
+    diagnostic: bool,
+    unit_of_measurement: Option<&'static str>,
+    value_template: &'static str,
+    /// Optional field of the reading the entity requires, only announced for sensor types providing it
+    field: Option<&'static str>,
+}
+
+/// All entities announced for each device, covering the whole ThermoBeaconFullReadResult
+const ENTITIES: [Entity; 18] = [
+    Entity {
+        component: "sensor",
+        topic_suffix: "temperature",
+        id_suffix: "temp",
+        name: None,
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("°C"),
+        value_template: "{{ value_json.data.temperature}}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "humidity",
+        id_suffix: "humidity",
+        name: None,
+        device_class: Some("humidity"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("%"),
+        value_template: "{{ value_json.data.humidity}}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "battery",
+        id_suffix: "battery",
+        name: None,
+        device_class: Some("battery"),
+        state_class: Some("measurement"),
+        precision: Some(0),
+        diagnostic: true,
+        unit_of_measurement: Some("%"),
+        value_template: "{{ value_json.data.battery_level}}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "min_temperature",
+        id_suffix: "min_temp",
+        name: Some("Minimum temperature"),
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("°C"),
+        value_template: "{{ value_json.data.min_temperature | default(none) }}",
+        field: Some("min_temperature"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "max_temperature",
+        id_suffix: "max_temp",
+        name: Some("Maximum temperature"),
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("°C"),
+        value_template: "{{ value_json.data.max_temperature | default(none) }}",
+        field: Some("max_temperature"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "max_temperature_time",
+        id_suffix: "max_temp_time",
+        name: Some("Maximum temperature time"),
+        device_class: Some("timestamp"),
+        state_class: None,
+        precision: None,
+        diagnostic: false,
+        unit_of_measurement: None,
+        value_template: "{{ value_json.max_temp_at | default(none) }}",
+        field: Some("max_temp_time"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "min_temperature_time",
+        id_suffix: "min_temp_time",
+        name: Some("Minimum temperature time"),
+        device_class: Some("timestamp"),
+        state_class: None,
+        precision: None,
+        diagnostic: false,
+        unit_of_measurement: None,
+        value_template: "{{ value_json.min_temp_at | default(none) }}",
+        field: Some("min_temp_time"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "uptime",
+        id_suffix: "uptime",
+        name: Some("Uptime"),
+        device_class: Some("duration"),
+        state_class: Some("total_increasing"),
+        precision: Some(0),
+        diagnostic: true,
+        unit_of_measurement: Some("s"),
+        value_template: "{{ value_json.data.uptime}}",
+        field: Some("uptime"),
+    },
+    Entity {
+        component: "binary_sensor",
+        topic_suffix: "button",
+        id_suffix: "button",
+        name: Some("Button"),
+        device_class: None,
+        state_class: None,
+        precision: None,
+        diagnostic: false,
+        unit_of_measurement: None,
+        value_template: "{{ 'ON' if value_json.data.button_pressed else 'OFF' }}",
+        field: Some("button_pressed"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "last_seen",
+        id_suffix: "last_seen",
+        name: Some("Last seen"),
+        device_class: Some("timestamp"),
+        state_class: None,
+        precision: None,
+        diagnostic: true,
+        unit_of_measurement: None,
+        value_template: "{{ value_json.measured_at }}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "rssi",
+        id_suffix: "rssi",
+        name: Some("Signal strength"),
+        device_class: Some("signal_strength"),
+        state_class: Some("measurement"),
+        precision: Some(0),
+        diagnostic: true,
+        unit_of_measurement: Some("dBm"),
+        value_template: "{{ value_json.data.rssi}}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "external_temperature",
+        id_suffix: "external_temp",
+        name: Some("External temperature"),
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("°C"),
+        value_template: "{{ value_json.data.external_temperature | default(none) }}",
+        field: Some("external_temperature"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "pressure",
+        id_suffix: "pressure",
+        name: Some("Pressure"),
+        device_class: Some("atmospheric_pressure"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("hPa"),
+        value_template: "{{ value_json.data.pressure | default(none) }}",
+        field: Some("pressure"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "acceleration_x",
+        id_suffix: "acceleration_x",
+        name: Some("Acceleration X"),
+        device_class: None,
+        state_class: Some("measurement"),
+        precision: Some(3),
+        diagnostic: false,
+        unit_of_measurement: Some("g"),
+        value_template: "{{ value_json.data.acceleration_x | default(none) }}",
+        field: Some("acceleration_x"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "acceleration_y",
+        id_suffix: "acceleration_y",
+        name: Some("Acceleration Y"),
+        device_class: None,
+        state_class: Some("measurement"),
+        precision: Some(3),
+        diagnostic: false,
+        unit_of_measurement: Some("g"),
+        value_template: "{{ value_json.data.acceleration_y | default(none) }}",
+        field: Some("acceleration_y"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "acceleration_z",
+        id_suffix: "acceleration_z",
+        name: Some("Acceleration Z"),
+        device_class: None,
+        state_class: Some("measurement"),
+        precision: Some(3),
+        diagnostic: false,
+        unit_of_measurement: Some("g"),
+        value_template: "{{ value_json.data.acceleration_z | default(none) }}",
+        field: Some("acceleration_z"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "movement_counter",
+        id_suffix: "movement_counter",
+        name: Some("Movement counter"),
+        device_class: None,
+        state_class: Some("measurement"),
+        precision: Some(0),
+        diagnostic: false,
+        unit_of_measurement: None,
+        value_template: "{{ value_json.data.movement_counter | default(none) }}",
+        field: Some("movement_counter"),
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "battery_voltage",
+        id_suffix: "battery_voltage",
+        name: Some("Battery voltage"),
+        device_class: Some("voltage"),
+        state_class: Some("measurement"),
+        precision: Some(2),
+        diagnostic: true,
+        unit_of_measurement: Some("V"),
+        value_template: "{{ value_json.data.battery_voltage | default(none) }}",
+        field: Some("battery_voltage"),
+    },
+];
+
+/// Entities of the comfort values, only announced if computed fields are enabled
+const COMPUTED_ENTITIES: [Entity; 3] = [
+    Entity {
+        component: "sensor",
+        topic_suffix: "dew_point",
+        id_suffix: "dew_point",
+        name: Some("Dew point"),
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("°C"),
+        value_template: "{{ value_json.computed.dew_point}}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "absolute_humidity",
+        id_suffix: "absolute_humidity",
+        name: Some("Absolute humidity"),
+        device_class: None,
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("g/m³"),
+        value_template: "{{ value_json.computed.absolute_humidity}}",
+        field: None,
+    },
+    Entity {
+        component: "sensor",
+        topic_suffix: "heat_index",
+        id_suffix: "heat_index",
+        name: Some("Heat index"),
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        precision: Some(1),
+        diagnostic: false,
+        unit_of_measurement: Some("°C"),
+        value_template: "{{ value_json.computed.heat_index}}",
+        field: None,
+    },
+];
+
+/// Seconds without reading after which Home Assistant marks the entities of the device unavailable. None if the device is only read once or the expiry is disabled.
+fn expire_after(config: &AppConfig, device: &AppDevice) -> Option<u64> {
+    let factor = config.mqtt.as_ref()?.expire_after_factor;
+    if factor == 0 {
+        return None;
+    }
+    let period = config.schedule(device)?.period()?;
+    Some(period.as_secs().max(1) * u64::from(factor))
+}
+
+/// Sends the Home assistant auto discovery messages for all configured devices publishing to the given broker (None for the default broker)
 pub async fn publish_homeassistant_device_discovery_messages(
-    config: &crate::configuration::AppConfig,
+    config: &AppConfig,
     cli: &AsyncClient,
+    broker: Option<&str>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     if !cli.is_connected() {
         info!("MQTT client is not connected. Try to reconnect ...");
         cli.reconnect().await?;
     }
 
-    for device in &config.devices {
+    // Config topics of all announced entities
+    let mut announced = vec![];
+    for device in config
+        .devices
+        .iter()
+        .filter(|d| d.broker.as_deref() == broker)
+    {
         // https://www.home-assistant.io/integrations/mqtt/
         // https://www.home-assistant.io/integrations/sensor/
+        // https://www.home-assistant.io/integrations/binary_sensor/
         // https://www.home-assistant.io/docs/configuration/customizing-devices/#device-class
 
         // State topic
-        let topic = &device
-            .topic
-            .clone()
-            .unwrap_or(format!("ThermoBeacon/{}", device.name));
-
-        let topic_temperature = format!(
-            "homeassistant/sensor/thermobeacon/{}_temperature/config",
-            device.mac.replace(':', "_")
-        );
+        let topic = &config.device_topic(device);
+        // Prefix of all ids, to avoid collisions between several gateways
+        let id_prefix = config.id_prefix();
+        // Entities become unavailable if the gateway disconnects
+        let availability_topic = config.availability_topic();
+        // Entities become unavailable if the device stops reporting
+        let expire_after = expire_after(config, device);
+        // Temperatures are published in the units of the device
+        let units = config.units(device);
+
+        // Configured revisions take precedence over the ones read from the device
+        let info = device
+            .mac
+            .parse()
+            .ok()
+            .and_then(device_info::get)
+            .unwrap_or_default();
         let device_id = MQTTDiscoveryDevice {
-            identifiers: vec![device.mac.clone()],
+            identifiers: vec![format!("{}{}", id_prefix, device.mac)],
             name: device.name.clone(),
             manufacturer: device
                 .manufacturer
@@ -60,81 +456,310 @@ pub async fn publish_homeassistant_device_discovery_messages(
                 .as_ref()
                 .unwrap_or(&"Smart hygrometer".to_string())
                 .to_string(),
+            hw_version: device.hw_version.clone().or(info.hardware_revision),
+            sw_version: device.sw_version.clone().or(info.firmware_revision),
+            configuration_url: config
+                .mqtt
+                .as_ref()
+                .and_then(|m| m.configuration_url.clone()),
         };
 
-        let payload_temperature = MQTTDiscovery {
-            device_class: "temperature".to_string(),
-            state_topic: topic.clone(),
-            unit_of_measurement: "°C".to_string(),
-            value_template: "{{ value_json.data.temperature}}".to_string(),
-            unique_id: format!("{}_temp", device.mac),
-            device: device_id.clone(),
+        let computed_entities = if config.computed_fields {
+            &COMPUTED_ENTITIES[..]
+        } else {
+            &[]
         };
+        for entity in ENTITIES
+            .iter()
+            .chain(computed_entities)
+            .filter(|e| e.field.map_or(true, |f| device.device_type.provides(f)))
+        {
+            let config_topic = format!(
+                "homeassistant/{}/thermobeacon/{}{}_{}/config",
+                entity.component,
+                id_prefix,
+                device.mac.replace(':', "_"),
+                entity.topic_suffix
+            );
+            let payload = MQTTDiscovery {
+                name: entity.name.map(str::to_string),
+                device_class: entity.device_class.map(str::to_string),
+                state_class: entity.state_class.map(str::to_string),
+                entity_category: entity.diagnostic.then(|| "diagnostic".to_string()),
+                state_topic: topic.clone(),
+                availability_topic: availability_topic.clone(),
+                expire_after,
+                unit_of_measurement: match entity.device_class {
+                    Some("temperature") => Some(units.temperature_unit().to_string()),
+                    _ => entity.unit_of_measurement.map(str::to_string),
+                },
+                suggested_display_precision: entity.precision,
+                value_template: entity.value_template.to_string(),
+                unique_id: format!("{}{}_{}", id_prefix, device.mac, entity.id_suffix),
+                device: device_id.clone(),
+            };
+            let payload = serde_json::to_string(&payload).unwrap();
+
+            debug!(
+                "Publish discovery message for {} of {} to {}: {}",
+                entity.topic_suffix, device.name, config_topic, payload
+            );
+            cli.publish(PublishPolicy::DISCOVERY.message(config_topic.clone(), payload))
+                .await?;
+            announced.push(config_topic);
+        }
+
+        // Devices missing for several runs are reported offline
+        if config.offline_after_missing_runs > 0 {
+            let config_topic = format!(
+                "homeassistant/binary_sensor/thermobeacon/{}{}_connectivity/config",
+                id_prefix,
+                device.mac.replace(':', "_")
+            );
+            let payload = MQTTDiscovery {
+                name: Some("Connectivity".to_string()),
+                device_class: Some("connectivity".to_string()),
+                entity_category: Some("diagnostic".to_string()),
+                state_topic: config.device_availability_topic(device),
+                availability_topic: availability_topic.clone(),
+                value_template: format!(
+                    "{{{{ 'ON' if value == '{}' else 'OFF' }}}}",
+                    device_availability::ONLINE
+                ),
+                unique_id: format!("{}{}_connectivity", id_prefix, device.mac),
+                device: device_id.clone(),
+                ..Default::default()
+            };
+            let payload = serde_json::to_string(&payload).unwrap();
+            debug!(
+                "Publish discovery message for connectivity of {} to {}: {}",
+                device.name, config_topic, payload
+            );
+            cli.publish(PublishPolicy::DISCOVERY.message(config_topic.clone(), payload))
+                .await?;
+            announced.push(config_topic);
+        }
 
-        let topic_humidity = format!(
-            "homeassistant/sensor/thermobeacon/{}_humidity/config",
-            device.mac.replace(':', "_")
+        // Battery-low state and the battery-low and reboot events derived from the readings
+        if config.device_events {
+            let mac_ = device.mac.replace(':', "_");
+            let sensor_topic = format!(
+                "homeassistant/binary_sensor/thermobeacon/{}{}_battery_low/config",
+                id_prefix, mac_
+            );
+            let sensor = MQTTDiscovery {
+                name: Some("Battery low".to_string()),
+                device_class: Some("battery".to_string()),
+                entity_category: Some("diagnostic".to_string()),
+                state_topic: config.battery_low_topic(device),
+                availability_topic: availability_topic.clone(),
+                value_template: "{{ value }}".to_string(),
+                unique_id: format!("{}{}_battery_low", id_prefix, device.mac),
+                device: device_id.clone(),
+                ..Default::default()
+            };
+            let mut event_types = vec!["battery_low".to_string()];
+            // Only devices reporting their uptime reveal reboots
+            if device.device_type.provides("uptime") {
+                event_types.push("reboot".to_string());
+            }
+            let event_topic = format!(
+                "homeassistant/event/thermobeacon/{}{}_device_event/config",
+                id_prefix, mac_
+            );
+            let event = MQTTEventDiscovery {
+                name: "Device event".to_string(),
+                state_topic: config.device_event_topic(device),
+                availability_topic: availability_topic.clone(),
+                event_types,
+                unique_id: format!("{}{}_device_event", id_prefix, device.mac),
+                device: device_id.clone(),
+            };
+            for (config_topic, payload) in [
+                (sensor_topic, serde_json::to_string(&sensor).unwrap()),
+                (event_topic, serde_json::to_string(&event).unwrap()),
+            ] {
+                debug!(
+                    "Publish discovery message for device events of {} to {}: {}",
+                    device.name, config_topic, payload
+                );
+                cli.publish(PublishPolicy::DISCOVERY.message(config_topic.clone(), payload))
+                    .await?;
+                announced.push(config_topic);
+            }
+        }
+
+        // Device actions are executed by commands, which are only received from the default broker. ThermoBeacons only.
+        let commands = broker.is_none() && config.mqtt.as_ref().map_or(false, |m| m.commands);
+        if commands && config.listen.is_none() && device.device_type == DeviceType::ThermoBeacon {
+            for action in DeviceAction::ALL {
+                let config_topic = format!(
+                    "homeassistant/button/thermobeacon/{}{}_{}/config",
+                    id_prefix,
+                    device.mac.replace(':', "_"),
+                    action.name()
+                );
+                let payload = MQTTButtonDiscovery {
+                    name: action.title().to_string(),
+                    command_topic: config.command_topic(),
+                    payload_press: serde_json::json!({
+                        "action": action.name(),
+                        "device": device.mac,
+                    })
+                    .to_string(),
+                    availability_topic: availability_topic.clone(),
+                    entity_category: action.entity_category().to_string(),
+                    unique_id: format!("{}{}_{}", id_prefix, device.mac, action.name()),
+                    device: device_id.clone(),
+                };
+                let payload = serde_json::to_string(&payload).unwrap();
+                debug!(
+                    "Publish discovery message for {} of {} to {}: {}",
+                    action, device.name, config_topic, payload
+                );
+                cli.publish(PublishPolicy::DISCOVERY.message(config_topic.clone(), payload))
+                    .await?;
+                announced.push(config_topic);
+            }
+        }
+
+        // Only sensors with a button send press events
+        if !device.device_type.provides("button_pressed") {
+            continue;
+        }
+
+        // Button presses as event entity and device trigger
+        let mac_ = device.mac.replace(':', "_");
+        let event_topic = format!(
+            "homeassistant/event/thermobeacon/{}{}_button_press/config",
+            id_prefix, mac_
         );
-        let payload_humidity = MQTTDiscovery {
-            device_class: "humidity".to_string(),
-            state_topic: topic.clone(),
-            unit_of_measurement: "%".to_string(),
-            value_template: "{{ value_json.data.humidity}}".to_string(),
-            unique_id: format!("{}_humidity", device.mac),
+        let event = MQTTEventDiscovery {
+            name: "Button press".to_string(),
+            state_topic: config.button_topic(device),
+            availability_topic: availability_topic.clone(),
+            event_types: vec!["press".to_string()],
+            unique_id: format!("{}{}_button_press", id_prefix, device.mac),
             device: device_id.clone(),
         };
-
-        let topic_battery = format!(
-            "homeassistant/sensor/thermobeacon/{}_battery/config",
-            device.mac.replace(':', "_")
+        let trigger_topic = format!(
+            "homeassistant/device_automation/thermobeacon/{}{}_button_press/config",
+            id_prefix, mac_
         );
-        let payload_battery = MQTTDiscovery {
-            device_class: "battery".to_string(),
-            state_topic: topic.clone(),
-            unit_of_measurement: "%".to_string(),
-            value_template: "{{ value_json.data.battery_level}}".to_string(),
-            unique_id: format!("{}_battery", device.mac),
+        let trigger = MQTTDeviceTrigger {
+            automation_type: "trigger".to_string(),
+            topic: config.button_topic(device),
+            payload: button::PRESS_EVENT.to_string(),
+            trigger_type: "button_short_press".to_string(),
+            subtype: "button_1".to_string(),
             device: device_id.clone(),
         };
+        for (config_topic, payload) in [
+            (event_topic, serde_json::to_string(&event).unwrap()),
+            (trigger_topic, serde_json::to_string(&trigger).unwrap()),
+        ] {
+            debug!(
+                "Publish discovery message for button presses of {} to {}: {}",
+                device.name, config_topic, payload
+            );
+            cli.publish(PublishPolicy::DISCOVERY.message(config_topic.clone(), payload))
+                .await?;
+            announced.push(config_topic);
+        }
+    }
 
-        debug!(
-            "Publish discovery message for temperature of {} to {}: {}",
-            device.name,
-            topic_temperature,
-            serde_json::to_string(&payload_temperature).unwrap()
-        );
-        cli.publish(mqtt::Message::new_retained(
-            topic_temperature,
-            serde_json::to_string(&payload_temperature).unwrap(),
-            1,
-        ))
-        .await?;
-
-        debug!(
-            "Publish discovery message for humidity of {} to {}: {}",
-            device.name,
-            topic_humidity,
-            serde_json::to_string(&payload_humidity).unwrap()
-        );
-        cli.publish(mqtt::Message::new_retained(
-            topic_humidity,
-            serde_json::to_string(&payload_humidity).unwrap(),
-            1,
-        ))
-        .await?;
-
-        debug!(
-            "Publish discovery message for battery level of {} to {}: {}",
-            device.name,
-            topic_battery,
-            serde_json::to_string(&payload_battery).unwrap()
-        );
-        cli.publish(mqtt::Message::new_retained(
-            topic_battery,
-            serde_json::to_string(&payload_battery).unwrap(),
-            1,
-        ))
-        .await?;
+    if let Some(state_dir) = config
+        .mqtt
+        .as_ref()
+        .and_then(|m| m.homeassistant_state.as_ref())
+    {
+        remove_stale_entities(Path::new(state_dir), cli, broker, announced).await?;
     }
     Ok(())
 }
+
+/// File remembering the config topics announced to the given broker
+fn state_file(state_dir: &Path, broker: Option<&str>) -> PathBuf {
+    state_dir.join(format!("{}.json", broker.unwrap_or("default")))
+}
+
+/// Config topics announced before, but not anymore
+fn stale_topics(previous: Vec<String>, announced: &[String]) -> Vec<String> {
+    previous
+        .into_iter()
+        .filter(|t| !announced.contains(t))
+        .collect()
+}
+
+/// Removes the entities announced by a previous run, which are not announced anymore (e.g. of deleted devices), by publishing empty retained config messages. Remembers the current entities for the next run.
+async fn remove_stale_entities(
+    state_dir: &Path,
+    cli: &AsyncClient,
+    broker: Option<&str>,
+    announced: Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let file = state_file(state_dir, broker);
+    let previous: Vec<String> = match std::fs::read(&file) {
+        Ok(content) => serde_json::from_slice(&content)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e.into()),
+    };
+
+    for topic in stale_topics(previous, &announced) {
+        info!("Remove Home Assistant entity {}", topic);
+        cli.publish(PublishPolicy::DISCOVERY.message(topic, ""))
+            .await?;
+    }
+
+    std::fs::create_dir_all(state_dir)?;
+    std::fs::write(&file, serde_json::to_vec(&announced)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::configuration::MqttConfig;
+
+    #[test]
+    fn expires_after_multiple_of_schedule_period() {
+        let config = AppConfig {
+            cron: Some("*/5 * * * *".to_string()),
+            mqtt: Some(MqttConfig {
+                expire_after_factor: 3,
+                ..Default::default()
+            }),
+            devices: vec![
+                AppDevice::default(),
+                AppDevice {
+                    interval: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(expire_after(&config, &config.devices[0]), Some(900));
+        assert_eq!(expire_after(&config, &config.devices[1]), Some(180));
+
+        let once = AppConfig {
+            cron: None,
+            ..config
+        };
+        assert_eq!(expire_after(&once, &once.devices[0]), None);
+    }
+
+    #[test]
+    fn detects_entities_no_longer_announced() {
+        let previous = vec![
+            "homeassistant/sensor/thermobeacon/11_22_33_44_55_66_temperature/config".to_string(),
+            "homeassistant/sensor/thermobeacon/66_55_44_33_22_11_temperature/config".to_string(),
+        ];
+        let announced = vec![previous[0].clone()];
+        assert_eq!(
+            stale_topics(previous.clone(), &announced),
+            vec![previous[1].clone()]
+        );
+    }
+}
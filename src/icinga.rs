@@ -0,0 +1,316 @@
+use std::error::Error;
+
+use crate::{
+    configuration::{AppDevice, IcingaConfig},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Exit status of a check, following the Nagios plugin conventions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum CheckState {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl CheckState {
+    fn label(&self) -> &'static str {
+        match self {
+            CheckState::Ok => "OK",
+            CheckState::Warning => "WARNING",
+            CheckState::Critical => "CRITICAL",
+            CheckState::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Threshold range in the Nagios plugin syntax ('10', '10:', '~:10', '10:20', '@10:20'): an alert is raised if the value is outside the range (or inside, if prefixed with '@')
+#[derive(Debug, Clone)]
+pub struct Range {
+    start: f32,
+    end: f32,
+    inside: bool,
+    spec: String,
+}
+
+impl TryFrom<String> for Range {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        let (inside, range) = match spec.strip_prefix('@') {
+            Some(range) => (true, range),
+            None => (false, spec.as_str()),
+        };
+        let parse = |s: &str, default: f32| -> Result<f32, String> {
+            match s.trim() {
+                "" => Ok(default),
+                "~" => Ok(f32::NEG_INFINITY),
+                value => value
+                    .parse::<f32>()
+                    .map_err(|e| format!("Invalid threshold '{}': {}", spec, e)),
+            }
+        };
+        let (start, end) = match range.split_once(':') {
+            Some((start, end)) => (parse(start, 0.0)?, parse(end, f32::INFINITY)?),
+            None => (0.0, parse(range, f32::INFINITY)?),
+        };
+        Ok(Range {
+            start,
+            end,
+            inside,
+            spec,
+        })
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Range {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Plain numbers (e.g. 'critical: 10') are accepted as well
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let spec = match value {
+            serde_json::Value::String(s) => s,
+            serde_json::Value::Number(n) => n.to_string(),
+            other => {
+                return Err(serde::de::Error::custom(format!(
+                    "Invalid threshold {}",
+                    other
+                )))
+            }
+        };
+        Range::try_from(spec).map_err(serde::de::Error::custom)
+    }
+}
+
+// Ranges are compared by their specification, which also makes them usable in the (Eq) configuration
+impl PartialEq for Range {
+    fn eq(&self, other: &Self) -> bool {
+        self.spec == other.spec
+    }
+}
+
+impl Eq for Range {}
+
+impl Range {
+    /// Checks if the value raises an alert
+    fn alerts(&self, value: f32) -> bool {
+        let within = self.start <= value && value <= self.end;
+        within == self.inside
+    }
+}
+
+/// Warning and critical threshold of a single metric
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct Thresholds {
+    pub warning: Option<Range>,
+    pub critical: Option<Range>,
+}
+
+impl Thresholds {
+    fn state(&self, value: f32) -> CheckState {
+        if self
+            .critical
+            .as_ref()
+            .map(|r| r.alerts(value))
+            .unwrap_or(false)
+        {
+            CheckState::Critical
+        } else if self
+            .warning
+            .as_ref()
+            .map(|r| r.alerts(value))
+            .unwrap_or(false)
+        {
+            CheckState::Warning
+        } else {
+            CheckState::Ok
+        }
+    }
+
+    /// Warning and critical part of the performance data
+    fn perfdata(&self) -> String {
+        format!(
+            "{};{}",
+            self.warning.as_ref().map(|r| r.spec.as_str()).unwrap_or(""),
+            self.critical
+                .as_ref()
+                .map(|r| r.spec.as_str())
+                .unwrap_or("")
+        )
+    }
+}
+
+/// Request body of the Icinga 2 API 'process-check-result' action
+#[derive(Debug, serde_derive::Serialize)]
+struct CheckResult {
+    #[serde(rename = "type")]
+    object_type: &'static str,
+    filter: &'static str,
+    /// Variables of the filter, so the names need no escaping in the filter expression
+    filter_vars: FilterVars,
+    exit_status: u8,
+    plugin_output: String,
+    performance_data: Vec<String>,
+    check_source: String,
+}
+
+/// Names of the service a check result is submitted for
+#[derive(Debug, serde_derive::Serialize)]
+struct FilterVars {
+    host: String,
+    service: String,
+}
+
+/// Filter of the service, with the names in the filter variables
+const SERVICE_FILTER: &str = "host.name==host && service.name==service";
+
+/// Derives the check result of a single device from its reading
+fn check_result(
+    config: &IcingaConfig,
+    name: &str,
+    data: Option<&ThermoBeaconFullReadResult>,
+) -> (CheckState, String, Vec<String>) {
+    let data = match data {
+        Some(data) => data,
+        None => {
+            return (
+                CheckState::Unknown,
+                format!("UNKNOWN - ThermoBeacon {} not found", name),
+                vec![],
+            )
+        }
+    };
+    let metrics = [
+        (
+            "temperature",
+            data.temperature,
+            "",
+            &config.thresholds.temperature,
+        ),
+        ("humidity", data.humidity, "%", &config.thresholds.humidity),
+        (
+            "battery_level",
+            data.battery_level,
+            "%",
+            &config.thresholds.battery_level,
+        ),
+    ];
+    let state = metrics
+        .iter()
+        .map(|(_, value, _, thresholds)| thresholds.state(*value))
+        .max()
+        .unwrap_or(CheckState::Ok);
+    let output = format!(
+        "{} - {:.1} °C, {:.0}% humidity, {:.0}% battery",
+        state.label(),
+        data.temperature,
+        data.humidity,
+        data.battery_level
+    );
+    let perfdata = metrics
+        .iter()
+        .map(|(label, value, unit, thresholds)| {
+            format!("{}={}{};{}", label, value, unit, thresholds.perfdata())
+        })
+        .collect();
+    (state, output, perfdata)
+}
+
+/// Submits a passive check result for each searched device to the Icinga 2 API. Devices not found are reported as UNKNOWN.
+pub async fn submit(
+    config: &IcingaConfig,
+    devices: &[AppDevice],
+    readings: &[(&str, &ThermoBeaconFullReadResult)],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(config.insecure)
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?;
+    let url = format!(
+        "{}/v1/actions/process-check-result",
+        config.url.trim_end_matches('/')
+    );
+
+    for device in devices {
+        let data = readings
+            .iter()
+            .find(|(name, _)| *name == device.name)
+            .map(|(_, data)| *data);
+        let (state, plugin_output, performance_data) = check_result(config, &device.name, data);
+        let service = format!("{}{}", config.service_prefix, device.name);
+        let body = CheckResult {
+            object_type: "Service",
+            filter: SERVICE_FILTER,
+            filter_vars: FilterVars {
+                host: config.host.clone(),
+                service: service.clone(),
+            },
+            exit_status: state as u8,
+            plugin_output,
+            performance_data,
+            check_source: config.host.clone(),
+        };
+
+        let response = client
+            .post(&url)
+            .basic_auth(&config.username, config.password.as_ref())
+            .header("Accept", "application/json")
+            .json(&body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Icinga API rejected check result of {}!{}: {}",
+                config.host,
+                service,
+                response.status()
+            )
+            .into());
+        }
+        debug!("Submitted {} check result for {}", state.label(), service);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(spec: &str) -> Range {
+        Range::try_from(spec.to_string()).unwrap()
+    }
+
+    #[test]
+    fn evaluates_nagios_ranges() {
+        assert!(range("10").alerts(11.0));
+        assert!(range("10").alerts(-1.0));
+        assert!(!range("10").alerts(5.0));
+        assert!(range("10:").alerts(9.9));
+        assert!(!range("~:10").alerts(-40.0));
+        assert!(range("5:30").alerts(30.5));
+        assert!(range("@5:30").alerts(20.0));
+        assert!(Range::try_from("abc".to_string()).is_err());
+    }
+
+    #[test]
+    fn passes_names_as_filter_variables() {
+        let body = CheckResult {
+            object_type: "Service",
+            filter: SERVICE_FILTER,
+            filter_vars: FilterVars {
+                host: "gateway".to_string(),
+                service: "thermobeacon-Freezer \"north\"".to_string(),
+            },
+            exit_status: 0,
+            plugin_output: "OK".to_string(),
+            performance_data: vec![],
+            check_source: "gateway".to_string(),
+        };
+        let json = serde_json::to_value(&body).unwrap();
+        assert_eq!(json["filter"], "host.name==host && service.name==service");
+        assert_eq!(
+            json["filter_vars"]["service"],
+            "thermobeacon-Freezer \"north\""
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::{error::Error, net::UdpSocket};
+
+use chrono::Utc;
+
+use crate::{configuration::InfluxUdpConfig, thermobeacon_protocol::ThermoBeaconFullReadResult};
+
+/// Escapes measurement names, tag keys and tag values according to the line protocol
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Formats a reading of the given device as InfluxDB line protocol
+fn to_line(
+    config: &InfluxUdpConfig,
+    instance: Option<&str>,
+    name: &str,
+    data: &ThermoBeaconFullReadResult,
+    timestamp_ns: i64,
+) -> String {
+    let mut tags = vec![
+        format!("name={}", escape(name)),
+        format!("mac={}", escape(&data.mac.to_string())),
+    ];
+    if let Some(instance) = instance {
+        tags.push(format!("instance={}", escape(instance)));
+    }
+    // Sorted tags are recommended for performance
+    let mut extra_tags: Vec<_> = config.tags.iter().collect();
+    extra_tags.sort();
+    for (key, value) in extra_tags {
+        tags.push(format!("{}={}", escape(key), escape(value)));
+    }
+
+    // The min/max data is missing in partial results
+    let mut fields = vec![
+        format!("battery_level={}", data.battery_level),
+        format!("humidity={}", data.humidity),
+        format!("temperature={}", data.temperature),
+        format!("uptime={}i", data.uptime),
+        format!("button_pressed={}", data.button_pressed),
+    ];
+    if let Some(max_temperature) = data.max_temperature {
+        fields.push(format!("max_temperature={}", max_temperature));
+    }
+    if let Some(min_temperature) = data.min_temperature {
+        fields.push(format!("min_temperature={}", min_temperature));
+    }
+    if let Some(max_temp_time) = data.max_temp_time {
+        fields.push(format!("max_temp_time={}i", max_temp_time));
+    }
+    if let Some(min_temp_time) = data.min_temp_time {
+        fields.push(format!("min_temp_time={}i", min_temp_time));
+    }
+
+    format!(
+        "{},{} {} {}",
+        escape(&config.measurement),
+        tags.join(","),
+        fields.join(","),
+        timestamp_ns
+    )
+}
+
+/// Sends the readings (name of the device and data) as line protocol datagrams (e.g. to the socket_listener of Telegraf). Delivery is not confirmed.
+pub fn send<'a>(
+    config: &InfluxUdpConfig,
+    instance: Option<&str>,
+    readings: impl Iterator<Item = (&'a str, &'a ThermoBeaconFullReadResult)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((config.host.as_str(), config.port))?;
+    let timestamp_ns = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+
+    for (name, data) in readings {
+        let line = to_line(config, instance, name, data, timestamp_ns);
+        trace!("Sending line protocol {}", line);
+        // One datagram per reading, to stay well below the MTU
+        socket.send(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn formats_line_protocol() {
+        let config = InfluxUdpConfig {
+            host: "localhost".to_string(),
+            port: 8089,
+            measurement: "thermobeacon".to_string(),
+            tags: HashMap::from([("site".to_string(), "main house".to_string())]),
+        };
+        let data = ThermoBeaconFullReadResult {
+            temperature: -2.5,
+            uptime: 42,
+            max_temperature: Some(0.0),
+            min_temperature: Some(0.0),
+            max_temp_time: Some(0),
+            min_temp_time: Some(0),
+            ..Default::default()
+        };
+        let line = to_line(&config, None, "Living room", &data, 1000);
+        assert_eq!(
+            line,
+            "thermobeacon,name=Living\\ room,mac=00:00:00:00:00:00,site=main\\ house battery_level=0,humidity=0,temperature=-2.5,uptime=42i,button_pressed=false,max_temperature=0,min_temperature=0,max_temp_time=0i,min_temp_time=0i 1000"
+        );
+    }
+}
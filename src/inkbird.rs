@@ -0,0 +1,71 @@
+//! Decoder for the advertisements of Inkbird IBS-TH1 / IBS-TH2 sensors (local name 'sps')
+//!
+//! The Inkbird sensors do not advertise a company id, the first two bytes of the manufacturer data are the temperature.
+//! Message length: 9 bytes (little endian), 2 bytes key of the manufacturer data and 7 bytes data
+//! bytes | content
+//! ========================================================
+//! 00-01 | temperature (0.01 °C, signed), key of the manufacturer data
+//! 02-03 | humidity (0.01 %)
+//! 04-04 | 1 if the temperature is measured by the external probe, else 0
+//! 05-06 | checksum
+//! 07-07 | battery level (%)
+//! 08-08 | 00 ?
+
+use btleplug::api::BDAddr;
+use std::error::Error;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// Length of the manufacturer data (without the key)
+pub const DATA_LENGTH: usize = 7;
+
+/// Decodes the manufacturer data (key and data) of an Inkbird advertisement. The advertisement does not contain the MAC, so it has to be given.
+/// If the temperature is measured by the external probe, it is also reported as external temperature.
+pub fn parse(
+    mac: BDAddr,
+    key: u16,
+    data: &[u8],
+) -> Result<ThermoBeaconFullReadResult, Box<dyn Error + Send + Sync>> {
+    if data.len() != DATA_LENGTH {
+        return Err(format!("Unknown Inkbird frame length {}", data.len()).into());
+    }
+    let temperature = f32::from(key as i16) / 100.0;
+    let external_probe = data[2] == 1;
+
+    Ok(ThermoBeaconFullReadResult {
+        temperature,
+        humidity: f32::from(u16::from_le_bytes([data[0], data[1]])) / 100.0,
+        battery_level: f32::from(data[5]),
+        external_temperature: external_probe.then_some(temperature),
+        mac,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_internal_and_external_temperatures() {
+        let mac: BDAddr = "49:42:08:00:00:03".parse().unwrap();
+        // 23.45 °C internal, 51.2 %, 80 %
+        let reading = parse(mac, 2345, &[0x00, 0x14, 0x00, 0xAB, 0xCD, 0x50, 0x08]).unwrap();
+        assert_eq!(reading.temperature, 23.45);
+        assert_eq!(reading.humidity, 51.2);
+        assert_eq!(reading.battery_level, 80.0);
+        assert_eq!(reading.external_temperature, None);
+
+        // -12.5 °C measured by the external probe
+        let reading = parse(
+            mac,
+            -1250i16 as u16,
+            &[0x00, 0x00, 0x01, 0xAB, 0xCD, 0x50, 0x08],
+        )
+        .unwrap();
+        assert_eq!(reading.temperature, -12.5);
+        assert_eq!(reading.external_temperature, Some(-12.5));
+
+        assert!(parse(mac, 2345, &[0x00, 0x14]).is_err());
+    }
+}
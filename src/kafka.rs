@@ -0,0 +1,174 @@
+//! Kafka output (Cargo feature `kafka`): produces each reading as record to a topic, keyed by the device, through librdkafka.
+//! The payload is the message in the configured payload format, so consumers decode it like the MQTT messages.
+
+use std::error::Error;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rdkafka::{
+    config::ClientConfig,
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+};
+
+use crate::{
+    configuration::{AppConfig, AppDevice, KafkaConfig, KafkaKey},
+    sink::{Message, Sink},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// librdkafka configuration of the producer
+fn client_config(kafka: &KafkaConfig) -> ClientConfig {
+    let mut client_config = ClientConfig::new();
+    client_config.set("bootstrap.servers", kafka.brokers.join(","));
+    let tls = kafka.tls || kafka.ca_cert.is_some();
+    let security_protocol = match (tls, kafka.sasl.is_some()) {
+        (false, false) => "plaintext",
+        (true, false) => "ssl",
+        (false, true) => "sasl_plaintext",
+        (true, true) => "sasl_ssl",
+    };
+    client_config.set("security.protocol", security_protocol);
+    if let Some(ca_cert) = &kafka.ca_cert {
+        client_config.set("ssl.ca.location", ca_cert);
+    }
+    if let Some(sasl) = &kafka.sasl {
+        client_config
+            .set("sasl.mechanisms", &sasl.mechanism)
+            .set("sasl.username", &sasl.username)
+            .set("sasl.password", sasl.password.clone().unwrap_or_default());
+    }
+    client_config.set("message.timeout.ms", kafka.timeout.as_millis().to_string());
+    for (key, value) in kafka.properties.iter() {
+        client_config.set(key, value);
+    }
+    client_config
+}
+
+/// Produces the readings to the configured Kafka topic
+pub struct KafkaSink {
+    config: AppConfig,
+    kafka: KafkaConfig,
+    producer: FutureProducer,
+}
+
+impl KafkaSink {
+    pub fn new(
+        config: &AppConfig,
+        kafka: &KafkaConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let producer = client_config(kafka).create()?;
+        Ok(KafkaSink {
+            config: config.clone(),
+            kafka: kafka.clone(),
+            producer,
+        })
+    }
+
+    /// Produces the message of the given device and waits for the acknowledgement of the brokers
+    async fn produce(
+        &self,
+        device: &AppDevice,
+        msg: &Message,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let payload = msg.payload(
+            self.config.payload_format(device),
+            self.config.payload_template(device),
+        )?;
+        let key = match self.kafka.key {
+            KafkaKey::Mac => msg.data.mac.to_string(),
+            KafkaKey::Name => device.name.clone(),
+        };
+        let record = FutureRecord::to(&self.kafka.topic)
+            .key(&key)
+            .payload(&payload)
+            .timestamp(timestamp.timestamp_millis());
+        let (partition, offset) = self
+            .producer
+            .send(record, Timeout::After(self.kafka.timeout))
+            .await
+            .map_err(|(e, _)| e)?;
+        trace!(
+            "Produced reading of {} to {} (partition {}, offset {})",
+            device.name,
+            self.kafka.topic,
+            partition,
+            offset
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let now = Utc::now();
+        let msg = Message::at(&self.config, device, data.clone(), now);
+        self.produce(device, &msg, now).await
+    }
+
+    /// Recovered readings carry the time they were taken as record timestamp
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::at(&self.config, device, data.clone(), measured_at);
+        self.produce(device, &msg, measured_at).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::KafkaSaslConfig;
+    use std::time::Duration;
+
+    #[test]
+    fn configures_security_protocol() {
+        let kafka = KafkaConfig {
+            brokers: vec!["kafka1:9093".to_string(), "kafka2:9093".to_string()],
+            topic: "thermobeacon".to_string(),
+            key: KafkaKey::Mac,
+            tls: true,
+            ca_cert: None,
+            sasl: Some(KafkaSaslConfig {
+                mechanism: "SCRAM-SHA-512".to_string(),
+                username: "gateway".to_string(),
+                password: Some("secret".to_string()),
+                password_file: None,
+            }),
+            timeout: Duration::from_secs(5),
+            properties: [("compression.type".to_string(), "zstd".to_string())].into(),
+        };
+        let producer_config = client_config(&kafka);
+        assert_eq!(
+            producer_config.get("bootstrap.servers"),
+            Some("kafka1:9093,kafka2:9093")
+        );
+        assert_eq!(producer_config.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(
+            producer_config.get("sasl.mechanisms"),
+            Some("SCRAM-SHA-512")
+        );
+        assert_eq!(producer_config.get("message.timeout.ms"), Some("5000"));
+        assert_eq!(producer_config.get("compression.type"), Some("zstd"));
+
+        let plain = client_config(&KafkaConfig {
+            tls: false,
+            sasl: None,
+            ..kafka
+        });
+        assert_eq!(plain.get("security.protocol"), Some("plaintext"));
+    }
+}
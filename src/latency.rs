@@ -0,0 +1,64 @@
+use std::{error::Error, time::Duration};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, Mutex},
+    time::Instant,
+};
+use uuid::Uuid;
+
+use crate::{configuration::AppConfig, mqtt_router::MessageRouter};
+
+/// Time to wait for a probe message to return
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Measures the publish -> receive round-trip latency of the MQTT broker by publishing probe messages to a topic the client is subscribed to
+pub struct LatencyProbe {
+    cli: AsyncClient,
+    topic: String,
+    receiver: Mutex<UnboundedReceiver<mqtt::Message>>,
+}
+
+impl LatencyProbe {
+    /// Creates a new probe and registers for the messages on the probe topic '{topic base}/latency'
+    pub fn new(config: &AppConfig, cli: &AsyncClient, router: &MessageRouter) -> Self {
+        let topic = format!("{}/latency", config.topic_base());
+        let receiver = router.route(&topic);
+
+        LatencyProbe {
+            cli: cli.clone(),
+            topic,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Publishes a probe message and waits for it to be received
+    pub async fn measure(&self) -> Result<Duration, Box<dyn Error + Send + Sync>> {
+        let cli = &self.cli;
+        // Subscribe every time, since the subscription is lost if the client reconnects with a clean session
+        cli.subscribe(&self.topic, 1).await?;
+
+        let mut receiver = self.receiver.lock().await;
+        // Drop outdated probes (e.g. from previous, timed out measurements)
+        while receiver.try_recv().is_ok() {}
+
+        let id = Uuid::new_v4().to_string();
+        let start = Instant::now();
+        let deadline = start + PROBE_TIMEOUT;
+        cli.publish(mqtt::Message::new(&self.topic, id.clone(), 1))
+            .await?;
+
+        loop {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(msg)) if msg.payload_str() == id => return Ok(start.elapsed()),
+                Ok(Some(_)) => continue,
+                Ok(None) => return Err("Probe message channel closed".into()),
+                Err(_) => {
+                    return Err(
+                        format!("Probe message not received within {:?}", PROBE_TIMEOUT).into(),
+                    )
+                }
+            }
+        }
+    }
+}
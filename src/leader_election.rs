@@ -0,0 +1,126 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use tokio::time::Instant;
+
+use crate::{configuration::LeaderElectionConfig, mqtt_router::MessageRouter};
+
+/// Heartbeat message published by the leader
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+struct Heartbeat {
+    /// Id of the leading node
+    node: String,
+    /// Time of the heartbeat (seconds since epoch, informational only)
+    timestamp: u64,
+}
+
+/// Coordinates redundant gateways covering the same area: Only the leader publishes sensor values, it announces itself by heartbeats on a shared lock topic.
+/// A standby node takes over if no heartbeat was received within the configured timeout. If two nodes claim leadership at the same time, the node with the lower id wins.
+pub struct LeaderElection {
+    node_id: String,
+    config: LeaderElectionConfig,
+    leader: AtomicBool,
+}
+
+impl LeaderElection {
+    pub fn new(node_id: String, config: LeaderElectionConfig) -> Arc<Self> {
+        info!(
+            "Leader election enabled on topic {} as node {}",
+            config.topic, node_id
+        );
+        Arc::new(LeaderElection {
+            node_id,
+            config,
+            leader: AtomicBool::new(false),
+        })
+    }
+
+    /// Is this node currently the leader?
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    fn set_leader(&self, leader: bool) {
+        if self.leader.swap(leader, Ordering::Relaxed) != leader {
+            if leader {
+                info!("Node {} became leader", self.node_id);
+            } else {
+                warn!("Node {} stepped down to standby", self.node_id);
+            }
+        }
+    }
+
+    /// Starts the background task exchanging heartbeats
+    pub fn start(self: &Arc<Self>, cli: AsyncClient, router: &MessageRouter) {
+        let election = self.clone();
+        let receiver = router.route(&self.config.topic);
+        tokio::spawn(async move { election.run(cli, receiver).await });
+    }
+
+    async fn run(
+        &self,
+        cli: AsyncClient,
+        mut receiver: tokio::sync::mpsc::UnboundedReceiver<mqtt::Message>,
+    ) {
+        let timeout = Duration::from_secs(self.config.heartbeat_timeout);
+        let started = Instant::now();
+        // Last heartbeat of another node
+        let mut last_foreign: Option<(String, Instant)> = None;
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.heartbeat_interval));
+
+        loop {
+            tokio::select! {
+                Some(msg) = receiver.recv() => {
+                    match serde_json::from_slice::<Heartbeat>(msg.payload()) {
+                        Ok(heartbeat) if heartbeat.node != self.node_id => {
+                            trace!("Received heartbeat of node {}", heartbeat.node);
+                            // Both nodes claim leadership: The lower id wins
+                            if self.is_leader() && heartbeat.node < self.node_id {
+                                self.set_leader(false);
+                            }
+                            last_foreign = Some((heartbeat.node, Instant::now()));
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("Invalid heartbeat on {}: {}", self.config.topic, e),
+                    }
+                }
+                _ = ticker.tick() => {
+                    // Subscribe every time, since the subscription is lost if the client reconnects with a clean session
+                    if let Err(e) = cli.subscribe(&self.config.topic, 1).await {
+                        warn!("Failed to subscribe to {}: {}", self.config.topic, e);
+                        continue;
+                    }
+
+                    let foreign_leader_alive = last_foreign
+                        .as_ref()
+                        .map(|(_, received)| received.elapsed() < timeout)
+                        .unwrap_or(false);
+                    // Wait one timeout period after start to learn about an existing leader
+                    if !self.is_leader() && !foreign_leader_alive && started.elapsed() >= timeout {
+                        self.set_leader(true);
+                    }
+
+                    if self.is_leader() {
+                        let heartbeat = Heartbeat {
+                            node: self.node_id.clone(),
+                            timestamp: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .map(|d| d.as_secs())
+                                .unwrap_or_default(),
+                        };
+                        let payload = serde_json::to_string(&heartbeat).unwrap();
+                        if let Err(e) = cli.publish(mqtt::Message::new(&self.config.topic, payload, 1)).await {
+                            warn!("Failed to publish heartbeat: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
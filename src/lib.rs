@@ -0,0 +1,43 @@
+//! Decoder for the BLE advertisements of ThermoBeacon hygrometers (and other sensor types, see [`DeviceType`]), used by the thermobeacon-server binary.
+//!
+//! Single advertisements are decoded with [`parse_advertisement`], [`scan_stream`] continuously listens for the
+//! advertisements of the given devices and yields a [`Reading`] for each of them:
+//!
+//! ```no_run
+//! use btleplug::{api::BDAddr, platform::Manager};
+//! use futures::StreamExt;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! let manager = Manager::new().await?;
+//! let devices: Vec<BDAddr> = vec!["11:22:33:44:55:66".parse()?];
+//! let mut readings = Box::pin(thermobeacon_server::scan_stream(&manager, &devices).await?);
+//! while let Some(reading) = readings.next().await {
+//!     println!("{}: {} °C", reading.mac, reading.temperature);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#[macro_use]
+extern crate log;
+
+#[cfg(all(feature = "bluer", target_os = "linux"))]
+pub mod bluer_scanner;
+pub mod bthome;
+pub mod capture;
+pub mod govee;
+pub mod inkbird;
+pub mod ruuvi;
+pub mod scanner;
+pub mod sensors;
+pub mod switchbot;
+pub mod thermobeacon_gatt;
+pub mod thermobeacon_models;
+pub mod thermobeacon_protocol;
+pub mod xiaomi_atc;
+
+pub use sensors::DeviceType;
+pub use thermobeacon_protocol::{
+    parse_advertisement, parse_advertisement_of_model, scan_stream, scan_stream_with_types,
+    AdvertisementDecoder, ThermoBeaconData, ThermoBeaconFrame,
+    ThermoBeaconFullReadResult as Reading, ThermoBeaconMinMaxData,
+};
@@ -1,78 +1,205 @@
-extern crate paho_mqtt as mqtt;
 extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
+mod alerts;
+mod aws_iot;
+mod azure_iot;
+mod backoff;
+mod backup;
+mod bridge;
+mod brokers;
+mod button;
+mod calendar;
+mod capture_file;
+mod change_filter;
+mod clock;
+mod comfort;
+mod command;
 mod configuration;
+mod dbus_service;
+mod device_actions;
+mod device_availability;
+mod device_events;
+mod device_info;
+mod discover;
+mod doctor;
+#[cfg(all(test, feature = "e2e"))]
+mod e2e_tests;
+mod file_output;
+mod graphite;
 mod health_check_server;
+mod history_download;
 mod homeassistant;
-mod thermobeacon_protocol;
+mod icinga;
+mod influx;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod latency;
+mod leader_election;
+mod mqtt;
+#[cfg(all(feature = "paho", not(feature = "rumqttc")))]
+mod mqtt_paho;
+mod mqtt_router;
+#[cfg(feature = "rumqttc")]
+mod mqtt_rumqttc;
+#[cfg(feature = "nats")]
+mod nats;
+mod notifications;
+mod number_format;
+mod payload_format;
+mod permission_check;
+mod publish_policy;
+mod readings;
+mod reconnect;
+mod record_log;
+mod remote_devices;
+mod replay;
+mod rest_api;
+mod scan_trigger;
+mod simulation;
+mod sink;
+mod snmp;
+mod sparkplug;
+mod spool;
+mod store;
+mod time_window;
+mod webhook;
+mod zabbix;
+
+// The protocol is provided by the library part of this crate
+use thermobeacon_server::{
+    capture,
+    scanner::{self, DefaultScanner, PeripheralOf},
+    sensors, thermobeacon_gatt, thermobeacon_models, thermobeacon_protocol,
+};
 
 use btleplug::{api::BDAddr, platform::Manager};
 use chrono::Utc;
+use clap::{Parser, Subcommand};
 use configuration::{AppDevice, MqttConfig};
-use mqtt::AsyncClient;
 
-use std::{error::Error, time::Duration};
+use futures::{future::join_all, StreamExt};
+use std::{collections::HashMap, error::Error, path::PathBuf, sync::Arc, time::Duration};
+use tokio::time::Instant;
 
 use crate::{
-    configuration::{read_configuration, AppConfig, DEFAULT_TIMEZONE},
-    health_check_server::{set_health_status, start_healthcheck_server, HealthStatus},
+    backoff::DeviceBackoff,
+    bridge::RunStats,
+    calendar::ScheduleExceptions,
+    configuration::{read_configuration, AppConfig, Schedule},
+    health_check_server::{
+        set_health_status, set_mqtt_latency, set_permission_denials, start_healthcheck_server,
+        HealthStatus,
+    },
+    latency::LatencyProbe,
+    leader_election::LeaderElection,
+    mqtt::{self, AsyncClient, MqttClient},
+    mqtt_router::MessageRouter,
+    sink::{Message, Sink},
+    thermobeacon_protocol::{PeripheralCache, ThermoBeaconFullReadResult},
 };
 
-/// Structure of MQTT message send
-#[derive(Debug, Default, serde_derive::Serialize, PartialEq)]
-struct Message {
-    data: thermobeacon_protocol::ThermoBeaconFullReadResult,
-    name: String,
+/// Command line interface of the server. Without any subcommand, the server collects and publishes data.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Verifies the hash chain and signatures of a record log file
+    VerifyLog {
+        /// Record log file of a single device
+        file: PathBuf,
+    },
+    /// Checks the environment (configuration, Bluetooth, D-Bus, MQTT) and prints a report with remediation hints
+    Doctor,
+    /// Bundles the local state (e.g. record logs) into a single archive (.tar.gz)
+    Backup {
+        /// Archive to create
+        file: PathBuf,
+    },
+    /// Restores the local state from an archive created by 'backup'. The server should be stopped meanwhile.
+    Restore {
+        /// Archive to restore
+        file: PathBuf,
+        /// Replace existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Scans for nearby ThermoBeacons and prints their MACs, signal strengths and current readings
+    Discover {
+        /// Duration of the scan in seconds
+        #[arg(long, default_value_t = 30)]
+        seconds: u64,
+    },
+    /// Captures the raw advertisements of the configured devices to a NDJSON file for protocol debugging
+    Capture {
+        /// File to append the advertisements to
+        file: PathBuf,
+        /// Duration of the capture in seconds, captures until stopped otherwise
+        #[arg(long)]
+        seconds: Option<u64>,
+        /// Capture the advertisements of all peripherals in range, not only of the configured devices
+        #[arg(long)]
+        all: bool,
+    },
+    /// Replays advertisements captured by 'capture' through the decoding and publishing of the readings, instead of scanning
+    Replay {
+        /// Captured advertisements
+        file: PathBuf,
+        /// Keep the original time between the advertisements
+        #[arg(long)]
+        realtime: bool,
+    },
 }
 
-/// Tries to connect to the MQTT server using the given MqttConfig
+/// Tries to connect to the MQTT server using the given MqttConfig.
+/// If an availability topic is given, a retained 'online' is published to it on each connect and 'offline' is registered as last will.
+/// Falls back to MQTT 3.1.1 if the server rejects MQTT 5.
 pub async fn connect_to_mqtt(
     mqtt_config: &MqttConfig,
+    availability_topic: Option<&str>,
 ) -> Result<AsyncClient, Box<dyn Error + Send + Sync>> {
-    // Create the client
-    let cli = mqtt::AsyncClient::new(mqtt_config.url.clone().unwrap()).unwrap();
-
-    let conn_opts = if mqtt_config.password.is_some() && mqtt_config.username.is_some() {
-        debug!(
-            "Configuration of MQTT with user {} and password ***",
-            mqtt_config.username.clone().unwrap()
-        );
-
-        mqtt::ConnectOptionsBuilder::new_v5()
-            .keep_alive_interval(Duration::from_secs(mqtt_config.keep_alive))
-            .user_name(mqtt_config.username.clone().unwrap())
-            .password(mqtt_config.password.clone().unwrap())
-            .finalize()
-    } else {
-        debug!("Configuration of MQTT without username / password");
-        mqtt::ConnectOptionsBuilder::new_v5()
-            .keep_alive_interval(Duration::from_secs(mqtt_config.keep_alive))
-            .finalize()
-    };
-    // Connect with default options and wait for it to complete or fail
+    // Connect and wait for it to complete or fail
     debug!("Connecting to the MQTT server");
-    cli.connect(Some(conn_opts)).await?;
-
-    Ok(cli)
+    let presence = availability_topic.map(mqtt::Presence::availability);
+    match mqtt::create_and_connect(mqtt_config, presence.as_ref()).await? {
+        (cli, None) => Ok(cli),
+        (_, Some(e)) => Err(e),
+    }
 }
 
-/// Collects all results and prints them as JSON to the screen
-async fn collect_and_print_results(
+/// Collects all results and delivers them to all sinks. Returns the delivered messages.
+async fn collect_and_deliver_results(
+    config: &AppConfig,
     devices: &[AppDevice],
-    manager: &Manager,
-    seconds_to_scan: u64,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    scanning: &Scanning,
+    sinks: &[Box<dyn Sink>],
+) -> Result<Vec<Message>, Box<dyn Error + Send + Sync>> {
     debug!("Start collecting data ...");
-
-    // MAC adresses to check for ThermoBeacon devices
+    // MAC addresses to check for ThermoBeacon devices
     let macs: Vec<BDAddr> = devices
         .iter()
         .map(|f| f.mac.parse::<BDAddr>().unwrap() as BDAddr)
         .collect();
-    let results =
-        thermobeacon_protocol::read_all_configured(manager, &macs, seconds_to_scan).await?;
+
+    // Adapters are powered off or blocked after some reboots
+    scanner::ensure_powered(&scanning.scanner).await?;
+
+    // Collect data from these MAC addresses
+    let report = thermobeacon_protocol::read_all_configured(
+        &scanning.scanner,
+        &scanning.cache,
+        &macs,
+        &config.scan_options(),
+    )
+    .await?;
+    health_check_server::set_failed_adapters(report.failed_adapters);
+    let results = report.readings;
 
     debug!(
         "Data collected. Found {} of {} devices.",
@@ -80,6 +207,7 @@ async fn collect_and_print_results(
         devices.len()
     );
 
+    let mut messages = Vec::with_capacity(results.len());
     for result in results.into_iter() {
         let device = devices
             .iter()
@@ -88,155 +216,637 @@ async fn collect_and_print_results(
 
         info!("ThermoBeacon data: {:?}", result);
 
-        let msg = Message {
-            data: result,
-            name: device.name.clone(),
-        };
-        println!("{}", serde_json::to_string(&msg).unwrap());
+        // Unchanged readings are only kept for the local interfaces
+        if change_filter::should_publish(config, &result) {
+            sink::publish_all(sinks, &result, device).await?;
+            change_filter::published(&result);
+        } else {
+            debug!("Reading of {} unchanged, not published", device.name);
+        }
+        messages.push(Message::new(config, device, result));
     }
+    sink::finish_run_all(sinks).await?;
 
-    Ok(())
+    Ok(messages)
 }
 
-/// Collects all results and sends them to the given MQTT client
-async fn collect_and_send_results(
-    client: &AsyncClient,
+/// Publishes the changed availability of the given (searched) devices to all sinks. Failures are only logged and retried in the next run.
+async fn publish_device_availability(
+    config: &AppConfig,
     devices: &[AppDevice],
+    backoff: &DeviceBackoff,
+    sinks: &[Box<dyn Sink>],
+) {
+    for device in devices {
+        let mac = device.mac.parse::<BDAddr>().unwrap();
+        let online = backoff.misses(&mac) < config.offline_after_missing_runs;
+        if !device_availability::changed(mac, online) {
+            continue;
+        }
+        if online {
+            debug!("Device {} is online", device.name);
+        } else {
+            warn!(
+                "Device {} missing for {} runs, reporting it offline",
+                device.name, config.offline_after_missing_runs
+            );
+        }
+        notifications::check_availability(config, device, online).await;
+        for sink in sinks {
+            if let Err(e) = sink.publish_availability(device, online).await {
+                error!(
+                    "Failed to deliver availability of {} to {}: {}",
+                    device.name,
+                    sink.name(),
+                    e
+                );
+                device_availability::reset(mac);
+            }
+        }
+    }
+}
+
+/// Executes the actual job: Reads all devices (of the given schedule) and delivers the results to all sinks. Returns the delivered messages.
+async fn job(
+    config: &AppConfig,
+    schedule: Option<&Schedule>,
     manager: &Manager,
-    seconds_to_scan: u64,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    debug!("Start collecting data ...");
-    // MAC addresses to check for ThermoBeacon devices
-    let macs: Vec<BDAddr> = devices
+    scanning: &Scanning,
+    sinks: &[Box<dyn Sink>],
+    probe: &Option<LatencyProbe>,
+    backoff: &DeviceBackoff,
+) -> Result<Vec<Message>, Box<dyn Error + Send + Sync>> {
+    // Readings are marked if the clock is not reliable
+    clock::check(config.check_ntp_sync).await;
+    clock::start_run();
+
+    // The device list might have been updated on the broker
+    let config = &remote_devices::apply(config);
+
+    let scheduled: Vec<AppDevice> = config
+        .devices
         .iter()
-        .map(|f| f.mac.parse::<BDAddr>().unwrap() as BDAddr)
+        .filter(|d| schedule.is_none() || config.schedule(d).as_ref() == schedule)
+        .cloned()
         .collect();
+    // Devices missing for many runs are not searched for in every run
+    let devices = backoff.devices_to_read(&scheduled);
 
-    // Collect data from these MAC addresses
-    let results =
-        thermobeacon_protocol::read_all_configured(manager, &macs, seconds_to_scan).await?;
+    let messages = collect_and_deliver_results(config, &devices, scanning, sinks).await?;
 
-    debug!(
-        "Data collected. Found {} of {} devices.",
-        results.len(),
-        devices.len()
-    );
+    let found: Vec<BDAddr> = messages.iter().map(|msg| msg.data.mac).collect();
+    backoff.update(&devices, &found);
 
-    for result in results.into_iter() {
-        let device = devices
-            .iter()
-            .find(|it| it.mac.parse::<BDAddr>().unwrap() == result.mac)
-            .unwrap();
+    // Readings missed since the last run are recovered from the logs of the devices
+    if devices.iter().any(|d| d.download_history) {
+        history_download::recover(config, &devices, &messages, manager, sinks).await;
+    }
 
-        info!("ThermoBeacon data: {:?}", result);
+    // Firmware and hardware revisions are read once per device
+    if config.read_device_info {
+        device_info::read_missing(&devices, &messages, manager).await;
+    }
 
-        let msg = Message {
-            data: result,
-            name: device.name.clone(),
-        };
+    // Devices missing for several runs are reported offline
+    if config.offline_after_missing_runs > 0 {
+        publish_device_availability(config, &devices, backoff, sinks).await;
+    }
 
-        let topic = &device
-            .topic
-            .clone()
-            .unwrap_or(format!("ThermoBeacon/{}", device.name));
-        let qos = device.qos.unwrap_or(1);
+    // Alerts raised or cleared by the readings
+    if config.alerts.is_some() {
+        alerts::publish(config, &devices, &messages, sinks).await;
+    }
 
-        // Json message
-        let payload = serde_json::to_string(&msg).unwrap();
-        let msg = if device.retained {
-            mqtt::Message::new(topic, payload, qos)
-        } else {
-            mqtt::Message::new_retained(topic, payload, qos)
-        };
-        if !client.is_connected() {
-            info!("MQTT client is not connected. Try to reconnect ...");
-            client.reconnect().await?;
+    // Measure the round-trip latency of the MQTT broker
+    if let Some(p) = probe {
+        match p.measure().await {
+            Ok(latency) => {
+                debug!("MQTT round-trip latency {:?}", latency);
+                set_mqtt_latency(Some(latency));
+            }
+            Err(e) => {
+                warn!("Failed to measure MQTT round-trip latency: {}", e);
+                set_mqtt_latency(None);
+            }
         }
-        client.publish(msg).await?;
     }
 
-    Ok(())
+    deliver(config, &devices, &messages).await?;
+    Ok(messages)
 }
 
-/// Executes the actual job: Check mqtt config, connect if possible else just print the results.
-async fn job(
+/// Delivers the readings of the given (searched) devices to all additional outputs
+async fn deliver(
     config: &AppConfig,
-    manager: &Manager,
-    client: &Option<AsyncClient>,
+    devices: &[AppDevice],
+    messages: &[Message],
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    match client {
-        Some(c) => {
-            collect_and_send_results(c, &config.devices, manager, config.seconds_to_scan).await?;
+    // Send all readings as InfluxDB line protocol. This is fire-and-forget, so errors do not fail the run
+    if let Some(influx_config) = &config.influx_udp {
+        let readings = messages.iter().map(|msg| (msg.name.as_str(), &msg.data));
+        if let Err(e) = influx::send(influx_config, config.instance_name.as_deref(), readings) {
+            warn!(
+                "Failed to send readings to {}:{}: {}",
+                influx_config.host, influx_config.port, e
+            );
         }
-        None => {
-            warn!("No valid mqtt configuration found. Results are just printed to the console");
-            collect_and_print_results(&config.devices, manager, config.seconds_to_scan).await?;
+    }
+
+    // Send all readings as StatsD gauges, also fire-and-forget
+    if let Some(statsd_config) = &config.statsd {
+        let readings = messages.iter().map(|msg| (msg.name.as_str(), &msg.data));
+        if let Err(e) =
+            graphite::send_statsd(statsd_config, config.instance_name.as_deref(), readings)
+        {
+            warn!(
+                "Failed to send readings to {}:{}: {}",
+                statsd_config.host, statsd_config.port, e
+            );
+        }
+    }
+
+    // Send the alert notifications of the rules changed by the readings. Failures are only logged
+    if config.notifications.is_some() {
+        notifications::check_readings(config, messages).await;
+    }
+
+    // Keep the latest reading of each device for the local interfaces
+    for msg in messages.iter() {
+        readings::update(&msg.name, &msg.data);
+    }
+
+    // Append all readings to the tamper-evident log
+    if let Some(record_log_config) = &config.record_log {
+        for msg in messages.iter() {
+            record_log::append(record_log_config, &msg.name, &msg.data)?;
+        }
+    }
+
+    // Keep all readings in the local history store
+    if let Some(store_config) = &config.store {
+        for msg in messages.iter() {
+            store::insert(store_config, &msg.name, &msg.data)?;
+        }
+        store::prune(store_config)?;
+    }
+
+    // Push all readings to the Zabbix trapper. Failures do not fail the run either
+    if let Some(zabbix_config) = &config.zabbix {
+        let readings = messages.iter().map(|msg| (msg.name.as_str(), &msg.data));
+        if let Err(e) = zabbix::send(zabbix_config, readings).await {
+            warn!(
+                "Failed to send readings to Zabbix server {}:{}: {}",
+                zabbix_config.server, zabbix_config.port, e
+            );
+        }
+    }
+
+    // Send all readings to the Graphite server. Failures do not fail the run either
+    if let Some(graphite_config) = &config.graphite {
+        let readings = messages.iter().map(|msg| (msg.name.as_str(), &msg.data));
+        if let Err(e) =
+            graphite::send(graphite_config, config.instance_name.as_deref(), readings).await
+        {
+            warn!(
+                "Failed to send readings to Graphite server {}:{}: {}",
+                graphite_config.host, graphite_config.port, e
+            );
+        }
+    }
+
+    // Submit a passive check result per searched device to Icinga. Failures do not fail the run either
+    if let Some(icinga_config) = &config.icinga {
+        let readings: Vec<_> = messages
+            .iter()
+            .map(|msg| (msg.name.as_str(), &msg.data))
+            .collect();
+        if let Err(e) = icinga::submit(icinga_config, devices, &readings).await {
+            warn!(
+                "Failed to submit check results to Icinga {}: {}",
+                icinga_config.url, e
+            );
         }
     }
     Ok(())
 }
 
-/// Executes the job using the configured cron schedule
-async fn run_scheduled(
+/// Continuously listens for advertisements of the configured devices and delivers the readings as they arrive
+async fn run_listening(
     manager: Manager,
     config: AppConfig,
-    client: Option<AsyncClient>,
+    sinks: Vec<Box<dyn Sink>>,
+    election: Option<Arc<LeaderElection>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // There is some cron expression present, so we execute the job at a regular interval. Also check for a timezone to correctly calculate next execution.
-    let cron_str = config.cron.clone().unwrap();
+    let min_interval = Duration::from_secs(config.listen.as_ref().unwrap().min_interval);
+    let macs: Vec<BDAddr> = config
+        .devices
+        .iter()
+        .map(|f| f.mac.parse::<BDAddr>().unwrap())
+        .collect();
 
-    info!("Execute job with cron expressions {}", &cron_str);
+    let options = config.scan_options();
+    let mut readings = Box::pin(
+        thermobeacon_protocol::scan_stream_with_types(
+            &manager,
+            &macs,
+            &options.device_types,
+            &options.models,
+        )
+        .await?,
+    );
 
-    let timezone_str = config.timezone.as_ref().unwrap();
-    let timezone: chrono_tz::Tz = timezone_str
-        .parse()
-        .unwrap_or_else(|_| DEFAULT_TIMEZONE.to_string().parse().unwrap());
+    // Time of the last delivered reading of each device
+    let mut last_delivered: HashMap<BDAddr, Instant> = HashMap::new();
+    while let Some(result) = readings.next().await {
+        if last_delivered
+            .get(&result.mac)
+            .map(|last| last.elapsed() < min_interval)
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        // Standby gateways do not publish sensor values
+        if let Some(election) = &election {
+            if !election.is_leader() {
+                continue;
+            }
+        }
+        let device = match config
+            .devices
+            .iter()
+            .find(|d| d.mac.parse::<BDAddr>().unwrap() == result.mac)
+        {
+            Some(device) => device,
+            None => continue,
+        };
+        last_delivered.insert(result.mac, Instant::now());
+        deliver_reading(&config, &sinks, device, result).await;
+    }
+    Err("Stopped listening for advertisements".into())
+}
 
-    loop {
-        // Calculate the time of the next run (using the configured timezone)
-        let now = Utc::now().with_timezone(&timezone);
+/// Delivers a single reading received outside of a scheduled run (e.g. while listening) to all sinks and local interfaces
+async fn deliver_reading(
+    config: &AppConfig,
+    sinks: &[Box<dyn Sink>],
+    device: &AppDevice,
+    result: ThermoBeaconFullReadResult,
+) {
+    info!("ThermoBeacon data: {:?}", result);
+
+    clock::check(config.check_ntp_sync).await;
+    clock::start_run();
+    // Unchanged readings are only kept for the local interfaces
+    let delivered = if change_filter::should_publish(config, &result) {
+        let delivered = match sink::publish_all(sinks, &result, device).await {
+            Ok(()) => sink::finish_run_all(sinks).await,
+            Err(e) => Err(e),
+        };
+        if delivered.is_ok() {
+            change_filter::published(&result);
+        }
+        delivered
+    } else {
+        Ok(())
+    };
+    let msg = Message::new(config, device, result);
+    if config.alerts.is_some() {
+        alerts::publish(
+            config,
+            std::slice::from_ref(device),
+            std::slice::from_ref(&msg),
+            sinks,
+        )
+        .await;
+    }
+    let delivered = match delivered {
+        Ok(()) => {
+            deliver(
+                config,
+                std::slice::from_ref(device),
+                std::slice::from_ref(&msg),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    match delivered {
+        Ok(()) => set_health_status(HealthStatus::Ok),
+        Err(e) => {
+            set_health_status(HealthStatus::LastRunFailed(e.to_string()));
+            error!("Failed to deliver data of {}: {:?}", device.name, e);
+        }
+    }
+}
 
-        let next = cron_parser::parse(&cron_str, &now).unwrap();
-        let dur = next.signed_duration_since(now).to_std().unwrap();
+/// Delay before calculating the next run of a cron schedule again, after it failed
+const CRON_RETRY: Duration = Duration::from_secs(60);
+
+/// Calculates the time of the next run of the cron expression (using the given timezone). Schedule exceptions only apply to the global schedule.
+async fn next_cron_run(
+    cron_str: &str,
+    timezone: &chrono_tz::Tz,
+    exceptions: Option<&mut ScheduleExceptions>,
+) -> Result<Instant, Box<dyn Error + Send + Sync>> {
+    let now = Utc::now().with_timezone(timezone);
+
+    // Schedule exceptions (e.g. vacation mode) might replace the cron expression for today
+    let active_cron_str = match exceptions {
+        Some(exceptions) => {
+            exceptions.refresh().await;
+            match exceptions.active(now.date_naive()) {
+                Some(exception) => {
+                    debug!(
+                        "Schedule exception {} active",
+                        exception.name.as_deref().unwrap_or("(unnamed)")
+                    );
+                    exception
+                        .cron
+                        .clone()
+                        .unwrap_or_else(|| cron_str.to_string())
+                }
+                None => cron_str.to_string(),
+            }
+        }
+        None => cron_str.to_string(),
+    };
 
-        let instant = tokio::time::Instant::now() + dur;
+    let next = match cron_parser::parse(&active_cron_str, &now) {
+        Ok(next) => next,
+        Err(e) => {
+            error!(
+                "Invalid cron expression '{}' of the schedule exception, using '{}': {:?}",
+                active_cron_str, cron_str, e
+            );
+            cron_parser::parse(cron_str, &now)
+                .map_err(|e| format!("Invalid cron expression '{}': {:?}", cron_str, e))?
+        }
+    };
+    let dur = next.signed_duration_since(now).to_std()?;
 
-        info!("Next job execution {:?}", next);
-        // Sleep until the next run
-        tokio::time::sleep_until(instant).await;
-        // Finally execute run
-        match job(&config, &manager, &client).await {
-            Ok(()) => {
-                set_health_status(HealthStatus::Ok);
-                debug!("Run was successful");
-            }
-            Err(e) => {
-                set_health_status(HealthStatus::LastRunFailed(e.to_string()));
-                error!(
-                    "Failed to read and deliver data, trying again next time: {:?}",
-                    e
+    info!("Next job execution of '{}' {:?}", cron_str, next);
+    Ok(Instant::now() + dur)
+}
+
+/// Scanner of the scheduled runs with its cache of discovered peripherals
+struct Scanning {
+    scanner: DefaultScanner,
+    cache: PeripheralCache<PeripheralOf<DefaultScanner>>,
+}
+
+/// Everything needed to execute scheduled jobs
+struct Scheduler {
+    manager: Manager,
+    scanning: Scanning,
+    config: AppConfig,
+    sinks: Vec<Box<dyn Sink>>,
+    probe: Option<LatencyProbe>,
+    backoff: DeviceBackoff,
+    election: Option<Arc<LeaderElection>>,
+    /// Jobs of different schedules must not scan at the same time
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Scheduler {
+    /// Executes the job for the devices of a single schedule
+    async fn run_schedule(&self, schedule: Schedule) {
+        let mut ticker = match &schedule {
+            Schedule::Interval(interval) => {
+                info!(
+                    "Execute job every {}",
+                    humantime::format_duration(*interval)
                 );
+                let mut ticker = tokio::time::interval(*interval);
+                // Long runs delay the following ones instead of triggering a burst of runs
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                Some(ticker)
             }
+            Schedule::Cron(cron_str) => {
+                info!("Execute job with cron expressions {}", cron_str);
+                None
+            }
+        };
+
+        // Also check for a timezone to correctly calculate next execution of the cron expression.
+        let timezone = self.config.tz();
+
+        let mut exceptions = (self.config.global_schedule().as_ref() == Some(&schedule))
+            .then(|| ScheduleExceptions::new(self.config.exceptions.clone()));
+
+        loop {
+            // Resolves to false if the time of the next run could not be calculated
+            let next_run = async {
+                if let Some(ticker) = ticker.as_mut() {
+                    ticker.tick().await;
+                } else if let Schedule::Cron(cron_str) = &schedule {
+                    match next_cron_run(cron_str, &timezone, exceptions.as_mut()).await {
+                        Ok(instant) => tokio::time::sleep_until(instant).await,
+                        Err(e) => {
+                            error!(
+                                "Failed to calculate the next run of '{}', trying again in {}: {}",
+                                cron_str,
+                                humantime::format_duration(CRON_RETRY),
+                                e
+                            );
+                            tokio::time::sleep(CRON_RETRY).await;
+                            return false;
+                        }
+                    }
+                }
+                true
+            };
+            // Sleep until the next run, unless an immediate run (of the devices of all schedules) is requested
+            let devices_of = tokio::select! {
+                due = next_run => {
+                    if !due {
+                        continue;
+                    }
+                    Some(&schedule)
+                },
+                _ = scan_trigger::requested() => {
+                    info!("Executing requested run");
+                    None
+                }
+            };
+            // Standby gateways do not publish sensor values
+            if let Some(election) = &self.election {
+                if !election.is_leader() {
+                    debug!("Standby node, skipping run");
+                    set_health_status(HealthStatus::Ok);
+                    if devices_of.is_none() {
+                        scan_trigger::completed(Err("Standby node, not scanning".to_string()));
+                    }
+                    continue;
+                }
+            }
+            // Finally execute run
+            let _running = self.running.lock().await;
+            let started = Instant::now();
+            let result = job(
+                &self.config,
+                devices_of,
+                &self.manager,
+                &self.scanning,
+                &self.sinks,
+                &self.probe,
+                &self.backoff,
+            )
+            .await;
+            bridge::completed(RunStats {
+                finished_at: Utc::now().to_rfc3339(),
+                duration_ms: started.elapsed().as_millis(),
+                devices_found: result
+                    .iter()
+                    .flatten()
+                    .map(|msg| msg.name.clone())
+                    .collect(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            });
+            match &result {
+                Ok(_) => {
+                    set_health_status(HealthStatus::Ok);
+                    debug!("Run was successful");
+                }
+                Err(e) => {
+                    set_health_status(HealthStatus::failed(e.as_ref()));
+                    error!(
+                        "Failed to read and deliver data, trying again next time: {:?}",
+                        e
+                    );
+                }
+            }
+            // Requested runs report their readings
+            if devices_of.is_none() {
+                scan_trigger::completed(result.map_err(|e| e.to_string()));
+            }
+        }
+    }
+
+    /// Executes the requested device actions (e.g. reset of the min/max values) between the runs
+    async fn run_device_actions(&self) {
+        let mut requests = device_actions::start();
+        while let Some(request) = requests.recv().await {
+            let _running = self.running.lock().await;
+            request.execute(&self.config, &self.manager).await;
         }
     }
 }
 
+/// Executes the job using the configured interval or cron schedules. Devices with a schedule of their own are read separately.
+async fn run_scheduled(scheduler: Scheduler) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let schedules = scheduler.config.schedules();
+    // Device lists received later on can only use these schedules
+    remote_devices::set_schedules(&schedules);
+    let unscheduled = scheduler
+        .config
+        .devices
+        .iter()
+        .filter(|d| scheduler.config.schedule(d).is_none());
+    for device in unscheduled {
+        warn!(
+            "No schedule for device {}, it is never read. Configure a global or a device specific 'cron' or 'interval'",
+            device.name
+        );
+    }
+    tokio::join!(
+        join_all(
+            schedules
+                .into_iter()
+                .map(|schedule| scheduler.run_schedule(schedule)),
+        ),
+        scheduler.run_device_actions()
+    );
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     pretty_env_logger::init();
 
-    let config = read_configuration();
+    let cli = Cli::parse();
+    let mut config = read_configuration();
+    // Simulated devices stand in for the real ones, if none are configured
+    if let Some(simulate) = &config.simulate {
+        if config.devices.is_empty() {
+            config.devices = simulation::devices(simulate);
+        }
+    }
+
+    match &cli.command {
+        Some(Command::VerifyLog { file }) => {
+            let key = config.record_log.as_ref().and_then(|c| c.key.as_deref());
+            if key.is_none() {
+                warn!("No record log key configured, only the hash chain is verified");
+            }
+            match record_log::verify(file, key) {
+                Ok(count) => {
+                    println!("{}: {} records verified", file.display(), count);
+                    return Ok(());
+                }
+                Err(e) => {
+                    println!("{}: verification failed: {}", file.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::Backup { file }) => match backup::backup(&config, file) {
+            Ok(contents) => {
+                println!(
+                    "{}: backup of [{}] created",
+                    file.display(),
+                    contents.join(", ")
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                println!("{}: backup failed: {}", file.display(), e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Restore { file, force }) => match backup::restore(&config, file, *force) {
+            Ok(count) => {
+                println!("{}: {} files restored", file.display(), count);
+                return Ok(());
+            }
+            Err(e) => {
+                println!("{}: restore failed: {}", file.display(), e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Doctor) => {
+            if doctor::run(&config).await {
+                return Ok(());
+            }
+            std::process::exit(1);
+        }
+        Some(Command::Discover { .. })
+        | Some(Command::Capture { .. })
+        | Some(Command::Replay { .. })
+        | None => {}
+    }
     // Single instance to prevent D-Bus error: The maximum number of active connections for UID 0 has been reached
     let manager = Manager::new().await?;
+    if let Some(Command::Discover { seconds }) = &cli.command {
+        return discover::run(&config, &manager, *seconds).await;
+    }
+    if let Some(Command::Capture { file, seconds, all }) = &cli.command {
+        return capture_file::run(&config, &manager, file, *seconds, *all).await;
+    }
+    let scanning = Scanning {
+        scanner: scanner::default_scanner(&manager).await?,
+        // Discovered peripherals are kept between runs
+        cache: PeripheralCache::default(),
+    };
+    let backoff = DeviceBackoff::new(config.backoff_after_missing_runs);
 
     debug!("config {:?}", &config);
 
     let client = if let Some(mqtt_config) = &config.mqtt {
-        let client = connect_to_mqtt(mqtt_config).await;
-        match client {
+        // Keeps trying to connect in the background if the broker is not reachable now
+        match reconnect::connect(&config, mqtt_config).await {
             Ok(c) => Some(c),
             Err(e) => {
-                error!("Failed to connect to MQTT server: {}", e);
+                error!("Failed to create MQTT client: {}", e);
                 None
             }
         }
@@ -245,23 +855,94 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         None
     };
 
-    // If an mqtt client is available, configure HA
+    // Dispatches incoming MQTT messages to all consumers
+    let router = client.as_ref().map(MessageRouter::new);
+
+    // The device list might be managed centrally on the broker
+    let devices_topic = config.mqtt.as_ref().and_then(|c| c.devices_topic.as_ref());
+    if let (Some(cli), Some(router), Some(topic)) = (&client, &router, devices_topic) {
+        remote_devices::start(&config, cli, router, topic).await;
+    }
+    let config = remote_devices::apply(&config);
+
+    // Commands like an immediate scan might be sent by Home Assistant
+    if let (Some(cli), Some(router), Some(true)) =
+        (&client, &router, config.mqtt.as_ref().map(|c| c.commands))
+    {
+        command::start(&config, cli, router).await;
+    }
+
+    // Device revisions read later on are announced again
     if let Some(cli) = &client {
+        device_info::start(&config, cli);
+    }
+
+    // Statistics of the gateway for fleet monitoring
+    let bridge_info_interval = config.mqtt.as_ref().and_then(|c| c.bridge_info_interval);
+    if let (Some(cli), Some(interval)) = (&client, bridge_info_interval) {
+        bridge::start(&config, cli, &manager, interval).await;
+    }
+
+    // If an mqtt client is connected, check the permissions of the broker and configure HA. Otherwise HA is configured once connected.
+    if let Some(cli) = client.as_ref().filter(|cli| cli.is_connected()) {
         if let Some(mqtt_config) = &config.mqtt {
+            if mqtt_config.permission_check {
+                info!("Checking publish permissions of the MQTT broker ...");
+                let denied = permission_check::check_publish_permissions(&config, cli).await;
+                set_permission_denials(denied);
+            }
             if mqtt_config.homeassistant {
                 info!("Home Assistant auto-discovery enabled!");
-                homeassistant::publish_homeassistant_device_discovery_messages(&config, cli)
+                homeassistant::publish_homeassistant_device_discovery_messages(&config, cli, None)
                     .await?;
             }
         }
     }
 
-    if config.cron.is_some() {
-        // Only start healthcheck server in cron jobs runs
+    // Devices might publish to other brokers than the default one
+    if !config.brokers.is_empty() {
+        brokers::init(&config).await;
+        brokers::publish_homeassistant_discovery(&config).await;
+    }
+
+    // Optionally measure the round-trip latency of the MQTT broker in each run
+    let probe = match (&client, &router, &config.mqtt) {
+        (Some(cli), Some(router), Some(mqtt_config)) if mqtt_config.latency_check => {
+            Some(LatencyProbe::new(&config, cli, router))
+        }
+        _ => None,
+    };
+
+    // Outputs the readings are delivered to
+    let sinks = sink::from_config(&config, &client);
+
+    if let Some(Command::Replay { file, realtime }) = &cli.command {
+        return replay::run(&config, &sinks, file, *realtime).await;
+    }
+
+    if !config.schedules().is_empty() || config.listen.is_some() || config.simulate.is_some() {
+        // Optionally coordinate with redundant gateways, only the leader publishes
+        let election = match (&client, &router, &config.mqtt) {
+            (Some(cli), Some(router), Some(mqtt_config)) => {
+                mqtt_config.leader_election.clone().map(|election_config| {
+                    let node_id = election_config
+                        .node_id
+                        .clone()
+                        .or(config.instance_name.clone())
+                        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                    let election = LeaderElection::new(node_id, election_config);
+                    election.start(cli.clone(), router);
+                    election
+                })
+            }
+            _ => None,
+        };
+
+        // Only start healthcheck server in cron jobs and listening runs
         if config.health.active {
             let ip = config.health.ip.as_str();
             let port = config.health.port;
-            start_healthcheck_server(ip.to_string(), port).await?;
+            start_healthcheck_server(ip.to_string(), port, &config).await?;
             info!(
                 "Started health check service at http://{}:{}/health",
                 ip, port
@@ -269,18 +950,46 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         } else {
             debug!("Health check server not active");
         }
-        tokio::spawn(run_scheduled(manager, config, client))
+        if let Some(dbus_config) = &config.dbus {
+            dbus_service::start(dbus_config.clone())?;
+            info!("Started D-Bus service {}", dbus_config.name);
+        }
+        if let Some(snmp_config) = &config.snmp {
+            snmp::start_agent(snmp_config.clone(), config.devices.clone()).await?;
+        }
+        if config.simulate.is_some() {
+            tokio::spawn(simulation::run(config, sinks, election))
+                .await?
+                .unwrap();
+        } else if config.listen.is_some() {
+            // The device list is fixed while listening
+            let config = remote_devices::apply(&config);
+            tokio::spawn(run_listening(manager, config, sinks, election))
+                .await?
+                .unwrap();
+        } else {
+            tokio::spawn(run_scheduled(Scheduler {
+                manager,
+                scanning,
+                config,
+                sinks,
+                probe,
+                backoff,
+                election,
+                running: tokio::sync::Mutex::new(()),
+            }))
             .await?
             .unwrap();
+        }
     } else {
-        info!("No cron descriptor found -> job is executed just once!");
-        match job(&config, &manager, &client).await {
-            Ok(()) => {
+        info!("No cron descriptor or interval found -> job is executed just once!");
+        match job(&config, None, &manager, &scanning, &sinks, &probe, &backoff).await {
+            Ok(_) => {
                 set_health_status(HealthStatus::Ok);
                 debug!("Run was successful");
             }
             Err(e) => {
-                set_health_status(HealthStatus::LastRunFailed(e.to_string()));
+                set_health_status(HealthStatus::failed(e.as_ref()));
                 error!("Failed to read and deliver data: {:?}", e);
             }
         };
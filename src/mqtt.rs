@@ -0,0 +1,241 @@
+//! MQTT client used by all MQTT features, behind the [`MqttClient`] trait. The backend is selected by Cargo feature:
+//! paho (C library, default) or rumqttc (pure Rust, feature `rumqttc`), e.g. for static musl / ARM cross-compiles.
+
+use async_trait::async_trait;
+use std::{borrow::Cow, error::Error, fmt, time::Duration};
+
+use crate::{
+    configuration::{MqttConfig, MqttProtocol},
+    publish_policy::PublishPolicy,
+};
+
+#[cfg(not(any(feature = "paho", feature = "rumqttc")))]
+compile_error!("Enable one of the MQTT backends with the Cargo feature 'paho' or 'rumqttc'");
+
+/// Client of the selected MQTT backend: rumqttc with the Cargo feature `rumqttc`, paho otherwise
+#[cfg(feature = "rumqttc")]
+pub type AsyncClient = crate::mqtt_rumqttc::RumqttcClient;
+/// Client of the selected MQTT backend: rumqttc with the Cargo feature `rumqttc`, paho otherwise
+#[cfg(all(feature = "paho", not(feature = "rumqttc")))]
+pub type AsyncClient = crate::mqtt_paho::PahoClient;
+
+/// Callback receiving all incoming messages
+pub type MessageCallback = Box<dyn Fn(Message) + Send + Sync>;
+
+/// Callback invoked after each connect, once the online message of the presence was published
+pub type ConnectedCallback = Box<dyn Fn() + Send + Sync>;
+
+/// A message published or received by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    qos: i32,
+    retained: bool,
+    /// MQTT 5 user properties
+    user_properties: Vec<(String, String)>,
+    /// MQTT 5 message expiry interval
+    message_expiry: Option<Duration>,
+}
+
+impl Message {
+    /// Creates a message, which is not retained by the broker
+    pub fn new<T: Into<String>, V: Into<Vec<u8>>>(topic: T, payload: V, qos: i32) -> Self {
+        Message {
+            topic: topic.into(),
+            payload: payload.into(),
+            qos,
+            retained: false,
+            user_properties: vec![],
+            message_expiry: None,
+        }
+    }
+
+    /// Creates a message, which is retained by the broker
+    pub fn new_retained<T: Into<String>, V: Into<Vec<u8>>>(topic: T, payload: V, qos: i32) -> Self {
+        Message {
+            retained: true,
+            ..Message::new(topic, payload, qos)
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Payload as (lossy) UTF-8 string
+    pub fn payload_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.payload)
+    }
+
+    pub fn qos(&self) -> i32 {
+        self.qos
+    }
+
+    pub fn retained(&self) -> bool {
+        self.retained
+    }
+
+    /// Sets the MQTT 5 user properties (key / value pairs). Not sent over MQTT 3.1.1 connections.
+    pub fn with_user_properties(self, user_properties: Vec<(String, String)>) -> Self {
+        Message {
+            user_properties,
+            ..self
+        }
+    }
+
+    /// Sets the MQTT 5 message expiry interval, after which the broker discards the message if not delivered yet
+    /// (retained messages included). Not sent over MQTT 3.1.1 connections.
+    pub fn with_message_expiry(self, message_expiry: Option<Duration>) -> Self {
+        Message {
+            message_expiry,
+            ..self
+        }
+    }
+
+    pub fn user_properties(&self) -> &[(String, String)] {
+        &self.user_properties
+    }
+
+    /// Message expiry interval in seconds, saturated to the maximum of the protocol
+    pub fn message_expiry_secs(&self) -> Option<u32> {
+        self.message_expiry
+            .map(|expiry| u32::try_from(expiry.as_secs()).unwrap_or(u32::MAX))
+    }
+}
+
+/// Messages announcing the presence of a client: `online` is published on each connect, `offline` is registered as last will
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Presence {
+    pub online: Message,
+    pub offline: Message,
+}
+
+impl Presence {
+    /// Retained 'online' / 'offline' on the given availability topic
+    pub fn availability(topic: &str) -> Self {
+        Presence {
+            online: PublishPolicy::AVAILABILITY.message(topic, "online"),
+            offline: PublishPolicy::AVAILABILITY.message(topic, "offline"),
+        }
+    }
+}
+
+/// Connection to an MQTT broker. Lost connections are re-established automatically once connected.
+#[async_trait]
+pub trait MqttClient: Clone + Send + Sync + Sized + 'static {
+    /// Creates a client for the given broker, without connecting it yet.
+    /// If a presence is given, its online message is published on each connect and its offline message is registered as last will.
+    fn new(
+        mqtt_config: &MqttConfig,
+        presence: Option<&Presence>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>>;
+
+    /// Connects to the broker and waits for the connection to complete or fail
+    async fn connect(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Re-establishes a lost connection
+    async fn reconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Disconnects from the broker
+    async fn disconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Replaces the password of the connection (e.g. a renewed access token) and reconnects with it
+    async fn set_password(&self, password: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    fn is_connected(&self) -> bool;
+
+    /// URL of the server the client is connected to, None if not connected or unknown
+    fn active_server(&self) -> Option<String>;
+
+    /// Publishes the message and waits for the acknowledgement of the broker (for QoS 1 and 2)
+    async fn publish(&self, msg: Message) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    async fn subscribe(&self, filter: &str, qos: i32) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Sets the callback receiving all incoming messages, replacing the previous one
+    fn set_message_callback(&self, callback: MessageCallback);
+
+    /// Sets the callback invoked after each connect (including automatic reconnects), replacing the previous one
+    fn set_connected_callback(&self, callback: ConnectedCallback);
+}
+
+/// The broker rejected the MQTT protocol version of the connection
+#[derive(Debug)]
+pub struct ProtocolRejected(pub MqttProtocol);
+
+impl fmt::Display for ProtocolRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MQTT server rejected protocol version {:?}", self.0)
+    }
+}
+
+impl Error for ProtocolRejected {}
+
+/// Creates a client and connects it, waiting for the connection to complete or fail. If the broker rejects MQTT 5, a client
+/// using MQTT 3.1.1 is connected instead. The client is returned even if the connection failed, together with the error.
+pub async fn create_and_connect(
+    mqtt_config: &MqttConfig,
+    presence: Option<&Presence>,
+) -> Result<(AsyncClient, Option<Box<dyn Error + Send + Sync>>), Box<dyn Error + Send + Sync>> {
+    if !mqtt_config.clean_start && mqtt_config.client_id.is_none() {
+        warn!("MQTT clean_start disabled without client_id, the session can't be resumed after a restart");
+    }
+    let cli = AsyncClient::new(mqtt_config, presence)?;
+    let e = match cli.connect().await {
+        Ok(()) => return Ok((cli, None)),
+        Err(e) => e,
+    };
+    if e.downcast_ref::<ProtocolRejected>().is_none() || mqtt_config.protocol != MqttProtocol::V5 {
+        return Ok((cli, Some(e)));
+    }
+
+    warn!("MQTT server rejected MQTT 5, falling back to MQTT 3.1.1");
+    let v311_config = MqttConfig {
+        protocol: MqttProtocol::V311,
+        ..mqtt_config.clone()
+    };
+    let cli = AsyncClient::new(&v311_config, presence)?;
+    let e = cli.connect().await.err();
+    Ok((cli, e))
+}
+
+/// Client id of the connection: the configured one or a random id
+pub fn client_id(mqtt_config: &MqttConfig) -> String {
+    match &mqtt_config.client_id {
+        Some(client_id) => client_id.clone(),
+        None => format!("thermobeacon-{}", uuid::Uuid::new_v4().simple()),
+    }
+}
+
+/// Session expiry interval in seconds (MQTT 5), saturated to the maximum of the protocol
+pub fn session_expiry_secs(mqtt_config: &MqttConfig) -> Option<u32> {
+    mqtt_config
+        .session_expiry
+        .map(|expiry| u32::try_from(expiry.as_secs()).unwrap_or(u32::MAX))
+}
+
+/// URLs of the MQTT servers in the order to try them: the primary server followed by the failover servers
+pub fn server_urls(mqtt_config: &MqttConfig) -> Vec<String> {
+    mqtt_config
+        .url
+        .iter()
+        .chain(mqtt_config.failover_urls.iter())
+        .cloned()
+        .collect()
+}
+
+/// Does the connection to the server with the given URL use TLS (configured or required by the URL scheme)?
+pub fn uses_tls(mqtt_config: &MqttConfig, url: &str) -> bool {
+    let tls_url = ["ssl://", "mqtts://", "wss://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme));
+    tls_url
+        || mqtt_config.ca_cert.is_some()
+        || mqtt_config.client_cert.is_some()
+        || mqtt_config.tls_insecure
+}
@@ -0,0 +1,363 @@
+//! MQTT backend using the paho C library (Cargo feature `paho`, default)
+
+use async_trait::async_trait;
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    configuration::{MqttConfig, MqttProtocol},
+    mqtt::{
+        self, ConnectedCallback, Message, MessageCallback, MqttClient, Presence, ProtocolRejected,
+    },
+    reconnect,
+};
+
+/// Paho client with the options to (re-)connect it
+#[derive(Clone)]
+pub struct PahoClient {
+    client: paho_mqtt::AsyncClient,
+    options: Arc<Mutex<paho_mqtt::ConnectOptions>>,
+    /// Configuration the options are built from, to rebuild them with a new password
+    mqtt_config: Arc<Mutex<MqttConfig>>,
+    presence: Option<Presence>,
+    protocol: MqttProtocol,
+    /// Server of the current connection
+    active_server: Arc<Mutex<Option<String>>>,
+    connected: Arc<Mutex<Option<ConnectedCallback>>>,
+}
+
+/// Did the broker reject the protocol version? MQTT 5 brokers answer with a reason code, older ones with a connect return code.
+fn protocol_rejected(e: &paho_mqtt::Error) -> bool {
+    matches!(
+        e,
+        paho_mqtt::Error::ReasonCode(paho_mqtt::ReasonCode::UnsupportedProtocolVersion)
+            | paho_mqtt::Error::ConnectReturn(
+                paho_mqtt::ConnectReturnCode::UnacceptableProtocolVersion
+            )
+    )
+}
+
+/// Converts the message, with its MQTT 5 properties unless they are not supported by the connection
+fn paho_message(msg: Message, properties: bool) -> paho_mqtt::Message {
+    let mut builder = paho_mqtt::MessageBuilder::new()
+        .topic(msg.topic())
+        .payload(msg.payload())
+        .qos(msg.qos())
+        .retained(msg.retained());
+    if properties {
+        let mut props = paho_mqtt::Properties::new();
+        for (key, value) in msg.user_properties() {
+            if let Err(e) =
+                props.push_string_pair(paho_mqtt::PropertyCode::UserProperty, key, value)
+            {
+                warn!("Invalid user property {}: {}", key, e);
+            }
+        }
+        if let Some(expiry) = msg.message_expiry_secs() {
+            if let Err(e) = props.push_u32(paho_mqtt::PropertyCode::MessageExpiryInterval, expiry) {
+                warn!("Invalid message expiry interval {}: {}", expiry, e);
+            }
+        }
+        builder = builder.properties(props);
+    }
+    builder.finalize()
+}
+
+impl From<Message> for paho_mqtt::Message {
+    fn from(msg: Message) -> Self {
+        paho_message(msg, true)
+    }
+}
+
+impl From<&paho_mqtt::Message> for Message {
+    fn from(msg: &paho_mqtt::Message) -> Self {
+        if msg.retained() {
+            Message::new_retained(msg.topic(), msg.payload(), msg.qos())
+        } else {
+            Message::new(msg.topic(), msg.payload(), msg.qos())
+        }
+    }
+}
+
+/// MQTT 5 connect properties of the session
+fn session_properties(
+    mqtt_config: &MqttConfig,
+) -> Result<paho_mqtt::Properties, Box<dyn Error + Send + Sync>> {
+    let mut properties = paho_mqtt::Properties::new();
+    if let Some(expiry) = mqtt::session_expiry_secs(mqtt_config) {
+        properties.push_u32(paho_mqtt::PropertyCode::SessionExpiryInterval, expiry)?;
+    }
+    if let Some(receive_maximum) = mqtt_config.receive_maximum {
+        properties.push_u16(paho_mqtt::PropertyCode::ReceiveMaximum, receive_maximum)?;
+    }
+    Ok(properties)
+}
+
+/// Builds the TLS options of the given MqttConfig. Returns None if TLS is not used (neither configured nor required by the URL scheme).
+fn ssl_options(
+    mqtt_config: &MqttConfig,
+) -> Result<Option<paho_mqtt::SslOptions>, Box<dyn Error + Send + Sync>> {
+    let tls = mqtt::server_urls(mqtt_config)
+        .iter()
+        .any(|url| mqtt::uses_tls(mqtt_config, url));
+    if !tls {
+        return Ok(None);
+    }
+
+    let mut builder = paho_mqtt::SslOptionsBuilder::new();
+    if let Some(ca_cert) = &mqtt_config.ca_cert {
+        builder.trust_store(ca_cert)?;
+    }
+    if let Some(client_cert) = &mqtt_config.client_cert {
+        builder.key_store(client_cert)?;
+    }
+    if let Some(client_key) = &mqtt_config.client_key {
+        builder.private_key(client_key)?;
+    }
+    if !mqtt_config.alpn.is_empty() {
+        let protos: Vec<&str> = mqtt_config.alpn.iter().map(String::as_str).collect();
+        builder.alpn_protos(&protos);
+    }
+    if mqtt_config.tls_insecure {
+        warn!("TLS certificate verification of the MQTT server is disabled");
+        builder.enable_server_cert_auth(false).verify(false);
+    }
+    Ok(Some(builder.finalize()))
+}
+
+/// Builds the options of the connections to the servers of the given MqttConfig
+fn connect_options(
+    mqtt_config: &MqttConfig,
+    presence: Option<&Presence>,
+) -> Result<paho_mqtt::ConnectOptions, Box<dyn Error + Send + Sync>> {
+    let urls = mqtt::server_urls(mqtt_config);
+    let mut conn_opts = match mqtt_config.protocol {
+        MqttProtocol::V5 => {
+            let mut builder = paho_mqtt::ConnectOptionsBuilder::new_v5();
+            builder
+                .clean_start(mqtt_config.clean_start)
+                .properties(session_properties(mqtt_config)?);
+            builder
+        }
+        MqttProtocol::V311 => {
+            let mut builder = paho_mqtt::ConnectOptionsBuilder::new();
+            builder
+                .mqtt_version(paho_mqtt::MQTT_VERSION_3_1_1)
+                .clean_session(mqtt_config.clean_start);
+            builder
+        }
+    };
+    conn_opts
+        .keep_alive_interval(Duration::from_secs(mqtt_config.keep_alive))
+        .automatic_reconnect(reconnect::MIN_DELAY, reconnect::MAX_DELAY);
+    if urls.len() > 1 {
+        debug!(
+            "Configuration of MQTT with failover servers {:?}",
+            &urls[1..]
+        );
+        conn_opts.server_uris(&urls);
+    }
+    if mqtt_config.password.is_some() && mqtt_config.username.is_some() {
+        debug!(
+            "Configuration of MQTT with user {} and password ***",
+            mqtt_config.username.clone().unwrap()
+        );
+        conn_opts
+            .user_name(mqtt_config.username.clone().unwrap())
+            .password(mqtt_config.password.clone().unwrap());
+    } else {
+        debug!("Configuration of MQTT without username / password");
+    }
+    if let Some(ssl_opts) = ssl_options(mqtt_config)? {
+        debug!("Configuration of MQTT with TLS");
+        conn_opts.ssl_options(ssl_opts);
+    }
+    // MQTT 3.1.1 does not support properties
+    let properties = mqtt_config.protocol == MqttProtocol::V5;
+    if let Some(presence) = presence {
+        debug!(
+            "Configuration of MQTT with last will on {}",
+            presence.offline.topic()
+        );
+        conn_opts.will_message(paho_message(presence.offline.clone(), properties));
+    }
+    Ok(conn_opts.finalize())
+}
+
+#[async_trait]
+impl MqttClient for PahoClient {
+    fn new(
+        mqtt_config: &MqttConfig,
+        presence: Option<&Presence>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let version = match mqtt_config.protocol {
+            MqttProtocol::V5 => paho_mqtt::MQTT_VERSION_5,
+            MqttProtocol::V311 => paho_mqtt::MQTT_VERSION_3_1_1,
+        };
+        let urls = mqtt::server_urls(mqtt_config);
+        let primary = urls.first().ok_or("No MQTT server URL configured")?;
+        let create_opts = paho_mqtt::CreateOptionsBuilder::new()
+            .server_uri(primary)
+            .client_id(mqtt::client_id(mqtt_config))
+            .mqtt_version(version)
+            .finalize();
+        let client = paho_mqtt::AsyncClient::new(create_opts)?;
+
+        let options = connect_options(mqtt_config, presence)?;
+        // MQTT 3.1.1 does not support properties
+        let properties = mqtt_config.protocol == MqttProtocol::V5;
+
+        let active_server = Arc::new(Mutex::new(None));
+        // Paho does not report the server of automatic reconnects, it is only known without failover servers
+        let single_server = (urls.len() == 1).then(|| primary.clone());
+        let online = presence.map(|p| p.online.clone());
+        let connected_server = active_server.clone();
+        let connected: Arc<Mutex<Option<ConnectedCallback>>> = Arc::new(Mutex::new(None));
+        let connected_callback = connected.clone();
+        client.set_connected_callback(move |cli| {
+            if let Some(url) = &single_server {
+                *connected_server.lock().unwrap() = Some(url.clone());
+            }
+            if let Some(online) = &online {
+                cli.publish(paho_message(online.clone(), properties));
+            }
+            if let Some(callback) = connected_callback.lock().unwrap().as_ref() {
+                callback();
+            }
+        });
+        let lost_server = active_server.clone();
+        client.set_connection_lost_callback(move |_| {
+            *lost_server.lock().unwrap() = None;
+        });
+
+        Ok(PahoClient {
+            client,
+            options: Arc::new(Mutex::new(options)),
+            mqtt_config: Arc::new(Mutex::new(mqtt_config.clone())),
+            presence: presence.cloned(),
+            protocol: mqtt_config.protocol,
+            active_server,
+            connected,
+        })
+    }
+
+    async fn connect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let options = self.options.lock().unwrap().clone();
+        match self.client.connect(Some(options)).await {
+            Ok(response) => {
+                if let Some(connection) = response.connect_response() {
+                    *self.active_server.lock().unwrap() = Some(connection.server_uri);
+                }
+                Ok(())
+            }
+            Err(e) if protocol_rejected(&e) => Err(ProtocolRejected(self.protocol).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn reconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = self.client.reconnect().await?;
+        if let Some(connection) = response.connect_response() {
+            *self.active_server.lock().unwrap() = Some(connection.server_uri);
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.client.disconnect(None).await?;
+        Ok(())
+    }
+
+    /// Paho keeps the options of the last connect for automatic reconnects, so the connection is re-established with the new options
+    async fn set_password(&self, password: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mqtt_config = MqttConfig {
+            password: Some(password),
+            ..self.mqtt_config.lock().unwrap().clone()
+        };
+        *self.options.lock().unwrap() = connect_options(&mqtt_config, self.presence.as_ref())?;
+        *self.mqtt_config.lock().unwrap() = mqtt_config;
+        if self.client.is_connected() {
+            self.client.disconnect(None).await?;
+        }
+        self.connect().await
+    }
+
+    fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    fn active_server(&self) -> Option<String> {
+        self.active_server.lock().unwrap().clone()
+    }
+
+    async fn publish(&self, msg: Message) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // MQTT 3.1.1 does not support properties
+        let properties = self.protocol == MqttProtocol::V5;
+        self.client.publish(paho_message(msg, properties)).await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self, filter: &str, qos: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.client.subscribe(filter, qos).await?;
+        Ok(())
+    }
+
+    fn set_message_callback(&self, callback: MessageCallback) {
+        self.client.set_message_callback(move |_, msg| {
+            if let Some(msg) = msg {
+                callback(Message::from(&msg));
+            }
+        });
+    }
+
+    fn set_connected_callback(&self, callback: ConnectedCallback) {
+        *self.connected.lock().unwrap() = Some(callback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publish_policy::PublishPolicy;
+
+    #[test]
+    fn converts_message_flags() {
+        let retained: paho_mqtt::Message =
+            PublishPolicy::AVAILABILITY.message("t", "online").into();
+        assert!(retained.retained());
+        assert_eq!(retained.qos(), 1);
+
+        let policy = PublishPolicy {
+            qos: 2,
+            retained: false,
+        };
+        let msg: paho_mqtt::Message = policy.message("t", "21.5").into();
+        assert!(!msg.retained());
+        assert_eq!(msg.qos(), 2);
+        assert_eq!(msg.payload_str(), "21.5");
+    }
+
+    #[test]
+    fn converts_message_properties() {
+        let msg = Message::new("t", "21.5", 1)
+            .with_user_properties(vec![("site".to_string(), "basement".to_string())])
+            .with_message_expiry(Some(Duration::from_secs(300)));
+
+        let converted = paho_message(msg.clone(), true);
+        let properties = converted.properties();
+        assert_eq!(
+            properties.get_string_pair(paho_mqtt::PropertyCode::UserProperty),
+            Some(("site".to_string(), "basement".to_string()))
+        );
+        assert_eq!(
+            properties.get_int(paho_mqtt::PropertyCode::MessageExpiryInterval),
+            Some(300)
+        );
+
+        let converted = paho_message(msg, false);
+        assert!(converted.properties().is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Registered topic filters and the channels to forward matching messages to
+type Routes = Arc<Mutex<Vec<(String, UnboundedSender<mqtt::Message>)>>>;
+
+/// Dispatches incoming MQTT messages to several consumers. The client only supports a single message callback, so all consumers register here instead.
+/// Consumers are responsible to subscribe to their topics themselves.
+#[derive(Clone)]
+pub struct MessageRouter {
+    routes: Routes,
+}
+
+impl MessageRouter {
+    /// Creates a new router and registers it as the message callback of the given client
+    pub fn new(cli: &AsyncClient) -> Self {
+        let routes: Routes = Arc::new(Mutex::new(vec![]));
+
+        let callback_routes = routes.clone();
+        cli.set_message_callback(Box::new(move |msg| {
+            let mut routes = callback_routes.lock().unwrap();
+            // Drop routes whose receiver is gone
+            routes.retain(|(_, sender)| !sender.is_closed());
+            for (filter, sender) in routes.iter() {
+                if topic_matches(filter, msg.topic()) {
+                    let _ = sender.send(msg.clone());
+                }
+            }
+        }));
+
+        MessageRouter { routes }
+    }
+
+    /// Returns a receiver for all messages matching the given topic filter (wildcards '+' and '#' are supported)
+    pub fn route(&self, filter: &str) -> UnboundedReceiver<mqtt::Message> {
+        let (sender, receiver) = unbounded_channel();
+        self.routes
+            .lock()
+            .unwrap()
+            .push((filter.to_string(), sender));
+        receiver
+    }
+}
+
+/// Checks if the topic matches the given MQTT topic filter
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => continue,
+            (Some(f), Some(t)) if f == t => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_topic_filters() {
+        assert!(topic_matches("ThermoBeacon/leader", "ThermoBeacon/leader"));
+        assert!(topic_matches(
+            "ThermoBeacon/+/command",
+            "ThermoBeacon/a/command"
+        ));
+        assert!(topic_matches("ThermoBeacon/#", "ThermoBeacon/a/b"));
+        assert!(!topic_matches("ThermoBeacon/+", "ThermoBeacon/a/b"));
+        assert!(!topic_matches("ThermoBeacon/a", "ThermoBeacon"));
+    }
+}
@@ -0,0 +1,581 @@
+//! Pure Rust MQTT backend using rumqttc (Cargo feature `rumqttc`)
+//!
+//! The event loop runs in a background task, which reconnects with exponential backoff after connection errors.
+//! Failover servers are tried in order after the connection to a server failed.
+//! rumqttc does not report the acknowledgements of publishes to the caller, so publishes are complete once queued
+//! and denied publishes (e.g. by the ACL of the broker) are not detected.
+
+use async_trait::async_trait;
+use rumqttc::v5;
+use rumqttc::v5::mqttbytes::v5::{ConnectReturnCode, LastWill, Packet, PublishProperties};
+use rumqttc::{TlsConfiguration, Transport};
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+use crate::{
+    configuration::{MqttConfig, MqttProtocol},
+    mqtt::{
+        self, ConnectedCallback, Message, MessageCallback, MqttClient, Presence, ProtocolRejected,
+    },
+    reconnect,
+};
+
+/// Capacity of the request queue of the client
+const QUEUE_CAPACITY: usize = 100;
+
+/// Maximum time to wait for the connection in `connect`
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// State of the connection, updated by the event loop
+#[derive(Debug, Clone, PartialEq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Failed(String),
+    /// The broker rejected the protocol version, the event loop stopped
+    ProtocolRejected,
+}
+
+/// rumqttc client, whose event loop is started on the first connect
+#[derive(Clone)]
+pub struct RumqttcClient {
+    client: Client,
+    protocol: MqttProtocol,
+    /// Servers in the order to try them, the event loop starts with the first one
+    servers: Arc<Mutex<Vec<Server>>>,
+    /// Username of the connection, to replace the password
+    username: Option<String>,
+    /// Index of the server of the current connection
+    active_server: Arc<Mutex<Option<usize>>>,
+    /// Event loop until it is started
+    eventloop: Arc<Mutex<Option<EventLoop>>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    callback: Arc<Mutex<Option<MessageCallback>>>,
+    connected: Arc<Mutex<Option<ConnectedCallback>>>,
+    /// Published on each connect
+    online: Option<Message>,
+}
+
+/// Client of the configured protocol version: rumqttc implements MQTT 5 and 3.1.1 in separate modules
+#[derive(Clone)]
+enum Client {
+    V5(v5::AsyncClient),
+    V311(rumqttc::AsyncClient),
+}
+
+/// Event loop of the configured protocol version
+enum EventLoop {
+    V5(v5::EventLoop),
+    V311(rumqttc::EventLoop),
+}
+
+/// Connection options of the configured protocol version
+#[derive(Clone)]
+enum ClientOptions {
+    V5(v5::MqttOptions),
+    V311(rumqttc::MqttOptions),
+}
+
+/// A server of the failover list
+struct Server {
+    url: String,
+    options: ClientOptions,
+}
+
+/// Event of the event loop handled by the client
+enum Incoming {
+    ConnAck,
+    Publish(Message),
+    Other,
+}
+
+fn qos_v5(qos: i32) -> v5::mqttbytes::QoS {
+    match qos {
+        0 => v5::mqttbytes::QoS::AtMostOnce,
+        1 => v5::mqttbytes::QoS::AtLeastOnce,
+        _ => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
+fn qos_v311(qos: i32) -> rumqttc::QoS {
+    match qos {
+        0 => rumqttc::QoS::AtMostOnce,
+        1 => rumqttc::QoS::AtLeastOnce,
+        _ => rumqttc::QoS::ExactlyOnce,
+    }
+}
+
+/// Message received from the broker
+fn received(topic: String, payload: Vec<u8>, qos: i32, retain: bool) -> Message {
+    if retain {
+        Message::new_retained(topic, payload, qos)
+    } else {
+        Message::new(topic, payload, qos)
+    }
+}
+
+/// Host and port of the broker URL (e.g. 'tcp://localhost:1883'). The port defaults to 1883, or 8883 with TLS.
+fn host_and_port(url: &str, tls: bool) -> Result<(String, u16), Box<dyn Error + Send + Sync>> {
+    let (scheme, address) = url.split_once("://").unwrap_or(("tcp", url));
+    if scheme.starts_with("ws") {
+        return Err("WebSocket URLs are not supported by the rumqttc backend".into());
+    }
+    let address = address.trim_end_matches('/');
+    match address.rsplit_once(':') {
+        Some((host, port)) => Ok((host.to_string(), port.parse()?)),
+        None => Ok((address.to_string(), if tls { 8883 } else { 1883 })),
+    }
+}
+
+/// Builds the TLS transport of the given MqttConfig
+fn tls_transport(mqtt_config: &MqttConfig) -> Result<Transport, Box<dyn Error + Send + Sync>> {
+    if mqtt_config.tls_insecure {
+        warn!("Disabling the TLS certificate verification is not supported by the rumqttc backend");
+    }
+    let ca = match &mqtt_config.ca_cert {
+        Some(ca_cert) => std::fs::read(ca_cert)?,
+        None if mqtt_config.alpn.is_empty() => return Ok(Transport::tls_with_default_config()),
+        None => return Err("ALPN requires the 'ca_cert' with the rumqttc backend".into()),
+    };
+    let client_auth = match &mqtt_config.client_cert {
+        Some(client_cert) => {
+            // The key might be contained in the certificate file
+            let key = mqtt_config.client_key.as_ref().unwrap_or(client_cert);
+            Some((std::fs::read(client_cert)?, std::fs::read(key)?))
+        }
+        None => None,
+    };
+    Ok(Transport::tls_with_config(TlsConfiguration::Simple {
+        ca,
+        alpn: (!mqtt_config.alpn.is_empty()).then(|| {
+            mqtt_config
+                .alpn
+                .iter()
+                .map(|protocol| protocol.as_bytes().to_vec())
+                .collect()
+        }),
+        client_auth,
+    }))
+}
+
+/// Builds the options of the connection to the server with the given URL
+fn client_options(
+    mqtt_config: &MqttConfig,
+    url: &str,
+    client_id: &str,
+    presence: Option<&Presence>,
+) -> Result<ClientOptions, Box<dyn Error + Send + Sync>> {
+    let tls = mqtt::uses_tls(mqtt_config, url);
+    let (host, port) = host_and_port(url, tls)?;
+    let keep_alive = Duration::from_secs(mqtt_config.keep_alive);
+    let credentials = mqtt_config
+        .username
+        .clone()
+        .zip(mqtt_config.password.clone());
+    let transport = if tls {
+        debug!("Configuration of MQTT with TLS for {}", url);
+        Some(tls_transport(mqtt_config)?)
+    } else {
+        None
+    };
+
+    Ok(match mqtt_config.protocol {
+        MqttProtocol::V5 => {
+            let mut options = v5::MqttOptions::new(client_id, host, port);
+            options
+                .set_keep_alive(keep_alive)
+                .set_clean_start(mqtt_config.clean_start)
+                .set_session_expiry_interval(mqtt::session_expiry_secs(mqtt_config))
+                .set_receive_maximum(mqtt_config.receive_maximum);
+            if let Some((username, password)) = credentials {
+                options.set_credentials(username, password);
+            }
+            if let Some(transport) = transport {
+                options.set_transport(transport);
+            }
+            if let Some(Presence { offline, .. }) = presence {
+                options.set_last_will(LastWill::new(
+                    offline.topic(),
+                    offline.payload().to_vec(),
+                    qos_v5(offline.qos()),
+                    offline.retained(),
+                    None,
+                ));
+            }
+            ClientOptions::V5(options)
+        }
+        MqttProtocol::V311 => {
+            let mut options = rumqttc::MqttOptions::new(client_id, host, port);
+            options
+                .set_keep_alive(keep_alive)
+                .set_clean_session(mqtt_config.clean_start);
+            if let Some((username, password)) = credentials {
+                options.set_credentials(username, password);
+            }
+            if let Some(transport) = transport {
+                options.set_transport(transport);
+            }
+            if let Some(Presence { offline, .. }) = presence {
+                options.set_last_will(rumqttc::LastWill::new(
+                    offline.topic(),
+                    offline.payload().to_vec(),
+                    qos_v311(offline.qos()),
+                    offline.retained(),
+                ));
+            }
+            ClientOptions::V311(options)
+        }
+    })
+}
+
+impl Client {
+    /// Publishes the message, MQTT 3.1.1 without the properties
+    async fn publish(&self, msg: Message) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let payload = msg.payload().to_vec();
+        match self {
+            Client::V5(client) => {
+                let properties = PublishProperties {
+                    message_expiry_interval: msg.message_expiry_secs(),
+                    user_properties: msg.user_properties().to_vec(),
+                    ..Default::default()
+                };
+                client
+                    .publish_with_properties(
+                        msg.topic(),
+                        qos_v5(msg.qos()),
+                        msg.retained(),
+                        payload,
+                        properties,
+                    )
+                    .await?
+            }
+            Client::V311(client) => {
+                client
+                    .publish(msg.topic(), qos_v311(msg.qos()), msg.retained(), payload)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues the message without waiting for space in the request queue
+    fn try_publish(&self, msg: &Message) {
+        let payload = msg.payload().to_vec();
+        let queued = match self {
+            Client::V5(client) => client
+                .try_publish(msg.topic(), qos_v5(msg.qos()), msg.retained(), payload)
+                .is_ok(),
+            Client::V311(client) => client
+                .try_publish(msg.topic(), qos_v311(msg.qos()), msg.retained(), payload)
+                .is_ok(),
+        };
+        if !queued {
+            debug!("Request queue full, dropped message to {}", msg.topic());
+        }
+    }
+
+    async fn subscribe(&self, filter: &str, qos: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Client::V5(client) => client.subscribe(filter, qos_v5(qos)).await?,
+            Client::V311(client) => client.subscribe(filter, qos_v311(qos)).await?,
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Client::V5(client) => client.disconnect().await?,
+            Client::V311(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+impl ClientOptions {
+    fn set_credentials(&mut self, username: &str, password: &str) {
+        match self {
+            ClientOptions::V5(options) => {
+                options.set_credentials(username, password);
+            }
+            ClientOptions::V311(options) => {
+                options.set_credentials(username, password);
+            }
+        }
+    }
+}
+
+impl EventLoop {
+    fn new(options: ClientOptions) -> (Client, EventLoop) {
+        match options {
+            ClientOptions::V5(options) => {
+                let (client, eventloop) = v5::AsyncClient::new(options, QUEUE_CAPACITY);
+                (Client::V5(client), EventLoop::V5(eventloop))
+            }
+            ClientOptions::V311(options) => {
+                let (client, eventloop) = rumqttc::AsyncClient::new(options, QUEUE_CAPACITY);
+                (Client::V311(client), EventLoop::V311(eventloop))
+            }
+        }
+    }
+
+    /// Switches to another server, used by the next connection attempt
+    fn set_options(&mut self, options: ClientOptions) {
+        match (self, options) {
+            (EventLoop::V5(eventloop), ClientOptions::V5(options)) => eventloop.options = options,
+            (EventLoop::V311(eventloop), ClientOptions::V311(options)) => {
+                eventloop.mqtt_options = options
+            }
+            _ => unreachable!("All servers use the same protocol version"),
+        }
+    }
+
+    /// Polls the next event. Errors are returned as the resulting connection state.
+    async fn poll(&mut self) -> Result<Incoming, ConnectionState> {
+        match self {
+            EventLoop::V5(eventloop) => match eventloop.poll().await {
+                Ok(v5::Event::Incoming(Packet::ConnAck(_))) => Ok(Incoming::ConnAck),
+                Ok(v5::Event::Incoming(Packet::Publish(publish))) => {
+                    Ok(Incoming::Publish(received(
+                        String::from_utf8_lossy(&publish.topic).to_string(),
+                        publish.payload.to_vec(),
+                        publish.qos as i32,
+                        publish.retain,
+                    )))
+                }
+                Ok(_) => Ok(Incoming::Other),
+                Err(v5::ConnectionError::ConnectionRefused(
+                    ConnectReturnCode::UnsupportedProtocolVersion,
+                )) => Err(ConnectionState::ProtocolRejected),
+                Err(e) => Err(ConnectionState::Failed(e.to_string())),
+            },
+            EventLoop::V311(eventloop) => match eventloop.poll().await {
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => Ok(Incoming::ConnAck),
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                    Ok(Incoming::Publish(received(
+                        publish.topic,
+                        publish.payload.to_vec(),
+                        publish.qos as i32,
+                        publish.retain,
+                    )))
+                }
+                Ok(_) => Ok(Incoming::Other),
+                Err(rumqttc::ConnectionError::ConnectionRefused(
+                    rumqttc::ConnectReturnCode::RefusedProtocolVersion,
+                )) => Err(ConnectionState::ProtocolRejected),
+                Err(e) => Err(ConnectionState::Failed(e.to_string())),
+            },
+        }
+    }
+}
+
+impl RumqttcClient {
+    /// Polls the event loop for the lifetime of the process. Connection errors are retried with the next server, and with
+    /// exponential backoff once all servers failed. The rejection of the protocol version is not retried.
+    async fn run(self, mut eventloop: EventLoop) {
+        let mut delay = reconnect::MIN_DELAY;
+        let mut server = 0;
+        loop {
+            match eventloop.poll().await {
+                Ok(Incoming::ConnAck) => {
+                    delay = reconnect::MIN_DELAY;
+                    debug!(
+                        "Connected to MQTT server {}",
+                        self.servers.lock().unwrap()[server].url
+                    );
+                    *self.active_server.lock().unwrap() = Some(server);
+                    self.state.send_replace(ConnectionState::Connected);
+                    if let Some(online) = &self.online {
+                        self.client.try_publish(online);
+                    }
+                    if let Some(callback) = self.connected.lock().unwrap().as_ref() {
+                        callback();
+                    }
+                }
+                Ok(Incoming::Publish(msg)) => {
+                    if let Some(callback) = self.callback.lock().unwrap().as_ref() {
+                        callback(msg);
+                    }
+                }
+                Ok(Incoming::Other) => {}
+                Err(ConnectionState::ProtocolRejected) => {
+                    debug!("MQTT server rejected protocol version {:?}", self.protocol);
+                    self.state.send_replace(ConnectionState::ProtocolRejected);
+                    return;
+                }
+                Err(state) => {
+                    *self.active_server.lock().unwrap() = None;
+                    {
+                        let servers = self.servers.lock().unwrap();
+                        if servers.len() > 1 {
+                            server = (server + 1) % servers.len();
+                            debug!(
+                                "MQTT connection error, trying {}: {:?}",
+                                servers[server].url, state
+                            );
+                        }
+                        // Also applies a replaced password
+                        eventloop.set_options(servers[server].options.clone());
+                    }
+                    // All servers failed
+                    if server == 0 {
+                        debug!(
+                            "MQTT connection error, retrying in {:?}: {:?}",
+                            delay, state
+                        );
+                        self.state.send_replace(state);
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(reconnect::MAX_DELAY);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MqttClient for RumqttcClient {
+    fn new(
+        mqtt_config: &MqttConfig,
+        presence: Option<&Presence>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let client_id = mqtt::client_id(mqtt_config);
+        match (&mqtt_config.username, &mqtt_config.password) {
+            (Some(username), Some(_)) => debug!(
+                "Configuration of MQTT with user {} and password ***",
+                username
+            ),
+            _ => debug!("Configuration of MQTT without username / password"),
+        }
+        if let Some(presence) = presence {
+            debug!(
+                "Configuration of MQTT with last will on {}",
+                presence.offline.topic()
+            );
+        }
+        let servers = mqtt::server_urls(mqtt_config)
+            .into_iter()
+            .map(|url| {
+                let options = client_options(mqtt_config, &url, &client_id, presence)?;
+                Ok(Server { url, options })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+        let primary = servers.first().ok_or("No MQTT server URL configured")?;
+        let (client, eventloop) = EventLoop::new(primary.options.clone());
+        Ok(RumqttcClient {
+            client,
+            protocol: mqtt_config.protocol,
+            servers: Arc::new(Mutex::new(servers)),
+            username: mqtt_config.username.clone(),
+            active_server: Arc::new(Mutex::new(None)),
+            eventloop: Arc::new(Mutex::new(Some(eventloop))),
+            state: Arc::new(watch::channel(ConnectionState::Connecting).0),
+            callback: Arc::new(Mutex::new(None)),
+            connected: Arc::new(Mutex::new(None)),
+            online: presence.map(|p| p.online.clone()),
+        })
+    }
+
+    /// Starts the event loop on the first call. Waits for the next connection attempt to complete or fail.
+    async fn connect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut state = self.state.subscribe();
+        if let Some(eventloop) = self.eventloop.lock().unwrap().take() {
+            tokio::spawn(self.clone().run(eventloop));
+        }
+        match *state.borrow_and_update() {
+            ConnectionState::Connected => return Ok(()),
+            ConnectionState::ProtocolRejected => return Err(ProtocolRejected(self.protocol).into()),
+            _ => {}
+        }
+        match tokio::time::timeout(CONNECT_TIMEOUT, state.changed()).await {
+            Ok(Ok(())) => match &*state.borrow() {
+                ConnectionState::Connected => Ok(()),
+                ConnectionState::Failed(e) => Err(e.clone().into()),
+                ConnectionState::ProtocolRejected => Err(ProtocolRejected(self.protocol).into()),
+                ConnectionState::Connecting => Err("Not connected".into()),
+            },
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err("Timeout connecting to the MQTT server".into()),
+        }
+    }
+
+    async fn reconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.connect().await
+    }
+
+    async fn disconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.client.disconnect().await
+    }
+
+    /// The event loop picks up the new options when it reconnects after the disconnect
+    async fn set_password(&self, password: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let username = self.username.clone().unwrap_or_default();
+        let primary = {
+            let mut servers = self.servers.lock().unwrap();
+            for server in servers.iter_mut() {
+                server.options.set_credentials(&username, &password);
+            }
+            servers[0].options.clone()
+        };
+        // Not started yet
+        if let Some(eventloop) = self.eventloop.lock().unwrap().as_mut() {
+            eventloop.set_options(primary);
+            return Ok(());
+        }
+        self.client.disconnect().await
+    }
+
+    fn is_connected(&self) -> bool {
+        *self.state.borrow() == ConnectionState::Connected
+    }
+
+    fn active_server(&self) -> Option<String> {
+        let server = (*self.active_server.lock().unwrap())?;
+        Some(self.servers.lock().unwrap()[server].url.clone())
+    }
+
+    async fn publish(&self, msg: Message) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.client.publish(msg).await
+    }
+
+    async fn subscribe(
+        &self,
+        filter: &str,
+        qos_level: i32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.client.subscribe(filter, qos_level).await
+    }
+
+    fn set_message_callback(&self, callback: MessageCallback) {
+        *self.callback.lock().unwrap() = Some(callback);
+    }
+
+    fn set_connected_callback(&self, callback: ConnectedCallback) {
+        *self.connected.lock().unwrap() = Some(callback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_broker_urls() {
+        assert_eq!(
+            host_and_port("tcp://localhost:1884", false).unwrap(),
+            ("localhost".to_string(), 1884)
+        );
+        assert_eq!(
+            host_and_port("ssl://broker.example.com", true).unwrap(),
+            ("broker.example.com".to_string(), 8883)
+        );
+        assert_eq!(
+            host_and_port("mqtt.local", false).unwrap(),
+            ("mqtt.local".to_string(), 1883)
+        );
+        assert!(host_and_port("ws://localhost:9001", false).is_err());
+    }
+}
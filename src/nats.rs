@@ -0,0 +1,234 @@
+//! NATS output (Cargo feature `nats`): publishes each reading to a subject of its device, as lightweight alternative to MQTT.
+//! With JetStream, each publish is acknowledged by the stream and deduplicated by its message id.
+
+use std::error::Error;
+
+use async_nats::{header::NATS_MESSAGE_ID, jetstream, Client, ConnectOptions, HeaderMap};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::OnceCell;
+
+use crate::{
+    configuration::{AppConfig, AppDevice, NatsConfig},
+    sink::{Message, Sink},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Replaces the characters not allowed in a subject token (separator, wildcards and whitespace)
+fn subject_token(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if matches!(c, '.' | '*' | '>') || c.is_whitespace() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Subject of the readings of the given device
+fn subject(config: &AppConfig, nats: &NatsConfig, device: &AppDevice) -> String {
+    match &config.instance_name {
+        Some(instance) => format!(
+            "{}.{}.{}",
+            nats.subject_prefix,
+            subject_token(instance),
+            subject_token(&device.name)
+        ),
+        None => format!("{}.{}", nats.subject_prefix, subject_token(&device.name)),
+    }
+}
+
+/// JetStream message id of a reading. The run id deduplicates repeated publishes of a run,
+/// the time of the reading keeps recovered readings (same run) and runs after a restart (run ids start over) apart.
+fn message_id(msg: &Message) -> String {
+    format!("{}-{}-{}", msg.data.mac, msg.run_id, msg.measured_at)
+}
+
+/// Connection to the NATS servers
+struct Connection {
+    client: Client,
+    /// JetStream context, if enabled
+    jetstream: Option<jetstream::Context>,
+}
+
+/// Publishes the readings to NATS subjects
+pub struct NatsSink {
+    config: AppConfig,
+    nats: NatsConfig,
+    connection: OnceCell<Connection>,
+}
+
+impl NatsSink {
+    pub fn new(config: &AppConfig, nats: &NatsConfig) -> Self {
+        NatsSink {
+            config: config.clone(),
+            nats: nats.clone(),
+            connection: OnceCell::new(),
+        }
+    }
+
+    /// Returns the connection, connecting on first use. The client reconnects on its own afterwards.
+    async fn connection(&self) -> Result<&Connection, Box<dyn Error + Send + Sync>> {
+        self.connection
+            .get_or_try_init(|| async {
+                let mut options = ConnectOptions::new()
+                    .name(
+                        self.config
+                            .instance_name
+                            .as_deref()
+                            .unwrap_or("thermobeacon"),
+                    )
+                    .connection_timeout(self.nats.timeout)
+                    .require_tls(self.nats.tls || self.nats.ca_cert.is_some());
+                if let Some(ca_cert) = &self.nats.ca_cert {
+                    options = options.add_root_certificates(ca_cert.into());
+                }
+                if let Some(credentials_file) = &self.nats.credentials_file {
+                    options = options.credentials_file(credentials_file).await?;
+                }
+                if let Some(token) = &self.nats.token {
+                    options = options.token(token.clone());
+                }
+                let client = options
+                    .connect(self.nats.servers.join(",").as_str())
+                    .await?;
+                info!("Connected to NATS {}", self.nats.servers.join(","));
+                let jetstream = self.nats.jetstream.then(|| {
+                    let mut context = jetstream::new(client.clone());
+                    context.set_timeout(self.nats.timeout);
+                    context
+                });
+                Ok::<_, Box<dyn Error + Send + Sync>>(Connection { client, jetstream })
+            })
+            .await
+    }
+
+    /// Publishes the message of the given device, through JetStream if enabled
+    async fn publish_message(
+        &self,
+        device: &AppDevice,
+        msg: &Message,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let payload = msg.payload(
+            self.config.payload_format(device),
+            self.config.payload_template(device),
+        )?;
+        let subject = subject(&self.config, &self.nats, device);
+        let connection = self.connection().await?;
+        match &connection.jetstream {
+            Some(jetstream) => {
+                let mut headers = HeaderMap::new();
+                headers.insert(NATS_MESSAGE_ID, message_id(msg).as_str());
+                let ack = jetstream
+                    .publish_with_headers(subject.clone(), headers, payload.into())
+                    .await?
+                    .await?;
+                if ack.duplicate {
+                    debug!(
+                        "Reading of {} already stored in stream {}",
+                        device.name, ack.stream
+                    );
+                } else {
+                    trace!(
+                        "Published reading of {} to {} (stream {}, sequence {})",
+                        device.name,
+                        subject,
+                        ack.stream,
+                        ack.sequence
+                    );
+                }
+            }
+            None => {
+                connection
+                    .client
+                    .publish(subject.clone(), payload.into())
+                    .await?;
+                // Core NATS has no acknowledgements, at least make sure the reading left the client buffer
+                connection.client.flush().await?;
+                trace!("Published reading of {} to {}", device.name, subject);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::new(&self.config, device, data.clone());
+        self.publish_message(device, &msg).await
+    }
+
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::at(&self.config, device, data.clone(), measured_at);
+        self.publish_message(device, &msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_subjects_of_devices() {
+        let nats = NatsConfig {
+            servers: vec!["nats://localhost:4222".to_string()],
+            subject_prefix: "thermobeacon".to_string(),
+            jetstream: true,
+            credentials_file: None,
+            token: None,
+            token_file: None,
+            tls: false,
+            ca_cert: None,
+            timeout: std::time::Duration::from_secs(10),
+        };
+        let device = AppDevice {
+            name: "Living room.north".to_string(),
+            ..Default::default()
+        };
+        let mut config = AppConfig::default();
+        assert_eq!(
+            subject(&config, &nats, &device),
+            "thermobeacon.Living_room_north"
+        );
+        config.instance_name = Some("site-a".to_string());
+        assert_eq!(
+            subject(&config, &nats, &device),
+            "thermobeacon.site-a.Living_room_north"
+        );
+    }
+
+    #[test]
+    fn message_ids_differ_between_restarts() {
+        let msg = Message {
+            run_id: 3,
+            measured_at: "2024-05-01T12:00:00+00:00".to_string(),
+            ..Default::default()
+        };
+        let restarted = Message {
+            measured_at: "2024-05-02T08:00:00+00:00".to_string(),
+            ..msg.clone()
+        };
+        let next_run = Message {
+            run_id: 4,
+            ..msg.clone()
+        };
+        assert_ne!(message_id(&msg), message_id(&restarted));
+        assert_ne!(message_id(&msg), message_id(&next_run));
+    }
+}
@@ -0,0 +1,300 @@
+//! Alert notifications to phones: rules on the readings (low battery) and on the availability of the devices send a notification
+//! through ntfy, Pushover or a Telegram bot when their condition becomes true for a device, and again once it is resolved.
+//! Threshold alerts (see `alerts`) are sent through the notifiers as well.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use btleplug::api::BDAddr;
+use chrono::Utc;
+
+use crate::{
+    configuration::{
+        AppConfig, AppDevice, Metric, NotificationCondition, NotificationRule, NotificationsConfig,
+        NotifierConfig, Units,
+    },
+    sink::Message,
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+    time_window,
+};
+
+/// Timeout of each request to a notification service
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTP client shared by all notifiers
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Last evaluated state of each rule (by index) and device
+static RULE_STATES: Mutex<Option<HashMap<(usize, BDAddr), bool>>> = Mutex::new(None);
+
+/// Checks that the rules reference configured notifiers
+pub fn validate(config: &NotificationsConfig) -> Result<(), String> {
+    for rule in config.rules.iter() {
+        if let Some(name) = rule
+            .notify
+            .iter()
+            .find(|name| !config.notifiers.contains_key(*name))
+        {
+            return Err(format!(
+                "Rule '{}' notifies the unknown notifier '{}'",
+                rule.name, name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Records the state of a rule for a device. Returns true if it changed. The first state only counts if the condition is true,
+/// so an alert still present after a restart is sent again, but not that everything is fine.
+fn changed(rule: usize, mac: BDAddr, active: bool) -> bool {
+    let mut states = RULE_STATES.lock().unwrap();
+    let previous = states
+        .get_or_insert_with(HashMap::new)
+        .insert((rule, mac), active);
+    previous.unwrap_or(false) != active
+}
+
+/// Name of the metric in the notifications
+pub fn metric_label(metric: Metric) -> &'static str {
+    match metric {
+        Metric::Temperature => "temperature",
+        Metric::Humidity => "humidity",
+        Metric::BatteryLevel => "battery level",
+    }
+}
+
+/// Value of the metric of a message (temperatures already in the units of the device) with its unit
+pub fn metric_value(
+    units: Units,
+    metric: Metric,
+    data: &ThermoBeaconFullReadResult,
+) -> (f32, &'static str) {
+    match metric {
+        Metric::Temperature => (data.temperature, units.temperature_unit()),
+        Metric::Humidity => (data.humidity, "%"),
+        Metric::BatteryLevel => (data.battery_level, "%"),
+    }
+}
+
+/// Evaluates a reading rule with the given battery-low threshold (%). Returns whether its condition is true and the message describing the reading
+fn evaluate(
+    rule: &NotificationRule,
+    name: &str,
+    battery_low: u8,
+    data: &ThermoBeaconFullReadResult,
+) -> Option<(bool, String)> {
+    match rule.condition {
+        NotificationCondition::BatteryLow => {
+            let active = data.battery_level < f32::from(battery_low);
+            let message = format!(
+                "{}: {} {:.1}% {} {}%",
+                name,
+                metric_label(Metric::BatteryLevel),
+                data.battery_level,
+                if active { "below" } else { "back at or above" },
+                battery_low
+            );
+            Some((active, message))
+        }
+        NotificationCondition::Offline => None,
+    }
+}
+
+/// Rules of the given condition kind applying to the device
+fn rules_of<'a>(
+    config: &'a AppConfig,
+    name: &'a str,
+    offline: bool,
+) -> impl Iterator<Item = (usize, &'a NotificationRule)> + 'a {
+    config
+        .notifications
+        .iter()
+        .flat_map(|n| n.rules.iter().enumerate())
+        .filter(move |(_, rule)| {
+            (rule.condition == NotificationCondition::Offline) == offline
+                && (rule.devices.is_empty() || rule.devices.iter().any(|d| d == name))
+        })
+}
+
+/// Evaluates the reading rules on the messages and sends the notifications of the changed ones
+pub async fn check_readings(config: &AppConfig, messages: &[Message]) {
+    for msg in messages {
+        for (index, rule) in rules_of(config, &msg.name, false) {
+            if !within_windows(config, rule) {
+                continue;
+            }
+            if let Some((active, message)) =
+                evaluate(rule, &msg.name, config.battery_low, &msg.data)
+            {
+                if changed(index, msg.data.mac, active) {
+                    send_all(config, &rule.notify, &rule.name, &message).await;
+                }
+            }
+        }
+    }
+}
+
+/// Evaluates the offline rules on the changed availability of the device
+pub async fn check_availability(config: &AppConfig, device: &AppDevice, online: bool) {
+    let Ok(mac) = device.mac.parse::<BDAddr>() else {
+        return;
+    };
+    for (index, rule) in rules_of(config, &device.name, true) {
+        if !within_windows(config, rule) {
+            continue;
+        }
+        if changed(index, mac, !online) {
+            let message = if online {
+                format!("{} is online again", device.name)
+            } else {
+                format!(
+                    "{} missing for {} runs",
+                    device.name, config.offline_after_missing_runs
+                )
+            };
+            send_all(config, &rule.notify, &rule.name, &message).await;
+        }
+    }
+}
+
+/// Checks if the rule is within its time windows. Outside, the rule is not evaluated, so an alert still present is sent once the window starts
+fn within_windows(config: &AppConfig, rule: &NotificationRule) -> bool {
+    time_window::any_active(&rule.windows, &Utc::now().with_timezone(&config.tz()))
+}
+
+/// Sends the notification with the given title to the given notifiers. Failures are only logged.
+pub async fn send_all(config: &AppConfig, notify: &[String], title: &str, message: &str) {
+    let Some(notifications) = &config.notifications else {
+        return;
+    };
+    for name in notify.iter() {
+        let Some(notifier) = notifications.notifiers.get(name) else {
+            continue;
+        };
+        match send(notifier, title, message).await {
+            Ok(()) => info!("Sent '{}: {}' to {}", title, message, name),
+            Err(e) => warn!("Failed to send '{}: {}' to {}: {}", title, message, name, e),
+        }
+    }
+}
+
+/// HTTP client of the notification services, created on first use
+fn client() -> Result<&'static reqwest::Client, Box<dyn Error + Send + Sync>> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// Sends the notification with the given title to the notification service
+async fn send(
+    notifier: &NotifierConfig,
+    title: &str,
+    message: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let client = client()?;
+    let request = match notifier {
+        NotifierConfig::Ntfy(ntfy) => {
+            let mut request = client
+                .post(format!("{}/{}", ntfy.url.trim_end_matches('/'), ntfy.topic))
+                .header("Title", title)
+                .body(message.to_string());
+            if let Some(priority) = ntfy.priority {
+                request = request.header("Priority", priority.to_string());
+            }
+            if let Some(token) = &ntfy.token {
+                request = request.bearer_auth(token);
+            }
+            request
+        }
+        NotifierConfig::Pushover(pushover) => {
+            let token = pushover.token.as_deref().ok_or("Pushover token missing")?;
+            let mut form = vec![
+                ("token", token.to_string()),
+                ("user", pushover.user.clone()),
+                ("title", title.to_string()),
+                ("message", message.to_string()),
+            ];
+            if let Some(priority) = pushover.priority {
+                form.push(("priority", priority.to_string()));
+            }
+            client
+                .post("https://api.pushover.net/1/messages.json")
+                .form(&form)
+        }
+        NotifierConfig::Telegram(telegram) => {
+            let token = telegram
+                .bot_token
+                .as_deref()
+                .ok_or("Telegram bot token missing")?;
+            client
+                .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+                .json(&serde_json::json!({
+                    "chat_id": telegram.chat_id,
+                    "text": format!("{}\n{}", title, message),
+                }))
+        }
+    };
+    // The URL of Telegram contains the bot token, so it must not end up in the logs
+    let response = request.send().await.map_err(|e| e.without_url())?;
+    if !response.status().is_success() {
+        return Err(format!("Rejected with {}", response.status()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(condition: NotificationCondition) -> NotificationRule {
+        NotificationRule {
+            name: "Freezer battery".to_string(),
+            devices: vec![],
+            condition,
+            notify: vec!["phone".to_string()],
+            windows: vec![],
+        }
+    }
+
+    #[test]
+    fn evaluates_battery_level() {
+        let data = ThermoBeaconFullReadResult {
+            battery_level: 15.0,
+            ..Default::default()
+        };
+        let battery_low = rule(NotificationCondition::BatteryLow);
+        assert_eq!(
+            evaluate(&battery_low, "Freezer", 20, &data),
+            Some((true, "Freezer: battery level 15.0% below 20%".to_string()))
+        );
+        let (active, _) = evaluate(&battery_low, "Freezer", 10, &data).unwrap();
+        assert!(!active);
+        let offline = rule(NotificationCondition::Offline);
+        assert!(evaluate(&offline, "Freezer", 20, &data).is_none());
+    }
+
+    #[test]
+    fn notifies_on_changes_only() {
+        let mac: BDAddr = "11:22:33:44:55:99".parse().unwrap();
+        // Nothing to resolve after a start
+        assert!(!changed(0, mac, false));
+        assert!(changed(0, mac, true));
+        assert!(!changed(0, mac, true));
+        assert!(changed(0, mac, false));
+    }
+
+    #[test]
+    fn rejects_unknown_notifiers() {
+        let config = NotificationsConfig {
+            notifiers: HashMap::new(),
+            rules: vec![rule(NotificationCondition::Offline)],
+        };
+        assert!(validate(&config).is_err());
+    }
+}
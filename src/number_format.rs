@@ -0,0 +1,89 @@
+//! Locale-aware formatting of numbers in the CSV files of the file output and the table of the `discover` subcommand.
+
+/// Formatting of numbers in human-readable outputs (CSV files, tables on the console).
+/// JSON based outputs are not affected, they always use the decimal point.
+#[derive(Debug, Clone, Default, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct NumberFormat {
+    /// Locale (e.g. 'de', 'fr_FR', 'en_US') to derive the decimal separator from
+    pub locale: Option<String>,
+    /// Explicit decimal separator, overrides the one derived from the locale
+    pub decimal_separator: Option<char>,
+}
+
+/// Languages using a decimal comma
+static DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "de", "fr", "es", "it", "nl", "pt", "pl", "cs", "da", "fi", "nb", "no", "sv", "ru", "tr",
+];
+
+impl NumberFormat {
+    /// Decimal separator to use. Defaults to '.'
+    pub fn decimal_separator(&self) -> char {
+        if let Some(separator) = self.decimal_separator {
+            return separator;
+        }
+        match &self.locale {
+            Some(locale) => {
+                // Only the language part of locales like 'de_DE.UTF-8' or 'de-AT' is relevant
+                let language = locale
+                    .split(|c| c == '_' || c == '-' || c == '.')
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if DECIMAL_COMMA_LANGUAGES.contains(&language.as_str()) {
+                    ','
+                } else {
+                    '.'
+                }
+            }
+            None => '.',
+        }
+    }
+
+    /// Field separator for CSV files: Spreadsheet applications expect ';' if the decimal separator is ','
+    pub fn field_separator(&self) -> char {
+        match self.decimal_separator() {
+            ',' => ';',
+            _ => ',',
+        }
+    }
+
+    /// Formats the given number with the given number of decimal places
+    pub fn format(&self, value: f32, precision: usize) -> String {
+        let formatted = format!("{:.*}", precision, value);
+        match self.decimal_separator() {
+            '.' => formatted,
+            separator => formatted.replace('.', &separator.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_with_locale() {
+        let german = NumberFormat {
+            locale: Some("de_DE.UTF-8".to_string()),
+            decimal_separator: None,
+        };
+        assert_eq!(german.format(21.5625, 2), "21,56");
+        assert_eq!(german.field_separator(), ';');
+
+        let english = NumberFormat {
+            locale: Some("en_US".to_string()),
+            decimal_separator: None,
+        };
+        assert_eq!(english.format(-3.0, 1), "-3.0");
+        assert_eq!(english.field_separator(), ',');
+    }
+
+    #[test]
+    fn explicit_separator_overrides_locale() {
+        let format = NumberFormat {
+            locale: Some("fr".to_string()),
+            decimal_separator: Some('.'),
+        };
+        assert_eq!(format.format(1.25, 2), "1.25");
+    }
+}
@@ -0,0 +1,122 @@
+//! Shared encoder of the messages of all sinks: JSON, CBOR / MessagePack for constrained consumers and low-bandwidth links,
+//! or protobuf for pipelines with a fixed schema (thermobeacon.proto, the Rust types are generated by the build script)
+
+use std::error::Error;
+
+use prost::Message as _;
+
+use crate::{configuration::PayloadFormat, sink::Message};
+
+/// Types generated from thermobeacon.proto
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/thermobeacon.rs"));
+}
+
+impl From<&Message> for proto::Message {
+    fn from(msg: &Message) -> Self {
+        let data = &msg.data;
+        proto::Message {
+            data: Some(proto::Data {
+                battery_level: data.battery_level,
+                humidity: data.humidity,
+                temperature: data.temperature,
+                uptime: data.uptime,
+                button_pressed: data.button_pressed,
+                mac: data.mac.to_string(),
+                max_temperature: data.max_temperature,
+                min_temperature: data.min_temperature,
+                max_temp_time: data.max_temp_time,
+                min_temp_time: data.min_temp_time,
+                rssi: data.rssi.map(i32::from),
+                tx_power: data.tx_power.map(i32::from),
+                external_temperature: data.external_temperature,
+                pressure: data.pressure,
+                acceleration_x: data.acceleration_x,
+                acceleration_y: data.acceleration_y,
+                acceleration_z: data.acceleration_z,
+                movement_counter: data.movement_counter.map(u32::from),
+                battery_voltage: data.battery_voltage,
+            }),
+            name: msg.name.clone(),
+            instance: msg.instance.clone(),
+            clock_unreliable: msg.clock_unreliable,
+            computed: msg.computed.as_ref().map(|c| proto::Computed {
+                dew_point: c.dew_point,
+                absolute_humidity: c.absolute_humidity,
+                heat_index: c.heat_index,
+            }),
+            measured_at: msg.measured_at.clone(),
+            run_id: msg.run_id,
+            max_temp_at: msg.max_temp_at.clone(),
+            min_temp_at: msg.min_temp_at.clone(),
+        }
+    }
+}
+
+/// Encodes the message in the given format
+pub fn encode(
+    format: PayloadFormat,
+    msg: &Message,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    Ok(match format {
+        PayloadFormat::Json => serde_json::to_vec(msg)?,
+        PayloadFormat::Cbor => {
+            let mut payload = vec![];
+            ciborium::into_writer(msg, &mut payload)?;
+            payload
+        }
+        // With field names, so consumers do not depend on the field order
+        PayloadFormat::Msgpack => rmp_serde::to_vec_named(msg)?,
+        PayloadFormat::Protobuf => proto::Message::from(msg).encode_to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+    fn message() -> Message {
+        Message {
+            data: ThermoBeaconFullReadResult {
+                temperature: 21.5,
+                humidity: 45.0,
+                rssi: Some(-70),
+                ..Default::default()
+            },
+            name: "Basement".to_string(),
+            measured_at: "2024-05-01T12:00:00+00:00".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn encodes_self_describing_formats() {
+        let json = encode(PayloadFormat::Json, &message()).unwrap();
+        let expected: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(expected["data"]["temperature"], 21.5);
+
+        let cbor = encode(PayloadFormat::Cbor, &message()).unwrap();
+        let decoded: serde_json::Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(decoded, expected);
+
+        let msgpack = encode(PayloadFormat::Msgpack, &message()).unwrap();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&msgpack).unwrap();
+        assert_eq!(decoded, expected);
+
+        assert!(cbor.len() < json.len());
+        assert!(msgpack.len() < json.len());
+    }
+
+    #[test]
+    fn encodes_protobuf() {
+        let payload = encode(PayloadFormat::Protobuf, &message()).unwrap();
+        let decoded = proto::Message::decode(payload.as_slice()).unwrap();
+        let data = decoded.data.unwrap();
+        assert_eq!(decoded.name, "Basement");
+        assert_eq!(data.temperature, 21.5);
+        assert_eq!(data.rssi, Some(-70));
+        assert_eq!(data.pressure, None);
+        assert_eq!(decoded.computed, None);
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+
+use crate::configuration::AppConfig;
+
+/// Time to wait for the acknowledgement of a test message
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Test-publishes an empty, non-retained message with QoS 1 to each configured state topic, the availability topic (and the Home Assistant discovery prefix, if enabled).
+/// MQTT 5 brokers acknowledge denied publishes with a 'Not authorized' reason code, which is reported here. Returns all denied topics.
+pub async fn check_publish_permissions(config: &AppConfig, cli: &AsyncClient) -> Vec<String> {
+    let mut topics: Vec<String> = config
+        .devices
+        .iter()
+        .map(|device| config.device_topic(device))
+        .collect();
+    topics.push(config.availability_topic());
+    if config
+        .mqtt
+        .as_ref()
+        .map(|c| c.homeassistant)
+        .unwrap_or(false)
+    {
+        // An empty discovery message for an unknown entity is ignored by Home Assistant
+        topics.push(format!(
+            "homeassistant/sensor/thermobeacon/{}permission_check/config",
+            config.id_prefix()
+        ));
+    }
+
+    let mut denied = vec![];
+    for topic in topics {
+        let msg = mqtt::Message::new(&topic, Vec::<u8>::new(), 1);
+        match tokio::time::timeout(ACK_TIMEOUT, cli.publish(msg)).await {
+            Ok(Ok(())) => debug!("MQTT broker permits publishing to {}", topic),
+            Ok(Err(e)) => {
+                error!("MQTT broker denied publishing to {}: {}", topic, e);
+                denied.push(topic);
+            }
+            Err(_) => warn!(
+                "MQTT broker did not acknowledge test message to {} within {:?}",
+                topic, ACK_TIMEOUT
+            ),
+        }
+    }
+    denied
+}
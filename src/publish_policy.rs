@@ -0,0 +1,197 @@
+//! QoS level and retain flag of each class of MQTT messages, decided in one place instead of at each publish.
+//! Readings and events use the configured QoS level of the device, the other classes have fixed policies.
+
+use crate::{
+    configuration::{AppConfig, AppDevice},
+    mqtt,
+};
+
+/// QoS level and retain flag of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishPolicy {
+    pub qos: i32,
+    pub retained: bool,
+}
+
+impl PublishPolicy {
+    /// Home Assistant discovery messages (and their removal) are retained, so Home Assistant finds the entities after a restart
+    pub const DISCOVERY: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: true,
+    };
+
+    /// Availability of the gateway and the devices is retained, so new subscribers know it
+    pub const AVAILABILITY: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: true,
+    };
+
+    /// Battery-low state of the devices is retained, so new subscribers (e.g. Home Assistant after a restart) know it
+    pub const BATTERY_LOW: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: true,
+    };
+
+    /// Alert states are retained, so new subscribers know the raised alerts
+    pub const ALERT: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: true,
+    };
+
+    /// Bridge state after each run is retained, so new subscribers know the outcome of the last run
+    pub const BRIDGE_STATE: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: true,
+    };
+
+    /// Bridge info is published periodically and not retained
+    pub const BRIDGE_INFO: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: false,
+    };
+
+    /// Sparkplug B births, data and device deaths: QoS 0 and not retained, as required by the specification
+    pub const SPARKPLUG: PublishPolicy = PublishPolicy {
+        qos: 0,
+        retained: false,
+    };
+
+    /// Sparkplug B death of the edge node, registered as last will: QoS 1 and not retained
+    pub const SPARKPLUG_DEATH: PublishPolicy = PublishPolicy {
+        qos: 1,
+        retained: false,
+    };
+
+    /// Readings of the given device to its state topic: the QoS level and retain flag of the device or the global defaults
+    pub fn state(config: &AppConfig, device: &AppDevice) -> Self {
+        PublishPolicy {
+            qos: config.device_qos(device),
+            retained: config.device_retained(device),
+        }
+    }
+
+    /// Events of the given device (button presses, readings recovered from its log): the QoS level of the device, never
+    /// retained, as the broker would deliver them to each new subscriber as new event
+    pub fn event(config: &AppConfig, device: &AppDevice) -> Self {
+        PublishPolicy {
+            qos: config.device_qos(device),
+            retained: false,
+        }
+    }
+
+    /// Message to the given topic with this policy
+    pub fn message<T: Into<String>, V: Into<Vec<u8>>>(self, topic: T, payload: V) -> mqtt::Message {
+        if self.retained {
+            mqtt::Message::new_retained(topic, payload, self.qos)
+        } else {
+            mqtt::Message::new(topic, payload, self.qos)
+        }
+    }
+}
+
+/// Checks a configured QoS level
+fn validate_qos(setting: &str, qos: Option<i32>) -> Result<(), String> {
+    match qos {
+        Some(qos) if !(0..=2).contains(&qos) => {
+            Err(format!("{} is {}, but must be 0, 1 or 2", setting, qos))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks the QoS levels of the given devices
+pub fn validate_devices(devices: &[AppDevice]) -> Result<(), String> {
+    for device in devices {
+        validate_qos(&format!("qos of device {}", device.name), device.qos)?;
+    }
+    Ok(())
+}
+
+/// Checks the configured QoS levels: the global default and the ones of the devices
+pub fn validate(config: &AppConfig) -> Result<(), String> {
+    validate_qos(
+        "mqtt.default_qos",
+        config.mqtt.as_ref().and_then(|m| m.default_qos),
+    )?;
+    validate_devices(&config.devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::MqttConfig;
+
+    fn config(default_qos: Option<i32>, default_retained: Option<bool>) -> AppConfig {
+        AppConfig {
+            mqtt: Some(MqttConfig {
+                default_qos,
+                default_retained,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn state_uses_device_settings_and_defaults() {
+        let device = AppDevice::default();
+        let retained = AppDevice {
+            qos: Some(2),
+            retained: Some(true),
+            ..Default::default()
+        };
+
+        let msg = PublishPolicy::state(&config(None, None), &device).message("t", "p");
+        assert!(!msg.retained());
+        assert_eq!(msg.qos(), 1);
+
+        let msg = PublishPolicy::state(&config(None, None), &retained).message("t", "p");
+        assert!(msg.retained());
+        assert_eq!(msg.qos(), 2);
+
+        let msg = PublishPolicy::state(&config(Some(0), Some(true)), &device).message("t", "p");
+        assert!(msg.retained());
+        assert_eq!(msg.qos(), 0);
+
+        let not_retained = AppDevice {
+            retained: Some(false),
+            ..Default::default()
+        };
+        let msg = PublishPolicy::state(&config(None, Some(true)), &not_retained).message("t", "p");
+        assert!(!msg.retained());
+    }
+
+    #[test]
+    fn events_are_never_retained() {
+        let device = AppDevice {
+            qos: Some(2),
+            retained: Some(true),
+            ..Default::default()
+        };
+        let msg = PublishPolicy::event(&config(None, Some(true)), &device).message("t", "p");
+        assert!(!msg.retained());
+        assert_eq!(msg.qos(), 2);
+    }
+
+    #[test]
+    fn fixed_classes() {
+        assert!(PublishPolicy::DISCOVERY.message("t", "").retained());
+        assert!(PublishPolicy::AVAILABILITY
+            .message("t", "online")
+            .retained());
+        assert!(PublishPolicy::BRIDGE_STATE.message("t", "{}").retained());
+        assert!(!PublishPolicy::BRIDGE_INFO.message("t", "{}").retained());
+        assert!(!PublishPolicy::SPARKPLUG.message("t", "").retained());
+    }
+
+    #[test]
+    fn rejects_invalid_qos() {
+        assert!(validate(&config(Some(1), None)).is_ok());
+        assert!(validate(&config(Some(3), None)).is_err());
+        let devices = [AppDevice {
+            qos: Some(-1),
+            ..Default::default()
+        }];
+        assert!(validate_devices(&devices).is_err());
+    }
+}
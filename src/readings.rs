@@ -0,0 +1,66 @@
+use std::sync::{Mutex, OnceLock};
+
+use btleplug::api::BDAddr;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// Latest reading of a device
+#[derive(Debug, Clone, serde_derive::Serialize)]
+pub struct LatestReading {
+    /// Name of the device
+    pub name: String,
+    /// Time the reading was received
+    pub timestamp: DateTime<Utc>,
+    pub data: ThermoBeaconFullReadResult,
+}
+
+/// Latest reading of each device, shared with the local interfaces (e.g. SNMP)
+static LATEST_READINGS: Mutex<Vec<LatestReading>> = Mutex::new(Vec::new());
+
+/// Live stream of all new readings
+static LIVE_READINGS: OnceLock<broadcast::Sender<LatestReading>> = OnceLock::new();
+
+/// Number of readings buffered for slow subscribers of the live stream
+const LIVE_BUFFER: usize = 64;
+
+fn live() -> &'static broadcast::Sender<LatestReading> {
+    LIVE_READINGS.get_or_init(|| broadcast::channel(LIVE_BUFFER).0)
+}
+
+/// Subscribes to the live stream of all new readings
+pub fn subscribe() -> broadcast::Receiver<LatestReading> {
+    live().subscribe()
+}
+
+/// Stores the reading as the latest one of its device and sends it to the subscribers of the live stream
+pub fn update(name: &str, data: &ThermoBeaconFullReadResult) {
+    let reading = LatestReading {
+        name: name.to_string(),
+        timestamp: Utc::now(),
+        data: data.clone(),
+    };
+    // Fails only without subscribers
+    let _ = live().send(reading.clone());
+    let mut readings = LATEST_READINGS.lock().unwrap();
+    match readings.iter_mut().find(|r| r.data.mac == data.mac) {
+        Some(existing) => *existing = reading,
+        None => readings.push(reading),
+    }
+}
+
+/// Returns the latest reading of the device with the given MAC, if any
+pub fn get(mac: BDAddr) -> Option<LatestReading> {
+    LATEST_READINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|r| r.data.mac == mac)
+        .cloned()
+}
+
+/// Returns the latest reading of all devices
+pub fn all() -> Vec<LatestReading> {
+    LATEST_READINGS.lock().unwrap().clone()
+}
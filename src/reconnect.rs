@@ -0,0 +1,101 @@
+use std::{error::Error, time::Duration};
+
+use crate::{
+    azure_iot,
+    configuration::{AppConfig, MqttConfig},
+    health_check_server, homeassistant,
+    mqtt::{self, AsyncClient, MqttClient},
+    remote_devices,
+};
+
+/// Minimum delay between two connection attempts
+pub const MIN_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between two connection attempts
+pub const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Interval to check the connection state
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Creates the client of the default MQTT broker and tries to connect it. If the broker is not reachable, the client is
+/// returned anyway and connected in the background, so it starts publishing as soon as the broker is available.
+pub async fn connect(
+    config: &AppConfig,
+    mqtt_config: &MqttConfig,
+) -> Result<AsyncClient, Box<dyn Error + Send + Sync>> {
+    debug!("Connecting to the MQTT server");
+    // Azure IoT Hub does not accept the availability topic
+    let presence = mqtt_config
+        .azure_iot_hub
+        .is_none()
+        .then(|| mqtt::Presence::availability(&config.availability_topic()));
+    let (cli, error) = mqtt::create_and_connect(mqtt_config, presence.as_ref()).await?;
+    let connected = match error {
+        None => true,
+        Some(e) => {
+            error!(
+                "Failed to connect to MQTT server, retrying in the background: {}",
+                e
+            );
+            false
+        }
+    };
+    azure_iot::spawn_token_renewal(cli.clone(), mqtt_config);
+    tokio::spawn(watch(cli.clone(), config.clone(), connected));
+    Ok(cli)
+}
+
+/// Watches the connection of the client. Until the first connection succeeds, connecting is retried with exponential backoff
+/// (afterwards the client reconnects automatically). Each time the connection returns, the Home Assistant discovery messages are sent again.
+async fn watch(cli: AsyncClient, config: AppConfig, connected: bool) {
+    let mut connected_once = connected;
+    let mut was_connected = connected;
+    let mut delay = MIN_DELAY;
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        health_check_server::set_mqtt_server(cli.active_server());
+
+        if cli.is_connected() {
+            if !was_connected {
+                info!("Connection to the MQTT server established");
+                was_connected = true;
+                delay = MIN_DELAY;
+                if config
+                    .mqtt
+                    .as_ref()
+                    .map(|c| c.homeassistant)
+                    .unwrap_or(false)
+                {
+                    if let Err(e) = homeassistant::publish_homeassistant_device_discovery_messages(
+                        &config, &cli, None,
+                    )
+                    .await
+                    {
+                        warn!("Failed to publish Home Assistant discovery messages: {}", e);
+                    }
+                }
+            }
+            continue;
+        }
+
+        if was_connected {
+            warn!("Connection to the MQTT server lost");
+            was_connected = false;
+        }
+        if connected_once {
+            // The client reconnects automatically
+            continue;
+        }
+        match cli.connect().await {
+            Ok(_) => connected_once = true,
+            Err(e) => {
+                debug!(
+                    "Failed to connect to MQTT server, retrying in {:?}: {}",
+                    delay, e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_DELAY);
+            }
+        }
+    }
+}
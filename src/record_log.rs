@@ -0,0 +1,276 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    clock, configuration::RecordLogConfig, thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Previous hash of the first record in a log
+static GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Content of a single record. The serialized form of this struct is hashed and signed.
+#[derive(Debug, serde_derive::Serialize)]
+struct Record<'a> {
+    /// Sequence number of the record within the log, starting with 0
+    seq: u64,
+    /// Time the record was written (RFC 3339, UTC)
+    timestamp: String,
+    /// Set if the system clock was not reliable when the record was written
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    clock_unreliable: bool,
+    /// Name of the device
+    device: &'a str,
+    /// Hash of the previous record
+    prev_hash: &'a str,
+    /// The actual reading
+    data: &'a ThermoBeaconFullReadResult,
+}
+
+/// Header fields of a record required to verify the chain
+#[derive(Debug, serde_derive::Deserialize)]
+struct RecordHeader {
+    seq: u64,
+    prev_hash: String,
+}
+
+/// A single line of the log: the record as written, its hash and the optional signature
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+struct LogLine {
+    record: Box<RawValue>,
+    hash: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    signature: Option<String>,
+}
+
+/// Returns the log file for the given device MAC
+fn log_file(config: &RecordLogConfig, mac: &str) -> PathBuf {
+    Path::new(&config.path).join(format!("{}.jsonl", mac.replace(':', "_")))
+}
+
+/// Calculates the hex encoded SHA-256 hash of the serialized record
+fn hash(record: &str) -> String {
+    hex::encode(Sha256::digest(record.as_bytes()))
+}
+
+/// Calculates the hex encoded HMAC-SHA256 signature of the serialized record
+fn sign(key: &str, record: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(record.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Reads the last line of the given file, without reading the whole file
+fn read_last_line(file: &mut File) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let len = file.metadata()?.len();
+    // A single record is well below this size
+    let start = len.saturating_sub(16 * 1024);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    Ok(String::from_utf8_lossy(&tail)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .last()
+        .map(|l| l.to_string()))
+}
+
+/// Appends a reading of the given device to its hash-chained log
+pub fn append(
+    config: &RecordLogConfig,
+    device: &str,
+    result: &ThermoBeaconFullReadResult,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    std::fs::create_dir_all(&config.path)?;
+    let path = log_file(config, &result.mac.to_string());
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(&path)?;
+
+    // Continue the chain of the last record
+    let (seq, prev_hash) = match read_last_line(&mut file)? {
+        Some(line) => {
+            let last: LogLine = serde_json::from_str(&line)?;
+            let header: RecordHeader = serde_json::from_str(last.record.get())?;
+            (header.seq + 1, last.hash)
+        }
+        None => (0, GENESIS_HASH.to_string()),
+    };
+
+    let record = serde_json::to_string(&Record {
+        seq,
+        timestamp: Utc::now().to_rfc3339(),
+        clock_unreliable: !clock::is_reliable(),
+        device,
+        prev_hash: &prev_hash,
+        data: result,
+    })?;
+    let line = LogLine {
+        hash: hash(&record),
+        signature: config.key.as_ref().map(|key| sign(key, &record)),
+        record: RawValue::from_string(record)?,
+    };
+
+    writeln!(file, "{}", serde_json::to_string(&line)?)?;
+    trace!("Appended record {} to {}", seq, path.display());
+    Ok(())
+}
+
+/// Verifies the hash chain (and signatures, if a key is given) of the given log file. Returns the number of verified records.
+pub fn verify(path: &Path, key: Option<&str>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let file = File::open(path)?;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_no = index + 1;
+        let entry: LogLine = serde_json::from_str(&line)
+            .map_err(|e| format!("Line {}: not a valid record: {}", line_no, e))?;
+        let record = entry.record.get();
+        let header: RecordHeader = serde_json::from_str(record)
+            .map_err(|e| format!("Line {}: not a valid record: {}", line_no, e))?;
+
+        if header.seq != count {
+            return Err(format!(
+                "Line {}: expected sequence number {} but found {}",
+                line_no, count, header.seq
+            )
+            .into());
+        }
+        if header.prev_hash != prev_hash {
+            return Err(format!(
+                "Line {}: chain broken, previous hash does not match",
+                line_no
+            )
+            .into());
+        }
+        if hash(record) != entry.hash {
+            return Err(
+                format!("Line {}: record was modified, hash does not match", line_no).into(),
+            );
+        }
+        if let Some(key) = key {
+            match &entry.signature {
+                Some(signature) if *signature == sign(key, record) => {}
+                Some(_) => return Err(format!("Line {}: signature does not match", line_no).into()),
+                None => return Err(format!("Line {}: record is not signed", line_no).into()),
+            }
+        }
+
+        prev_hash = entry.hash;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a signed log of three readings and returns its directory and log file
+    fn write_log(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "thermobeacon-record-log-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = RecordLogConfig {
+            path: dir.to_string_lossy().to_string(),
+            key: Some("secret".to_string()),
+            key_file: None,
+        };
+        let mac = "11:22:33:44:55:66".parse().unwrap();
+        for i in 0..3 {
+            let result = ThermoBeaconFullReadResult {
+                temperature: 20.0 + i as f32,
+                mac,
+                ..Default::default()
+            };
+            append(&config, "Basement", &result).unwrap();
+        }
+        let path = log_file(&config, "11:22:33:44:55:66");
+        (dir, path)
+    }
+
+    /// Modifies the lines of the log with the given function
+    fn rewrite(path: &Path, f: impl FnOnce(&mut Vec<String>)) {
+        let content = std::fs::read_to_string(path).unwrap();
+        let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+        f(&mut lines);
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn verifies_clean_chain() {
+        let (dir, path) = write_log("clean");
+        let signed = verify(&path, Some("secret"));
+        let unsigned = verify(&path, None);
+        let wrong_key = verify(&path, Some("other"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(signed.unwrap(), 3);
+        assert_eq!(unsigned.unwrap(), 3);
+        assert!(wrong_key
+            .unwrap_err()
+            .to_string()
+            .contains("signature does not match"));
+    }
+
+    #[test]
+    fn detects_edited_reading() {
+        let (dir, path) = write_log("edited");
+        rewrite(&path, |lines| {
+            assert!(lines[1].contains("\"temperature\":21.0"));
+            lines[1] = lines[1].replace("\"temperature\":21.0", "\"temperature\":-5.0");
+        });
+        let result = verify(&path, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Line 2: record was modified, hash does not match"
+        );
+    }
+
+    #[test]
+    fn detects_deleted_and_reordered_lines() {
+        let (dir, path) = write_log("deleted");
+        rewrite(&path, |lines| {
+            lines.remove(1);
+        });
+        let deleted = verify(&path, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (dir, path) = write_log("reordered");
+        rewrite(&path, |lines| lines.swap(1, 2));
+        let reordered = verify(&path, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            deleted.unwrap_err().to_string(),
+            "Line 2: expected sequence number 1 but found 2"
+        );
+        assert_eq!(
+            reordered.unwrap_err().to_string(),
+            "Line 2: expected sequence number 1 but found 2"
+        );
+    }
+}
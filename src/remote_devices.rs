@@ -0,0 +1,243 @@
+use std::{sync::Mutex, time::Duration};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use btleplug::api::BDAddr;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    configuration::{self, AppConfig, AppDevice, Schedule},
+    homeassistant,
+    mqtt_router::MessageRouter,
+    publish_policy, sink,
+};
+
+/// Time to wait for the retained device list at startup
+const INITIAL_WAIT: Duration = Duration::from_secs(5);
+
+/// Device list received from the broker. Replaces the configured devices, once received.
+static REMOTE_DEVICES: Mutex<Option<Vec<AppDevice>>> = Mutex::new(None);
+
+/// Schedules run by the scheduler. They are fixed at startup, so later device lists must not need other ones.
+static SCHEDULES: Mutex<Option<Vec<Schedule>>> = Mutex::new(None);
+
+/// Registers the schedules run by the scheduler, device lists received afterwards are checked against them
+pub fn set_schedules(schedules: &[Schedule]) {
+    *SCHEDULES.lock().unwrap() = Some(schedules.to_vec());
+}
+
+/// Returns the device list received from the broker, if any
+pub fn current() -> Option<Vec<AppDevice>> {
+    REMOTE_DEVICES.lock().unwrap().clone()
+}
+
+/// Returns the given configuration with the device list received from the broker, if any
+pub fn apply(config: &AppConfig) -> AppConfig {
+    match current() {
+        Some(devices) => AppConfig {
+            devices,
+            ..config.clone()
+        },
+        None => config.clone(),
+    }
+}
+
+/// Checks that the MACs, the publish policies, the schedules, the brokers and the payload templates of a device list are
+/// valid, before it replaces the configured devices. Compiles the payload templates for the sinks.
+pub fn validate(config: &AppConfig, devices: &[AppDevice]) -> Result<(), String> {
+    if let Some(device) = devices.iter().find(|d| d.mac.parse::<BDAddr>().is_err()) {
+        return Err(format!("{} is not a valid MAC", device.mac));
+    }
+    publish_policy::validate_devices(devices)?;
+    configuration::validate_device_intervals(devices)?;
+    configuration::validate_device_crons(devices)?;
+    if let Some(schedules) = SCHEDULES.lock().unwrap().as_deref() {
+        validate_schedules(config, devices, schedules)?;
+    }
+    if let Some((device, broker)) = devices
+        .iter()
+        .filter_map(|d| Some((d, d.broker.as_ref()?)))
+        .find(|(_, broker)| !config.brokers.contains_key(*broker))
+    {
+        return Err(format!(
+            "Broker {} of device {} is not configured",
+            broker, device.name
+        ));
+    }
+    sink::compile_templates(devices.iter().filter_map(|d| d.payload_template.as_deref()))
+}
+
+/// Checks that all devices are read on one of the given schedules
+fn validate_schedules(
+    config: &AppConfig,
+    devices: &[AppDevice],
+    schedules: &[Schedule],
+) -> Result<(), String> {
+    for device in devices {
+        match config.schedule(device) {
+            Some(schedule) if schedules.contains(&schedule) => {}
+            Some(schedule) => {
+                return Err(format!(
+                "Schedule {:?} of device {} is not one of the running schedules, restart to add it",
+                schedule, device.name
+            ))
+            }
+            None => return Err(format!("Device {} has no schedule", device.name)),
+        }
+    }
+    Ok(())
+}
+
+/// Parses and validates a device list message. Returns None for invalid or empty (deleted retained) messages.
+fn parse(config: &AppConfig, msg: &mqtt::Message) -> Option<Vec<AppDevice>> {
+    if msg.payload().is_empty() {
+        debug!(
+            "Device list on {} was deleted, keeping the current devices",
+            msg.topic()
+        );
+        return None;
+    }
+    let devices: Vec<AppDevice> = serde_json::from_slice(msg.payload())
+        .map_err(|e| error!("Invalid device list on {}: {}", msg.topic(), e))
+        .ok()?;
+    if let Err(e) = validate(config, &devices) {
+        error!("Invalid device list on {}: {}", msg.topic(), e);
+        return None;
+    }
+    Some(devices)
+}
+
+/// Stores a new device list, which replaces the configured devices. Returns true if it differs from the current one.
+pub fn update(devices: Vec<AppDevice>) -> bool {
+    let mut current = REMOTE_DEVICES.lock().unwrap();
+    if current.as_ref() == Some(&devices) {
+        return false;
+    }
+    info!("Received device list with {} devices", devices.len());
+    *current = Some(devices);
+    true
+}
+
+/// Subscribes to the (retained) JSON device list on the given topic and keeps it up to date. Waits a few seconds for the retained list before returning.
+/// If Home Assistant auto-discovery is enabled, the discovery messages are published again for each changed list.
+pub async fn start(config: &AppConfig, cli: &AsyncClient, router: &MessageRouter, topic: &str) {
+    let mut receiver = router.route(topic);
+    if let Err(e) = cli.subscribe(topic, 1).await {
+        error!("Failed to subscribe to device list topic {}: {}", topic, e);
+        return;
+    }
+
+    match tokio::time::timeout(INITIAL_WAIT, receiver.recv()).await {
+        Ok(Some(msg)) => {
+            if let Some(devices) = parse(config, &msg) {
+                update(devices);
+            }
+        }
+        _ => warn!(
+            "No device list received on {}, using the configured devices",
+            topic
+        ),
+    }
+
+    tokio::spawn(watch(config.clone(), cli.clone(), receiver));
+}
+
+async fn watch(
+    config: AppConfig,
+    cli: AsyncClient,
+    mut receiver: UnboundedReceiver<mqtt::Message>,
+) {
+    let homeassistant = config
+        .mqtt
+        .as_ref()
+        .map(|c| c.homeassistant)
+        .unwrap_or(false);
+
+    while let Some(msg) = receiver.recv().await {
+        if let Some(devices) = parse(&config, &msg) {
+            if update(devices) && homeassistant {
+                if let Err(e) = homeassistant::publish_homeassistant_device_discovery_messages(
+                    &apply(&config),
+                    &cli,
+                    None,
+                )
+                .await
+                {
+                    warn!("Failed to publish Home Assistant discovery messages: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_devices() {
+        let device = AppDevice {
+            mac: "11:22:33:44:55:66".to_string(),
+            name: "Attic".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig::default();
+        assert!(validate(&config, std::slice::from_ref(&device)).is_ok());
+
+        let invalid_mac = AppDevice {
+            mac: "attic".to_string(),
+            ..device.clone()
+        };
+        assert_eq!(
+            validate(&config, &[invalid_mac]),
+            Err("attic is not a valid MAC".to_string())
+        );
+
+        let zero_interval = AppDevice {
+            interval: Some(Duration::ZERO),
+            ..device.clone()
+        };
+        assert!(validate(&config, &[zero_interval]).is_err());
+
+        let invalid_cron = AppDevice {
+            cron: Some("every hour".to_string()),
+            ..device.clone()
+        };
+        assert!(validate(&config, &[invalid_cron]).is_err());
+
+        let unknown_broker = AppDevice {
+            broker: Some("cloud".to_string()),
+            ..device
+        };
+        assert_eq!(
+            validate(&config, &[unknown_broker]),
+            Err("Broker cloud of device Attic is not configured".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_devices_of_other_schedules() {
+        let config = AppConfig {
+            interval: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let schedules = [Schedule::Interval(Duration::from_secs(60))];
+        let device = AppDevice {
+            mac: "11:22:33:44:55:66".to_string(),
+            name: "Attic".to_string(),
+            ..Default::default()
+        };
+        assert!(validate_schedules(&config, std::slice::from_ref(&device), &schedules).is_ok());
+
+        let own_interval = AppDevice {
+            interval: Some(Duration::from_secs(300)),
+            ..device.clone()
+        };
+        assert!(validate_schedules(&config, &[own_interval], &schedules).is_err());
+
+        let unscheduled = AppConfig::default();
+        assert_eq!(
+            validate_schedules(&unscheduled, &[device], &schedules),
+            Err("Device Attic has no schedule".to_string())
+        );
+    }
+}
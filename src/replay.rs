@@ -0,0 +1,80 @@
+//! Replays the advertisements captured by the 'capture' subcommand through the decoding and delivery of the listening mode,
+//! e.g. to test the MQTT and Home Assistant output on machines without Bluetooth.
+
+use btleplug::api::BDAddr;
+use chrono::{DateTime, Utc};
+use std::{error::Error, path::Path};
+
+use crate::{
+    capture::CapturedAdvertisement,
+    configuration::AppConfig,
+    deliver_reading,
+    sink::Sink,
+    thermobeacon_protocol::{AdvertisementDecoder, ThermoBeaconFullReadResult},
+};
+
+/// Delivers the readings of the configured devices decoded from the captured advertisements of the given file.
+/// With `realtime`, the advertisements are replayed with their original time spacing, otherwise as fast as possible.
+pub async fn run(
+    config: &AppConfig,
+    sinks: &[Box<dyn Sink>],
+    file: &Path,
+    realtime: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let content = std::fs::read_to_string(file)?;
+    let macs: Vec<BDAddr> = config
+        .devices
+        .iter()
+        .filter_map(|d| d.mac.parse().ok())
+        .collect();
+    let options = config.scan_options();
+    let mut decoder = AdvertisementDecoder::new(&macs, &options.device_types, &options.models);
+
+    let mut previous: Option<DateTime<Utc>> = None;
+    let (mut advertisements, mut readings) = (0, 0);
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let advertisement: CapturedAdvertisement = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid advertisement in line {}: {}", number + 1, e))?;
+        if realtime {
+            if let Some(previous) = previous {
+                let delay = (advertisement.time - previous).to_std().unwrap_or_default();
+                tokio::time::sleep(delay).await;
+            }
+            previous = Some(advertisement.time);
+        }
+        advertisements += 1;
+
+        let props = advertisement.properties()?;
+        for reading in decoder.decode(
+            Some(advertisement.mac),
+            &props.manufacturer_data,
+            &props.service_data,
+        ) {
+            let device = match config
+                .devices
+                .iter()
+                .find(|d| d.mac.parse::<BDAddr>().ok() == Some(reading.mac))
+            {
+                Some(device) => device,
+                None => continue,
+            };
+            let reading = ThermoBeaconFullReadResult {
+                rssi: advertisement.rssi,
+                tx_power: advertisement.tx_power,
+                ..reading
+            };
+            deliver_reading(config, sinks, device, reading).await;
+            readings += 1;
+        }
+    }
+    info!(
+        "Replayed {} readings from {} advertisements of {}",
+        readings,
+        advertisements,
+        file.display()
+    );
+    Ok(())
+}
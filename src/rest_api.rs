@@ -0,0 +1,213 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use btleplug::api::BDAddr;
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::broadcast;
+
+use crate::{
+    configuration::AppConfig,
+    device_actions::{self, DeviceAction},
+    readings, remote_devices, scan_trigger, store,
+};
+
+/// Body of all error responses
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    let body = ErrorResponse {
+        message: message.into(),
+    };
+    (status, Json(body)).into_response()
+}
+
+/// A configured device with the time of its latest reading
+#[derive(Serialize)]
+struct Device {
+    mac: String,
+    name: String,
+    topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<DateTime<Utc>>,
+}
+
+/// Time range of the history, defaults to the last 24 hours
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// Parses the MAC of the path. Colons might be replaced by underscores.
+fn parse_mac(mac: &str) -> Result<BDAddr, Response> {
+    mac.replace('_', ":")
+        .parse::<BDAddr>()
+        .map_err(|_| error(StatusCode::BAD_REQUEST, format!("Invalid MAC {}", mac)))
+}
+
+async fn devices(State(config): State<Arc<AppConfig>>) -> Response {
+    // The device list might have been updated on the broker
+    let config = remote_devices::apply(&config);
+    let devices: Vec<Device> = config
+        .devices
+        .iter()
+        .map(|d| Device {
+            mac: d.mac.clone(),
+            name: d.name.clone(),
+            topic: config.device_topic(d),
+            last_seen: d
+                .mac
+                .parse::<BDAddr>()
+                .ok()
+                .and_then(readings::get)
+                .map(|r| r.timestamp),
+        })
+        .collect();
+    Json(devices).into_response()
+}
+
+async fn latest(Path(mac): Path<String>) -> Response {
+    let mac = match parse_mac(&mac) {
+        Ok(mac) => mac,
+        Err(response) => return response,
+    };
+    match readings::get(mac) {
+        Some(reading) => Json(reading).into_response(),
+        None => error(StatusCode::NOT_FOUND, format!("No reading of {} yet", mac)),
+    }
+}
+
+async fn history(
+    State(config): State<Arc<AppConfig>>,
+    Path(mac): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let mac = match parse_mac(&mac) {
+        Ok(mac) => mac,
+        Err(response) => return response,
+    };
+    let store_config = match &config.store {
+        Some(store_config) => store_config.clone(),
+        None => return error(StatusCode::NOT_FOUND, "History store not configured"),
+    };
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(1));
+
+    match tokio::task::spawn_blocking(move || store::history(&store_config, mac, from, to)).await {
+        Ok(Ok(readings)) => Json(readings).into_response(),
+        Ok(Err(e)) => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read history: {}", e),
+        ),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+async fn stream() -> Response {
+    let receiver = readings::subscribe();
+    let events = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(reading) => {
+                    let event = match serde_json::to_string(&reading) {
+                        Ok(json) => format!("event: reading\ndata: {}\n\n", json),
+                        Err(e) => {
+                            warn!("Failed to serialize reading for the live stream: {}", e);
+                            continue;
+                        }
+                    };
+                    return Some((Ok::<_, Infallible>(event), receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Live stream client too slow, skipped {} readings", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let headers = [
+        (header::CONTENT_TYPE, "text/event-stream"),
+        (header::CACHE_CONTROL, "no-cache"),
+    ];
+    (headers, Body::from_stream(events)).into_response()
+}
+
+/// Time to wait for a requested run in addition to the scan itself, e.g. for a run in progress
+const SCAN_TIMEOUT_MARGIN: std::time::Duration = std::time::Duration::from_secs(30);
+
+async fn scan(State(config): State<Arc<AppConfig>>) -> Response {
+    if config.listen.is_some() {
+        return error(
+            StatusCode::CONFLICT,
+            "Readings are delivered continuously in the listening mode",
+        );
+    }
+    // A scheduled run might be in progress
+    let timeout = std::time::Duration::from_secs(2 * config.seconds_to_scan) + SCAN_TIMEOUT_MARGIN;
+    match scan_trigger::run(timeout).await {
+        Ok(messages) => Json(messages).into_response(),
+        Err(e) => error(StatusCode::SERVICE_UNAVAILABLE, e),
+    }
+}
+
+/// Time to wait for a requested device action, e.g. for a run in progress
+const ACTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+async fn action(
+    State(config): State<Arc<AppConfig>>,
+    Path((mac, action)): Path<(String, String)>,
+) -> Response {
+    let mac = match parse_mac(&mac) {
+        Ok(mac) => mac,
+        Err(response) => return response,
+    };
+    let action = match DeviceAction::by_name(&action) {
+        Some(action) => action,
+        None => return error(StatusCode::NOT_FOUND, format!("Unknown action {}", action)),
+    };
+    if device_actions::find_device(&config, &mac.to_string()).is_none() {
+        return error(
+            StatusCode::NOT_FOUND,
+            format!("No ThermoBeacon {} configured", mac),
+        );
+    }
+    if config.listen.is_some() {
+        return error(
+            StatusCode::CONFLICT,
+            "Device actions are not supported in the listening mode",
+        );
+    }
+    match device_actions::run(mac, action, ACTION_TIMEOUT).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error(StatusCode::SERVICE_UNAVAILABLE, e),
+    }
+}
+
+/// Minimal dashboard using the REST API
+static STATUS_PAGE: &str = include_str!("status.html");
+
+async fn status_page() -> Html<&'static str> {
+    Html(STATUS_PAGE)
+}
+
+/// Routes of the REST API endpoints and the status page
+pub fn router() -> Router<Arc<AppConfig>> {
+    Router::new()
+        .route("/", get(status_page))
+        .route("/api/devices", get(devices))
+        .route("/api/devices/:mac/latest", get(latest))
+        .route("/api/devices/:mac/history", get(history))
+        .route("/api/stream", get(stream))
+        .route("/api/scan", post(scan))
+        .route("/api/devices/:mac/actions/:action", post(action))
+}
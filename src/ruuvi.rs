@@ -0,0 +1,105 @@
+//! Decoder for the advertisements of RuuviTags in the RAWv2 format (data format 5, manufacturer data of company id 0x0499)
+//!
+//! bytes | content (big endian)
+//! ========================================================
+//! 00-00 | data format (5)
+//! 01-02 | temperature (0.005 °C, signed)
+//! 03-04 | humidity (0.0025 %)
+//! 05-06 | pressure (Pa, offset -50000)
+//! 07-12 | acceleration x, y, z (mG, signed)
+//! 13-14 | battery voltage (11 bits, mV above 1600) and tx power (5 bits, 2 dBm steps above -40 dBm)
+//! 15-15 | movement counter
+//! 16-17 | measurement sequence number
+//! 18-23 | mac address
+
+use btleplug::api::BDAddr;
+use std::error::Error;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// Company id of Ruuvi Innovations
+pub const MANUFACTURER_KEY: u16 = 0x0499;
+
+/// Data format of RAWv2 advertisements
+const DATA_FORMAT: u8 = 5;
+
+/// Battery voltage (mV) considered empty (0 %) resp. full (100 %)
+const BATTERY_EMPTY_MV: u16 = 2000;
+const BATTERY_FULL_MV: u16 = 3000;
+
+/// Decodes the manufacturer data of a RAWv2 advertisement. Ruuvi does not report a battery level, so it is estimated from the battery voltage.
+pub fn parse(
+    mac: BDAddr,
+    data: &[u8],
+) -> Result<ThermoBeaconFullReadResult, Box<dyn Error + Send + Sync>> {
+    if data.len() < 18 {
+        return Err(format!("Unexpected Ruuvi frame length {}", data.len()).into());
+    }
+    if data[0] != DATA_FORMAT {
+        return Err(format!("Unsupported Ruuvi data format {}", data[0]).into());
+    }
+    let i16_at = |i: usize| i16::from_be_bytes([data[i], data[i + 1]]);
+    let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+
+    // 0x8000 (signed) resp. 0xFFFF (unsigned) mark invalid measurements
+    if i16_at(1) == i16::MIN || u16_at(3) == u16::MAX {
+        return Err("Invalid Ruuvi measurement".into());
+    }
+    let power = u16_at(13);
+    let voltage_mv = (power >> 5) + 1600;
+    let battery_level =
+        f32::from(voltage_mv.clamp(BATTERY_EMPTY_MV, BATTERY_FULL_MV) - BATTERY_EMPTY_MV)
+            / f32::from(BATTERY_FULL_MV - BATTERY_EMPTY_MV)
+            * 100.0;
+    let acceleration = |i: usize| {
+        Some(i16_at(i))
+            .filter(|a| *a != i16::MIN)
+            .map(|a| f32::from(a) / 1000.0)
+    };
+
+    Ok(ThermoBeaconFullReadResult {
+        temperature: f32::from(i16_at(1)) / 200.0,
+        humidity: f32::from(u16_at(3)) / 400.0,
+        battery_level,
+        mac,
+        tx_power: Some((power & 0x1F) as i16 * 2 - 40),
+        pressure: Some(u16_at(5))
+            .filter(|p| *p != u16::MAX)
+            .map(|p| (u32::from(p) + 50000) as f32 / 100.0),
+        acceleration_x: acceleration(7),
+        acceleration_y: acceleration(9),
+        acceleration_z: acceleration(11),
+        movement_counter: Some(data[15]).filter(|c| *c != u8::MAX),
+        battery_voltage: Some(f32::from(voltage_mv) / 1000.0),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_rawv2() {
+        let mac: BDAddr = "cb:b8:33:4c:88:4f".parse().unwrap();
+        // Valid data of the format specification
+        let data = [
+            0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04, 0xFF, 0xFC, 0x04, 0x0C, 0xAC,
+            0x36, 0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+        ];
+        let reading = parse(mac, &data).unwrap();
+        assert_eq!(reading.temperature, 24.3);
+        assert_eq!(reading.humidity, 53.49);
+        assert_eq!(reading.pressure, Some(1000.44));
+        assert_eq!(reading.acceleration_x, Some(0.004));
+        assert_eq!(reading.acceleration_y, Some(-0.004));
+        assert_eq!(reading.acceleration_z, Some(1.036));
+        assert_eq!(reading.battery_voltage, Some(2.977));
+        assert_eq!(reading.tx_power, Some(4));
+        assert_eq!(reading.movement_counter, Some(66));
+
+        let mut other_format = data;
+        other_format[0] = 3;
+        assert!(parse(mac, &other_format).is_err());
+    }
+}
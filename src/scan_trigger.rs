@@ -0,0 +1,46 @@
+use std::{sync::OnceLock, time::Duration};
+
+use tokio::sync::{broadcast, Notify};
+
+use crate::sink::Message;
+
+/// Pending request for an immediate run (e.g. from the D-Bus service)
+static SCAN_REQUESTED: OnceLock<Notify> = OnceLock::new();
+
+/// Results of the requested runs, for the requesters waiting for them
+static SCAN_RESULTS: OnceLock<broadcast::Sender<Result<Vec<Message>, String>>> = OnceLock::new();
+
+fn notify() -> &'static Notify {
+    SCAN_REQUESTED.get_or_init(Notify::new)
+}
+
+fn results() -> &'static broadcast::Sender<Result<Vec<Message>, String>> {
+    SCAN_RESULTS.get_or_init(|| broadcast::channel(1).0)
+}
+
+/// Requests an immediate run of the scheduled job. Several requests before the run are merged.
+pub fn request() {
+    notify().notify_one();
+}
+
+/// Waits until an immediate run is requested
+pub async fn requested() {
+    notify().notified().await
+}
+
+/// Publishes the result of a requested run to all waiting requesters
+pub fn completed(result: Result<Vec<Message>, String>) {
+    // Nobody might be waiting (e.g. requested by D-Bus)
+    let _ = results().send(result);
+}
+
+/// Requests an immediate run and waits (at most for the given timeout) for its readings
+pub async fn run(timeout: Duration) -> Result<Vec<Message>, String> {
+    let mut receiver = results().subscribe();
+    request();
+    match tokio::time::timeout(timeout, receiver.recv()).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("No run within {:?}", timeout)),
+    }
+}
@@ -0,0 +1,308 @@
+//! Access to the Bluetooth adapters and peripherals used by the scans. Implemented by btleplug and by mock scanners with
+//! canned advertisements, so the scans can be tested without hardware.
+
+use async_trait::async_trait;
+use btleplug::api::{BDAddr, Central, CentralState, PeripheralProperties, ScanFilter};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use std::error::Error;
+use std::fmt;
+use uuid::Uuid;
+
+/// Identifies the advertisements of a sensor type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisementId {
+    /// Company id (key) of the manufacturer data
+    CompanyId(u16),
+    /// UUID of the service data
+    Service(Uuid),
+}
+
+/// Parameters of the scans of an adapter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanParameters {
+    /// Scan passively without scan requests. Requires a filter and is only supported by the bluer scanner, others scan actively.
+    pub passive: bool,
+    /// Report each advertisement, even if its content did not change. Only supported by the bluer scanner.
+    pub duplicate_data: bool,
+    /// Advertisements to restrict the scan to, all advertisements if None
+    pub filter: Option<Vec<AdvertisementId>>,
+}
+
+impl ScanParameters {
+    /// Service UUIDs to restrict an active scan to. Empty (all advertisements) unless all filtered advertisements are identified by a service UUID,
+    /// as active scans can't be restricted to company ids.
+    pub fn services(&self) -> Vec<Uuid> {
+        let filter = match &self.filter {
+            Some(filter) => filter,
+            None => return vec![],
+        };
+        let services: Vec<Uuid> = filter
+            .iter()
+            .filter_map(|id| match id {
+                AdvertisementId::Service(uuid) => Some(*uuid),
+                AdvertisementId::CompanyId(_) => None,
+            })
+            .collect();
+        if services.len() < filter.len() {
+            return vec![];
+        }
+        services
+    }
+}
+
+/// Source of the Bluetooth adapters
+#[async_trait]
+pub trait BleScanner: Send + Sync {
+    type Adapter: ScannerAdapter;
+
+    /// All available adapters
+    async fn adapters(&self) -> Result<Vec<Self::Adapter>, Box<dyn Error + Send + Sync>>;
+}
+
+/// A single Bluetooth adapter
+#[async_trait]
+pub trait ScannerAdapter: Send + Sync {
+    type Peripheral: ScannerPeripheral;
+
+    /// Name of the adapter, e.g. for the logs and as key of the peripheral cache
+    async fn info(&self) -> Result<String, Box<dyn Error + Send + Sync>>;
+
+    /// Starts scanning for advertisements
+    async fn start_scan(
+        &self,
+        parameters: &ScanParameters,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Stops scanning for advertisements
+    async fn stop_scan(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// All peripherals seen by the adapter
+    async fn peripherals(&self) -> Result<Vec<Self::Peripheral>, Box<dyn Error + Send + Sync>>;
+
+    /// Powers the adapter (back) on, e.g. to recover from a failed scan
+    async fn power_on(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("Powering on the adapter is not supported".into())
+    }
+
+    /// Is the adapter powered on? None if unknown
+    async fn powered(&self) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+}
+
+/// A peripheral seen by an adapter
+#[async_trait]
+pub trait ScannerPeripheral: Clone + Send + Sync {
+    /// Address of the peripheral
+    fn address(&self) -> BDAddr;
+
+    /// Properties of the latest advertisement, if any
+    async fn properties(
+        &self,
+    ) -> Result<Option<PeripheralProperties>, Box<dyn Error + Send + Sync>>;
+}
+
+/// No Bluetooth adapter can scan: none found, all powered off or blocked by rfkill
+#[derive(Debug)]
+pub struct AdapterUnavailable(pub String);
+
+impl fmt::Display for AdapterUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AdapterUnavailable {}
+
+/// Unblocks soft blocked Bluetooth rfkill switches and powers on the adapters which are powered off, e.g. after a reboot.
+/// Fails with [`AdapterUnavailable`] if no adapter is found or none could be powered on.
+pub async fn ensure_powered<S: BleScanner>(
+    scanner: &S,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let hard_blocked = unblock_rfkill();
+    let adapters = scanner
+        .adapters()
+        .await
+        .map_err(|e| AdapterUnavailable(format!("Bluetooth not available: {}", e)))?;
+    if adapters.is_empty() {
+        let reason = if hard_blocked.is_empty() {
+            "No Bluetooth adapters found".to_string()
+        } else {
+            format!(
+                "Bluetooth blocked by a hardware switch: {}",
+                hard_blocked.join(", ")
+            )
+        };
+        return Err(AdapterUnavailable(reason).into());
+    }
+
+    let mut powered_off = vec![];
+    for adapter in &adapters {
+        if let Ok(Some(false)) = adapter.powered().await {
+            let info = adapter.info().await.unwrap_or_default();
+            warn!("{} is powered off, powering it on", info);
+            if let Err(e) = adapter.power_on().await {
+                warn!("Failed to power on {}: {}", info, e);
+                powered_off.push(info);
+            }
+        }
+    }
+    if powered_off.len() == adapters.len() {
+        return Err(AdapterUnavailable(format!(
+            "Bluetooth adapters powered off: {}",
+            powered_off.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Unblocks the soft blocked Bluetooth rfkill switches. Returns the names of the hard blocked ones, which can't be unblocked by software.
+#[cfg(target_os = "linux")]
+fn unblock_rfkill() -> Vec<String> {
+    let read = |path: std::path::PathBuf| {
+        std::fs::read_to_string(path)
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    let entries = match std::fs::read_dir("/sys/class/rfkill") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut hard_blocked = vec![];
+    for path in entries.flatten().map(|e| e.path()) {
+        if read(path.join("type")) != "bluetooth" {
+            continue;
+        }
+        let name = read(path.join("name"));
+        if read(path.join("hard")) == "1" {
+            hard_blocked.push(name);
+        } else if read(path.join("soft")) == "1" {
+            warn!("{} is soft blocked, unblocking it", name);
+            if let Err(e) = std::fs::write(path.join("soft"), "0") {
+                warn!("Failed to unblock {}: {}", name, e);
+            }
+        }
+    }
+    hard_blocked
+}
+
+/// rfkill is only available on Linux
+#[cfg(not(target_os = "linux"))]
+fn unblock_rfkill() -> Vec<String> {
+    vec![]
+}
+
+/// Peripherals of the adapters of the given scanner
+pub type PeripheralOf<S> = <<S as BleScanner>::Adapter as ScannerAdapter>::Peripheral;
+
+/// Scanner of the scheduled scans: BlueZ through bluer with the Cargo feature `bluer` on Linux, btleplug otherwise
+#[cfg(all(feature = "bluer", target_os = "linux"))]
+pub type DefaultScanner = crate::bluer_scanner::BluerScanner;
+/// Scanner of the scheduled scans: BlueZ through bluer with the Cargo feature `bluer` on Linux, btleplug otherwise
+#[cfg(not(all(feature = "bluer", target_os = "linux")))]
+pub type DefaultScanner = Manager;
+
+/// Creates the scanner of the scheduled scans, sharing the btleplug manager unless bluer is used
+pub async fn default_scanner(
+    manager: &Manager,
+) -> Result<DefaultScanner, Box<dyn Error + Send + Sync>> {
+    #[cfg(all(feature = "bluer", target_os = "linux"))]
+    {
+        let _ = manager;
+        info!("Scanning with bluer");
+        crate::bluer_scanner::BluerScanner::new().await
+    }
+    #[cfg(not(all(feature = "bluer", target_os = "linux")))]
+    Ok(manager.clone())
+}
+
+#[async_trait]
+impl BleScanner for Manager {
+    type Adapter = Adapter;
+
+    async fn adapters(&self) -> Result<Vec<Adapter>, Box<dyn Error + Send + Sync>> {
+        Ok(btleplug::api::Manager::adapters(self).await?)
+    }
+}
+
+#[async_trait]
+impl ScannerAdapter for Adapter {
+    type Peripheral = Peripheral;
+
+    async fn info(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.adapter_info().await?)
+    }
+
+    /// btleplug always scans actively and filters duplicate advertisements depending on the platform
+    async fn start_scan(
+        &self,
+        parameters: &ScanParameters,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if parameters.passive {
+            debug!("Passive scans are not supported by btleplug, scanning actively");
+        }
+        let filter = ScanFilter {
+            services: parameters.services(),
+        };
+        Ok(Central::start_scan(self, filter).await?)
+    }
+
+    async fn stop_scan(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(Central::stop_scan(self).await?)
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Peripheral>, Box<dyn Error + Send + Sync>> {
+        Ok(Central::peripherals(self).await?)
+    }
+
+    /// btleplug can't power adapters, so the adapter is powered on through BlueZ on the system D-Bus
+    #[cfg(target_os = "linux")]
+    async fn power_on(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let info = self.adapter_info().await?;
+        let name = info
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        tokio::task::spawn_blocking(move || bluez_power_on(&name)).await?
+    }
+
+    async fn powered(&self) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        Ok(match self.adapter_state().await? {
+            CentralState::PoweredOn => Some(true),
+            CentralState::PoweredOff => Some(false),
+            CentralState::Unknown => None,
+        })
+    }
+}
+
+/// Sets the 'Powered' property of the BlueZ adapter with the given name (e.g. 'hci0')
+#[cfg(target_os = "linux")]
+fn bluez_power_on(name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    use dbus::blocking::{stdintf::org_freedesktop_dbus::Properties, Connection};
+    use std::time::Duration;
+
+    let connection = Connection::new_system()?;
+    let proxy = connection.with_proxy(
+        "org.bluez",
+        format!("/org/bluez/{}", name),
+        Duration::from_secs(5),
+    );
+    proxy.set("org.bluez.Adapter1", "Powered", true)?;
+    Ok(())
+}
+
+#[async_trait]
+impl ScannerPeripheral for Peripheral {
+    fn address(&self) -> BDAddr {
+        btleplug::api::Peripheral::address(self)
+    }
+
+    async fn properties(
+        &self,
+    ) -> Result<Option<PeripheralProperties>, Box<dyn Error + Send + Sync>> {
+        Ok(btleplug::api::Peripheral::properties(self).await?)
+    }
+}
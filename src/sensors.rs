@@ -0,0 +1,119 @@
+//! Sensor types besides the ThermoBeacons. Each type has a decoder for its advertisements, which yields the common reading struct.
+
+use btleplug::api::BDAddr;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+    bthome, govee, inkbird, ruuvi, scanner::AdvertisementId, switchbot,
+    thermobeacon_models::MODELS, thermobeacon_protocol::ThermoBeaconFullReadResult, xiaomi_atc,
+};
+
+/// Type of a sensor, selects the decoder of its advertisements
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    /// ThermoBeacon hygrometer, alternately advertising the current data and the min/max data
+    #[default]
+    #[serde(rename = "thermobeacon")]
+    ThermoBeacon,
+    /// Govee H5072 / H5075 hygrometer
+    #[serde(alias = "govee_h5072")]
+    GoveeH5075,
+    /// Xiaomi LYWSD03MMC hygrometer with the custom ATC1441 or pvvx firmware
+    #[serde(alias = "lywsd03mmc")]
+    XiaomiAtc,
+    /// Inkbird IBS-TH1 / IBS-TH2 sensor, optionally with external probe
+    #[serde(alias = "ibs_th1", alias = "ibs_th2")]
+    Inkbird,
+    /// SwitchBot Meter / Meter Plus hygrometer
+    #[serde(alias = "switchbot_meter")]
+    SwitchBot,
+    /// RuuviTag environmental sensor advertising the RAWv2 format
+    #[serde(alias = "ruuvitag")]
+    Ruuvi,
+    /// Any sensor advertising the BTHome v2 format (e.g. Shelly BLU H&T), its fields are determined by the advertisement
+    #[serde(rename = "bthome")]
+    BtHome,
+}
+
+impl DeviceType {
+    /// Does this type provide the given optional field of the reading (e.g. 'uptime')? Temperature, humidity and battery level are provided by all types.
+    pub fn provides(&self, field: &str) -> bool {
+        match self {
+            DeviceType::ThermoBeacon => matches!(
+                field,
+                "uptime"
+                    | "button_pressed"
+                    | "max_temperature"
+                    | "min_temperature"
+                    | "max_temp_time"
+                    | "min_temp_time"
+            ),
+            DeviceType::Inkbird => field == "external_temperature",
+            DeviceType::Ruuvi => matches!(
+                field,
+                "pressure"
+                    | "acceleration_x"
+                    | "acceleration_y"
+                    | "acceleration_z"
+                    | "movement_counter"
+                    | "battery_voltage"
+            ),
+            DeviceType::BtHome => {
+                matches!(field, "button_pressed" | "pressure" | "battery_voltage")
+            }
+            DeviceType::GoveeH5075 | DeviceType::XiaomiAtc | DeviceType::SwitchBot => false,
+        }
+    }
+
+    /// Company ids of the manufacturer data or UUIDs of the service data advertised by this type, to filter scans.
+    /// Empty if the advertisements can't be identified (Inkbird sensors do not advertise a company id).
+    pub fn advertisement_ids(&self) -> Vec<AdvertisementId> {
+        match self {
+            DeviceType::ThermoBeacon => MODELS
+                .iter()
+                .flat_map(|m| m.manufacturer_keys.iter())
+                .map(|key| AdvertisementId::CompanyId(*key))
+                .collect(),
+            DeviceType::GoveeH5075 => vec![AdvertisementId::CompanyId(govee::MANUFACTURER_KEY)],
+            DeviceType::Ruuvi => vec![AdvertisementId::CompanyId(ruuvi::MANUFACTURER_KEY)],
+            DeviceType::XiaomiAtc => vec![AdvertisementId::Service(xiaomi_atc::SERVICE_UUID)],
+            DeviceType::SwitchBot => vec![AdvertisementId::Service(switchbot::SERVICE_UUID)],
+            DeviceType::BtHome => vec![AdvertisementId::Service(bthome::SERVICE_UUID)],
+            DeviceType::Inkbird => vec![],
+        }
+    }
+}
+
+/// Decodes a reading of a sensor of the given type from the manufacturer and service data of an advertisement. Returns None if the data does not contain a reading of this type.
+/// ThermoBeacons need two frames for a complete reading, they are decoded by `thermobeacon_protocol`.
+pub fn decode(
+    device_type: DeviceType,
+    mac: BDAddr,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+    service_data: &HashMap<Uuid, Vec<u8>>,
+) -> Option<ThermoBeaconFullReadResult> {
+    let result = match device_type {
+        DeviceType::ThermoBeacon => return None,
+        DeviceType::GoveeH5075 => {
+            govee::parse(mac, manufacturer_data.get(&govee::MANUFACTURER_KEY)?)
+        }
+        DeviceType::XiaomiAtc => {
+            xiaomi_atc::parse(mac, service_data.get(&xiaomi_atc::SERVICE_UUID)?)
+        }
+        DeviceType::Inkbird => {
+            // The key of the manufacturer data is part of the reading
+            let (key, data) = manufacturer_data
+                .iter()
+                .find(|(_, data)| data.len() == inkbird::DATA_LENGTH)?;
+            inkbird::parse(mac, *key, data)
+        }
+        DeviceType::BtHome => bthome::parse(mac, service_data.get(&bthome::SERVICE_UUID)?),
+        DeviceType::Ruuvi => ruuvi::parse(mac, manufacturer_data.get(&ruuvi::MANUFACTURER_KEY)?),
+        DeviceType::SwitchBot => switchbot::parse(mac, service_data.get(&switchbot::SERVICE_UUID)?),
+    };
+    result
+        .map_err(|e| trace!("Invalid advertisement of {}: {}", mac, e))
+        .ok()
+}
@@ -0,0 +1,185 @@
+//! Simulated devices publishing synthetic readings through the normal sinks, e.g. to develop dashboards and automations before the hardware arrives.
+
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use btleplug::api::BDAddr;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::time::Instant;
+
+use crate::{
+    configuration::{AppConfig, AppDevice, SimulateConfig, ValueRange},
+    deliver_reading,
+    leader_election::LeaderElection,
+    sink::Sink,
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Maximum change of a value between two readings, relative to its range
+const MAX_STEP: f32 = 0.05;
+
+/// Number of readings to drain the battery from the maximum to the minimum of its range
+const BATTERY_LIFETIME: f32 = 10_000.0;
+
+/// Generated devices, if no devices are configured
+pub fn devices(config: &SimulateConfig) -> Vec<AppDevice> {
+    (1..=config.count)
+        .map(|n| AppDevice {
+            mac: format!("00:00:00:00:00:{:02X}", n),
+            name: format!("Simulated {}", n),
+            model: Some("Simulated".to_string()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+impl ValueRange {
+    fn bounds(&self) -> (f32, f32) {
+        (self.min.min(self.max), self.min.max(self.max))
+    }
+
+    fn middle(&self) -> f32 {
+        (self.min + self.max) / 2.0
+    }
+}
+
+/// Next value of a random walk within the range
+fn step(value: f32, range: &ValueRange, rng: &mut impl Rng) -> f32 {
+    let (min, max) = range.bounds();
+    (value + rng.gen_range(-1.0..=1.0) * (max - min) * MAX_STEP).clamp(min, max)
+}
+
+/// Current state of a simulated device
+struct SimulatedDevice {
+    temperature: f32,
+    humidity: f32,
+    battery: f32,
+    /// Extremes of the temperature with the uptime they occurred at
+    max: (f32, u32),
+    min: (f32, u32),
+}
+
+impl SimulatedDevice {
+    fn new(config: &SimulateConfig) -> Self {
+        let temperature = config.temperature.middle();
+        SimulatedDevice {
+            temperature,
+            humidity: config.humidity.middle(),
+            battery: config.battery.bounds().1,
+            max: (temperature, 0),
+            min: (temperature, 0),
+        }
+    }
+
+    /// Advances the simulation by one reading
+    fn next(
+        &mut self,
+        config: &SimulateConfig,
+        mac: BDAddr,
+        uptime: u32,
+        rng: &mut impl Rng,
+    ) -> ThermoBeaconFullReadResult {
+        self.temperature = step(self.temperature, &config.temperature, rng);
+        self.humidity = step(self.humidity, &config.humidity, rng);
+        // The battery drains slowly and is replaced once empty
+        let (min, max) = config.battery.bounds();
+        self.battery -= (max - min) / BATTERY_LIFETIME;
+        if self.battery < min {
+            self.battery = max;
+        }
+        if self.temperature > self.max.0 {
+            self.max = (self.temperature, uptime);
+        }
+        if self.temperature < self.min.0 {
+            self.min = (self.temperature, uptime);
+        }
+        ThermoBeaconFullReadResult {
+            battery_level: self.battery,
+            humidity: self.humidity,
+            temperature: self.temperature,
+            uptime,
+            mac,
+            max_temperature: Some(self.max.0),
+            min_temperature: Some(self.min.0),
+            max_temp_time: Some(self.max.1),
+            min_temp_time: Some(self.min.1),
+            rssi: Some(rng.gen_range(-90..=-50)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Delivers a reading of each configured device in the configured interval
+pub async fn run(
+    config: AppConfig,
+    sinks: Vec<Box<dyn Sink>>,
+    election: Option<Arc<LeaderElection>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let simulate = config.simulate.clone().unwrap();
+    info!(
+        "Simulating {} devices, a reading every {}",
+        config.devices.len(),
+        humantime::format_duration(simulate.interval)
+    );
+    let started = Instant::now();
+    let mut rng = StdRng::from_entropy();
+    let mut states: HashMap<BDAddr, SimulatedDevice> = HashMap::new();
+    let mut ticker = tokio::time::interval(simulate.interval);
+    loop {
+        ticker.tick().await;
+        // Standby gateways do not publish sensor values
+        if let Some(election) = &election {
+            if !election.is_leader() {
+                continue;
+            }
+        }
+        let uptime = started.elapsed().as_secs() as u32;
+        for device in &config.devices {
+            let mac = match device.mac.parse::<BDAddr>() {
+                Ok(mac) => mac,
+                Err(_) => continue,
+            };
+            let reading = states
+                .entry(mac)
+                .or_insert_with(|| SimulatedDevice::new(&simulate))
+                .next(&simulate, mac, uptime, &mut rng);
+            deliver_reading(&config, &sinks, device, reading).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_values_stay_within_their_ranges() {
+        let config = SimulateConfig {
+            interval: std::time::Duration::from_secs(1),
+            count: 1,
+            temperature: ValueRange {
+                min: -5.0,
+                max: 5.0,
+            },
+            humidity: ValueRange {
+                min: 90.0,
+                max: 30.0,
+            },
+            battery: ValueRange {
+                min: 50.0,
+                max: 50.0,
+            },
+        };
+        let mac = "00:00:00:00:00:01".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut device = SimulatedDevice::new(&config);
+        for uptime in 0..1000 {
+            let reading = device.next(&config, mac, uptime, &mut rng);
+            assert!((-5.0..=5.0).contains(&reading.temperature));
+            assert!((30.0..=90.0).contains(&reading.humidity));
+            assert_eq!(reading.battery_level, 50.0);
+            assert!(reading.min_temperature.unwrap() <= reading.temperature);
+            assert!(reading.max_temperature.unwrap() >= reading.temperature);
+        }
+        assert_eq!(devices(&config)[0].name, "Simulated 1");
+    }
+}
@@ -0,0 +1,837 @@
+use std::{error::Error, io::Write, sync::Mutex, time::Duration};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{
+    alerts::Alert,
+    aws_iot, azure_iot, brokers, button, clock,
+    comfort::ComputedFields,
+    configuration::{AppConfig, AppDevice, OutputConfig, PayloadFormat, PublishMode, SpoolConfig},
+    device_availability, device_events,
+    file_output::FileSink,
+    payload_format,
+    publish_policy::PublishPolicy,
+    sparkplug::SparkplugSink,
+    spool,
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+    webhook::WebhookSink,
+};
+
+/// Payload templates compiled by `compile_templates`, named by their source
+static TEMPLATES: Mutex<Option<minijinja::Environment<'static>>> = Mutex::new(None);
+
+/// Compiles the given payload templates once, so they can be rendered by `Message::payload`. Fails on the first invalid one.
+pub fn compile_templates<'a>(templates: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+    let mut env = TEMPLATES.lock().unwrap();
+    let env = env.get_or_insert_with(minijinja::Environment::new);
+    for template in templates {
+        if env.get_template(template).is_err() {
+            env.add_template_owned(template.to_string(), template.to_string())
+                .map_err(|e| format!("Invalid payload template '{}': {}", template, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Structure of the message delivered by the sinks (e.g. as MQTT payload)
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
+pub struct Message {
+    pub data: ThermoBeaconFullReadResult,
+    pub name: String,
+    /// Name of the gateway instance, if configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Set if the system clock was not reliable while reading the data
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub clock_unreliable: bool,
+    /// Comfort values derived from the reading, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub computed: Option<ComputedFields>,
+    /// Time of the reading (RFC 3339 in the configured timezone)
+    pub measured_at: String,
+    /// Id of the run the reading belongs to (each reading in the continuous mode), increasing while the server is running
+    pub run_id: u64,
+    /// Time of the maximum temperature (RFC 3339), resolved from `max_temp_time` and the uptime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_temp_at: Option<String>,
+    /// Time of the minimum temperature (RFC 3339), resolved from `min_temp_time` and the uptime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_temp_at: Option<String>,
+}
+
+impl Message {
+    /// Creates the message for a current reading of the given device
+    pub fn new(config: &AppConfig, device: &AppDevice, data: ThermoBeaconFullReadResult) -> Self {
+        Self::at(config, device, data, Utc::now())
+    }
+
+    /// Creates the message for a reading of the given device taken at the given time
+    pub fn at(
+        config: &AppConfig,
+        device: &AppDevice,
+        data: ThermoBeaconFullReadResult,
+        time: DateTime<Utc>,
+    ) -> Self {
+        // Computed from the metric values, converted afterwards
+        let units = config.units(device);
+        let computed = if config.computed_fields {
+            ComputedFields::new(data.temperature, data.humidity).map(|c| ComputedFields {
+                dew_point: units.temperature(c.dew_point),
+                heat_index: units.temperature(c.heat_index),
+                ..c
+            })
+        } else {
+            None
+        };
+        let now = time.with_timezone(&config.tz());
+        // Times of the device are relative to its last reset
+        let resolve =
+            |time: Option<u32>| time.map(|t| since_reset(now, data.uptime, t).to_rfc3339());
+        Message {
+            max_temp_at: resolve(data.max_temp_time),
+            min_temp_at: resolve(data.min_temp_time),
+            data: ThermoBeaconFullReadResult {
+                temperature: units.temperature(data.temperature),
+                max_temperature: data.max_temperature.map(|t| units.temperature(t)),
+                min_temperature: data.min_temperature.map(|t| units.temperature(t)),
+                external_temperature: data.external_temperature.map(|t| units.temperature(t)),
+                ..data
+            },
+            name: device.name.clone(),
+            instance: config.instance_name.clone(),
+            clock_unreliable: !clock::is_reliable(),
+            computed,
+            measured_at: now.to_rfc3339(),
+            run_id: clock::run_id(),
+        }
+    }
+}
+
+/// Resolves a time of the device (seconds since its last reset) into an absolute time, given the current time and uptime of the device
+fn since_reset<Tz: TimeZone>(now: DateTime<Tz>, uptime: u32, time: u32) -> DateTime<Tz> {
+    now - chrono::Duration::seconds(i64::from(uptime) - i64::from(time))
+}
+
+impl Message {
+    /// Payload of the message: the rendered template (Jinja2 syntax, with the fields of the message as context) or the
+    /// message encoded in the given format. The template must have been compiled with `compile_templates`.
+    pub fn payload(
+        &self,
+        format: PayloadFormat,
+        template: Option<&str>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match template {
+            Some(template) => {
+                let env = TEMPLATES.lock().unwrap();
+                let compiled = env
+                    .as_ref()
+                    .and_then(|env| env.get_template(template).ok())
+                    .ok_or_else(|| format!("Payload template '{}' was not compiled", template))?;
+                Ok(compiled.render(self)?.into_bytes())
+            }
+            None => payload_format::encode(format, self),
+        }
+    }
+
+    /// Scalar values of all present fields with the name of their topic, for the per-field publish mode
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let data = &self.data;
+        let mut fields = vec![
+            ("temperature", data.temperature.to_string()),
+            ("humidity", data.humidity.to_string()),
+            ("battery", data.battery_level.to_string()),
+            ("uptime", data.uptime.to_string()),
+            ("button_pressed", data.button_pressed.to_string()),
+        ];
+        let optional = [
+            (
+                "max_temperature",
+                data.max_temperature.map(|v| v.to_string()),
+            ),
+            (
+                "min_temperature",
+                data.min_temperature.map(|v| v.to_string()),
+            ),
+            ("max_temp_time", data.max_temp_time.map(|v| v.to_string())),
+            ("min_temp_time", data.min_temp_time.map(|v| v.to_string())),
+            ("rssi", data.rssi.map(|v| v.to_string())),
+            ("tx_power", data.tx_power.map(|v| v.to_string())),
+            (
+                "external_temperature",
+                data.external_temperature.map(|v| v.to_string()),
+            ),
+            ("pressure", data.pressure.map(|v| v.to_string())),
+            ("acceleration_x", data.acceleration_x.map(|v| v.to_string())),
+            ("acceleration_y", data.acceleration_y.map(|v| v.to_string())),
+            ("acceleration_z", data.acceleration_z.map(|v| v.to_string())),
+            (
+                "movement_counter",
+                data.movement_counter.map(|v| v.to_string()),
+            ),
+            (
+                "battery_voltage",
+                data.battery_voltage.map(|v| v.to_string()),
+            ),
+        ];
+        fields.extend(
+            optional
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|v| (name, v))),
+        );
+        let resolved = [
+            ("max_temp_at", self.max_temp_at.clone()),
+            ("min_temp_at", self.min_temp_at.clone()),
+        ];
+        fields.extend(
+            resolved
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|v| (name, v))),
+        );
+        if let Some(computed) = &self.computed {
+            fields.push(("dew_point", computed.dew_point.to_string()));
+            fields.push(("absolute_humidity", computed.absolute_humidity.to_string()));
+            fields.push(("heat_index", computed.heat_index.to_string()));
+        }
+        fields
+    }
+}
+
+/// Output readings are delivered to
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Name of the sink, used in log messages
+    fn name(&self) -> &'static str;
+
+    /// Delivers a reading of the given device
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Delivers a reading recovered from the log of the given device with the time it was taken. Ignored by default.
+    async fn publish_history(
+        &self,
+        _data: &ThermoBeaconFullReadResult,
+        _device: &AppDevice,
+        _measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Completes a run (a scheduled scan or a single reading while listening), e.g. delivers the readings batched during the
+    /// run. Ignored by default.
+    async fn finish_run(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Announces if the given device is online (found recently) or offline (missing for several runs). Ignored by default.
+    async fn publish_availability(
+        &self,
+        _device: &AppDevice,
+        _online: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+    /// Announces a raised or cleared alert of the given device. Ignored by default.
+    async fn publish_alert(
+        &self,
+        _device: &AppDevice,
+        _alert: &Alert,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+}
+
+/// Number of attempts to publish a message before giving up
+const PUBLISH_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a failed publish, doubled with each attempt
+const PUBLISH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Publishes a message, reconnecting the client first if necessary
+async fn publish_once(
+    client: &AsyncClient,
+    msg: mqtt::Message,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !client.is_connected() {
+        info!("MQTT client is not connected. Try to reconnect ...");
+        client.reconnect().await?;
+    }
+    client.publish(msg).await?;
+    Ok(())
+}
+
+/// Publishes a message, retrying failed attempts with exponential backoff
+async fn publish_with_retry(
+    client: &AsyncClient,
+    msg: mqtt::Message,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut delay = PUBLISH_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match publish_once(client, msg.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < PUBLISH_ATTEMPTS => {
+                warn!(
+                    "Failed to publish to {} (attempt {} of {}), retrying in {:?}: {}",
+                    msg.topic(),
+                    attempt,
+                    PUBLISH_ATTEMPTS,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Publishes the spooled messages (in order) and the given message to the broker
+async fn publish_spooled(
+    client: &AsyncClient,
+    spool_config: Option<&SpoolConfig>,
+    broker: Option<&str>,
+    msg: mqtt::Message,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(spool_config) = spool_config {
+        let replayed = spool::replay(spool_config, broker, client).await?;
+        if replayed > 0 {
+            info!("Published {} spooled messages", replayed);
+        }
+    }
+    publish_with_retry(client, msg).await
+}
+
+/// Publishes the readings as JSON to the state topic of each device
+pub struct MqttSink {
+    client: AsyncClient,
+    config: AppConfig,
+}
+
+impl MqttSink {
+    pub fn new(client: AsyncClient, config: &AppConfig) -> Self {
+        MqttSink {
+            client,
+            config: config.clone(),
+        }
+    }
+
+    /// Messages of the battery-low state and the events detected in the reading of the given device
+    fn device_event_messages(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        changes: &device_events::Changes,
+    ) -> Vec<mqtt::Message> {
+        let mut msgs = vec![];
+        if let Some(low) = changes.battery_low {
+            let payload = if low {
+                warn!("Battery of {} low ({}%)", device.name, data.battery_level);
+                device_events::ON
+            } else {
+                device_events::OFF
+            };
+            msgs.push(
+                match azure_iot::event_topic(
+                    &self.config,
+                    device,
+                    &[("battery_low", payload)],
+                    false,
+                ) {
+                    Some(topic) => {
+                        PublishPolicy::event(&self.config, device).message(topic, payload)
+                    }
+                    None => PublishPolicy::BATTERY_LOW
+                        .message(self.config.battery_low_topic(device), payload),
+                },
+            );
+        }
+        for &event in changes.events.iter() {
+            if event == device_events::REBOOT_EVENT {
+                info!("{} rebooted (uptime {}s)", device.name, data.uptime);
+            }
+            let topic = azure_iot::event_topic(&self.config, device, &[("event", "device")], false)
+                .unwrap_or_else(|| self.config.device_event_topic(device));
+            msgs.push(PublishPolicy::event(&self.config, device).message(topic, event));
+        }
+        msgs
+    }
+}
+
+#[async_trait]
+impl Sink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let topic = &self.config.device_topic(device);
+        let policy = PublishPolicy::state(&self.config, device);
+        let user_properties = self.config.user_properties();
+        let message_expiry = self.config.message_expiry(device);
+        let state_message = |topic: String, payload: Vec<u8>| {
+            policy
+                .message(topic, payload)
+                .with_user_properties(user_properties.clone())
+                .with_message_expiry(message_expiry)
+        };
+        // Shadow topics and IoT Hub do not retain messages
+        let unretained_message = |topic: String, payload: Vec<u8>| {
+            PublishPolicy {
+                retained: false,
+                ..policy
+            }
+            .message(topic, payload)
+            .with_user_properties(user_properties.clone())
+            .with_message_expiry(message_expiry)
+        };
+
+        let msg = Message::new(&self.config, device, data.clone());
+        let publish_mode = self
+            .config
+            .mqtt
+            .as_ref()
+            .map(|m| m.publish_mode)
+            .unwrap_or_default();
+        let mut mqtt_msgs = vec![];
+        // Json message
+        if publish_mode != PublishMode::PerField {
+            let payload = msg.payload(
+                self.config.payload_format(device),
+                self.config.payload_template(device),
+            )?;
+            let shadow_topic = aws_iot::shadow_topic(&self.config, device);
+            let event_topic = azure_iot::event_topic(&self.config, device, &[], true);
+            match (shadow_topic, event_topic) {
+                (Some(shadow_topic), _) => mqtt_msgs.push(unretained_message(
+                    shadow_topic,
+                    aws_iot::reported_state(&payload)?,
+                )),
+                (None, Some(event_topic)) => {
+                    mqtt_msgs.push(unretained_message(event_topic, payload))
+                }
+                (None, None) => mqtt_msgs.push(state_message(topic.clone(), payload)),
+            }
+        }
+        // Scalar value of each field
+        if publish_mode != PublishMode::Json {
+            for (field, value) in msg.fields() {
+                mqtt_msgs.push(state_message(format!("{}/{}", topic, field), value.into()));
+            }
+        }
+
+        // Button presses are published as separate events, recorded once published
+        let button_event = button::pressed(data.mac, data.button_pressed).then(|| {
+            let topic = azure_iot::event_topic(&self.config, device, &[("event", "button")], false)
+                .unwrap_or_else(|| self.config.button_topic(device));
+            PublishPolicy::event(&self.config, device).message(topic, button::PRESS_EVENT)
+        });
+
+        // Battery-low and reboot events, detected from consecutive readings, if enabled
+        let uptime = device.device_type.provides("uptime").then_some(data.uptime);
+        let device_events = self.config.device_events.then(|| {
+            device_events::detect(
+                self.config.battery_low,
+                data.mac,
+                data.battery_level,
+                uptime,
+            )
+        });
+        let device_event_msgs = device_events
+            .as_ref()
+            .map(|changes| self.device_event_messages(data, device, changes))
+            .unwrap_or_default();
+
+        // Devices might publish to another broker than the default one
+        let broker = device.broker.as_deref();
+        let (client, mut result) = match broker {
+            Some(name) => match brokers::client(name).await {
+                Ok(client) => (Some(client), Ok(())),
+                Err(e) => (None, Err(e)),
+            },
+            None => (Some(self.client.clone()), Ok(())),
+        };
+
+        for mqtt_msg in mqtt_msgs.iter() {
+            if let (Some(client), Ok(())) = (&client, &result) {
+                result =
+                    publish_spooled(client, self.config.spool.as_ref(), broker, mqtt_msg.clone())
+                        .await;
+            }
+            // Keep the messages until the broker is reachable again
+            if let (Err(_), Some(spool_config)) = (&result, &self.config.spool) {
+                spool::store(spool_config, broker, mqtt_msg)?;
+            }
+        }
+
+        match (result, client) {
+            (Err(e), _) if self.config.spool.is_some() => {
+                Err(format!("Messages to {} spooled: {}", topic, e).into())
+            }
+            (Ok(()), Some(client)) => {
+                if let Some(event) = button_event {
+                    info!("Button of {} pressed", device.name);
+                    publish_with_retry(&client, event).await?;
+                }
+                for msg in device_event_msgs {
+                    publish_with_retry(&client, msg).await?;
+                }
+                // Only published changes are recorded, others are detected again with the next reading
+                button::commit(data.mac, data.button_pressed);
+                if let Some(changes) = &device_events {
+                    device_events::commit(data.mac, changes, uptime);
+                }
+                Ok(())
+            }
+            (result, _) => result,
+        }
+    }
+
+    async fn publish_availability(
+        &self,
+        device: &AppDevice,
+        online: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = match device.broker.as_deref() {
+            Some(name) => brokers::client(name).await?,
+            None => self.client.clone(),
+        };
+        let payload = if online {
+            device_availability::ONLINE
+        } else {
+            device_availability::OFFLINE
+        };
+        let msg =
+            match azure_iot::event_topic(&self.config, device, &[("availability", payload)], false)
+            {
+                Some(topic) => PublishPolicy {
+                    retained: false,
+                    ..PublishPolicy::AVAILABILITY
+                }
+                .message(topic, payload),
+                None => PublishPolicy::AVAILABILITY
+                    .message(self.config.device_availability_topic(device), payload),
+            };
+        publish_with_retry(&client, msg).await
+    }
+
+    async fn publish_alert(
+        &self,
+        device: &AppDevice,
+        alert: &Alert,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = match device.broker.as_deref() {
+            Some(name) => brokers::client(name).await?,
+            None => self.client.clone(),
+        };
+        let payload = serde_json::to_vec(alert)?;
+        let msg = match azure_iot::event_topic(
+            &self.config,
+            device,
+            &[("alert", alert.rule.as_str())],
+            false,
+        ) {
+            Some(topic) => PublishPolicy {
+                retained: false,
+                ..PublishPolicy::ALERT
+            }
+            .message(topic, payload),
+            None => {
+                PublishPolicy::ALERT.message(self.config.alert_topic(device, &alert.rule), payload)
+            }
+        };
+        publish_with_retry(&client, msg).await
+    }
+
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = match device.broker.as_deref() {
+            Some(name) => brokers::client(name).await?,
+            None => self.client.clone(),
+        };
+        // Not published to the state topic, as consumers would take the old values for current ones
+        let msg = Message::at(&self.config, device, data.clone(), measured_at);
+        let payload = msg.payload(
+            self.config.payload_format(device),
+            self.config.payload_template(device),
+        )?;
+        let topic = azure_iot::event_topic(&self.config, device, &[("history", "true")], true)
+            .unwrap_or_else(|| self.config.history_topic(device));
+        publish_with_retry(
+            &client,
+            PublishPolicy::event(&self.config, device).message(topic, payload),
+        )
+        .await
+    }
+}
+
+/// Prints the readings as JSON to the console
+pub struct StdoutSink {
+    config: AppConfig,
+}
+
+impl StdoutSink {
+    pub fn new(config: &AppConfig) -> Self {
+        StdoutSink {
+            config: config.clone(),
+        }
+    }
+
+    /// Prints the payload of the message: text line by line, binary formats as they are (e.g. to pipe them into a decoder)
+    fn print(&self, msg: &Message, device: &AppDevice) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let template = self.config.payload_template(device);
+        let format = self.config.payload_format(device);
+        let mut payload = msg.payload(format, template)?;
+        if template.is_some() || !format.is_binary() {
+            payload.push(b'\n');
+        }
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&payload)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::new(&self.config, device, data.clone());
+        self.print(&msg, device)
+    }
+
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::at(&self.config, device, data.clone(), measured_at);
+        self.print(&msg, device)
+    }
+}
+
+/// Creates the configured sinks. Without any configured output, readings are published to MQTT if a client is available, else printed to the console.
+pub fn from_config(config: &AppConfig, client: &Option<AsyncClient>) -> Vec<Box<dyn Sink>> {
+    if config.outputs.is_empty() {
+        return match client {
+            Some(c) => vec![Box::new(MqttSink::new(c.clone(), config))],
+            None => {
+                warn!("No valid mqtt configuration found. Results are just printed to the console");
+                vec![Box::new(StdoutSink::new(config))]
+            }
+        };
+    }
+
+    let mut sinks: Vec<Box<dyn Sink>> = vec![];
+    for output in config.outputs.iter() {
+        match output {
+            OutputConfig::Mqtt => match client {
+                Some(c) => sinks.push(Box::new(MqttSink::new(c.clone(), config))),
+                None => error!("MQTT output configured, but no MQTT client available"),
+            },
+            OutputConfig::Stdout => sinks.push(Box::new(StdoutSink::new(config))),
+            OutputConfig::Sparkplug(sparkplug_config) => match &config.mqtt {
+                Some(mqtt_config) => sinks.push(Box::new(SparkplugSink::new(
+                    config,
+                    mqtt_config,
+                    sparkplug_config,
+                ))),
+                None => error!("Sparkplug output configured, but no MQTT broker configured"),
+            },
+            #[cfg(feature = "kafka")]
+            OutputConfig::Kafka(kafka_config) => {
+                match crate::kafka::KafkaSink::new(config, kafka_config) {
+                    Ok(sink) => sinks.push(Box::new(sink)),
+                    Err(e) => error!("Failed to create Kafka output: {}", e),
+                }
+            }
+            #[cfg(not(feature = "kafka"))]
+            OutputConfig::Kafka(_) => {
+                error!("Kafka output configured, but built without the Cargo feature 'kafka'")
+            }
+            #[cfg(feature = "nats")]
+            OutputConfig::Nats(nats_config) => {
+                sinks.push(Box::new(crate::nats::NatsSink::new(config, nats_config)))
+            }
+            #[cfg(not(feature = "nats"))]
+            OutputConfig::Nats(_) => {
+                error!("NATS output configured, but built without the Cargo feature 'nats'")
+            }
+            OutputConfig::File(file_config) => {
+                sinks.push(Box::new(FileSink::new(config, file_config)))
+            }
+            OutputConfig::Webhook(webhook_config) => {
+                match WebhookSink::new(config, webhook_config) {
+                    Ok(sink) => sinks.push(Box::new(sink)),
+                    Err(e) => error!(
+                        "Failed to create webhook output {}: {}",
+                        webhook_config.url, e
+                    ),
+                }
+            }
+        }
+    }
+    sinks
+}
+
+/// Delivers a reading to all sinks in parallel. All sinks are tried, the first error is returned.
+pub async fn publish_all(
+    sinks: &[Box<dyn Sink>],
+    data: &ThermoBeaconFullReadResult,
+    device: &AppDevice,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let results =
+        futures::future::join_all(sinks.iter().map(|sink| sink.publish(data, device))).await;
+
+    let mut first_error = None;
+    for (sink, result) in sinks.iter().zip(results) {
+        if let Err(e) = result {
+            error!(
+                "Failed to deliver data of {} to {}: {}",
+                device.name,
+                sink.name(),
+                e
+            );
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Completes the run of all sinks in parallel. All sinks are tried, the first error is returned.
+pub async fn finish_run_all(sinks: &[Box<dyn Sink>]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let results = futures::future::join_all(sinks.iter().map(|sink| sink.finish_run())).await;
+
+    let mut first_error = None;
+    for (sink, result) in sinks.iter().zip(results) {
+        if let Err(e) = result {
+            error!("Failed to complete the run of {}: {}", sink.name(), e);
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Units;
+
+    #[test]
+    fn converts_temperatures_to_device_units() {
+        let config = AppConfig::default();
+        let device = AppDevice {
+            name: "Porch".to_string(),
+            units: Some(Units::Imperial),
+            ..Default::default()
+        };
+        let data = ThermoBeaconFullReadResult {
+            temperature: 21.5,
+            max_temperature: Some(-40.0),
+            min_temperature: Some(0.0),
+            humidity: 45.0,
+            ..Default::default()
+        };
+
+        let message = Message::new(&config, &device, data.clone());
+        assert_eq!(message.data.temperature, 70.7);
+        assert_eq!(message.data.max_temperature, Some(-40.0));
+        assert_eq!(message.data.min_temperature, Some(32.0));
+        assert_eq!(message.data.humidity, 45.0);
+
+        let message = Message::new(&config, &AppDevice::default(), data);
+        assert_eq!(message.data.temperature, 21.5);
+    }
+
+    #[test]
+    fn resolves_times_since_reset() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            since_reset(now, 7200, 3600),
+            Utc.with_ymd_and_hms(2024, 5, 1, 11, 0, 0).unwrap()
+        );
+        assert_eq!(since_reset(now, 7200, 7200), now);
+    }
+
+    #[test]
+    fn renders_payload_template() {
+        let data = ThermoBeaconFullReadResult {
+            temperature: 21.5,
+            humidity: 45.0,
+            ..Default::default()
+        };
+        let device = AppDevice {
+            name: "Basement".to_string(),
+            ..Default::default()
+        };
+        let message = Message::new(&AppConfig::default(), &device, data);
+        let template = r#"{"room": {{ name | tojson }}, "temp": {{ data.temperature }}, "source": "thermobeacon"}"#;
+        assert!(message
+            .payload(PayloadFormat::Json, Some(template))
+            .is_err());
+        compile_templates([template]).unwrap();
+        // Templates are rendered as text regardless of the payload format
+        assert_eq!(
+            message
+                .payload(PayloadFormat::Cbor, Some(template))
+                .unwrap(),
+            br#"{"room": "Basement", "temp": 21.5, "source": "thermobeacon"}"#
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_payload_template() {
+        assert!(compile_templates(["{{ name "]).is_err());
+    }
+
+    #[test]
+    fn lists_present_fields() {
+        let data = ThermoBeaconFullReadResult {
+            temperature: 21.5,
+            humidity: 45.0,
+            battery_level: 100.0,
+            rssi: Some(-70),
+            ..Default::default()
+        };
+        let message = Message::new(&AppConfig::default(), &AppDevice::default(), data);
+        assert_eq!(
+            message.fields(),
+            vec![
+                ("temperature", "21.5".to_string()),
+                ("humidity", "45".to_string()),
+                ("battery", "100".to_string()),
+                ("uptime", "0".to_string()),
+                ("button_pressed", "false".to_string()),
+                ("rssi", "-70".to_string()),
+            ]
+        );
+    }
+}
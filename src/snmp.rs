@@ -0,0 +1,484 @@
+use std::error::Error;
+
+use btleplug::api::BDAddr;
+use chrono::Utc;
+use tokio::net::UdpSocket;
+
+use crate::{
+    configuration::{AppDevice, SnmpConfig},
+    readings, remote_devices,
+};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+const PDU_GET: u8 = 0xA0;
+const PDU_GET_NEXT: u8 = 0xA1;
+const PDU_RESPONSE: u8 = 0xA2;
+const PDU_GET_BULK: u8 = 0xA5;
+
+const VERSION_1: i64 = 0;
+const VERSION_2C: i64 = 1;
+
+/// Error status 'noSuchName' of SNMPv1
+const ERROR_NO_SUCH_NAME: i64 = 2;
+
+/// Upper limit of variable bindings in a GETBULK response, to keep it within a single datagram
+const MAX_BULK_VARBINDS: usize = 100;
+
+type Oid = Vec<u32>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Gauge32(u32),
+    Null,
+    NoSuchObject,
+    EndOfMibView,
+}
+
+/// A decoded GET, GETNEXT or GETBULK request
+#[derive(Debug, PartialEq)]
+struct Request {
+    version: i64,
+    community: Vec<u8>,
+    pdu_type: u8,
+    request_id: i64,
+    /// Error status field, used as 'non-repeaters' by GETBULK
+    non_repeaters: i64,
+    /// Error index field, used as 'max-repetitions' by GETBULK
+    max_repetitions: i64,
+    oids: Vec<Oid>,
+}
+
+/// Reader of BER encoded TLVs
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Reads the next TLV and returns its tag and content
+    fn read_tlv(&mut self) -> Result<(u8, &'a [u8]), String> {
+        if self.data.len() < 2 {
+            return Err("Truncated TLV".to_string());
+        }
+        let tag = self.data[0];
+        let (len, header) = match self.data[1] {
+            len if len < 0x80 => (len as usize, 2),
+            len => {
+                let bytes = (len & 0x7f) as usize;
+                if bytes == 0 || bytes > 4 || self.data.len() < 2 + bytes {
+                    return Err("Unsupported length encoding".to_string());
+                }
+                let len = self.data[2..2 + bytes]
+                    .iter()
+                    .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+                (len, 2 + bytes)
+            }
+        };
+        let end = header
+            .checked_add(len)
+            .ok_or_else(|| "Invalid TLV length".to_string())?;
+        if self.data.len() < end {
+            return Err("Truncated TLV".to_string());
+        }
+        let content = &self.data[header..end];
+        self.data = &self.data[end..];
+        Ok((tag, content))
+    }
+
+    /// Reads the next TLV, which must have the given tag
+    fn read(&mut self, expected: u8) -> Result<&'a [u8], String> {
+        let (tag, content) = self.read_tlv()?;
+        if tag != expected {
+            return Err(format!(
+                "Expected tag {:#04x}, found {:#04x}",
+                expected, tag
+            ));
+        }
+        Ok(content)
+    }
+
+    fn read_integer(&mut self) -> Result<i64, String> {
+        let content = self.read(TAG_INTEGER)?;
+        if content.is_empty() || content.len() > 8 {
+            return Err("Invalid integer".to_string());
+        }
+        // Sign extension of the first byte
+        let initial = if content[0] & 0x80 != 0 { -1i64 } else { 0 };
+        Ok(content
+            .iter()
+            .fold(initial, |acc, b| (acc << 8) | *b as i64))
+    }
+
+    fn read_oid(&mut self) -> Result<Oid, String> {
+        let content = self.read(TAG_OID)?;
+        if content.is_empty() {
+            return Err("Empty OID".to_string());
+        }
+        let mut oid = vec![(content[0] / 40) as u32, (content[0] % 40) as u32];
+        let mut sub_id = 0u32;
+        for b in &content[1..] {
+            sub_id = sub_id
+                .checked_mul(128)
+                .ok_or("OID sub-identifier too large")?
+                | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                oid.push(sub_id);
+                sub_id = 0;
+            }
+        }
+        Ok(oid)
+    }
+}
+
+fn encode_tlv(tag: u8, content: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+}
+
+/// Minimal two's complement encoding of an integer
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut out = vec![(oid[0] * 40 + oid.get(1).copied().unwrap_or(0)) as u8];
+    for sub_id in oid.iter().skip(2) {
+        let mut groups = vec![(sub_id & 0x7f) as u8];
+        let mut rest = sub_id >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(groups.iter().rev());
+    }
+    out
+}
+
+fn encode_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Integer(i) => encode_tlv(TAG_INTEGER, &encode_integer(*i), out),
+        Value::OctetString(s) => encode_tlv(TAG_OCTET_STRING, s, out),
+        Value::Gauge32(g) => encode_tlv(TAG_GAUGE32, &encode_integer(*g as i64), out),
+        Value::Null => encode_tlv(TAG_NULL, &[], out),
+        Value::NoSuchObject => encode_tlv(TAG_NO_SUCH_OBJECT, &[], out),
+        Value::EndOfMibView => encode_tlv(TAG_END_OF_MIB_VIEW, &[], out),
+    }
+}
+
+fn parse_request(data: &[u8]) -> Result<Request, String> {
+    let mut message = Reader::new(Reader::new(data).read(TAG_SEQUENCE)?);
+    let version = message.read_integer()?;
+    let community = message.read(TAG_OCTET_STRING)?.to_vec();
+    let (pdu_type, pdu) = message.read_tlv()?;
+
+    let mut pdu = Reader::new(pdu);
+    let request_id = pdu.read_integer()?;
+    let non_repeaters = pdu.read_integer()?;
+    let max_repetitions = pdu.read_integer()?;
+    let mut varbinds = Reader::new(pdu.read(TAG_SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !varbinds.is_empty() {
+        // The values of a request are ignored
+        oids.push(Reader::new(varbinds.read(TAG_SEQUENCE)?).read_oid()?);
+    }
+
+    Ok(Request {
+        version,
+        community,
+        pdu_type,
+        request_id,
+        non_repeaters,
+        max_repetitions,
+        oids,
+    })
+}
+
+fn encode_response(
+    request: &Request,
+    error_status: i64,
+    error_index: i64,
+    varbinds: &[(Oid, Value)],
+) -> Vec<u8> {
+    let mut varbind_list = Vec::new();
+    for (oid, value) in varbinds {
+        let mut varbind = Vec::new();
+        encode_tlv(TAG_OID, &encode_oid(oid), &mut varbind);
+        encode_value(value, &mut varbind);
+        encode_tlv(TAG_SEQUENCE, &varbind, &mut varbind_list);
+    }
+
+    let mut pdu = Vec::new();
+    encode_tlv(TAG_INTEGER, &encode_integer(request.request_id), &mut pdu);
+    encode_tlv(TAG_INTEGER, &encode_integer(error_status), &mut pdu);
+    encode_tlv(TAG_INTEGER, &encode_integer(error_index), &mut pdu);
+    encode_tlv(TAG_SEQUENCE, &varbind_list, &mut pdu);
+
+    let mut message = Vec::new();
+    encode_tlv(TAG_INTEGER, &encode_integer(request.version), &mut message);
+    encode_tlv(TAG_OCTET_STRING, &request.community, &mut message);
+    encode_tlv(PDU_RESPONSE, &pdu, &mut message);
+
+    let mut out = Vec::new();
+    encode_tlv(TAG_SEQUENCE, &message, &mut out);
+    out
+}
+
+/// Parses a dotted OID (e.g. '1.3.6.1.4.1')
+fn parse_oid(oid: &str) -> Result<Oid, String> {
+    let oid = oid
+        .trim_start_matches('.')
+        .split('.')
+        .map(|s| s.parse::<u32>())
+        .collect::<Result<Oid, _>>()
+        .map_err(|e| format!("Invalid OID {}: {}", oid, e))?;
+    if oid.len() < 2 || oid[0] > 2 || oid[1] >= 40 {
+        return Err(format!("Invalid OID {:?}", oid));
+    }
+    Ok(oid)
+}
+
+/// Builds the (sorted) MIB view of the latest readings:
+/// - `base.1.0`: number of configured devices
+/// - `base.2.1.column.index`: device table, indexed by the position of the device in the configuration (starting with 1).
+///   Columns: 2 = name, 3 = MAC, 4 = temperature (0.01 °C), 5 = humidity (0.01 %), 6 = battery level (%), 7 = age of the reading (s)
+fn mib(base: &[u32], devices: &[AppDevice]) -> Vec<(Oid, Value)> {
+    let oid = |suffix: &[u32]| -> Oid { base.iter().chain(suffix.iter()).copied().collect() };
+
+    let mut mib = vec![(oid(&[1, 0]), Value::Integer(devices.len() as i64))];
+
+    let now = Utc::now();
+    let rows: Vec<_> = devices
+        .iter()
+        .enumerate()
+        .filter_map(|(i, device)| {
+            let mac = device.mac.parse::<BDAddr>().ok()?;
+            readings::get(mac).map(|reading| (i as u32 + 1, device, reading))
+        })
+        .collect();
+    for (index, device, reading) in rows.iter() {
+        let data = &reading.data;
+        let age = (now - reading.timestamp).num_seconds().max(0) as u32;
+        let columns = [
+            (2, Value::OctetString(device.name.as_bytes().to_vec())),
+            (3, Value::OctetString(data.mac.to_string().into_bytes())),
+            (4, Value::Integer((data.temperature * 100.0).round() as i64)),
+            (5, Value::Integer((data.humidity * 100.0).round() as i64)),
+            (6, Value::Integer(data.battery_level.round() as i64)),
+            (7, Value::Gauge32(age)),
+        ];
+        for (column, value) in columns {
+            mib.push((oid(&[2, 1, column, *index]), value));
+        }
+    }
+    mib.sort_by(|a, b| a.0.cmp(&b.0));
+    mib
+}
+
+/// Returns the first object after the given OID
+fn next(mib: &[(Oid, Value)], oid: &Oid) -> (Oid, Value) {
+    mib.iter()
+        .find(|(o, _)| o > oid)
+        .cloned()
+        .unwrap_or_else(|| (oid.clone(), Value::EndOfMibView))
+}
+
+/// Handles a single request datagram and returns the response
+fn handle(
+    config: &SnmpConfig,
+    base: &[u32],
+    devices: &[AppDevice],
+    data: &[u8],
+) -> Result<Vec<u8>, String> {
+    let request = parse_request(data)?;
+    if request.version != VERSION_1 && request.version != VERSION_2C {
+        return Err(format!("Unsupported SNMP version {}", request.version));
+    }
+    // Requests with a wrong community are silently dropped
+    if request.community != config.community.as_bytes() {
+        return Err("Wrong community".to_string());
+    }
+
+    // The device list might have been updated on the broker
+    let devices = remote_devices::current().unwrap_or_else(|| devices.to_vec());
+    let mib = mib(base, &devices);
+    let varbinds: Vec<(Oid, Value)> = match request.pdu_type {
+        PDU_GET => request
+            .oids
+            .iter()
+            .map(|oid| {
+                mib.iter()
+                    .find(|(o, _)| o == oid)
+                    .cloned()
+                    .unwrap_or_else(|| (oid.clone(), Value::NoSuchObject))
+            })
+            .collect(),
+        PDU_GET_NEXT => request.oids.iter().map(|oid| next(&mib, oid)).collect(),
+        PDU_GET_BULK if request.version == VERSION_2C => {
+            let non_repeaters = (request.non_repeaters.max(0) as usize).min(request.oids.len());
+            let mut varbinds: Vec<(Oid, Value)> = request.oids[..non_repeaters]
+                .iter()
+                .map(|oid| next(&mib, oid))
+                .collect();
+            let mut repeaters: Vec<Oid> = request.oids[non_repeaters..].to_vec();
+            for _ in 0..request.max_repetitions.max(0) {
+                if repeaters.is_empty() || varbinds.len() + repeaters.len() > MAX_BULK_VARBINDS {
+                    break;
+                }
+                for oid in repeaters.iter_mut() {
+                    let (next_oid, value) = next(&mib, oid);
+                    *oid = next_oid.clone();
+                    varbinds.push((next_oid, value));
+                }
+            }
+            varbinds
+        }
+        other => return Err(format!("Unsupported PDU type {:#04x}", other)),
+    };
+
+    // SNMPv1 has no exception values, missing objects are reported by the error status
+    if request.version == VERSION_1 {
+        if let Some(index) = varbinds
+            .iter()
+            .position(|(_, v)| matches!(v, Value::NoSuchObject | Value::EndOfMibView))
+        {
+            let echo: Vec<_> = request
+                .oids
+                .iter()
+                .map(|oid| (oid.clone(), Value::Null))
+                .collect();
+            return Ok(encode_response(
+                &request,
+                ERROR_NO_SUCH_NAME,
+                index as i64 + 1,
+                &echo,
+            ));
+        }
+    }
+    Ok(encode_response(&request, 0, 0, &varbinds))
+}
+
+/// Starts the SNMP agent (SNMPv1 and SNMPv2c, read-only) exposing the latest readings of the configured devices
+pub async fn start_agent(
+    config: SnmpConfig,
+    devices: Vec<AppDevice>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let base = parse_oid(&config.base_oid)?;
+    let socket = UdpSocket::bind((config.ip.as_str(), config.port)).await?;
+    info!(
+        "Started SNMP agent at {}:{} (base OID {})",
+        config.ip, config.port, config.base_oid
+    );
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 65535];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("Failed to receive SNMP request: {}", e);
+                    continue;
+                }
+            };
+            match handle(&config, &base, &devices, &buf[..len]) {
+                Ok(response) => {
+                    if let Err(e) = socket.send_to(&response, peer).await {
+                        warn!("Failed to send SNMP response to {}: {}", peer, e);
+                    }
+                }
+                Err(e) => debug!("Ignored SNMP request from {}: {}", peer, e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// snmpget -v2c -c public <host> 1.3.6.1.2.1.1.1.0
+    const GET_SYS_DESCR: [u8; 43] = [
+        0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0xa0, 0x1c,
+        0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+        0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+    ];
+
+    #[test]
+    fn parses_get_request() {
+        let request = parse_request(&GET_SYS_DESCR).unwrap();
+        assert_eq!(request.version, VERSION_2C);
+        assert_eq!(request.community, b"public");
+        assert_eq!(request.pdu_type, PDU_GET);
+        assert_eq!(request.request_id, 0x12345678);
+        assert_eq!(request.oids, vec![vec![1, 3, 6, 1, 2, 1, 1, 1, 0]]);
+    }
+
+    #[test]
+    fn rejects_truncated_requests() {
+        // Length of 4 GiB without content
+        let oversized = [0x30, 0x84, 0xff, 0xff, 0xff, 0xff];
+        assert!(Reader::new(&oversized).read_tlv().is_err());
+        assert!(parse_request(&GET_SYS_DESCR[..20]).is_err());
+    }
+
+    #[test]
+    fn encodes_integers_and_oids() {
+        assert_eq!(encode_integer(0), vec![0x00]);
+        assert_eq!(encode_integer(128), vec![0x00, 0x80]);
+        assert_eq!(encode_integer(-129), vec![0xff, 0x7f]);
+        let oid = parse_oid("1.3.6.1.4.1.8072.9999.9999.1").unwrap();
+        let mut encoded = Vec::new();
+        encode_tlv(TAG_OID, &encode_oid(&oid), &mut encoded);
+        assert_eq!(Reader::new(&encoded).read_oid().unwrap(), oid);
+    }
+
+    #[test]
+    fn response_echoes_request() {
+        let request = parse_request(&GET_SYS_DESCR).unwrap();
+        let response = encode_response(
+            &request,
+            0,
+            0,
+            &[(request.oids[0].clone(), Value::NoSuchObject)],
+        );
+        let mut message = Reader::new(Reader::new(&response).read(TAG_SEQUENCE).unwrap());
+        assert_eq!(message.read_integer().unwrap(), VERSION_2C);
+        assert_eq!(message.read(TAG_OCTET_STRING).unwrap(), b"public");
+        let mut pdu = Reader::new(message.read(PDU_RESPONSE).unwrap());
+        assert_eq!(pdu.read_integer().unwrap(), 0x12345678);
+    }
+}
@@ -0,0 +1,576 @@
+//! Sparkplug B output (`spBv1.0` namespace) for Ignition and other SCADA systems, which only accept Sparkplug topics and payloads.
+//!
+//! The gateway is the edge node, the beacons are its devices. The edge node uses a connection of its own, with NDEATH as last
+//! will and NBIRTH published on each connect. Each device is born (DBIRTH with all metrics of its reading) before its first
+//! DDATA, and born again if a reading has metrics its birth did not define. Hosts request a rebirth of the node and all its
+//! devices with the NCMD metric 'Node Control/Rebirth', e.g. after they missed messages during a reconnect.
+
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use prost::Message as _;
+use tokio::sync::{mpsc::UnboundedReceiver, Mutex, OnceCell};
+
+use crate::mqtt::{self, AsyncClient, MqttClient, Presence};
+
+use crate::{
+    configuration::{AppConfig, AppDevice, MqttConfig, SparkplugConfig},
+    mqtt_router::MessageRouter,
+    publish_policy::PublishPolicy,
+    sink::{self, Sink},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Namespace of the Sparkplug B topics
+const NAMESPACE: &str = "spBv1.0";
+
+/// Metric of NBIRTH and NDEATH, which identifies the connection of the edge node
+const BD_SEQ_METRIC: &str = "bdSeq";
+
+/// Metric of NCMD requesting a rebirth of the edge node and all its devices
+const REBIRTH_METRIC: &str = "Node Control/Rebirth";
+
+/// Sparkplug B payload, the subset of `sparkplug_b.proto` used by the gateway
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Payload {
+        #[prost(uint64, optional, tag = "1")]
+        pub timestamp: Option<u64>,
+        #[prost(message, repeated, tag = "2")]
+        pub metrics: Vec<Metric>,
+        #[prost(uint64, optional, tag = "3")]
+        pub seq: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Metric {
+        #[prost(string, optional, tag = "1")]
+        pub name: Option<String>,
+        #[prost(uint64, optional, tag = "3")]
+        pub timestamp: Option<u64>,
+        #[prost(uint32, optional, tag = "4")]
+        pub datatype: Option<u32>,
+        #[prost(bool, optional, tag = "5")]
+        pub is_historical: Option<bool>,
+        #[prost(oneof = "Value", tags = "10, 11, 12, 14")]
+        pub value: Option<Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(uint32, tag = "10")]
+        IntValue(u32),
+        #[prost(uint64, tag = "11")]
+        LongValue(u64),
+        #[prost(float, tag = "12")]
+        FloatValue(f32),
+        #[prost(bool, tag = "14")]
+        BooleanValue(bool),
+    }
+}
+
+/// Sparkplug B data types of the metrics
+mod data_type {
+    pub const INT16: u32 = 2;
+    pub const UINT8: u32 = 5;
+    pub const UINT32: u32 = 7;
+    pub const UINT64: u32 = 8;
+    pub const FLOAT: u32 = 9;
+    pub const BOOLEAN: u32 = 11;
+}
+
+impl proto::Metric {
+    fn new(name: &str, datatype: u32, value: proto::Value) -> Self {
+        proto::Metric {
+            name: Some(name.to_string()),
+            timestamp: None,
+            datatype: Some(datatype),
+            is_historical: None,
+            value: Some(value),
+        }
+    }
+
+    fn float(name: &str, value: f32) -> Self {
+        Self::new(name, data_type::FLOAT, proto::Value::FloatValue(value))
+    }
+
+    /// Signed integers are sent as their two's complement in the unsigned int value
+    fn int16(name: &str, value: i16) -> Self {
+        Self::new(
+            name,
+            data_type::INT16,
+            proto::Value::IntValue(i32::from(value) as u32),
+        )
+    }
+
+    fn uint8(name: &str, value: u8) -> Self {
+        Self::new(name, data_type::UINT8, proto::Value::IntValue(value.into()))
+    }
+
+    fn uint32(name: &str, value: u32) -> Self {
+        Self::new(name, data_type::UINT32, proto::Value::IntValue(value))
+    }
+
+    fn uint64(name: &str, value: u64) -> Self {
+        Self::new(name, data_type::UINT64, proto::Value::LongValue(value))
+    }
+
+    fn boolean(name: &str, value: bool) -> Self {
+        Self::new(name, data_type::BOOLEAN, proto::Value::BooleanValue(value))
+    }
+}
+
+/// Metrics of a reading, named like the per-field topics of the MQTT output
+fn metrics(msg: &sink::Message) -> Vec<proto::Metric> {
+    use proto::Metric;
+    let data = &msg.data;
+    let mut metrics = vec![
+        Metric::float("temperature", data.temperature),
+        Metric::float("humidity", data.humidity),
+        Metric::float("battery", data.battery_level),
+        Metric::uint32("uptime", data.uptime),
+        Metric::boolean("button_pressed", data.button_pressed),
+    ];
+    let optional = [
+        data.max_temperature
+            .map(|v| Metric::float("max_temperature", v)),
+        data.min_temperature
+            .map(|v| Metric::float("min_temperature", v)),
+        data.max_temp_time
+            .map(|v| Metric::uint32("max_temp_time", v)),
+        data.min_temp_time
+            .map(|v| Metric::uint32("min_temp_time", v)),
+        data.rssi.map(|v| Metric::int16("rssi", v)),
+        data.tx_power.map(|v| Metric::int16("tx_power", v)),
+        data.external_temperature
+            .map(|v| Metric::float("external_temperature", v)),
+        data.pressure.map(|v| Metric::float("pressure", v)),
+        data.acceleration_x
+            .map(|v| Metric::float("acceleration_x", v)),
+        data.acceleration_y
+            .map(|v| Metric::float("acceleration_y", v)),
+        data.acceleration_z
+            .map(|v| Metric::float("acceleration_z", v)),
+        data.movement_counter
+            .map(|v| Metric::uint8("movement_counter", v)),
+        data.battery_voltage
+            .map(|v| Metric::float("battery_voltage", v)),
+    ];
+    metrics.extend(optional.into_iter().flatten());
+    if let Some(computed) = &msg.computed {
+        metrics.push(Metric::float("dew_point", computed.dew_point));
+        metrics.push(Metric::float(
+            "absolute_humidity",
+            computed.absolute_humidity,
+        ));
+        metrics.push(Metric::float("heat_index", computed.heat_index));
+    }
+    metrics
+}
+
+/// Sparkplug id of a device or node: the characters '/', '+' and '#' are not allowed and replaced by '_'
+fn sparkplug_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if matches!(c, '/' | '+' | '#') { '_' } else { c })
+        .collect()
+}
+
+/// Milliseconds since the epoch, the time format of Sparkplug
+fn millis(time: DateTime<Utc>) -> u64 {
+    u64::try_from(time.timestamp_millis()).unwrap_or_default()
+}
+
+/// Topics of the edge node and its devices
+#[derive(Debug, Clone)]
+struct Topics {
+    group_id: String,
+    edge_node_id: String,
+}
+
+impl Topics {
+    fn new(config: &AppConfig, sparkplug_config: &SparkplugConfig) -> Self {
+        let edge_node_id = sparkplug_config
+            .edge_node_id
+            .clone()
+            .or_else(|| config.instance_name.clone())
+            .unwrap_or_else(|| "gateway".to_string());
+        Topics {
+            group_id: sparkplug_id(&sparkplug_config.group_id),
+            edge_node_id: sparkplug_id(&edge_node_id),
+        }
+    }
+
+    /// 'spBv1.0/{group_id}/{message_type}/{edge_node_id}'
+    fn node(&self, message_type: &str) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            NAMESPACE, self.group_id, message_type, self.edge_node_id
+        )
+    }
+
+    /// 'spBv1.0/{group_id}/{message_type}/{edge_node_id}/{device_id}'
+    fn device(&self, message_type: &str, device_id: &str) -> String {
+        format!("{}/{}", self.node(message_type), device_id)
+    }
+}
+
+/// NBIRTH of the edge node, which starts the sequence numbers at 0
+fn node_birth(bd_seq: u64) -> proto::Payload {
+    proto::Payload {
+        timestamp: Some(millis(Utc::now())),
+        metrics: vec![
+            proto::Metric::uint64(BD_SEQ_METRIC, bd_seq),
+            proto::Metric::boolean(REBIRTH_METRIC, false),
+        ],
+        seq: Some(0),
+    }
+}
+
+/// NDEATH of the edge node, registered as last will. Has no sequence number.
+fn node_death(bd_seq: u64) -> proto::Payload {
+    proto::Payload {
+        timestamp: Some(millis(Utc::now())),
+        metrics: vec![proto::Metric::uint64(BD_SEQ_METRIC, bd_seq)],
+        seq: None,
+    }
+}
+
+/// Does the NCMD payload request a rebirth?
+fn requests_rebirth(payload: &proto::Payload) -> bool {
+    payload.metrics.iter().any(|m| {
+        m.name.as_deref() == Some(REBIRTH_METRIC)
+            && m.value == Some(proto::Value::BooleanValue(true))
+    })
+}
+
+/// State of the edge node
+struct EdgeNode {
+    topics: Topics,
+    /// Birth / death sequence number, shared by NBIRTH and the NDEATH last will. Changes with each start.
+    bd_seq: u64,
+    /// Sequence number of the last message, 0 for NBIRTH
+    seq: u8,
+    /// Metric names of the born devices
+    born: HashMap<String, Vec<String>>,
+    /// Latest reading of each device, born again on rebirth
+    latest: HashMap<String, sink::Message>,
+}
+
+impl EdgeNode {
+    /// Publishes a device message with the next sequence number
+    async fn publish_device(
+        &mut self,
+        client: &AsyncClient,
+        message_type: &str,
+        device_id: &str,
+        timestamp: DateTime<Utc>,
+        metrics: Vec<proto::Metric>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.seq = self.seq.wrapping_add(1);
+        let payload = proto::Payload {
+            timestamp: Some(millis(timestamp)),
+            metrics,
+            seq: Some(self.seq.into()),
+        };
+        let topic = self.topics.device(message_type, device_id);
+        trace!("Publishing Sparkplug {} to {}", message_type, topic);
+        client
+            .publish(PublishPolicy::SPARKPLUG.message(topic, payload.encode_to_vec()))
+            .await
+    }
+
+    /// Publishes the DBIRTH of a device with the metrics of its reading
+    async fn birth(
+        &mut self,
+        client: &AsyncClient,
+        device_id: &str,
+        msg: &sink::Message,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let metrics = metrics(msg);
+        let names = metrics.iter().filter_map(|m| m.name.clone()).collect();
+        self.publish_device(client, "DBIRTH", device_id, Utc::now(), metrics)
+            .await?;
+        self.born.insert(device_id.to_string(), names);
+        Ok(())
+    }
+
+    /// Is the device born with all the given metrics?
+    fn is_born(&self, device_id: &str, metrics: &[proto::Metric]) -> bool {
+        match self.born.get(device_id) {
+            Some(names) => metrics
+                .iter()
+                .all(|m| m.name.as_ref().map(|n| names.contains(n)).unwrap_or(false)),
+            None => false,
+        }
+    }
+
+    /// Publishes NBIRTH and the DBIRTH of each device with a reading
+    async fn rebirth(&mut self, client: &AsyncClient) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let payload = node_birth(self.bd_seq);
+        client
+            .publish(
+                PublishPolicy::SPARKPLUG
+                    .message(self.topics.node("NBIRTH"), payload.encode_to_vec()),
+            )
+            .await?;
+        self.birth_devices(client).await
+    }
+
+    /// Restarts the sequence numbers after an NBIRTH and publishes the DBIRTH of each device with a reading
+    async fn birth_devices(
+        &mut self,
+        client: &AsyncClient,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.seq = 0;
+        self.born.clear();
+        let latest: Vec<(String, sink::Message)> = self.latest.clone().into_iter().collect();
+        for (device_id, msg) in latest {
+            self.birth(client, &device_id, &msg).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Acts on the NCMD messages of the edge node
+async fn watch_commands(
+    client: AsyncClient,
+    node: Arc<Mutex<EdgeNode>>,
+    mut receiver: UnboundedReceiver<mqtt::Message>,
+) {
+    while let Some(msg) = receiver.recv().await {
+        match proto::Payload::decode(msg.payload()) {
+            Ok(payload) if requests_rebirth(&payload) => {
+                info!("Sparkplug rebirth requested");
+                if let Err(e) = node.lock().await.rebirth(&client).await {
+                    warn!("Sparkplug rebirth failed: {}", e);
+                }
+            }
+            Ok(_) => debug!("Ignoring Sparkplug command on {}", msg.topic()),
+            Err(e) => warn!("Invalid Sparkplug command on {}: {}", msg.topic(), e),
+        }
+    }
+}
+
+/// Publishes the readings as Sparkplug B messages over a connection of its own, established on first use
+pub struct SparkplugSink {
+    config: AppConfig,
+    mqtt_config: MqttConfig,
+    client: OnceCell<AsyncClient>,
+    node: Arc<Mutex<EdgeNode>>,
+}
+
+impl SparkplugSink {
+    pub fn new(
+        config: &AppConfig,
+        mqtt_config: &MqttConfig,
+        sparkplug_config: &SparkplugConfig,
+    ) -> Self {
+        let node = EdgeNode {
+            topics: Topics::new(config, sparkplug_config),
+            bd_seq: (Utc::now().timestamp() % 256) as u64,
+            seq: 0,
+            born: HashMap::new(),
+            latest: HashMap::new(),
+        };
+        SparkplugSink {
+            config: config.clone(),
+            // The connection must not take over the session of the main connection
+            mqtt_config: MqttConfig {
+                client_id: mqtt_config
+                    .client_id
+                    .as_ref()
+                    .map(|id| format!("{}-sparkplug", id)),
+                ..mqtt_config.clone()
+            },
+            client: OnceCell::new(),
+            node: Arc::new(Mutex::new(node)),
+        }
+    }
+
+    /// Returns the client of the edge node, connecting it on first use. NBIRTH is published on each connect, followed by the
+    /// DBIRTH of the devices on reconnects.
+    async fn client(&self) -> Result<&AsyncClient, Box<dyn Error + Send + Sync>> {
+        self.client
+            .get_or_try_init(|| async {
+                let (topics, bd_seq) = {
+                    let node = self.node.lock().await;
+                    (node.topics.clone(), node.bd_seq)
+                };
+                let presence = Presence {
+                    online: PublishPolicy::SPARKPLUG
+                        .message(topics.node("NBIRTH"), node_birth(bd_seq).encode_to_vec()),
+                    offline: PublishPolicy::SPARKPLUG_DEATH
+                        .message(topics.node("NDEATH"), node_death(bd_seq).encode_to_vec()),
+                };
+                let client =
+                    match mqtt::create_and_connect(&self.mqtt_config, Some(&presence)).await? {
+                        (client, None) => client,
+                        (_, Some(e)) => return Err(e),
+                    };
+                info!(
+                    "Connected Sparkplug edge node {}/{}",
+                    topics.group_id, topics.edge_node_id
+                );
+
+                let command_topic = topics.node("NCMD");
+                let router = MessageRouter::new(&client);
+                let receiver = router.route(&command_topic);
+                if let Err(e) = client.subscribe(&command_topic, 0).await {
+                    warn!("Failed to subscribe to {}: {}", command_topic, e);
+                }
+                tokio::spawn(watch_commands(client.clone(), self.node.clone(), receiver));
+
+                // The client might call back from a thread of its own
+                let runtime = tokio::runtime::Handle::current();
+                let (reconnected, node) = (client.clone(), self.node.clone());
+                client.set_connected_callback(Box::new(move || {
+                    let (client, node) = (reconnected.clone(), node.clone());
+                    runtime.spawn(async move {
+                        if let Err(e) = node.lock().await.birth_devices(&client).await {
+                            warn!("Sparkplug rebirth after reconnect failed: {}", e);
+                        }
+                    });
+                }));
+                Ok(client)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Sink for SparkplugSink {
+    fn name(&self) -> &'static str {
+        "sparkplug"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.client().await?;
+        let msg = sink::Message::new(&self.config, device, data.clone());
+        let device_id = sparkplug_id(&device.name);
+        let metrics = metrics(&msg);
+
+        let mut node = self.node.lock().await;
+        node.latest.insert(device_id.clone(), msg.clone());
+        if node.is_born(&device_id, &metrics) {
+            node.publish_device(client, "DDATA", &device_id, Utc::now(), metrics)
+                .await
+        } else {
+            // DBIRTH already contains the values of the reading
+            node.birth(client, &device_id, &msg).await
+        }
+    }
+
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let client = self.client().await?;
+        let msg = sink::Message::at(&self.config, device, data.clone(), measured_at);
+        let device_id = sparkplug_id(&device.name);
+        let metrics: Vec<proto::Metric> = metrics(&msg)
+            .into_iter()
+            .map(|m| proto::Metric {
+                timestamp: Some(millis(measured_at)),
+                is_historical: Some(true),
+                ..m
+            })
+            .collect();
+
+        let mut node = self.node.lock().await;
+        if !node.is_born(&device_id, &metrics) {
+            debug!(
+                "Sparkplug device {} is not born, skipping its history",
+                device_id
+            );
+            return Ok(());
+        }
+        node.publish_device(client, "DDATA", &device_id, Utc::now(), metrics)
+            .await
+    }
+
+    async fn publish_availability(
+        &self,
+        device: &AppDevice,
+        online: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Devices coming online are born with their next reading
+        if online {
+            return Ok(());
+        }
+        let client = self.client().await?;
+        let device_id = sparkplug_id(&device.name);
+        let mut node = self.node.lock().await;
+        node.latest.remove(&device_id);
+        if node.born.remove(&device_id).is_some() {
+            node.publish_device(client, "DDEATH", &device_id, Utc::now(), vec![])
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_topics() {
+        let config = AppConfig {
+            instance_name: Some("basement".to_string()),
+            ..Default::default()
+        };
+        let sparkplug_config = SparkplugConfig {
+            group_id: "Plant 1".to_string(),
+            edge_node_id: None,
+        };
+        let topics = Topics::new(&config, &sparkplug_config);
+        assert_eq!(topics.node("NBIRTH"), "spBv1.0/Plant 1/NBIRTH/basement");
+        assert_eq!(
+            topics.device("DDATA", &sparkplug_id("Living room/North")),
+            "spBv1.0/Plant 1/DDATA/basement/Living room_North"
+        );
+    }
+
+    #[test]
+    fn encodes_payloads() {
+        let payload = node_birth(7);
+        let decoded = proto::Payload::decode(payload.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!(decoded.seq, Some(0));
+        assert_eq!(decoded.metrics[0].value, Some(proto::Value::LongValue(7)));
+        assert!(!requests_rebirth(&decoded));
+
+        let rebirth = proto::Payload {
+            timestamp: None,
+            metrics: vec![proto::Metric::boolean(REBIRTH_METRIC, true)],
+            seq: None,
+        };
+        assert!(requests_rebirth(&rebirth));
+        assert_eq!(
+            proto::Metric::int16("rssi", -70).value,
+            Some(proto::Value::IntValue(-70i32 as u32))
+        );
+    }
+
+    #[test]
+    fn defines_metrics_of_present_fields() {
+        let msg = sink::Message {
+            data: ThermoBeaconFullReadResult {
+                rssi: Some(-60),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let names: Vec<String> = metrics(&msg).into_iter().filter_map(|m| m.name).collect();
+        assert!(names.contains(&"temperature".to_string()));
+        assert!(names.contains(&"rssi".to_string()));
+        assert!(!names.contains(&"pressure".to_string()));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::mqtt::{self, AsyncClient, MqttClient};
+use chrono::Utc;
+
+use crate::configuration::SpoolConfig;
+
+/// Distinguishes messages spooled within the same nanosecond
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// A message which could not be published
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize, PartialEq)]
+struct SpooledMessage {
+    topic: String,
+    payload: String,
+    qos: i32,
+    retained: bool,
+    /// Hex encoded payload, if it is binary (not UTF-8). Replaces the payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_hex: Option<String>,
+    /// MQTT 5 user properties
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    user_properties: Vec<(String, String)>,
+    /// MQTT 5 message expiry in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_expiry_secs: Option<u32>,
+}
+
+/// Spool directory of the given broker (None for the default broker)
+fn dir(config: &SpoolConfig, broker: Option<&str>) -> PathBuf {
+    Path::new(&config.path).join(broker.unwrap_or("default"))
+}
+
+/// Returns the spooled message files, oldest first
+fn entries(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+        .collect();
+    // File names start with the time the message was spooled
+    entries.sort();
+    Ok(entries)
+}
+
+/// Drops the oldest messages until the spool fits into the configured size
+fn enforce_max_size(config: &SpoolConfig, dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let entries = entries(dir)?;
+    let sizes: Vec<u64> = entries
+        .iter()
+        .map(|e| fs::metadata(e).map(|m| m.len()).unwrap_or(0))
+        .collect();
+    let mut total: u64 = sizes.iter().sum();
+    for (entry, size) in entries.iter().zip(sizes) {
+        if total <= config.max_size {
+            break;
+        }
+        warn!(
+            "Spool {} exceeds {} bytes, dropping oldest message {}",
+            dir.display(),
+            config.max_size,
+            entry.display()
+        );
+        fs::remove_file(entry)?;
+        total -= size;
+    }
+    Ok(())
+}
+
+/// Persists a message which could not be published to the given broker
+pub fn store(
+    config: &SpoolConfig,
+    broker: Option<&str>,
+    msg: &mqtt::Message,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let dir = dir(config, broker);
+    fs::create_dir_all(&dir)?;
+
+    let spooled = SpooledMessage {
+        topic: msg.topic().to_string(),
+        payload: std::str::from_utf8(msg.payload())
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        payload_hex: std::str::from_utf8(msg.payload())
+            .is_err()
+            .then(|| hex::encode(msg.payload())),
+        qos: msg.qos(),
+        retained: msg.retained(),
+        user_properties: msg.user_properties().to_vec(),
+        message_expiry_secs: msg.message_expiry_secs(),
+    };
+    let file = dir.join(format!(
+        "{:020}-{:010}.json",
+        Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&file, serde_json::to_vec(&spooled)?)?;
+    debug!("Spooled message to {} as {}", spooled.topic, file.display());
+
+    enforce_max_size(config, &dir)
+}
+
+/// Publishes all spooled messages of the given broker in order, removing each one once published. Stops at the first failure. Returns the number of published messages.
+pub async fn replay(
+    config: &SpoolConfig,
+    broker: Option<&str>,
+    client: &AsyncClient,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let entries = entries(&dir(config, broker))?;
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    if !client.is_connected() {
+        return Err(format!("{} messages spooled, broker not connected", entries.len()).into());
+    }
+
+    for entry in entries.iter() {
+        let spooled = serde_json::from_slice::<SpooledMessage>(&fs::read(entry)?)
+            .map_err(|e| e.to_string())
+            .and_then(|spooled| {
+                let payload = match &spooled.payload_hex {
+                    Some(payload) => hex::decode(payload).map_err(|e| e.to_string())?,
+                    None => spooled.payload.clone().into_bytes(),
+                };
+                Ok((spooled, payload))
+            });
+        let (spooled, payload) = match spooled {
+            Ok(spooled) => spooled,
+            Err(e) => {
+                warn!(
+                    "Dropping unreadable spooled message {}: {}",
+                    entry.display(),
+                    e
+                );
+                fs::remove_file(entry)?;
+                continue;
+            }
+        };
+        let msg = if spooled.retained {
+            mqtt::Message::new_retained(spooled.topic, payload, spooled.qos)
+        } else {
+            mqtt::Message::new(spooled.topic, payload, spooled.qos)
+        };
+        // The expiry starts again, as the broker never saw the message
+        let msg = msg
+            .with_user_properties(spooled.user_properties)
+            .with_message_expiry(
+                spooled
+                    .message_expiry_secs
+                    .map(|s| Duration::from_secs(s.into())),
+            );
+        client.publish(msg).await?;
+        fs::remove_file(entry)?;
+    }
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_oldest_messages_above_max_size() {
+        let path = std::env::temp_dir().join(format!("thermobeacon-spool-{}", std::process::id()));
+        let config = SpoolConfig {
+            path: path.to_string_lossy().to_string(),
+            max_size: 250,
+        };
+        for i in 0..5 {
+            let msg = mqtt::Message::new("ThermoBeacon/Basement", format!("{{\"n\":{}}}", i), 1);
+            store(&config, None, &msg).unwrap();
+        }
+
+        let entries = entries(&dir(&config, None)).unwrap();
+        let newest: SpooledMessage =
+            serde_json::from_slice(&fs::read(entries.last().unwrap()).unwrap()).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert!(entries.len() < 5);
+        assert_eq!(newest.payload, "{\"n\":4}");
+    }
+
+    #[test]
+    fn spools_binary_payloads_as_hex() {
+        let path =
+            std::env::temp_dir().join(format!("thermobeacon-spool-binary-{}", std::process::id()));
+        let config = SpoolConfig {
+            path: path.to_string_lossy().to_string(),
+            max_size: 1024,
+        };
+        let msg = mqtt::Message::new("ThermoBeacon/Basement", vec![0xa1, 0xff, 0x00], 1);
+        store(&config, None, &msg).unwrap();
+
+        let entries = entries(&dir(&config, None)).unwrap();
+        let spooled: SpooledMessage =
+            serde_json::from_slice(&fs::read(&entries[0]).unwrap()).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(spooled.payload_hex.as_deref(), Some("a1ff00"));
+    }
+
+    #[test]
+    fn reads_spooled_messages_without_properties() {
+        let spooled: SpooledMessage = serde_json::from_str(
+            r#"{"topic":"ThermoBeacon/Basement","payload":"{}","qos":1,"retained":false}"#,
+        )
+        .unwrap();
+        assert!(spooled.user_properties.is_empty());
+        assert_eq!(spooled.message_expiry_secs, None);
+    }
+}
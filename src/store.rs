@@ -0,0 +1,244 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use btleplug::api::BDAddr;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+
+use crate::{configuration::StoreConfig, thermobeacon_protocol::ThermoBeaconFullReadResult};
+
+/// Open connection to the database and its file
+static CONNECTION: Mutex<Option<(PathBuf, Connection)>> = Mutex::new(None);
+
+/// Name of the database file within the store directory
+static DATABASE_FILE: &str = "readings.sqlite";
+
+/// Schema of the database, applied on each start
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS readings (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    mac TEXT NOT NULL,
+    name TEXT NOT NULL,
+    temperature REAL NOT NULL,
+    humidity REAL NOT NULL,
+    battery_level REAL NOT NULL,
+    uptime INTEGER NOT NULL,
+    button_pressed INTEGER NOT NULL,
+    max_temperature REAL,
+    min_temperature REAL,
+    max_temp_time INTEGER,
+    min_temp_time INTEGER,
+    rssi INTEGER,
+    tx_power INTEGER
+);
+CREATE INDEX IF NOT EXISTS readings_time ON readings (time);
+CREATE INDEX IF NOT EXISTS readings_mac_time ON readings (mac, time);
+";
+
+/// Formats a time as stored in the database. The fixed format (UTC, milliseconds) keeps the text sortable.
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Opens the database in the given directory, creating it if necessary
+fn open(path: &Path) -> Result<Connection, Box<dyn Error + Send + Sync>> {
+    std::fs::create_dir_all(path)?;
+    let connection = Connection::open(path.join(DATABASE_FILE))?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+/// Runs the given function with the (lazily opened) connection to the database
+fn with_connection<T>(
+    config: &StoreConfig,
+    f: impl FnOnce(&Connection) -> rusqlite::Result<T>,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let path = PathBuf::from(&config.path);
+    let mut connection = CONNECTION.lock().unwrap();
+    if connection.as_ref().map(|(p, _)| p != &path).unwrap_or(true) {
+        *connection = Some((path.clone(), open(&path)?));
+    }
+    let (_, connection) = connection.as_ref().unwrap();
+    Ok(f(connection)?)
+}
+
+fn insert_reading(
+    connection: &Connection,
+    time: DateTime<Utc>,
+    name: &str,
+    data: &ThermoBeaconFullReadResult,
+) -> rusqlite::Result<usize> {
+    connection.execute(
+        "INSERT INTO readings (time, mac, name, temperature, humidity, battery_level, uptime, button_pressed, \
+         max_temperature, min_temperature, max_temp_time, min_temp_time, rssi, tx_power) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            format_time(time),
+            data.mac.to_string(),
+            name,
+            data.temperature,
+            data.humidity,
+            data.battery_level,
+            data.uptime,
+            data.button_pressed,
+            data.max_temperature,
+            data.min_temperature,
+            data.max_temp_time,
+            data.min_temp_time,
+            data.rssi,
+            data.tx_power,
+        ],
+    )
+}
+
+fn delete_before(connection: &Connection, time: DateTime<Utc>) -> rusqlite::Result<usize> {
+    connection.execute(
+        "DELETE FROM readings WHERE time < ?1",
+        params![format_time(time)],
+    )
+}
+
+/// A reading from the store
+#[derive(Debug, Clone, serde_derive::Serialize, PartialEq)]
+pub struct StoredReading {
+    /// Time the reading was recorded (RFC 3339, UTC)
+    pub time: String,
+    /// Name of the device
+    pub name: String,
+    pub data: ThermoBeaconFullReadResult,
+}
+
+fn select_readings(
+    connection: &Connection,
+    mac: BDAddr,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> rusqlite::Result<Vec<StoredReading>> {
+    let mut statement = connection.prepare(
+        "SELECT time, name, temperature, humidity, battery_level, uptime, button_pressed, \
+         max_temperature, min_temperature, max_temp_time, min_temp_time, rssi, tx_power \
+         FROM readings WHERE mac = ?1 AND time >= ?2 AND time <= ?3 ORDER BY time",
+    )?;
+    let rows = statement.query_map(
+        params![mac.to_string(), format_time(from), format_time(to)],
+        |row| {
+            Ok(StoredReading {
+                time: row.get(0)?,
+                name: row.get(1)?,
+                data: ThermoBeaconFullReadResult {
+                    temperature: row.get(2)?,
+                    humidity: row.get(3)?,
+                    battery_level: row.get(4)?,
+                    uptime: row.get(5)?,
+                    button_pressed: row.get(6)?,
+                    mac,
+                    max_temperature: row.get(7)?,
+                    min_temperature: row.get(8)?,
+                    max_temp_time: row.get(9)?,
+                    min_temp_time: row.get(10)?,
+                    rssi: row.get(11)?,
+                    tx_power: row.get(12)?,
+                    ..Default::default()
+                },
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+fn select_latest_time(connection: &Connection, mac: BDAddr) -> rusqlite::Result<Option<String>> {
+    connection.query_row(
+        "SELECT MAX(time) FROM readings WHERE mac = ?1",
+        params![mac.to_string()],
+        |row| row.get(0),
+    )
+}
+
+/// Records a reading of the given device
+pub fn insert(
+    config: &StoreConfig,
+    name: &str,
+    data: &ThermoBeaconFullReadResult,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    insert_at(config, Utc::now(), name, data)
+}
+
+/// Records a reading of the given device taken at the given time, e.g. recovered from the log of the device
+pub fn insert_at(
+    config: &StoreConfig,
+    time: DateTime<Utc>,
+    name: &str,
+    data: &ThermoBeaconFullReadResult,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    with_connection(config, |c| insert_reading(c, time, name, data))?;
+    Ok(())
+}
+
+/// Time of the latest reading of the given device, if any
+pub fn latest(
+    config: &StoreConfig,
+    mac: BDAddr,
+) -> Result<Option<DateTime<Utc>>, Box<dyn Error + Send + Sync>> {
+    match with_connection(config, |c| select_latest_time(c, mac))? {
+        Some(time) => Ok(Some(
+            DateTime::parse_from_rfc3339(&time)?.with_timezone(&Utc),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Returns the readings of the given device within the time range (inclusive), oldest first
+pub fn history(
+    config: &StoreConfig,
+    mac: BDAddr,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<StoredReading>, Box<dyn Error + Send + Sync>> {
+    with_connection(config, |c| select_readings(c, mac, from, to))
+}
+
+/// Deletes the readings older than the retention period. Returns the number of deleted readings.
+pub fn prune(config: &StoreConfig) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    if config.retention_days == 0 {
+        return Ok(0);
+    }
+    let cutoff = Utc::now() - Duration::days(i64::from(config.retention_days));
+    let deleted = with_connection(config, |c| delete_before(c, cutoff))?;
+    if deleted > 0 {
+        debug!("Pruned {} readings older than {}", deleted, cutoff);
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_old_readings() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        let now = Utc::now();
+        let data = ThermoBeaconFullReadResult {
+            temperature: 21.5,
+            min_temperature: Some(12.75),
+            ..Default::default()
+        };
+        insert_reading(&connection, now - Duration::days(40), "Basement", &data).unwrap();
+        insert_reading(&connection, now, "Basement", &data).unwrap();
+
+        assert_eq!(
+            delete_before(&connection, now - Duration::days(30)).unwrap(),
+            1
+        );
+        let readings =
+            select_readings(&connection, data.mac, now - Duration::days(50), now).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].name, "Basement");
+        assert_eq!(readings[0].data, data);
+    }
+}
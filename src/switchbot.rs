@@ -0,0 +1,64 @@
+//! Decoder for the advertisements of SwitchBot Meter / Meter Plus hygrometers (service data of the UUID 0xFD3D)
+//!
+//! bytes | content
+//! ========================================================
+//! 00-00 | device type ('T' Meter, 'i' Meter Plus)
+//! 01-01 | group / status flags
+//! 02-02 | battery level (%, lower 7 bits)
+//! 03-03 | temperature decimal (0.1 °C, lower 4 bits)
+//! 04-04 | temperature (°C, lower 7 bits), bit 7 set for positive temperatures
+//! 05-05 | humidity (%, lower 7 bits)
+
+use btleplug::api::{bleuuid::uuid_from_u16, BDAddr};
+use std::error::Error;
+use uuid::Uuid;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// UUID of the service data of the SwitchBot advertisements
+pub const SERVICE_UUID: Uuid = uuid_from_u16(0xFD3D);
+
+/// Decodes the service data of a SwitchBot Meter advertisement
+pub fn parse(
+    mac: BDAddr,
+    data: &[u8],
+) -> Result<ThermoBeaconFullReadResult, Box<dyn Error + Send + Sync>> {
+    if data.len() < 6 {
+        return Err(format!("Unexpected SwitchBot frame length {}", data.len()).into());
+    }
+    let temperature = f32::from(data[4] & 0x7F) + f32::from(data[3] & 0x0F) / 10.0;
+    Ok(ThermoBeaconFullReadResult {
+        temperature: if data[4] & 0x80 != 0 {
+            temperature
+        } else {
+            -temperature
+        },
+        humidity: f32::from(data[5] & 0x7F),
+        battery_level: f32::from(data[2] & 0x7F),
+        mac,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_positive_and_negative_temperatures() {
+        let mac: BDAddr = "d4:e4:a3:00:00:03".parse().unwrap();
+        // Meter: 21.5 °C, 48 %, 95 %
+        let reading = parse(mac, &[0x54, 0x00, 0xDF, 0x05, 0x95, 0x30]).unwrap();
+        assert_eq!(reading.temperature, 21.5);
+        assert_eq!(reading.humidity, 48.0);
+        assert_eq!(reading.battery_level, 95.0);
+
+        // Meter Plus: -3.2 °C
+        let reading = parse(mac, &[0x69, 0x00, 0x64, 0x02, 0x03, 0x50]).unwrap();
+        assert_eq!(reading.temperature, -3.2);
+        assert_eq!(reading.humidity, 80.0);
+        assert_eq!(reading.battery_level, 100.0);
+
+        assert!(parse(mac, &[0x54, 0x00, 0x64]).is_err());
+    }
+}
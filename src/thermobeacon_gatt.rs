@@ -0,0 +1,369 @@
+//! Commands sent to ThermoBeacons over a GATT connection, @see https://github.com/iskalchev/ThermoBeacon-pyhap
+//!
+//! Commands are written to the characteristic 0xFFF5 of the service 0xFFF0, the responses are notified on the characteristic 0xFFF3.
+//! The first byte of commands and responses is the command code.
+//!
+//! Query the number of log entries (0x01)
+//! bytes | content
+//! ========================================================
+//! 00-00 | 0x01
+//! 01-04 | number of log entries
+//!
+//! Dump log entries (0x07), command
+//! bytes | content
+//! ========================================================
+//! 00-00 | 0x07
+//! 01-04 | index of the first entry
+//! 05-05 | number of entries (at most 3)
+//!
+//! Dump log entries (0x07), response
+//! bytes | content
+//! ========================================================
+//! 00-00 | 0x07
+//! 01-04 | index of the first entry
+//! 05-05 | number of entries
+//! 06-   | temperature and humidity of each entry (2 bytes each, as in the advertisements)
+//!
+//! Reset the min/max values (0x02), no response
+//! bytes | content
+//! ========================================================
+//! 00-00 | 0x02
+//!
+//! Set the clock (0x03), no response
+//! bytes | content
+//! ========================================================
+//! 00-00 | 0x03
+//! 01-04 | seconds since 1970-01-01 in the local time of the display
+//!
+//! The firmware and hardware revision are read from the standard Device Information Service (0x180A).
+
+use btleplug::api::{
+    bleuuid::uuid_from_u16, BDAddr, Central, Characteristic, Manager as _, Peripheral as _,
+    ValueNotification, WriteType,
+};
+use btleplug::platform::{Manager, Peripheral};
+use futures::stream::{Stream, StreamExt};
+use std::error::Error;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::thermobeacon_models::ThermoBeaconModel;
+
+/// UUID of the service of the commands
+pub const SERVICE_UUID: Uuid = uuid_from_u16(0xFFF0);
+/// UUID of the characteristic the commands are written to
+pub const COMMAND_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0xFFF5);
+/// UUID of the characteristic notifying the responses
+pub const RESPONSE_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0xFFF3);
+/// UUID of the Device Information Service
+pub const DEVICE_INFORMATION_SERVICE_UUID: Uuid = uuid_from_u16(0x180A);
+/// UUID of the firmware revision string of the Device Information Service
+pub const FIRMWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A26);
+/// UUID of the hardware revision string of the Device Information Service
+pub const HARDWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A27);
+
+const QUERY: u8 = 0x01;
+const RESET_MIN_MAX: u8 = 0x02;
+const SET_TIME: u8 = 0x03;
+const DUMP: u8 = 0x07;
+
+/// Maximum number of log entries of a single dump response
+const ENTRIES_PER_DUMP: u8 = 3;
+
+/// Time to wait for the response to a command
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stream of the notified responses
+type Responses = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// A single entry of the log of a ThermoBeacon
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    /// Index of the entry, the oldest entry has index 0
+    pub index: u32,
+    /// Temperature (°C)
+    pub temperature: f32,
+    /// Humidity (0 - 100%)
+    pub humidity: f32,
+}
+
+/// Revisions of a ThermoBeacon read from its Device Information Service
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
+pub struct DeviceInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardware_revision: Option<String>,
+}
+
+/// Value of a string characteristic, without the trailing zero bytes some devices send. None if empty.
+fn parse_string(value: &[u8]) -> Option<String> {
+    let value = String::from_utf8_lossy(value);
+    let value = value.trim_end_matches('\0').trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Command dumping up to 3 log entries starting at the given index
+fn dump_command(index: u32, count: u8) -> Vec<u8> {
+    let mut command = vec![DUMP];
+    command.extend_from_slice(&index.to_le_bytes());
+    command.push(count);
+    command
+}
+
+/// Command setting the clock to the given local time (seconds since 1970-01-01)
+fn set_time_command(local_time: u32) -> Vec<u8> {
+    let mut command = vec![SET_TIME];
+    command.extend_from_slice(&local_time.to_le_bytes());
+    command
+}
+
+/// Number of log entries of a query response
+fn parse_query_response(data: &[u8]) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    match data {
+        [QUERY, a, b, c, d, ..] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+        _ => Err(format!("Invalid query response {:02x?}", data).into()),
+    }
+}
+
+/// Log entries of a dump response
+fn parse_dump_response(
+    data: &[u8],
+    model: &ThermoBeaconModel,
+) -> Result<Vec<LogEntry>, Box<dyn Error + Send + Sync>> {
+    let (index, count) = match data {
+        [DUMP, a, b, c, d, count, ..] => (u32::from_le_bytes([*a, *b, *c, *d]), *count),
+        _ => return Err(format!("Invalid dump response {:02x?}", data).into()),
+    };
+    let values = &data[6..];
+    if values.len() < usize::from(count) * 4 {
+        return Err(format!("Truncated dump response {:02x?}", data).into());
+    }
+    let value = |i: usize| u16::from_le_bytes([values[i], values[i + 1]]);
+    Ok((0..count)
+        .map(|n| {
+            let offset = usize::from(n) * 4;
+            LogEntry {
+                index: index + u32::from(n),
+                temperature: model.temperature(value(offset)),
+                humidity: model.humidity(value(offset + 2)),
+            }
+        })
+        .collect())
+}
+
+/// Returns the peripheral of the given device from any adapter. The device must have been seen by a scan before.
+pub async fn find_peripheral(
+    manager: &Manager,
+    mac: BDAddr,
+) -> Result<Peripheral, Box<dyn Error + Send + Sync>> {
+    for adapter in manager.adapters().await? {
+        if let Some(peripheral) = adapter
+            .peripherals()
+            .await?
+            .into_iter()
+            .find(|p| p.address() == mac)
+        {
+            return Ok(peripheral);
+        }
+    }
+    Err(format!("ThermoBeacon {} not known to any adapter", mac).into())
+}
+
+/// GATT connection to a ThermoBeacon, disconnect it with `disconnect` after use
+pub struct Connection {
+    peripheral: Peripheral,
+    command: Characteristic,
+    responses: Responses,
+}
+
+impl Connection {
+    /// Connects to the given ThermoBeacon and subscribes to the responses of its commands
+    pub async fn open(peripheral: Peripheral) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        peripheral.connect().await?;
+        match Self::subscribe(&peripheral).await {
+            Ok((command, responses)) => Ok(Connection {
+                peripheral,
+                command,
+                responses,
+            }),
+            Err(e) => {
+                let _ = peripheral.disconnect().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn subscribe(
+        peripheral: &Peripheral,
+    ) -> Result<(Characteristic, Responses), Box<dyn Error + Send + Sync>> {
+        peripheral.discover_services().await?;
+        let characteristics = peripheral.characteristics();
+        let find = |uuid: Uuid| {
+            characteristics
+                .iter()
+                .find(|c| c.service_uuid == SERVICE_UUID && c.uuid == uuid)
+                .cloned()
+                .ok_or_else(|| format!("Characteristic {} not found", uuid))
+        };
+        let command = find(COMMAND_CHARACTERISTIC_UUID)?;
+        let response = find(RESPONSE_CHARACTERISTIC_UUID)?;
+        peripheral.subscribe(&response).await?;
+        Ok((command, peripheral.notifications().await?))
+    }
+
+    /// Writes a command and returns the first response to it
+    async fn request(&mut self, command: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        trace!(
+            "Sending command {:02x?} to {}",
+            command,
+            self.peripheral.address()
+        );
+        self.peripheral
+            .write(&self.command, command, WriteType::WithoutResponse)
+            .await?;
+        let code = command[0];
+        let response = time::timeout(RESPONSE_TIMEOUT, async {
+            while let Some(notification) = self.responses.next().await {
+                if notification.uuid == RESPONSE_CHARACTERISTIC_UUID
+                    && notification.value.first() == Some(&code)
+                {
+                    return Some(notification.value);
+                }
+            }
+            None
+        })
+        .await
+        .map_err(|_| format!("No response to command {:#04x}", code))?;
+        response.ok_or_else(|| "Connection closed".into())
+    }
+
+    /// Writes a command without response, acknowledged by the device on the GATT level
+    async fn send(&self, command: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        trace!(
+            "Sending command {:02x?} to {}",
+            command,
+            self.peripheral.address()
+        );
+        self.peripheral
+            .write(&self.command, command, WriteType::WithResponse)
+            .await?;
+        Ok(())
+    }
+
+    /// Resets the min/max values tracked by the device
+    pub async fn reset_min_max(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.send(&[RESET_MIN_MAX]).await
+    }
+
+    /// Sets the clock of the device to the given local time (seconds since 1970-01-01)
+    pub async fn set_time(&self, local_time: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.send(&set_time_command(local_time)).await
+    }
+
+    /// Reads the revisions of the Device Information Service. Revisions the device does not provide are None.
+    pub async fn device_info(&self) -> Result<DeviceInfo, Box<dyn Error + Send + Sync>> {
+        let characteristics = self.peripheral.characteristics();
+        let mut info = DeviceInfo::default();
+        for characteristic in characteristics
+            .iter()
+            .filter(|c| c.service_uuid == DEVICE_INFORMATION_SERVICE_UUID)
+        {
+            let field = match characteristic.uuid {
+                FIRMWARE_REVISION_UUID => &mut info.firmware_revision,
+                HARDWARE_REVISION_UUID => &mut info.hardware_revision,
+                _ => continue,
+            };
+            *field = parse_string(&self.peripheral.read(characteristic).await?);
+        }
+        Ok(info)
+    }
+
+    /// Number of entries of the log of the device
+    pub async fn log_length(&mut self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        parse_query_response(&self.request(&[QUERY, 0, 0, 0, 0]).await?)
+    }
+
+    /// Downloads the log entries in the given range of indices
+    pub async fn download_log(
+        &mut self,
+        from: u32,
+        to: u32,
+        model: &ThermoBeaconModel,
+    ) -> Result<Vec<LogEntry>, Box<dyn Error + Send + Sync>> {
+        let mut entries = Vec::with_capacity(to.saturating_sub(from) as usize);
+        let mut index = from;
+        while index < to {
+            let count = (to - index).min(u32::from(ENTRIES_PER_DUMP)) as u8;
+            let dumped =
+                parse_dump_response(&self.request(&dump_command(index, count)).await?, model)?;
+            if dumped.is_empty() {
+                break;
+            }
+            index += dumped.len() as u32;
+            entries.extend(dumped);
+        }
+        Ok(entries)
+    }
+
+    /// Closes the connection
+    pub async fn disconnect(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.peripheral.disconnect().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thermobeacon_models;
+
+    #[test]
+    fn encodes_and_decodes_commands() {
+        assert_eq!(
+            dump_command(0x0102, 3),
+            vec![0x07, 0x02, 0x01, 0x00, 0x00, 0x03]
+        );
+        assert_eq!(
+            set_time_command(0x66303A80),
+            vec![0x03, 0x80, 0x3A, 0x30, 0x66]
+        );
+        assert_eq!(
+            parse_query_response(&[0x01, 0x2C, 0x01, 0x00, 0x00]).unwrap(),
+            300
+        );
+        assert!(parse_query_response(&[0x07, 0x2C, 0x01, 0x00, 0x00]).is_err());
+
+        // Entries 298 and 299: 21.5 °C / 45 %, -0.125 °C / 50 %
+        let response = [
+            0x07, 0x2A, 0x01, 0x00, 0x00, 0x02, 0x58, 0x01, 0xD0, 0x02, 0xFE, 0xFF, 0x20, 0x03,
+        ];
+        let entries = parse_dump_response(&response, thermobeacon_models::DEFAULT).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                LogEntry {
+                    index: 298,
+                    temperature: 21.5,
+                    humidity: 45.0
+                },
+                LogEntry {
+                    index: 299,
+                    temperature: -0.125,
+                    humidity: 50.0
+                }
+            ]
+        );
+        assert!(parse_dump_response(&response[..12], thermobeacon_models::DEFAULT).is_err());
+    }
+
+    #[test]
+    fn parses_device_information_strings() {
+        assert_eq!(parse_string(b"V1.2.3\0\0"), Some("V1.2.3".to_string()));
+        assert_eq!(parse_string(b" 2.0 "), Some("2.0".to_string()));
+        assert_eq!(parse_string(b"\0"), None);
+        assert_eq!(parse_string(b""), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! Registry of the known ThermoBeacon models. The model of a beacon is detected by the key of its manufacturer data
+//! and can be overridden with the `model` of a device, e.g. for variants advertising an unknown key.
+
+/// Protocol parameters of a ThermoBeacon model
+#[derive(Debug, PartialEq)]
+pub struct ThermoBeaconModel {
+    /// Name of the model, selects the model with the `model` of a device (case insensitive)
+    pub name: &'static str,
+    /// Short description of the model
+    pub description: &'static str,
+    /// Keys of the manufacturer data advertised by this model
+    pub manufacturer_keys: &'static [u16],
+    /// Length of the current data frame (at least 18 bytes, additional bytes are ignored)
+    pub data_length: usize,
+    /// Length of the min/max data frame (at least 20 bytes, additional bytes are ignored)
+    pub min_max_length: usize,
+    /// Raw temperature value of 1 °C
+    pub temperature_scale: f32,
+    /// Number of bits of the signed (two's complement) temperature values, 12 or 16. Higher bits are ignored.
+    pub temperature_bits: u8,
+    /// Raw humidity value of 1 %
+    pub humidity_scale: f32,
+    /// Battery level (%) at the given battery voltages (mV), interpolated linearly in between. Must be sorted by the voltage.
+    pub battery_curve: &'static [(u16, f32)],
+}
+
+/// Battery curve of all models known so far: 3400 mV is considered full
+const LINEAR_3400_MV: &[(u16, f32)] = &[(0, 0.0), (3400, 100.0)];
+
+/// Known models. All of them share the same frame layout so far, they are distinguished to allow for differences.
+pub static MODELS: [ThermoBeaconModel; 3] = [
+    ThermoBeaconModel {
+        name: "WS02",
+        description: "Square hygrometer with LCD display",
+        manufacturer_keys: &[0x10, 0x11],
+        data_length: 18,
+        min_max_length: 20,
+        temperature_scale: 16.0,
+        temperature_bits: 16,
+        humidity_scale: 16.0,
+        battery_curve: LINEAR_3400_MV,
+    },
+    ThermoBeaconModel {
+        name: "rounded",
+        description: "Hygrometer with rounded corners and LCD display",
+        manufacturer_keys: &[0x15],
+        data_length: 18,
+        min_max_length: 20,
+        temperature_scale: 16.0,
+        temperature_bits: 16,
+        humidity_scale: 16.0,
+        battery_curve: LINEAR_3400_MV,
+    },
+    ThermoBeaconModel {
+        name: "WS08",
+        description: "Hygrometer WS08",
+        manufacturer_keys: &[0x1B],
+        data_length: 18,
+        min_max_length: 20,
+        temperature_scale: 16.0,
+        temperature_bits: 16,
+        humidity_scale: 16.0,
+        battery_curve: LINEAR_3400_MV,
+    },
+];
+
+/// Model used to decode frames of an unknown model
+pub static DEFAULT: &ThermoBeaconModel = &MODELS[0];
+
+/// Model advertising the given manufacturer data key, if any
+pub fn by_key(key: u16) -> Option<&'static ThermoBeaconModel> {
+    MODELS.iter().find(|m| m.manufacturer_keys.contains(&key))
+}
+
+/// Model of the given name (case insensitive), if any
+pub fn by_name(name: &str) -> Option<&'static ThermoBeaconModel> {
+    MODELS.iter().find(|m| m.name.eq_ignore_ascii_case(name))
+}
+
+impl ThermoBeaconModel {
+    /// Battery level (0 - 100%) at the given battery voltage (mV)
+    pub fn battery_level(&self, voltage_mv: u16) -> f32 {
+        let curve = self.battery_curve;
+        let (first, last) = match (curve.first(), curve.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if voltage_mv <= first.0 {
+            return first.1;
+        }
+        curve
+            .windows(2)
+            .find(|w| voltage_mv <= w[1].0)
+            .map(|w| {
+                let ((v0, l0), (v1, l1)) = (w[0], w[1]);
+                l0 + (l1 - l0) * f32::from(voltage_mv - v0) / f32::from(v1 - v0)
+            })
+            .unwrap_or(last.1)
+    }
+
+    /// Temperature (°C) of the raw value
+    pub fn temperature(&self, raw: u16) -> f32 {
+        signed_fixed_point(raw, self.temperature_bits, self.temperature_scale)
+    }
+
+    /// Humidity (%) of the raw value. Slightly negative values of badly calibrated sensors are kept.
+    pub fn humidity(&self, raw: u16) -> f32 {
+        signed_fixed_point(raw, 16, self.humidity_scale)
+    }
+}
+
+/// Decodes a signed (two's complement) fixed-point value of the given number of bits (1 - 16) from the lower bits of the raw value.
+/// The value is divided by the given scale, e.g. 16 for 4 fractional bits.
+pub fn signed_fixed_point(raw: u16, bits: u8, scale: f32) -> f32 {
+    let shift = 16 - u32::from(bits.clamp(1, 16));
+    // Shift the sign bit into the highest bit, the arithmetic shift back extends it
+    let value = ((raw << shift) as i16) >> shift;
+    f32::from(value) / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_models_by_key_and_name() {
+        assert_eq!(by_key(0x15).unwrap().name, "rounded");
+        assert_eq!(by_key(0x11).unwrap().name, "WS02");
+        assert!(by_key(0x42).is_none());
+        assert_eq!(by_name("ws08").unwrap().manufacturer_keys, &[0x1B]);
+        assert!(by_name("Smart hygrometer").is_none());
+    }
+
+    #[test]
+    fn decodes_signed_fixed_point_values() {
+        // 16 bit
+        assert_eq!(signed_fixed_point(21 * 16 + 8, 16, 16.0), 21.5);
+        assert_eq!(signed_fixed_point(0xFFFF, 16, 16.0), -0.0625);
+        assert_eq!(signed_fixed_point(0xFFFE, 16, 16.0), -0.125);
+        assert_eq!(signed_fixed_point(0x7FFF, 16, 16.0), 2047.9375);
+        assert_eq!(signed_fixed_point(0x8000, 16, 16.0), -2048.0);
+        // 12 bit, higher bits are ignored
+        assert_eq!(signed_fixed_point(0x0FFF, 12, 16.0), -0.0625);
+        assert_eq!(signed_fixed_point(0xFFFE, 12, 16.0), -0.125);
+        assert_eq!(signed_fixed_point(0x07FF, 12, 16.0), 127.9375);
+        assert_eq!(signed_fixed_point(0x0800, 12, 16.0), -128.0);
+        assert_eq!(signed_fixed_point(0xF150, 12, 16.0), 21.0);
+        // Other scales
+        assert_eq!(signed_fixed_point(0xFF9C, 16, 100.0), -1.0);
+    }
+
+    #[test]
+    fn interpolates_battery_curve() {
+        let model = ThermoBeaconModel {
+            battery_curve: &[(2000, 0.0), (2800, 80.0), (3000, 100.0)],
+            ..MODELS[0]
+        };
+        assert_eq!(model.battery_level(1800), 0.0);
+        assert_eq!(model.battery_level(2400), 40.0);
+        assert_eq!(model.battery_level(2900), 90.0);
+        assert_eq!(model.battery_level(3300), 100.0);
+        assert_eq!(DEFAULT.battery_level(1700), 50.0);
+    }
+}
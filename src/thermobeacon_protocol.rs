@@ -1,14 +1,23 @@
-extern crate paho_mqtt as mqtt;
-extern crate pretty_env_logger;
-
-use btleplug::api::{BDAddr, Central, Manager as _, Peripheral, PeripheralProperties, ScanFilter};
-use btleplug::platform::Manager;
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, Manager as _, Peripheral, PeripheralProperties, ScanFilter,
+};
+use btleplug::platform::{Manager, Peripheral as PlatformPeripheral};
+use futures::future::join_all;
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashMap;
 use std::error::Error;
+use std::sync::Mutex;
 use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
 use tokio::time::{self};
+use uuid::Uuid;
 // Prelude import with the common imports
 use packed_struct::prelude::*;
 
+use crate::scanner::{self, PeripheralOf};
+use crate::sensors::{self, DeviceType};
+use crate::thermobeacon_models::{self, ThermoBeaconModel};
+
 /// Raw data from ThermoBeacon. Struct uses PackedStruct to parse a byte array
 /// @see https://github.com/iskalchev/ThermoBeacon-pyhap
 ///
@@ -80,54 +89,58 @@ pub struct ThermoBeaconMinMaxRawData {
 }
 
 /// Struct containing the parsed data from a ThermoBeacon
-#[allow(dead_code)]
 #[derive(Debug, Default, Clone, serde_derive::Serialize, PartialEq)]
 pub struct ThermoBeaconData {
     /// Battery level (0 - 100%)
-    battery_level: f32,
+    pub battery_level: f32,
     /// Humidity (0 - 100%)
-    humidity: f32,
+    pub humidity: f32,
     /// Temperature (°C)
-    temperature: f32,
+    pub temperature: f32,
     /// Uptime in s
-    uptime_s: u32,
+    pub uptime_s: u32,
     /// Updtime in days
-    uptime_d: f32,
+    pub uptime_d: f32,
     /// Mac Adress of the ThermoBeacon
-    mac: BDAddr,
+    pub mac: BDAddr,
     /// Is the button currently pressed?
-    button_pressed: bool,
+    pub button_pressed: bool,
 }
 
 /// Struct containing the parsed min/max data from a ThermoBeacon
-#[allow(dead_code)]
 #[derive(Debug, Default, Clone, serde_derive::Serialize, PartialEq)]
 pub struct ThermoBeaconMinMaxData {
     /// Is the button currently pressed?
-    button_pressed: bool,
+    pub button_pressed: bool,
     /// Mac Adress of the ThermoBeacon
-    mac: BDAddr,
+    pub mac: BDAddr,
     /// max. temperature (°C)
-    max_temperature: f32,
-    // min. temperature (°C)
-    min_temperature: f32,
-    // time of max temperature (relative to start time)
-    max_temp_time: u32,
-    // time of min temperature  (relative to start time)
-    min_temp_time: u32,
+    pub max_temperature: f32,
+    /// min. temperature (°C)
+    pub min_temperature: f32,
+    /// time of max temperature (relative to start time)
+    pub max_temp_time: u32,
+    /// time of min temperature  (relative to start time)
+    pub min_temp_time: u32,
 }
 
-/// Allows to convert the ThermoBeaconRawData to a ThermoBeaconData struct.
-impl From<ThermoBeaconRawData> for ThermoBeaconData {
-    fn from(value: ThermoBeaconRawData) -> Self {
-        // https://github.com/iskalchev/ThermoBeacon-pyhap/blob/main/tb_protocol.py
-        let t = value.temperature_raw as f32 / 16.0;
-        let h = value.humidity_raw as f32 / 16.0;
+/// A single decoded advertisement frame. ThermoBeacons alternately advertise both kinds of frames.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThermoBeaconFrame {
+    /// Current temperature, humidity, battery level and uptime (18 bytes)
+    Data(ThermoBeaconData),
+    /// Minimum and maximum temperature since the last reset (20 bytes)
+    MinMax(ThermoBeaconMinMaxData),
+}
 
+impl ThermoBeaconData {
+    /// Decodes the raw data of a ThermoBeacon of the given model
+    pub fn decode(value: ThermoBeaconRawData, model: &ThermoBeaconModel) -> Self {
+        // https://github.com/iskalchev/ThermoBeacon-pyhap/blob/main/tb_protocol.py
         ThermoBeaconData {
-            battery_level: value.voltage_raw as f32 * 100.0 / 3400.0,
-            humidity: if h > 4000.0 { h - 4096.0 } else { h },
-            temperature: if t > 4000.0 { t - 4096.0 } else { t },
+            battery_level: model.battery_level(value.voltage_raw),
+            humidity: model.humidity(value.humidity_raw),
+            temperature: model.temperature(value.temperature_raw),
             uptime_s: value.uptime_seconds,
             uptime_d: value.uptime_seconds as f32 / 86400.0,
             mac: value.mac.try_into().unwrap(),
@@ -136,112 +149,126 @@ impl From<ThermoBeaconRawData> for ThermoBeaconData {
     }
 }
 
-/// Allows to convert the ThermoBeaconMinMaxRawData to a ThermoBeaconMinMaxData struct.
-impl From<ThermoBeaconMinMaxRawData> for ThermoBeaconMinMaxData {
-    fn from(value: ThermoBeaconMinMaxRawData) -> Self {
-        let t_max = value.max_temperature_raw as f32 / 16.0;
-        let t_min = value.min_temperature_raw as f32 / 16.0;
+/// Allows to convert the ThermoBeaconRawData of the default model to a ThermoBeaconData struct.
+impl From<ThermoBeaconRawData> for ThermoBeaconData {
+    fn from(value: ThermoBeaconRawData) -> Self {
+        ThermoBeaconData::decode(value, thermobeacon_models::DEFAULT)
+    }
+}
 
+impl ThermoBeaconMinMaxData {
+    /// Decodes the raw min/max data of a ThermoBeacon of the given model
+    pub fn decode(value: ThermoBeaconMinMaxRawData, model: &ThermoBeaconModel) -> Self {
         ThermoBeaconMinMaxData {
             button_pressed: value.button == 0x80,
             mac: value.mac.try_into().unwrap(),
-            max_temperature: if t_max > 4000.0 {
-                t_max - 4096.0
-            } else {
-                t_max
-            },
-            min_temperature: if t_min > 4000.0 {
-                t_min - 4096.0
-            } else {
-                t_min
-            },
+            max_temperature: model.temperature(value.max_temperature_raw),
+            min_temperature: model.temperature(value.min_temperature_raw),
             max_temp_time: value.max_temp_time_seconds,
             min_temp_time: value.mintemp_time_seconds,
         }
     }
 }
 
-/// Returns the length of the manufacturer_data field
-fn get_property_length(properties: &PeripheralProperties) -> usize {
-    if let Some(key) = properties.manufacturer_data.keys().next() {
-        return match key {
-            key if check_if_device_type_is_valid(key) => {
-                properties.manufacturer_data.get(key).unwrap().len()
-            }
-            _ => 0,
-        };
+/// Allows to convert the ThermoBeaconMinMaxRawData of the default model to a ThermoBeaconMinMaxData struct.
+impl From<ThermoBeaconMinMaxRawData> for ThermoBeaconMinMaxData {
+    fn from(value: ThermoBeaconMinMaxRawData) -> Self {
+        ThermoBeaconMinMaxData::decode(value, thermobeacon_models::DEFAULT)
     }
-    0
 }
 
-/// Checks if the device type is valid
-fn check_if_device_type_is_valid(key: &u16) -> bool {
-    match key {
-        // Allowed values 0x10, 0x11, 0x15, 0x1B -> Different for different device types. 0x15 for Thermobeacon rounded corne with display
-        0x10 | 0x11 | 0x15 | 0x1B => true,
-        _ => false,
+impl ThermoBeaconFrame {
+    /// Mac Adress of the ThermoBeacon which sent the frame
+    pub fn mac(&self) -> BDAddr {
+        match self {
+            ThermoBeaconFrame::Data(data) => data.mac,
+            ThermoBeaconFrame::MinMax(min_max_data) => min_max_data.mac,
+        }
     }
 }
 
-/// Parses the current temperature and humidity data from PeripheralProperties
-fn parse_thermo_beacon_data(
+/// Decodes the manufacturer data of a single ThermoBeacon advertisement of the default model. The frame kind is determined by its length.
+pub fn parse_advertisement(data: &[u8]) -> Result<ThermoBeaconFrame, Box<dyn Error + Send + Sync>> {
+    parse_advertisement_of_model(data, thermobeacon_models::DEFAULT)
+}
+
+/// Decodes the manufacturer data of a single advertisement of a ThermoBeacon of the given model. The frame kind is determined by its length.
+pub fn parse_advertisement_of_model(
+    data: &[u8],
+    model: &ThermoBeaconModel,
+) -> Result<ThermoBeaconFrame, Box<dyn Error + Send + Sync>> {
+    match data.len() {
+        len if len == model.data_length => Ok(ThermoBeaconFrame::Data(ThermoBeaconData::decode(
+            ThermoBeaconRawData::unpack(data[..18].try_into()?)?,
+            model,
+        ))),
+        len if len == model.min_max_length => {
+            Ok(ThermoBeaconFrame::MinMax(ThermoBeaconMinMaxData::decode(
+                ThermoBeaconMinMaxRawData::unpack(data[..20].try_into()?)?,
+                model,
+            )))
+        }
+        len => Err(format!("Unknown frame length {} of model {}", len, model.name).into()),
+    }
+}
+
+/// Returns the manufacturer data of a ThermoBeacon together with its model. With a configured model, the first manufacturer data
+/// of a frame length of this model is returned, otherwise the model is detected by the key of the manufacturer data.
+fn thermobeacon_manufacturer_data<'a>(
+    properties: &'a PeripheralProperties,
+    model: Option<&'static ThermoBeaconModel>,
+) -> Option<(&'static ThermoBeaconModel, &'a [u8])> {
+    properties
+        .manufacturer_data
+        .iter()
+        .find_map(|(key, data)| match model {
+            Some(model)
+                if data.len() == model.data_length || data.len() == model.min_max_length =>
+            {
+                Some((model, data.as_slice()))
+            }
+            Some(_) => None,
+            None => Some((thermobeacon_models::by_key(*key)?, data.as_slice())),
+        })
+}
+
+/// Parses the current temperature and humidity data from PeripheralProperties, using the given model instead of the detected one
+pub(crate) fn parse_thermo_beacon_data(
     p: &PeripheralProperties,
+    model: Option<&'static ThermoBeaconModel>,
 ) -> Result<ThermoBeaconData, Box<dyn Error + Send + Sync>> {
     trace!("  ThermoBeacon properties {:?}", p);
-    for key in p.manufacturer_data.keys() {
-        match key {
-            key if check_if_device_type_is_valid(key) => {
-                // Read the data
-                let data = p.manufacturer_data.get(key).unwrap();
-                trace!("  Fetched {:?} bytes of raw data", data.len());
-
-                if data.len() == 18 {
-                    let tbrd: ThermoBeaconData = ThermoBeaconRawData::unpack(
-                        data[0..18].try_into().expect("slice with incorrect length"),
-                    )?
-                    .into();
-
-                    return Ok(tbrd);
-                } else {
-                    warn!("  Data length not 18 but {:?}", data.len());
-                }
-            }
-            _ => warn!("  Device ID not supported {:?}", key),
-        }
+    let (model, data) = thermobeacon_manufacturer_data(p, model).ok_or("No data found")?;
+    trace!(
+        "  Fetched {:?} bytes of raw data of model {}",
+        data.len(),
+        model.name
+    );
+    match parse_advertisement_of_model(data, model)? {
+        ThermoBeaconFrame::Data(data) => Ok(data),
+        ThermoBeaconFrame::MinMax(_) => Err("No current data found".into()),
     }
-    Err("No data found".into())
 }
 
-/// Parses the min and max temperature data from PeripheralProperties
-fn parse_thermo_beacon_min_max_data(
+/// Parses the min and max temperature data from PeripheralProperties, using the given model instead of the detected one
+pub(crate) fn parse_thermo_beacon_min_max_data(
     p: &PeripheralProperties,
+    model: Option<&'static ThermoBeaconModel>,
 ) -> Result<ThermoBeaconMinMaxData, Box<dyn Error + Send + Sync>> {
     trace!("  ThermoBeacon properties {:?}", p);
-    for key in p.manufacturer_data.keys() {
-        match key {
-            key if check_if_device_type_is_valid(key) => {
-                // Read the data
-                let data = p.manufacturer_data.get(key).unwrap();
-                trace!("  Fetched {:?} bytes of raw data", data.len());
-
-                if data.len() == 20 {
-                    let tbrd: ThermoBeaconMinMaxData = ThermoBeaconMinMaxRawData::unpack(
-                        data[0..20].try_into().expect("slice with incorrect length"),
-                    )?
-                    .into();
-
-                    return Ok(tbrd);
-                } else {
-                    warn!("  Data length not 18 but {:?}", data.len());
-                }
-            }
-            _ => warn!("  Device ID not supported {:?}", key),
-        }
+    let (model, data) = thermobeacon_manufacturer_data(p, model).ok_or("No data found")?;
+    trace!(
+        "  Fetched {:?} bytes of raw data of model {}",
+        data.len(),
+        model.name
+    );
+    match parse_advertisement_of_model(data, model)? {
+        ThermoBeaconFrame::MinMax(min_max_data) => Ok(min_max_data),
+        ThermoBeaconFrame::Data(_) => Err("No min/max data found".into()),
     }
-    Err("No data found".into())
 }
 
-#[derive(Debug, Default, serde_derive::Serialize, PartialEq)]
+#[derive(Debug, Clone, Default, serde_derive::Serialize, PartialEq)]
 pub struct ThermoBeaconFullReadResult {
     /// Battery level (0 - 100%)
     pub battery_level: f32,
@@ -255,136 +282,1358 @@ pub struct ThermoBeaconFullReadResult {
     pub button_pressed: bool,
     /// Mac Adress of the ThermoBeacon
     pub mac: BDAddr,
-    /// max. temperature (°C)
-    pub max_temperature: f32,
-    // min. temperature (°C)
-    pub min_temperature: f32,
-    // time of max temperature (relative to start time)
-    pub max_temp_time: u32,
-    // time of min temperature  (relative to start time)
-    pub min_temp_time: u32,
+    /// max. temperature (°C). Missing if the min/max data was not received in time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_temperature: Option<f32>,
+    /// min. temperature (°C). Missing if the min/max data was not received in time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_temperature: Option<f32>,
+    /// time of max temperature (relative to start time)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_temp_time: Option<u32>,
+    /// time of min temperature  (relative to start time)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_temp_time: Option<u32>,
+    /// Signal strength (dBm) of the last advertisement, if reported by the adapter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i16>,
+    /// Advertised transmission power (dBm), if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_power: Option<i16>,
+    /// Temperature of the external probe (°C), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_temperature: Option<f32>,
+    /// Air pressure (hPa), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f32>,
+    /// Acceleration along the x axis (g), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acceleration_x: Option<f32>,
+    /// Acceleration along the y axis (g), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acceleration_y: Option<f32>,
+    /// Acceleration along the z axis (g), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acceleration_z: Option<f32>,
+    /// Number of movements detected by the accelerometer (wraps at 255), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub movement_counter: Option<u8>,
+    /// Battery voltage (V), only reported by some sensor types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_voltage: Option<f32>,
 }
 
-/// Reads all possible available data for the configured devices
-pub async fn read_all_configured(
-    manager: &Manager,
+/// Allows to combine the current data and the min/max data of a ThermoBeacon into a ThermoBeaconFullReadResult.
+impl From<(ThermoBeaconData, ThermoBeaconMinMaxData)> for ThermoBeaconFullReadResult {
+    fn from((data, min_max_data): (ThermoBeaconData, ThermoBeaconMinMaxData)) -> Self {
+        ThermoBeaconFullReadResult {
+            battery_level: data.battery_level,
+            humidity: data.humidity,
+            temperature: data.temperature,
+            uptime: data.uptime_s,
+            button_pressed: data.button_pressed,
+            mac: data.mac,
+            max_temperature: Some(min_max_data.max_temperature),
+            min_temperature: Some(min_max_data.min_temperature),
+            max_temp_time: Some(min_max_data.max_temp_time),
+            min_temp_time: Some(min_max_data.min_temp_time),
+            ..Default::default()
+        }
+    }
+}
+
+/// Allows to create a partial ThermoBeaconFullReadResult if the min/max data of a ThermoBeacon is missing.
+impl From<ThermoBeaconData> for ThermoBeaconFullReadResult {
+    fn from(data: ThermoBeaconData) -> Self {
+        ThermoBeaconFullReadResult {
+            battery_level: data.battery_level,
+            humidity: data.humidity,
+            temperature: data.temperature,
+            uptime: data.uptime_s,
+            button_pressed: data.button_pressed,
+            mac: data.mac,
+            ..Default::default()
+        }
+    }
+}
+
+/// Cache of discovered peripherals (keyed by adapter and MAC address), kept between scheduled runs.
+/// Enumerating all peripherals of an adapter takes several seconds in dense RF environments, so
+/// known devices are only re-enumerated if they are not cached yet or their handle became invalid.
+pub struct PeripheralCache<P = PlatformPeripheral> {
+    peripherals: Mutex<HashMap<(String, BDAddr), P>>,
+}
+
+impl<P> Default for PeripheralCache<P> {
+    fn default() -> Self {
+        PeripheralCache {
+            peripherals: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<P: scanner::ScannerPeripheral> PeripheralCache<P> {
+    /// Returns all cached peripherals of the given adapter matching one of the given devices
+    fn get(&self, adapter: &str, devices: &[BDAddr]) -> Vec<P> {
+        let peripherals = self.peripherals.lock().unwrap();
+        devices
+            .iter()
+            .filter_map(|mac| peripherals.get(&(adapter.to_string(), *mac)).cloned())
+            .collect()
+    }
+
+    /// Adds a peripheral of the given adapter to the cache
+    fn insert(&self, adapter: &str, peripheral: P) {
+        let mut peripherals = self.peripherals.lock().unwrap();
+        peripherals.insert((adapter.to_string(), peripheral.address()), peripheral);
+    }
+
+    /// Removes an invalid peripheral of the given adapter from the cache
+    fn remove(&self, adapter: &str, mac: &BDAddr) {
+        let mut peripherals = self.peripherals.lock().unwrap();
+        peripherals.remove(&(adapter.to_string(), *mac));
+    }
+}
+
+/// Aggregation of the current data frames received from a device during a scan
+#[derive(Debug, Clone, Copy, Default, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    /// Latest frame
+    #[default]
+    Last,
+    /// Mean of all frames
+    Mean,
+    /// Median of all frames, robust against single garbage values
+    Median,
+}
+
+impl Aggregation {
+    /// Aggregates the given values. There must be at least one value.
+    fn apply(&self, mut values: Vec<f32>) -> f32 {
+        match self {
+            Aggregation::Last => values[values.len() - 1],
+            Aggregation::Mean => values.iter().sum::<f32>() / values.len() as f32,
+            Aggregation::Median => {
+                values.sort_by(|a, b| a.total_cmp(b));
+                let mid = values.len() / 2;
+                if values.len() % 2 == 0 {
+                    (values[mid - 1] + values[mid]) / 2.0
+                } else {
+                    values[mid]
+                }
+            }
+        }
+    }
+
+    /// Aggregates the current data frames of a device. The uptime and the button state are taken from the latest frame.
+    fn aggregate(&self, samples: Vec<ThermoBeaconData>) -> Option<ThermoBeaconData> {
+        let field = |f: fn(&ThermoBeaconData) -> f32| self.apply(samples.iter().map(f).collect());
+        let last = samples.last()?;
+        Some(ThermoBeaconData {
+            battery_level: field(|d| d.battery_level),
+            humidity: field(|d| d.humidity),
+            temperature: field(|d| d.temperature),
+            ..last.clone()
+        })
+    }
+
+    /// Aggregates the readings of a sensor of another type. All other fields are taken from the latest reading.
+    fn aggregate_readings(
+        &self,
+        samples: Vec<ThermoBeaconFullReadResult>,
+    ) -> Option<ThermoBeaconFullReadResult> {
+        let field =
+            |f: fn(&ThermoBeaconFullReadResult) -> f32| self.apply(samples.iter().map(f).collect());
+        let last = samples.last()?;
+        Some(ThermoBeaconFullReadResult {
+            battery_level: field(|d| d.battery_level),
+            humidity: field(|d| d.humidity),
+            temperature: field(|d| d.temperature),
+            ..last.clone()
+        })
+    }
+}
+
+/// Options of a scan for the configured devices
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanOptions {
+    /// Maximum duration of the scan
+    pub scan_duration: Duration,
+    /// Time to wait for the missing frame of a device after the first one was received
+    pub device_read_timeout: Duration,
+    /// Number of distinct current data frames to collect from each device
+    pub samples: usize,
+    /// Aggregation of the collected current data frames
+    pub aggregation: Aggregation,
+    /// Types of the devices, devices not contained are ThermoBeacons
+    pub device_types: HashMap<BDAddr, DeviceType>,
+    /// Configured models of ThermoBeacons, the model of other ThermoBeacons is detected from their advertisements
+    pub models: HashMap<BDAddr, &'static ThermoBeaconModel>,
+    /// Names of the adapters to scan with (e.g. 'hci1'), all adapters if empty
+    pub adapters: Vec<String>,
+    /// Adapters the given devices are only read with
+    pub device_adapters: HashMap<BDAddr, String>,
+    /// Interval to poll the properties of the peripherals while scanning
+    pub poll_interval: Duration,
+    /// Parameters of the scans of the adapters
+    pub parameters: scanner::ScanParameters,
+}
+
+impl ScanOptions {
+    /// Type of the given device
+    fn device_type(&self, mac: &BDAddr) -> DeviceType {
+        self.device_types.get(mac).copied().unwrap_or_default()
+    }
+
+    /// Configured model of the given device, if any
+    fn model(&self, mac: &BDAddr) -> Option<&'static ThermoBeaconModel> {
+        self.models.get(mac).copied()
+    }
+
+    /// Is the adapter with the given info selected for the scan?
+    fn uses_adapter(&self, adapter_info: &str) -> bool {
+        self.adapters.is_empty()
+            || self
+                .adapters
+                .iter()
+                .any(|name| adapter_matches(adapter_info, name))
+    }
+
+    /// Devices to read with the adapter with the given info: all devices not pinned to another adapter
+    fn devices_of_adapter(&self, devices: &[BDAddr], adapter_info: &str) -> Vec<BDAddr> {
+        devices
+            .iter()
+            .filter(|mac| {
+                self.device_adapters
+                    .get(mac)
+                    .map_or(true, |name| adapter_matches(adapter_info, name))
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Does the adapter info (e.g. 'hci0 (usb:v1D6Bp0246d0537)') name the given adapter (e.g. 'hci0')?
+fn adapter_matches(adapter_info: &str, name: &str) -> bool {
+    adapter_info == name || adapter_info.split_whitespace().next() == Some(name)
+}
+
+/// Frames received from a single ThermoBeacon during a scan
+#[derive(Default)]
+struct PendingRead {
+    /// Time the first frame was received
+    first_frame: Option<time::Instant>,
+    /// Distinct current data frames
+    data: Vec<ThermoBeaconData>,
+    min_max: Option<ThermoBeaconMinMaxData>,
+    /// Distinct readings of sensors of other types, which send complete readings in each advertisement
+    readings: Vec<ThermoBeaconFullReadResult>,
+    /// Signal strength and transmission power of the last advertisement received by each adapter
+    signals: HashMap<String, (Option<i16>, Option<i16>)>,
+}
+
+impl PendingRead {
+    /// Are both the current data and the min/max data (or a complete reading of another sensor type) available?
+    fn has_both_frames(&self) -> bool {
+        (!self.data.is_empty() && self.min_max.is_some()) || !self.readings.is_empty()
+    }
+
+    /// Are the min/max data and enough samples of the current data (or enough readings of another sensor type) available?
+    fn is_complete(&self, samples: usize) -> bool {
+        self.has_both_frames() && self.data.len().max(self.readings.len()) >= samples
+    }
+
+    /// Is the read complete or was the missing frame not received within the timeout after the first one? With both frames received, further samples are collected until the end of the scan.
+    fn is_done(&self, options: &ScanOptions) -> bool {
+        self.is_complete(options.samples)
+            || (!self.has_both_frames()
+                && self
+                    .first_frame
+                    .map(|t| t.elapsed() >= options.device_read_timeout)
+                    .unwrap_or(false))
+    }
+
+    /// Signal strength and transmission power received by the adapter with the strongest signal
+    fn strongest_signal(&self) -> (Option<i16>, Option<i16>) {
+        self.signals
+            .values()
+            .max_by_key(|(rssi, _)| *rssi)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Combines the received frames. Without the min/max data, a partial result is returned. Without the current data, there is no result.
+    fn into_result(
+        self,
+        mac: &BDAddr,
+        aggregation: Aggregation,
+    ) -> Option<ThermoBeaconFullReadResult> {
+        if self.data.len().max(self.readings.len()) > 1 {
+            debug!(
+                "Aggregating {} samples of {}",
+                self.data.len().max(self.readings.len()),
+                mac
+            );
+        }
+        let (rssi, tx_power) = self.strongest_signal();
+        if !self.readings.is_empty() {
+            return aggregation.aggregate_readings(self.readings).map(|result| {
+                ThermoBeaconFullReadResult {
+                    rssi,
+                    tx_power,
+                    ..result
+                }
+            });
+        }
+        let data = aggregation.aggregate(self.data);
+        let result: ThermoBeaconFullReadResult = match (data, self.min_max) {
+            (Some(data), Some(min_max)) => (data, min_max).into(),
+            (Some(data), None) => {
+                warn!(
+                    "Min/max data of ThermoBeacon {} missing, publishing partial result",
+                    mac
+                );
+                data.into()
+            }
+            (None, Some(_)) => {
+                warn!(
+                    "Temperature and humidity data of ThermoBeacon {} missing, no result",
+                    mac
+                );
+                return None;
+            }
+            (None, None) => return None,
+        };
+        Some(ThermoBeaconFullReadResult {
+            rssi,
+            tx_power,
+            ..result
+        })
+    }
+}
+
+/// Reads the currently advertised frame of a single peripheral into the pending read. Peripherals which are no ThermoBeacon (or no sensor of the configured type) are ignored.
+/// BlueZ keeps the properties of a device between scans, so the current data seen on the first poll (of each adapter) might be
+/// from a previous scan. It contains the uptime and therefore changes with each advertisement. The min/max data
+/// is the same until the extremes change, so it is always accepted. Readings of other sensor types are never accepted on the first poll.
+/// An advertisement received by several adapters is only added once. The current data is kept ordered by the uptime, so the
+/// newest data is the last one regardless of the adapter it was received by.
+fn apply_properties(
+    adapter_info: &str,
+    props: &PeripheralProperties,
+    mac: &BDAddr,
+    device_type: DeviceType,
+    model: Option<&'static ThermoBeaconModel>,
+    first_poll: bool,
+    pending: &mut PendingRead,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if device_type != DeviceType::ThermoBeacon {
+        if first_poll {
+            return Ok(());
+        }
+        let reading = match sensors::decode(
+            device_type,
+            *mac,
+            &props.manufacturer_data,
+            &props.service_data,
+        ) {
+            Some(reading) => reading,
+            None => return Ok(()),
+        };
+        debug!("Reading {:?} sensor {:?}", device_type, mac);
+        // The properties stay the same until the next advertisement
+        if !pending.readings.contains(&reading) {
+            pending.readings.push(reading);
+        }
+    } else if props.local_name.as_deref() != Some("ThermoBeacon") {
+        return Ok(());
+    } else {
+        let (model, data) = match thermobeacon_manufacturer_data(props, model) {
+            Some(manufacturer_data) => manufacturer_data,
+            None => return Ok(()),
+        };
+        match data.len() {
+            len if len == model.data_length && !first_poll => {
+                debug!(
+                    "Reading temperature and humidity from ThermoBeacon {:?}",
+                    mac
+                );
+                // The properties stay the same until the next advertisement
+                let data = parse_thermo_beacon_data(props, Some(model))?;
+                if !pending.data.contains(&data) {
+                    let index = pending
+                        .data
+                        .partition_point(|d| d.uptime_s <= data.uptime_s);
+                    pending.data.insert(index, data);
+                }
+            }
+            len if len == model.min_max_length => {
+                debug!(
+                    "Reading min and max temperature from ThermoBeacon {:?}",
+                    mac
+                );
+                pending.min_max = Some(parse_thermo_beacon_min_max_data(props, Some(model))?);
+            }
+            _ => return Ok(()),
+        }
+    }
+    pending.first_frame.get_or_insert_with(time::Instant::now);
+    pending
+        .signals
+        .insert(adapter_info.to_string(), (props.rssi, props.tx_power_level));
+    Ok(())
+}
+
+/// Returns the peripherals of the given devices known to the adapter. Enumerates all peripherals in range if not all devices are cached yet.
+async fn known_peripherals<A: scanner::ScannerAdapter>(
+    adapter: &A,
+    adapter_info: &str,
+    cache: &PeripheralCache<A::Peripheral>,
     devices: &[BDAddr],
+) -> Result<Vec<A::Peripheral>, Box<dyn Error + Send + Sync>> {
+    let mut peripherals = cache.get(adapter_info, devices);
+    if peripherals.len() < devices.len() {
+        // Not all configured devices are known yet, so enumerate all peripherals in range
+        trace!(
+            "{} of {} devices cached for {}, enumerating peripherals ...",
+            peripherals.len(),
+            devices.len(),
+            adapter_info
+        );
+        for peripheral in adapter.peripherals().await?.into_iter() {
+            let device_present = devices.iter().any(|d| peripheral.address() == *d);
+            let cached = peripherals
+                .iter()
+                .any(|p| p.address() == peripheral.address());
+
+            if device_present && !cached {
+                cache.insert(adapter_info, peripheral.clone());
+                peripherals.push(peripheral);
+            }
+        }
+    }
+    Ok(peripherals)
+}
+
+/// Polls the peripherals of the given devices on a single adapter concurrently, until all devices are done (on any adapter) or the deadline is reached
+async fn poll_adapter<A: scanner::ScannerAdapter>(
+    adapter: &A,
+    adapter_info: &str,
+    cache: &PeripheralCache<A::Peripheral>,
+    devices: &[BDAddr],
+    pending: &Mutex<HashMap<BDAddr, PendingRead>>,
+    deadline: time::Instant,
+    options: &ScanOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Devices polled at least once by this adapter
+    let mut polled: Vec<BDAddr> = vec![];
+    loop {
+        let peripherals: Vec<A::Peripheral> = {
+            let peripherals = known_peripherals(adapter, adapter_info, cache, devices).await?;
+            let pending = pending.lock().unwrap();
+            peripherals
+                .into_iter()
+                .filter(|p| {
+                    !pending
+                        .get(&p.address())
+                        .map(|r| r.is_done(options))
+                        .unwrap_or(false)
+                })
+                .collect()
+        };
+        let properties = join_all(peripherals.iter().map(|p| p.properties())).await;
+        for (peripheral, props) in peripherals.iter().zip(properties) {
+            let mac = peripheral.address();
+            let props = match props {
+                Ok(Some(props)) => props,
+                Ok(None) => continue,
+                Err(e) => {
+                    // Handle might be invalid (e.g. device was removed by the bluetooth stack), so enumerate again next time
+                    cache.remove(adapter_info, &mac);
+                    return Err(e);
+                }
+            };
+            let first_poll = !polled.contains(&mac);
+            if first_poll {
+                polled.push(mac);
+            }
+            let mut pending = pending.lock().unwrap();
+            apply_properties(
+                adapter_info,
+                &props,
+                &mac,
+                options.device_type(&mac),
+                options.model(&mac),
+                first_poll,
+                pending.entry(mac).or_default(),
+            )?;
+        }
+
+        let done = {
+            let pending = pending.lock().unwrap();
+            devices
+                .iter()
+                .all(|d| pending.get(d).map(|r| r.is_done(options)).unwrap_or(false))
+        };
+        if done {
+            debug!("All devices done, stopping scan on {}", adapter_info);
+            return Ok(());
+        }
+        if time::Instant::now() >= deadline {
+            debug!("Scan time elapsed on {}", adapter_info);
+            return Ok(());
+        }
+        time::sleep(options.poll_interval).await;
+    }
+}
+
+/// Scans on a single adapter while polling the peripherals of the given devices
+async fn scan_adapter<A: scanner::ScannerAdapter>(
+    adapter: &A,
+    cache: &PeripheralCache<A::Peripheral>,
+    devices: &[BDAddr],
+    pending: &Mutex<HashMap<BDAddr, PendingRead>>,
+    deadline: time::Instant,
+    options: &ScanOptions,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let adapter_info = adapter.info().await?;
+    let devices = options.devices_of_adapter(devices, &adapter_info);
+    if !options.uses_adapter(&adapter_info) || devices.is_empty() {
+        debug!("Skipping adapter {}", adapter_info);
+        return Ok(false);
+    }
+    debug!("Starting scan on {}...", adapter_info);
+    adapter.start_scan(&options.parameters).await?;
+    let result = poll_adapter(
+        adapter,
+        &adapter_info,
+        cache,
+        &devices,
+        pending,
+        deadline,
+        options,
+    )
+    .await;
+    adapter.stop_scan().await?;
+    result.map(|_| true)
+}
+
+/// Tries to power a failed adapter back on, so it can scan again in the next run
+async fn recover_adapter<A: scanner::ScannerAdapter>(adapter: &A, adapter_info: &str) {
+    match adapter.power_on().await {
+        Ok(()) => info!("Powered on {} again", adapter_info),
+        Err(e) => warn!("Failed to power on {}: {}", adapter_info, e),
+    }
+}
+
+/// Result of a scan for the configured devices
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    /// Readings of the devices found
+    pub readings: Vec<ThermoBeaconFullReadResult>,
+    /// Adapters whose scan failed
+    pub failed_adapters: Vec<String>,
+}
+
+/// Reads all possible available data for the configured devices. All adapters scan concurrently until all devices sent both frames (and the requested number of samples), at most for the scan duration.
+/// If a device does not send the missing frame within the device read timeout after the first one, the device is not waited for any longer.
+/// Scans with the adapters of the given scanner, e.g. the btleplug `Manager`. If the scan fails on some adapters, the readings of the others
+/// are returned and the failed adapters are powered on again for the next run. Fails only if the scan failed on all adapters.
+pub async fn read_all_configured<S: scanner::BleScanner>(
+    scanner: &S,
+    cache: &PeripheralCache<PeripheralOf<S>>,
+    devices: &[BDAddr],
+    options: &ScanOptions,
+) -> Result<ScanReport, Box<dyn Error + Send + Sync>> {
+    let adapter_list = scanner.adapters().await?;
+    if adapter_list.is_empty() {
+        error!("No Bluetooth adapters found");
+        return Err("No Bluetooth adapters found".into());
+    }
+
+    // Frames of each device, received by any adapter
+    let pending: Mutex<HashMap<BDAddr, PendingRead>> = Mutex::new(HashMap::new());
+    let deadline = time::Instant::now() + options.scan_duration;
+    let scans = join_all(
+        adapter_list
+            .iter()
+            .map(|adapter| scan_adapter(adapter, cache, devices, &pending, deadline, options)),
+    )
+    .await;
+    let mut scanned = false;
+    let mut failed_adapters = vec![];
+    for (adapter, scan) in adapter_list.iter().zip(scans) {
+        match scan {
+            Ok(used) => scanned |= used,
+            Err(e) => {
+                let adapter_info = adapter
+                    .info()
+                    .await
+                    .unwrap_or_else(|_| "unknown adapter".to_string());
+                error!("Scan on {} failed: {}", adapter_info, e);
+                recover_adapter(adapter, &adapter_info).await;
+                failed_adapters.push(adapter_info);
+            }
+        }
+    }
+    if !scanned && !failed_adapters.is_empty() {
+        return Err(format!(
+            "Scan failed on all adapters: {}",
+            failed_adapters.join(", ")
+        )
+        .into());
+    }
+    if !scanned && !options.adapters.is_empty() {
+        warn!("None of the adapters {:?} found", options.adapters);
+    }
+
+    let pending = pending.into_inner().unwrap();
+    let complete = pending.values().filter(|p| p.has_both_frames()).count();
+    debug!("{} of {} devices completely read", complete, devices.len());
+    Ok(ScanReport {
+        readings: pending
+            .into_iter()
+            .filter_map(|(mac, p)| p.into_result(&mac, options.aggregation))
+            .collect(),
+        failed_adapters,
+    })
+}
+
+/// A ThermoBeacon found by discover()
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredBeacon {
+    /// Mac Adress of the ThermoBeacon
+    pub mac: BDAddr,
+    /// Signal strength (dBm) of the last advertisement
+    pub rssi: Option<i16>,
+    /// Key of the manufacturer data, identifies the device type
+    pub manufacturer_key: u16,
+    /// Name of the model detected from the key of the manufacturer data
+    pub model: &'static str,
+    /// Current temperature (°C), if the last advertisement contained the current data
+    pub temperature: Option<f32>,
+    /// Current humidity (0 - 100%), if the last advertisement contained the current data
+    pub humidity: Option<f32>,
+    /// Battery level (0 - 100%), if the last advertisement contained the current data
+    pub battery_level: Option<f32>,
+}
+
+/// Scans all adapters for the given time and returns all ThermoBeacons in range, regardless of the configured devices
+pub async fn discover(
+    manager: &Manager,
     seconds_to_scan: u64,
-) -> Result<Vec<ThermoBeaconFullReadResult>, Box<dyn Error + Send + Sync>> {
-    let time_to_wait_between_scans = 5;
+) -> Result<Vec<DiscoveredBeacon>, Box<dyn Error + Send + Sync>> {
     let adapter_list = manager.adapters().await?;
     if adapter_list.is_empty() {
         error!("No Bluetooth adapters found");
         return Err("No Bluetooth adapters found".into());
     }
 
-    let mut result: Vec<ThermoBeaconFullReadResult> = vec![];
+    let mut result: Vec<DiscoveredBeacon> = vec![];
     for adapter in adapter_list.iter() {
-        debug!("Starting scan on {}...", adapter.adapter_info().await?);
-        adapter
-            .start_scan(ScanFilter::default())
-            .await
-            .expect("Can't scan BLE adapter for connected devices...");
+        let adapter_info = adapter.adapter_info().await?;
+        debug!("Starting discovery on {}...", adapter_info);
+        adapter.start_scan(ScanFilter::default()).await?;
         time::sleep(Duration::from_secs(seconds_to_scan)).await;
-        let peripherals = adapter.peripherals().await?;
-        if peripherals.is_empty() {
-            error!("->>> BLE peripheral devices were not found, sorry. Exiting...");
-        } else {
-            // All peripheral devices in range
-            for peripheral in peripherals.iter() {
-                let device_present = devices.iter().any(|d| peripheral.address() == *d);
-
-                if device_present {
-                    let properties = peripheral.properties().await?;
-
-                    if properties.is_some() {
-                        let mut props = properties.unwrap();
-                        let local_name = props
-                            .clone()
-                            .local_name
-                            .unwrap_or(String::from("(peripheral name unknown)"));
-
-                        if local_name == "ThermoBeacon" {
-                            let measurement = match get_property_length(&props) {
-                                18 => {
-                                    // Temperature and humdity data is available
-                                    debug!(
-                                        "Reading temperature and humidity from ThermoBeacon {:?}",
-                                        peripheral.address()
-                                    );
-                                    let data = parse_thermo_beacon_data(&props)?;
-
-                                    // Wait for the min_max data
-                                    while get_property_length(&props) != 20 {
-                                        time::sleep(Duration::from_secs(
-                                            time_to_wait_between_scans,
-                                        ))
-                                        .await;
-                                        props = match peripheral.properties().await? {
-                                            Some(p) => p,
-                                            None => props,
-                                        }
-                                    }
-                                    debug!(
-                                        "Reading min and max temperature from ThermoBeacon {:?}",
-                                        peripheral.address()
-                                    );
-                                    let min_max_data = parse_thermo_beacon_min_max_data(&props)?;
-
-                                    Some((data, min_max_data))
-                                }
-                                20 => {
-                                    // Min-max data is available
-                                    debug!(
-                                        "Reading min and max temperature from ThermoBeacon {:?}",
-                                        peripheral.address()
-                                    );
-                                    let min_max_data = parse_thermo_beacon_min_max_data(&props)?;
-
-                                    // Wait  temperature and humidity data
-                                    while get_property_length(&props) != 18 {
-                                        time::sleep(Duration::from_secs(
-                                            time_to_wait_between_scans,
-                                        ))
-                                        .await;
-                                        props = match peripheral.properties().await? {
-                                            Some(p) => p,
-                                            None => props,
-                                        }
-                                    }
-                                    debug!(
-                                        "Reading temperature and humidity from ThermoBeacon {:?}",
-                                        peripheral.address()
-                                    );
-                                    let data = parse_thermo_beacon_data(&props)?;
-
-                                    Some((data, min_max_data))
-                                }
-                                _ => None,
-                            };
-
-                            if let Some((data, min_max_data)) = measurement {
-                                let r = ThermoBeaconFullReadResult {
-                                    battery_level: data.battery_level,
-                                    humidity: data.humidity,
-                                    temperature: data.temperature,
-                                    uptime: data.uptime_s,
-                                    button_pressed: data.button_pressed,
-                                    mac: data.mac,
-                                    max_temperature: min_max_data.max_temperature,
-                                    min_temperature: min_max_data.min_temperature,
-                                    max_temp_time: min_max_data.max_temp_time,
-                                    min_temp_time: min_max_data.min_temp_time,
-                                };
-
-                                result.push(r);
-                            }
+
+        for peripheral in adapter.peripherals().await? {
+            let props = match peripheral.properties().await? {
+                Some(p) => p,
+                None => continue,
+            };
+            let (manufacturer_key, model) = match props
+                .manufacturer_data
+                .keys()
+                .find_map(|key| Some((*key, thermobeacon_models::by_key(*key)?)))
+            {
+                Some(key_and_model) => key_and_model,
+                None => continue,
+            };
+            if props.local_name.as_deref() != Some("ThermoBeacon")
+                || result.iter().any(|b| b.mac == peripheral.address())
+            {
+                continue;
+            }
+            // Only the current data frame contains readings
+            let data = parse_thermo_beacon_data(&props, Some(model)).ok();
+            result.push(DiscoveredBeacon {
+                mac: peripheral.address(),
+                rssi: props.rssi,
+                manufacturer_key,
+                model: model.name,
+                temperature: data.as_ref().map(|d| d.temperature),
+                humidity: data.as_ref().map(|d| d.humidity),
+                battery_level: data.as_ref().map(|d| d.battery_level),
+            });
+        }
+        adapter.stop_scan().await?;
+    }
+    Ok(result)
+}
+
+/// Continuously listens for advertisements of the given devices on all adapters and returns the readings as a stream. ThermoBeacons alternately advertise the current data and the min/max data,
+/// so each received current data frame is combined with the latest min/max data of the device. Must be called within a tokio runtime, the listeners stop when the stream is dropped.
+pub async fn scan_stream(
+    manager: &Manager,
+    devices: &[BDAddr],
+) -> Result<impl Stream<Item = ThermoBeaconFullReadResult>, Box<dyn Error + Send + Sync>> {
+    scan_stream_with_types(manager, devices, &HashMap::new(), &HashMap::new()).await
+}
+
+/// Combines the advertisements of the given devices to readings, as received by a continuous scan. ThermoBeacons alternately advertise the current data and the min/max data,
+/// so each current data frame is combined with the latest min/max data of the device.
+pub struct AdvertisementDecoder {
+    /// ThermoBeacons, their frames contain the MAC
+    thermobeacons: Vec<BDAddr>,
+    /// Devices of other types, their advertisements do not contain the MAC
+    others: HashMap<BDAddr, DeviceType>,
+    /// Configured models of ThermoBeacons
+    models: HashMap<BDAddr, &'static ThermoBeaconModel>,
+    /// Latest min/max data of each ThermoBeacon
+    min_max: HashMap<BDAddr, ThermoBeaconMinMaxData>,
+}
+
+impl AdvertisementDecoder {
+    /// Decoder for the given devices. Devices without a type are ThermoBeacons, their model is detected from the advertisements unless configured in `models`.
+    pub fn new(
+        devices: &[BDAddr],
+        device_types: &HashMap<BDAddr, DeviceType>,
+        models: &HashMap<BDAddr, &'static ThermoBeaconModel>,
+    ) -> Self {
+        let others: HashMap<BDAddr, DeviceType> = device_types
+            .iter()
+            .filter(|(mac, device_type)| {
+                devices.contains(mac) && **device_type != DeviceType::ThermoBeacon
+            })
+            .map(|(mac, device_type)| (*mac, *device_type))
+            .collect();
+        AdvertisementDecoder {
+            thermobeacons: devices
+                .iter()
+                .filter(|mac| !others.contains_key(mac))
+                .copied()
+                .collect(),
+            others,
+            models: models.clone(),
+            min_max: HashMap::new(),
+        }
+    }
+
+    /// Whether the address of the advertising peripheral is required, because sensors of other types are identified by it
+    pub fn needs_address(&self) -> bool {
+        !self.others.is_empty()
+    }
+
+    /// Decodes an advertisement of the peripheral with the given address (if known) into the readings it completes.
+    /// The signal strength is not part of the advertisement, it has to be added to the readings.
+    pub fn decode(
+        &mut self,
+        address: Option<BDAddr>,
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Vec<ThermoBeaconFullReadResult> {
+        // Sensors of other types are identified by the address of the peripheral
+        if let Some(mac) = address {
+            let reading = self.others.get(&mac).and_then(|device_type| {
+                sensors::decode(*device_type, mac, manufacturer_data, service_data)
+            });
+            if let Some(reading) = reading {
+                trace!("Received advertisement of {}", mac);
+                return vec![reading];
+            }
+        }
+        // The frames contain the MAC of the device, so the peripheral does not need to be resolved.
+        // All models share the position of the MAC, so the frame is decoded with the detected model first.
+        let frames: Vec<ThermoBeaconFrame> = manufacturer_data
+            .iter()
+            .filter_map(|(key, data)| {
+                let detected = thermobeacon_models::by_key(*key);
+                let frame = parse_advertisement_of_model(
+                    data,
+                    detected.unwrap_or(thermobeacon_models::DEFAULT),
+                )
+                .ok()?;
+                match self.models.get(&frame.mac()) {
+                    Some(model) => parse_advertisement_of_model(data, model).ok(),
+                    None => detected.map(|_| frame),
+                }
+            })
+            .collect();
+        let mut readings = vec![];
+        for frame in frames {
+            match frame {
+                ThermoBeaconFrame::Data(data) if self.thermobeacons.contains(&data.mac) => {
+                    match self.min_max.get(&data.mac) {
+                        Some(min_max_data) => {
+                            trace!("Received advertisement of ThermoBeacon {}", data.mac);
+                            readings.push((data, min_max_data.clone()).into());
+                        }
+                        None => {
+                            trace!("Waiting for min/max data of ThermoBeacon {}", data.mac)
                         }
                     }
                 }
+                ThermoBeaconFrame::MinMax(min_max_data)
+                    if self.thermobeacons.contains(&min_max_data.mac) =>
+                {
+                    self.min_max.insert(min_max_data.mac, min_max_data);
+                }
+                _ => {}
             }
         }
-        adapter.stop_scan().await?;
+        readings
+    }
+}
+
+/// Like `scan_stream`, for devices of different sensor types. Devices without a type are ThermoBeacons, their model is detected
+/// from the advertisements unless configured in `models`.
+pub async fn scan_stream_with_types(
+    manager: &Manager,
+    devices: &[BDAddr],
+    device_types: &HashMap<BDAddr, DeviceType>,
+    models: &HashMap<BDAddr, &'static ThermoBeaconModel>,
+) -> Result<impl Stream<Item = ThermoBeaconFullReadResult>, Box<dyn Error + Send + Sync>> {
+    let adapter_list = manager.adapters().await?;
+    if adapter_list.is_empty() {
+        error!("No Bluetooth adapters found");
+        return Err("No Bluetooth adapters found".into());
+    }
+
+    let (sender, receiver) = unbounded_channel();
+    let recent = std::sync::Arc::new(Mutex::new(RecentReadings::default()));
+    for adapter in adapter_list.into_iter() {
+        let adapter_info = adapter.adapter_info().await?;
+        let mut events = adapter.events().await?;
+        adapter.start_scan(ScanFilter::default()).await?;
+        info!("Listening for advertisements on {}", adapter_info);
+
+        let mut decoder = AdvertisementDecoder::new(devices, device_types, models);
+        let sender = sender.clone();
+        let recent = recent.clone();
+        tokio::spawn(async move {
+            'events: while let Some(event) = events.next().await {
+                let (id, manufacturer_data, service_data) = match event {
+                    CentralEvent::ManufacturerDataAdvertisement {
+                        id,
+                        manufacturer_data,
+                    } => (id, manufacturer_data, HashMap::new()),
+                    CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                        (id, HashMap::new(), service_data)
+                    }
+                    _ => continue,
+                };
+                let address = if decoder.needs_address() {
+                    adapter.peripheral(&id).await.ok().map(|p| p.address())
+                } else {
+                    None
+                };
+                for reading in decoder.decode(address, &manufacturer_data, &service_data) {
+                    if !recent
+                        .lock()
+                        .unwrap()
+                        .insert(&reading, time::Instant::now())
+                    {
+                        trace!(
+                            "Dropping reading of {} received by another adapter",
+                            reading.mac
+                        );
+                        continue;
+                    }
+                    // The signal strength is not part of the advertisement event
+                    let props = match adapter.peripheral(&id).await {
+                        Ok(p) => p.properties().await.ok().flatten(),
+                        Err(_) => None,
+                    }
+                    .unwrap_or_default();
+                    let reading = ThermoBeaconFullReadResult {
+                        rssi: props.rssi,
+                        tx_power: props.tx_power_level,
+                        ..reading
+                    };
+                    if sender.send(reading).is_err() {
+                        // Stream was dropped
+                        break 'events;
+                    }
+                }
+            }
+            warn!("Stopped listening on {}", adapter_info);
+            let _ = adapter.stop_scan().await;
+        });
+    }
+
+    Ok(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move { receiver.recv().await.map(|reading| (reading, receiver)) },
+    ))
+}
+
+/// Time within which the same reading is considered a copy of an advertisement received by another adapter
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Readings recently sent by the listening mode, to drop the copies of an advertisement received by several adapters
+#[derive(Default)]
+struct RecentReadings(HashMap<BDAddr, (time::Instant, ThermoBeaconFullReadResult)>);
+
+impl RecentReadings {
+    /// Records the reading. False if the same reading (regardless of the signal strength) was recorded within the duplicate window.
+    fn insert(&mut self, reading: &ThermoBeaconFullReadResult, now: time::Instant) -> bool {
+        let reading = ThermoBeaconFullReadResult {
+            rssi: None,
+            tx_power: None,
+            ..reading.clone()
+        };
+        if let Some((time, previous)) = self.0.get(&reading.mac) {
+            if *previous == reading && now.duration_since(*time) < DUPLICATE_WINDOW {
+                return false;
+            }
+        }
+        self.0.insert(reading.mac, (now, reading));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::sync::Arc;
+
+    /// Builds PeripheralProperties carrying the given frame under the given device type key
+    fn properties_with_frame(key: u16, frame: &[u8]) -> PeripheralProperties {
+        let mut props = PeripheralProperties::default();
+        props.manufacturer_data.insert(key, frame.to_vec());
+        props
+    }
+
+    fn raw_data(temperature_raw: u16, humidity_raw: u16) -> ThermoBeaconRawData {
+        ThermoBeaconRawData {
+            unknown: 0,
+            button: 0,
+            mac: 0x0000_1122_3344_5566,
+            voltage_raw: 3400,
+            temperature_raw,
+            humidity_raw,
+            uptime_seconds: 86400,
+        }
+    }
+
+    #[test]
+    fn aggregates_samples() {
+        let values = vec![21.0, 21.5, 85.0, 21.25];
+        assert_eq!(Aggregation::Last.apply(values.clone()), 21.25);
+        assert_eq!(Aggregation::Mean.apply(values.clone()), 37.1875);
+        assert_eq!(Aggregation::Median.apply(values.clone()), 21.375);
+        assert_eq!(Aggregation::Median.apply(values[..3].to_vec()), 21.5);
+    }
+
+    #[test]
+    fn converts_positive_temperature() {
+        let data: ThermoBeaconData = raw_data(21 * 16 + 8, 45 * 16).into();
+        assert_eq!(data.temperature, 21.5);
+        assert_eq!(data.humidity, 45.0);
+        assert_eq!(data.battery_level, 100.0);
+        assert_eq!(data.uptime_d, 1.0);
+    }
+
+    #[test]
+    fn converts_negative_temperatures() {
+        // Values are signed 16 bit for the known models
+        let data: ThermoBeaconData = raw_data(0x7FFF, 0).into();
+        assert_eq!(data.temperature, 2047.9375);
+        let data: ThermoBeaconData = raw_data(0x8000, 0).into();
+        assert_eq!(data.temperature, -2048.0);
+        let data: ThermoBeaconData = raw_data(u16::MAX, 0).into();
+        assert_eq!(data.temperature, -0.0625);
+        let data: ThermoBeaconData = raw_data(u16::MAX - 1, 0).into();
+        assert_eq!(data.temperature, -0.125);
+        let data: ThermoBeaconData = raw_data(65536 - 16 * 5, 0).into();
+        assert_eq!(data.temperature, -5.0);
+    }
+
+    #[test]
+    fn parses_frames_from_peripheral_properties() {
+        let frame = raw_data(20 * 16, 50 * 16).pack().unwrap();
+        let data = parse_thermo_beacon_data(&properties_with_frame(0x15, &frame), None).unwrap();
+        assert_eq!(data.temperature, 20.0);
+        assert_eq!(data.mac, "11:22:33:44:55:66".parse::<BDAddr>().unwrap());
+
+        assert!(parse_thermo_beacon_data(&properties_with_frame(0x42, &frame), None).is_err());
+        assert!(
+            parse_thermo_beacon_min_max_data(&properties_with_frame(0x15, &frame), None).is_err()
+        );
+        // Unknown keys are accepted with a configured model
+        let ws08 = thermobeacon_models::by_name("WS08");
+        assert!(parse_thermo_beacon_data(&properties_with_frame(0x42, &frame), ws08).is_ok());
+    }
+
+    #[test]
+    fn parses_advertisements_by_length() {
+        let frame = raw_data(20 * 16, 50 * 16).pack().unwrap();
+        match parse_advertisement(&frame).unwrap() {
+            ThermoBeaconFrame::Data(data) => assert_eq!(data.humidity, 50.0),
+            frame => panic!("Unexpected frame {:?}", frame),
+        }
+        assert!(parse_advertisement(&frame[..17]).is_err());
+    }
+
+    #[test]
+    fn decoder_combines_data_with_latest_min_max_data() {
+        let mac: BDAddr = "11:22:33:44:55:66".parse().unwrap();
+        let mut decoder = AdvertisementDecoder::new(&[mac], &HashMap::new(), &HashMap::new());
+        let data = HashMap::from([(0x10, raw_data(20 * 16, 50 * 16).pack().unwrap().to_vec())]);
+        let min_max = ThermoBeaconMinMaxRawData {
+            unknown: 0,
+            button: 0,
+            mac: 0x0000_1122_3344_5566,
+            max_temperature_raw: 25 * 16,
+            max_temp_time_seconds: 3600,
+            min_temperature_raw: 15 * 16,
+            mintemp_time_seconds: 7200,
+        };
+        let min_max = HashMap::from([(0x10, min_max.pack().unwrap().to_vec())]);
+
+        // The current data is dropped until the min/max data is known
+        assert!(decoder.decode(None, &data, &HashMap::new()).is_empty());
+        assert!(decoder.decode(None, &min_max, &HashMap::new()).is_empty());
+        let readings = decoder.decode(None, &data, &HashMap::new());
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].mac, mac);
+        assert_eq!(readings[0].temperature, 20.0);
+        assert_eq!(readings[0].max_temperature, Some(25.0));
+
+        // Frames of other devices are ignored
+        let mut decoder = AdvertisementDecoder::new(&[], &HashMap::new(), &HashMap::new());
+        decoder.decode(None, &min_max, &HashMap::new());
+        assert!(decoder.decode(None, &data, &HashMap::new()).is_empty());
+    }
+
+    /// Scanner with a single adapter, whose peripherals advertise the given frames one after another (repeating the last one)
+    #[derive(Clone)]
+    struct MockScanner {
+        peripherals: Vec<MockPeripheral>,
+        /// Fail to start the scan
+        failing: bool,
+    }
+
+    #[derive(Clone)]
+    struct MockPeripheral {
+        mac: BDAddr,
+        advertisements: Arc<Mutex<Vec<PeripheralProperties>>>,
+    }
+
+    impl MockPeripheral {
+        fn new(mac: BDAddr, frames: &[Vec<u8>]) -> Self {
+            let advertisements = frames
+                .iter()
+                .map(|frame| PeripheralProperties {
+                    address: mac,
+                    local_name: Some("ThermoBeacon".to_string()),
+                    rssi: Some(-60),
+                    ..properties_with_frame(0x10, frame)
+                })
+                .collect();
+            MockPeripheral {
+                mac,
+                advertisements: Arc::new(Mutex::new(advertisements)),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl scanner::BleScanner for MockScanner {
+        type Adapter = MockScanner;
+
+        async fn adapters(&self) -> Result<Vec<MockScanner>, Box<dyn Error + Send + Sync>> {
+            Ok(vec![self.clone()])
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl scanner::ScannerAdapter for MockScanner {
+        type Peripheral = MockPeripheral;
+
+        async fn info(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+            Ok("mock".to_string())
+        }
+
+        async fn start_scan(
+            &self,
+            _parameters: &scanner::ScanParameters,
+        ) -> Result<(), Box<dyn Error + Send + Sync>> {
+            if self.failing {
+                return Err("Adapter not powered".into());
+            }
+            Ok(())
+        }
+
+        async fn stop_scan(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Ok(())
+        }
+
+        async fn peripherals(&self) -> Result<Vec<MockPeripheral>, Box<dyn Error + Send + Sync>> {
+            Ok(self.peripherals.clone())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl scanner::ScannerPeripheral for MockPeripheral {
+        fn address(&self) -> BDAddr {
+            self.mac
+        }
+
+        async fn properties(
+            &self,
+        ) -> Result<Option<PeripheralProperties>, Box<dyn Error + Send + Sync>> {
+            let mut advertisements = self.advertisements.lock().unwrap();
+            if advertisements.len() > 1 {
+                return Ok(Some(advertisements.remove(0)));
+            }
+            Ok(advertisements.first().cloned())
+        }
+    }
+
+    #[test]
+    fn drops_readings_received_by_another_adapter() {
+        let reading = ThermoBeaconFullReadResult {
+            temperature: 21.0,
+            uptime: 100,
+            rssi: Some(-60),
+            ..Default::default()
+        };
+        let mut recent = RecentReadings::default();
+        let now = time::Instant::now();
+        assert!(recent.insert(&reading, now));
+        let copy = ThermoBeaconFullReadResult {
+            rssi: Some(-80),
+            ..reading.clone()
+        };
+        assert!(!recent.insert(&copy, now + Duration::from_millis(100)));
+        let next = ThermoBeaconFullReadResult {
+            uptime: 101,
+            ..reading.clone()
+        };
+        assert!(recent.insert(&next, now + Duration::from_millis(200)));
+        assert!(recent.insert(&next, now + DUPLICATE_WINDOW + Duration::from_millis(200)));
+    }
+
+    #[tokio::test]
+    async fn reads_configured_devices_with_mock_scanner() {
+        let mac: BDAddr = "11:22:33:44:55:66".parse().unwrap();
+        let min_max = ThermoBeaconMinMaxRawData {
+            unknown: 0,
+            button: 0,
+            mac: 0x0000_1122_3344_5566,
+            max_temperature_raw: 25 * 16,
+            max_temp_time_seconds: 3600,
+            min_temperature_raw: 15 * 16,
+            mintemp_time_seconds: 7200,
+        };
+        // The min/max data is accepted on the first poll, the current data only afterwards
+        let scanner = MockScanner {
+            peripherals: vec![MockPeripheral::new(
+                mac,
+                &[
+                    min_max.pack().unwrap().to_vec(),
+                    raw_data(21 * 16, 40 * 16).pack().unwrap().to_vec(),
+                ],
+            )],
+            failing: false,
+        };
+        let options = ScanOptions {
+            scan_duration: Duration::from_secs(5),
+            device_read_timeout: Duration::from_secs(5),
+            samples: 1,
+            aggregation: Aggregation::Last,
+            device_types: HashMap::new(),
+            models: HashMap::new(),
+            adapters: vec![],
+            device_adapters: HashMap::new(),
+            poll_interval: Duration::from_millis(100),
+            parameters: scanner::ScanParameters::default(),
+        };
+        let missing: BDAddr = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let cache = PeripheralCache::default();
+
+        let readings = read_all_configured(&scanner, &cache, &[mac], &options)
+            .await
+            .unwrap()
+            .readings;
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].temperature, 21.0);
+        assert_eq!(readings[0].min_temperature, Some(15.0));
+        assert_eq!(readings[0].rssi, Some(-60));
+
+        // Devices out of range have no reading
+        let options = ScanOptions {
+            scan_duration: Duration::from_millis(100),
+            ..options
+        };
+        let readings = read_all_configured(&scanner, &cache, &[missing], &options)
+            .await
+            .unwrap()
+            .readings;
+        assert!(readings.is_empty());
+
+        // Devices pinned to other adapters are not read
+        let options = ScanOptions {
+            device_adapters: HashMap::from([(mac, "hci1".to_string())]),
+            ..options
+        };
+        let readings = read_all_configured(&scanner, &cache, &[mac], &options)
+            .await
+            .unwrap()
+            .readings;
+        assert!(readings.is_empty());
+
+        // The run only fails if the scan failed on all adapters
+        let options = ScanOptions {
+            device_adapters: HashMap::new(),
+            ..options
+        };
+        let failing = MockScanner {
+            failing: true,
+            ..scanner
+        };
+        assert!(read_all_configured(&failing, &cache, &[mac], &options)
+            .await
+            .is_err());
+    }
+
+    /// Options of a scan for a single sample, waiting the given time for a missing frame
+    fn scan_options(device_read_timeout: Duration) -> ScanOptions {
+        ScanOptions {
+            scan_duration: Duration::from_secs(10),
+            device_read_timeout,
+            samples: 1,
+            aggregation: Aggregation::Last,
+            device_types: HashMap::new(),
+            models: HashMap::new(),
+            adapters: vec![],
+            device_adapters: HashMap::new(),
+            poll_interval: Duration::from_millis(50),
+            parameters: scanner::ScanParameters::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn times_out_on_devices_sending_a_single_frame_type() {
+        let data_only: BDAddr = "11:22:33:44:55:66".parse().unwrap();
+        let min_max_only: BDAddr = "11:22:33:44:55:77".parse().unwrap();
+        let min_max = ThermoBeaconMinMaxRawData {
+            unknown: 0,
+            button: 0,
+            mac: 0x0000_1122_3344_5577,
+            max_temperature_raw: 25 * 16,
+            max_temp_time_seconds: 3600,
+            min_temperature_raw: 15 * 16,
+            mintemp_time_seconds: 7200,
+        };
+        let scanner = MockScanner {
+            peripherals: vec![
+                MockPeripheral::new(
+                    data_only,
+                    &[raw_data(21 * 16, 40 * 16).pack().unwrap().to_vec()],
+                ),
+                MockPeripheral::new(min_max_only, &[min_max.pack().unwrap().to_vec()]),
+            ],
+            failing: false,
+        };
+        let options = scan_options(Duration::from_millis(300));
+        let cache = PeripheralCache::default();
+
+        let start = time::Instant::now();
+        let readings = read_all_configured(&scanner, &cache, &[data_only, min_max_only], &options)
+            .await
+            .unwrap()
+            .readings;
+        // The scan ends once the missing frames timed out, not at the end of the scan duration
+        assert!(start.elapsed() < options.scan_duration);
+
+        // Partial result without the min/max data, the device without current data has no result
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].mac, data_only);
+        assert_eq!(readings[0].temperature, 21.0);
+        assert_eq!(readings[0].min_temperature, None);
+        assert_eq!(readings[0].max_temperature, None);
+    }
+
+    #[test]
+    fn waits_for_the_missing_frame_until_the_timeout() {
+        let options = scan_options(Duration::from_secs(5));
+        let pending = PendingRead {
+            first_frame: Some(time::Instant::now()),
+            data: vec![raw_data(21 * 16, 40 * 16).into()],
+            ..Default::default()
+        };
+        assert!(!pending.is_done(&options));
+
+        let timed_out = PendingRead {
+            first_frame: time::Instant::now().checked_sub(Duration::from_secs(6)),
+            ..pending
+        };
+        assert!(timed_out.is_done(&options));
+    }
+
+    proptest! {
+        #[test]
+        fn raw_data_roundtrips(
+            button in any::<u8>(),
+            mac in 0..(1u64 << 48),
+            voltage_raw in any::<u16>(),
+            temperature_raw in any::<u16>(),
+            humidity_raw in any::<u16>(),
+            uptime_seconds in any::<u32>(),
+        ) {
+            let raw = ThermoBeaconRawData {
+                unknown: 0,
+                button,
+                mac,
+                voltage_raw,
+                temperature_raw,
+                humidity_raw,
+                uptime_seconds,
+            };
+            let unpacked = ThermoBeaconRawData::unpack(&raw.pack().unwrap()).unwrap();
+            prop_assert_eq!(unpacked.button, button);
+            prop_assert_eq!(unpacked.mac, mac);
+            prop_assert_eq!(unpacked.voltage_raw, voltage_raw);
+            prop_assert_eq!(unpacked.temperature_raw, temperature_raw);
+            prop_assert_eq!(unpacked.humidity_raw, humidity_raw);
+            prop_assert_eq!(unpacked.uptime_seconds, uptime_seconds);
+        }
+
+        #[test]
+        fn min_max_raw_data_roundtrips(
+            mac in 0..(1u64 << 48),
+            max_temperature_raw in any::<u16>(),
+            max_temp_time_seconds in any::<u32>(),
+            min_temperature_raw in any::<u16>(),
+            mintemp_time_seconds in any::<u32>(),
+        ) {
+            let raw = ThermoBeaconMinMaxRawData {
+                unknown: 0,
+                button: 0,
+                mac,
+                max_temperature_raw,
+                max_temp_time_seconds,
+                min_temperature_raw,
+                mintemp_time_seconds,
+            };
+            let data: ThermoBeaconMinMaxData =
+                ThermoBeaconMinMaxRawData::unpack(&raw.pack().unwrap()).unwrap().into();
+            prop_assert_eq!(data.max_temp_time, max_temp_time_seconds);
+            prop_assert_eq!(data.min_temp_time, mintemp_time_seconds);
+            prop_assert_eq!(u64::from(data.mac), mac);
+        }
+
+        #[test]
+        fn temperature_stays_within_sensor_range(temperature_raw in any::<u16>()) {
+            let data: ThermoBeaconData = raw_data(temperature_raw, 0).into();
+            prop_assert!((-2048.0..2048.0).contains(&data.temperature));
+            // Wrapped values must differ from the unwrapped value by exactly 4096
+            let t = temperature_raw as f32 / 16.0;
+            prop_assert!(data.temperature == t || data.temperature == t - 4096.0);
+        }
     }
-    Ok(result)
 }
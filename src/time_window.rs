@@ -0,0 +1,149 @@
+//! Time windows of the notification and alert rules (e.g. frost alerts only 22:00 - 08:00 on workdays), evaluated in the
+//! configured timezone. Outside their windows, rules do not notify.
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Weekday};
+
+/// Days a time window is active on
+#[derive(Debug, Clone, Copy, serde_derive::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Days {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+    /// Monday to friday
+    Workdays,
+    /// Saturday and sunday
+    Weekend,
+    /// Every day of the week
+    Daily,
+}
+
+impl Days {
+    /// Checks if the given weekday is part of these days
+    fn contains(&self, weekday: Weekday) -> bool {
+        match self {
+            Days::Mon => weekday == Weekday::Mon,
+            Days::Tue => weekday == Weekday::Tue,
+            Days::Wed => weekday == Weekday::Wed,
+            Days::Thu => weekday == Weekday::Thu,
+            Days::Fri => weekday == Weekday::Fri,
+            Days::Sat => weekday == Weekday::Sat,
+            Days::Sun => weekday == Weekday::Sun,
+            Days::Workdays => !matches!(weekday, Weekday::Sat | Weekday::Sun),
+            Days::Weekend => matches!(weekday, Weekday::Sat | Weekday::Sun),
+            Days::Daily => true,
+        }
+    }
+}
+
+/// Time window (e.g. for alert notifications), evaluated in the configured timezone.
+/// If `to` is before `from`, the window spans midnight (e.g. 22:00 - 08:00) and the configured days refer to the day the window starts.
+#[derive(Debug, Clone, serde_derive::Deserialize, PartialEq, Eq)]
+pub struct TimeWindow {
+    /// Start of the window (e.g. "22:00"), defaults to the start of the day
+    #[serde(default = "default_from")]
+    pub from: NaiveTime,
+    /// End of the window (e.g. "08:00", exclusive). If not set, the window lasts until midnight.
+    #[serde(default)]
+    pub to: Option<NaiveTime>,
+    /// Days the window starts on. Defaults to every day
+    #[serde(default)]
+    pub days: Vec<Days>,
+}
+
+fn default_from() -> NaiveTime {
+    NaiveTime::from_hms_opt(0, 0, 0).unwrap()
+}
+
+impl TimeWindow {
+    /// Checks if the window starts on the given weekday
+    fn starts_on(&self, weekday: Weekday) -> bool {
+        self.days.is_empty() || self.days.iter().any(|d| d.contains(weekday))
+    }
+
+    /// Checks if the given point in time is within this window
+    pub fn is_active<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> bool {
+        let time = now.time();
+        let today = now.weekday();
+
+        match self.to {
+            None => self.starts_on(today) && time >= self.from,
+            Some(to) if self.from <= to => self.starts_on(today) && time >= self.from && time < to,
+            Some(to) => {
+                // Window spans midnight: either we are in the part before midnight (started today) or after midnight (started yesterday)
+                let yesterday = (now.clone() - Duration::days(1)).weekday();
+                (self.starts_on(today) && time >= self.from)
+                    || (self.starts_on(yesterday) && time < to)
+            }
+        }
+    }
+}
+
+/// Checks if any of the given windows is active at the given point in time. Without any window configured, this is always the case.
+pub fn any_active<Tz: TimeZone>(windows: &[TimeWindow], now: &DateTime<Tz>) -> bool {
+    windows.is_empty() || windows.iter().any(|w| w.is_active(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Berlin;
+
+    fn window(from: &str, to: &str, days: Vec<Days>) -> TimeWindow {
+        TimeWindow {
+            from: from.parse().unwrap(),
+            to: Some(to.parse().unwrap()),
+            days,
+        }
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let w = window("08:00", "17:00", vec![Days::Workdays]);
+        // 2024-01-05 is a friday
+        assert!(w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap()));
+        assert!(!w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 5, 17, 0, 0).unwrap()));
+        assert!(!w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 6, 12, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn window_spanning_midnight() {
+        let w = window("22:00", "08:00", vec![Days::Fri]);
+        assert!(w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 5, 23, 0, 0).unwrap()));
+        // Saturday morning still belongs to the window started on friday
+        assert!(w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 6, 7, 59, 0).unwrap()));
+        assert!(!w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 6, 23, 0, 0).unwrap()));
+        assert!(!w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 5, 7, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn reads_configured_windows() {
+        let w: TimeWindow =
+            serde_json::from_str(r#"{"from": "22:00", "to": "08:00", "days": ["workdays"]}"#)
+                .unwrap();
+        assert_eq!(w, window("22:00", "08:00", vec![Days::Workdays]));
+        let all_day: TimeWindow = serde_json::from_str(r#"{"days": ["weekend"]}"#).unwrap();
+        assert!(all_day.is_active(&Berlin.with_ymd_and_hms(2024, 1, 6, 23, 59, 0).unwrap()));
+    }
+
+    #[test]
+    fn window_without_end_lasts_until_midnight() {
+        let w: TimeWindow = serde_json::from_str(r#"{"from": "18:00"}"#).unwrap();
+        let last_second = Berlin.with_ymd_and_hms(2024, 1, 6, 23, 59, 59).unwrap();
+        assert!(w.is_active(&(last_second + Duration::milliseconds(500))));
+        assert!(!w.is_active(&(last_second + Duration::seconds(1))));
+        assert!(!w.is_active(&Berlin.with_ymd_and_hms(2024, 1, 6, 17, 59, 0).unwrap()));
+    }
+
+    #[test]
+    fn no_windows_are_always_active() {
+        assert!(any_active(
+            &[],
+            &Berlin.with_ymd_and_hms(2024, 1, 5, 12, 0, 0).unwrap()
+        ));
+    }
+}
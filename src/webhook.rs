@@ -0,0 +1,155 @@
+//! Webhook output: POSTs the readings as JSON to an HTTP endpoint (e.g. a serverless function), without any broker.
+//! Each reading is posted on its own, or the readings of a run as one JSON array.
+
+use std::{error::Error, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+
+use crate::{
+    configuration::{AppConfig, AppDevice, WebhookConfig},
+    sink::{Message, Sink},
+    thermobeacon_protocol::ThermoBeaconFullReadResult,
+};
+
+/// Delay before the first retry of a failed request, doubled with each retry
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Is the request worth retrying after this status? Other client errors (e.g. a wrong token) fail again.
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+/// POSTs the readings as JSON to the configured URL
+pub struct WebhookSink {
+    config: AppConfig,
+    webhook: WebhookConfig,
+    client: reqwest::Client,
+    /// Readings of the current run, in batch mode
+    batch: Mutex<Vec<Message>>,
+}
+
+impl WebhookSink {
+    pub fn new(
+        config: &AppConfig,
+        webhook: &WebhookConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let client = reqwest::Client::builder()
+            .timeout(webhook.timeout)
+            .build()?;
+        Ok(WebhookSink {
+            config: config.clone(),
+            webhook: webhook.clone(),
+            client,
+            batch: Mutex::new(vec![]),
+        })
+    }
+
+    /// POSTs the body once
+    async fn post_once<T: serde::Serialize + Sync>(
+        &self,
+        body: &T,
+    ) -> Result<(), (bool, Box<dyn Error + Send + Sync>)> {
+        let mut request = self.client.post(&self.webhook.url).json(body);
+        if let Some(token) = &self.webhook.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            // Connection errors and timeouts
+            Err(e) => return Err((true, e.into())),
+        };
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        Err((
+            is_transient(status),
+            format!("Webhook {} responded {}", self.webhook.url, status).into(),
+        ))
+    }
+
+    /// POSTs the body, retrying transient failures (connection errors, timeouts, server errors) with exponential backoff
+    async fn post<T: serde::Serialize + Sync>(
+        &self,
+        body: &T,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut delay = RETRY_DELAY;
+        let mut retry = 0;
+        loop {
+            match self.post_once(body).await {
+                Ok(()) => return Ok(()),
+                Err((true, e)) if retry < self.webhook.retries => {
+                    retry += 1;
+                    warn!(
+                        "{} (retry {} of {} in {:?})",
+                        e, retry, self.webhook.retries, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err((_, e)) => return Err(e),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn publish(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let msg = Message::new(&self.config, device, data.clone());
+        if self.webhook.batch {
+            self.batch.lock().unwrap().push(msg);
+            return Ok(());
+        }
+        self.post(&msg).await
+    }
+
+    async fn publish_history(
+        &self,
+        data: &ThermoBeaconFullReadResult,
+        device: &AppDevice,
+        measured_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Recovered after the run, posted right away. The endpoint always receives arrays in batch mode.
+        let msg = Message::at(&self.config, device, data.clone(), measured_at);
+        if self.webhook.batch {
+            self.post(&[msg]).await
+        } else {
+            self.post(&msg).await
+        }
+    }
+
+    async fn finish_run(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let batch = std::mem::take(&mut *self.batch.lock().unwrap());
+        if batch.is_empty() {
+            return Ok(());
+        }
+        debug!("Posting {} readings to {}", batch.len(), self.webhook.url);
+        self.post(&batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_transient_failures_only() {
+        assert!(is_transient(StatusCode::BAD_GATEWAY));
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient(StatusCode::UNAUTHORIZED));
+        assert!(!is_transient(StatusCode::NOT_FOUND));
+    }
+}
@@ -0,0 +1,88 @@
+//! Decoder for the advertisements of Xiaomi LYWSD03MMC hygrometers with the custom ATC or pvvx firmware (service data of the UUID 0x181A)
+//!
+//! ATC1441 format, 13 bytes (big endian)
+//! bytes | content
+//! ========================================================
+//! 00-05 | mac address
+//! 06-07 | temperature (0.1 °C, signed)
+//! 08-08 | humidity (%)
+//! 09-09 | battery level (%)
+//! 10-11 | battery voltage (mV)
+//! 12-12 | frame counter
+//!
+//! pvvx custom format, 15 bytes (little endian)
+//! bytes | content
+//! ========================================================
+//! 00-05 | mac address (reversed)
+//! 06-07 | temperature (0.01 °C, signed)
+//! 08-09 | humidity (0.01 %)
+//! 10-11 | battery voltage (mV)
+//! 12-12 | battery level (%)
+//! 13-13 | frame counter
+//! 14-14 | flags
+
+use btleplug::api::{bleuuid::uuid_from_u16, BDAddr};
+use std::error::Error;
+use uuid::Uuid;
+
+use crate::thermobeacon_protocol::ThermoBeaconFullReadResult;
+
+/// UUID of the service data of the ATC and pvvx advertisements (Environmental Sensing)
+pub const SERVICE_UUID: Uuid = uuid_from_u16(0x181A);
+
+/// Decodes the service data of an ATC1441 or pvvx advertisement. The format is determined by its length.
+pub fn parse(
+    mac: BDAddr,
+    data: &[u8],
+) -> Result<ThermoBeaconFullReadResult, Box<dyn Error + Send + Sync>> {
+    let (temperature, humidity, battery_level) = match data.len() {
+        13 => (
+            f32::from(i16::from_be_bytes([data[6], data[7]])) / 10.0,
+            f32::from(data[8]),
+            f32::from(data[9]),
+        ),
+        15 => (
+            f32::from(i16::from_le_bytes([data[6], data[7]])) / 100.0,
+            f32::from(u16::from_le_bytes([data[8], data[9]])) / 100.0,
+            f32::from(data[12]),
+        ),
+        len => return Err(format!("Unknown ATC frame length {}", len).into()),
+    };
+    Ok(ThermoBeaconFullReadResult {
+        temperature,
+        humidity,
+        battery_level,
+        mac,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_atc_and_pvvx_formats() {
+        let mac: BDAddr = "a4:c1:38:00:00:02".parse().unwrap();
+        // ATC1441: -1.5 °C, 60 %, 87 %, 2950 mV
+        let atc = [
+            0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02, 0xFF, 0xF1, 0x3C, 0x57, 0x0B, 0x86, 0x01,
+        ];
+        let reading = parse(mac, &atc).unwrap();
+        assert_eq!(reading.temperature, -1.5);
+        assert_eq!(reading.humidity, 60.0);
+        assert_eq!(reading.battery_level, 87.0);
+
+        // pvvx: 22.34 °C, 45.67 %, 2950 mV, 87 %
+        let pvvx = [
+            0x02, 0x00, 0x00, 0x38, 0xC1, 0xA4, 0xBA, 0x08, 0xD7, 0x11, 0x86, 0x0B, 0x57, 0x01,
+            0x04,
+        ];
+        let reading = parse(mac, &pvvx).unwrap();
+        assert_eq!(reading.temperature, 22.34);
+        assert_eq!(reading.humidity, 45.67);
+        assert_eq!(reading.battery_level, 87.0);
+
+        assert!(parse(mac, &atc[..12]).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{error::Error, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{configuration::ZabbixConfig, thermobeacon_protocol::ThermoBeaconFullReadResult};
+
+/// Timeout for connecting to the Zabbix server and for each read and write
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum accepted length of a response. The responses of the trapper are a few hundred bytes.
+const MAX_RESPONSE_LEN: u64 = 1024 * 1024;
+
+/// Header of each message of the Zabbix protocol (including the protocol version)
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+
+/// Single value sent to the Zabbix trapper
+#[derive(Debug, serde_derive::Serialize)]
+struct SenderValue {
+    host: String,
+    key: String,
+    value: String,
+}
+
+/// Request of the Zabbix sender protocol
+#[derive(Debug, serde_derive::Serialize)]
+struct SenderRequest {
+    request: &'static str,
+    data: Vec<SenderValue>,
+}
+
+/// Response of the Zabbix server
+#[derive(Debug, serde_derive::Deserialize)]
+struct SenderResponse {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+/// Converts a reading into trapper values with the keys '{key_prefix}.{metric}[{device name}]'
+fn to_values(
+    config: &ZabbixConfig,
+    name: &str,
+    data: &ThermoBeaconFullReadResult,
+) -> Vec<SenderValue> {
+    // The min/max data is missing in partial results
+    let metrics = [
+        ("temperature", Some(data.temperature.to_string())),
+        ("humidity", Some(data.humidity.to_string())),
+        ("battery_level", Some(data.battery_level.to_string())),
+        (
+            "max_temperature",
+            data.max_temperature.map(|t| t.to_string()),
+        ),
+        (
+            "min_temperature",
+            data.min_temperature.map(|t| t.to_string()),
+        ),
+        ("uptime", Some(data.uptime.to_string())),
+    ];
+    metrics
+        .into_iter()
+        .filter_map(|(metric, value)| value.map(|v| (metric, v)))
+        .map(|(metric, value)| SenderValue {
+            host: config.host.clone(),
+            key: format!("{}.{}[{}]", config.key_prefix, metric, name),
+            value,
+        })
+        .collect()
+}
+
+/// Frames a request with the header and its length
+fn frame(request: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER.len() + 8 + request.len());
+    packet.extend_from_slice(HEADER);
+    packet.extend_from_slice(&(request.len() as u64).to_le_bytes());
+    packet.extend_from_slice(request);
+    packet
+}
+
+/// Length of the response body given in the header of a response
+fn response_len(header: &[u8; 13]) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    if &header[0..4] != b"ZBXD" {
+        return Err("Invalid response header of Zabbix server".into());
+    }
+    let len = u64::from_le_bytes(header[5..13].try_into().unwrap());
+    if len > MAX_RESPONSE_LEN {
+        return Err(format!("Response of Zabbix server too large: {} bytes", len).into());
+    }
+    Ok(len as usize)
+}
+
+/// Sends a request to the Zabbix server and reads its response
+async fn exchange(
+    config: &ZabbixConfig,
+    request: &[u8],
+) -> Result<SenderResponse, Box<dyn Error + Send + Sync>> {
+    let mut stream = tokio::time::timeout(
+        TIMEOUT,
+        TcpStream::connect((config.server.as_str(), config.port)),
+    )
+    .await
+    .map_err(|_| "Timeout while connecting to Zabbix server")??;
+
+    tokio::time::timeout(TIMEOUT, stream.write_all(&frame(request)))
+        .await
+        .map_err(|_| "Timeout while sending data to Zabbix server")??;
+
+    let body = tokio::time::timeout(TIMEOUT, async {
+        let mut header = [0u8; 13];
+        stream.read_exact(&mut header).await?;
+        let mut body = vec![0u8; response_len(&header)?];
+        stream.read_exact(&mut body).await?;
+        Ok::<_, Box<dyn Error + Send + Sync>>(body)
+    })
+    .await
+    .map_err(|_| "Timeout while reading the response of Zabbix server")??;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Sends the readings (name of the device and data) to the Zabbix trapper
+pub async fn send<'a>(
+    config: &ZabbixConfig,
+    readings: impl Iterator<Item = (&'a str, &'a ThermoBeaconFullReadResult)>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let data: Vec<SenderValue> = readings
+        .flat_map(|(name, data)| to_values(config, name, data))
+        .collect();
+    if data.is_empty() {
+        return Ok(());
+    }
+    let request = serde_json::to_vec(&SenderRequest {
+        request: "sender data",
+        data,
+    })?;
+
+    let response = exchange(config, &request).await?;
+    if response.response != "success" {
+        return Err(format!("Zabbix server rejected data: {}", response.info).into());
+    }
+    // Info contains e.g. 'processed: 6; failed: 0; total: 6; seconds spent: 0.000055'
+    debug!("Sent data to Zabbix server: {}", response.info);
+    if !response.info.contains("failed: 0") {
+        warn!(
+            "Zabbix server did not process all values (are all items configured?): {}",
+            response.info
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_partial_readings() {
+        let config = ZabbixConfig {
+            server: "zabbix".to_string(),
+            port: 10051,
+            host: "gateway".to_string(),
+            key_prefix: "thermobeacon".to_string(),
+        };
+        let data = ThermoBeaconFullReadResult {
+            temperature: 21.5,
+            humidity: 45.0,
+            battery_level: 90.0,
+            uptime: 42,
+            ..Default::default()
+        };
+        let values: Vec<_> = to_values(&config, "Basement", &data)
+            .into_iter()
+            .map(|v| (v.host, v.key, v.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "gateway".to_string(),
+                    "thermobeacon.temperature[Basement]".to_string(),
+                    "21.5".to_string()
+                ),
+                (
+                    "gateway".to_string(),
+                    "thermobeacon.humidity[Basement]".to_string(),
+                    "45".to_string()
+                ),
+                (
+                    "gateway".to_string(),
+                    "thermobeacon.battery_level[Basement]".to_string(),
+                    "90".to_string()
+                ),
+                (
+                    "gateway".to_string(),
+                    "thermobeacon.uptime[Basement]".to_string(),
+                    "42".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_requests_and_checks_responses() {
+        assert_eq!(frame(b"{}"), b"ZBXD\x01\x02\0\0\0\0\0\0\0{}".to_vec());
+
+        let mut header = *b"ZBXD\x01\0\0\0\0\0\0\0\0";
+        header[5..13].copy_from_slice(&90u64.to_le_bytes());
+        assert_eq!(response_len(&header).unwrap(), 90);
+        header[5..13].copy_from_slice(&(MAX_RESPONSE_LEN + 1).to_le_bytes());
+        assert!(response_len(&header).is_err());
+        assert!(response_len(b"HTTP/1.1 400 ").is_err());
+    }
+}